@@ -10,8 +10,8 @@ def error_out(message):
     sys.stderr.write("Error: " + sys.argv[0] + " " + message)
     sys.exit(-1);
 
-if len(sys.argv) != 4:
-    error_out("<sw_dir> <cos base> libdeps|ifdeps|libpaths|objpaths|incpaths|shallowlibdeps|shallowifdeps|shallowifexp: \n\tThe first argument must be the path to the directory of the component|interface|library. The second should be the base of the composite repo's component directory (src/components/). Both must end in a /.\n")
+if len(sys.argv) < 4:
+    error_out("<sw_dir> <cos base> libdeps|ifdeps|libpaths|objpaths|incpaths|shallowlibdeps|shallowifdeps|shallowifexp [libs...]: \n\tThe first argument must be the path to the directory of the component|interface|library. The second should be the base of the composite repo's component directory (src/components/). Both must end in a /. Any further arguments are libraries to depend on in addition to those in the Makefile.\n")
 
 target_path = sys.argv[1]
 # path to the composite repo
@@ -165,6 +165,8 @@ def gen_paths(libdeps, ifdeps, expifs):
 (success, shallow_ls, shallow_ifs, shallow_exps) = makefile_dependencies(target_path)
 if not success:
     error_out("Could not find Makefile in " + target_path + ").\n")
+# Libraries added by the composer (e.g. from the sysspec)
+shallow_ls += new_deps(shallow_ls, sys.argv[4:])
 
 (libs, ifs, exps) = resolve_deps(target_path, shallow_ls, shallow_ifs, shallow_exps)
 
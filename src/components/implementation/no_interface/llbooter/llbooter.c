@@ -157,6 +157,15 @@ comps_init(void)
 	int cont, ret, j;
 	int comp_idx = 0;
 	struct protdom_ns_asid *ns_asid;
+	char *format;
+
+	/*
+	 * The composer can archive the binaries as cpio or a
+	 * flat, indexed blob (see the system's archive option), in
+	 * which our initargs look them up by their paths.
+	 */
+	format = args_get("initfs_format");
+	if (format) printc("Component binaries are in a %s archive.\n", format);
 
 	/*
 	 * Assume: our component id is the lowest of the ids for all
@@ -35,12 +35,22 @@ kv_value(struct kv_entry *kv)
 	}
 }
 
+/* A binary value, which can include '\0's, so has a size */
+static unsigned char *
+kv_blob(struct kv_entry *kv, int *sz)
+{
+	if (!kv || kv->vtype != VTYPE_BLOB) return NULL;
+	*sz = kv->val.blob.sz;
+	return kv->val.blob.data;
+}
+
 static args_type_t
 kv_type(struct kv_entry *kv)
 {
 	switch (kv->vtype) {
 	case VTYPE_STR: return ARGS_VAL;
 	case VTYPE_ARR: return ARGS_MAP;
+	case VTYPE_BLOB: return ARGS_VAL;
 	default:        return ARGS_ERR;
 	}
 }
@@ -58,6 +68,7 @@ kv_len(struct kv_entry *kv)
 	switch (kv->vtype) {
 	case VTYPE_STR: return 1;
 	case VTYPE_ARR: return kv->val.arr.sz;
+	case VTYPE_BLOB: return 1;
 	default:        return 0;
 	}
 }
@@ -68,7 +79,7 @@ __kv_index(struct kv_entry *kv, int idx)
 {
 	if (!kv) return NULL;
 	if (idx >= kv_len(kv) || idx < 0) return NULL;
-	if (kv->vtype == VTYPE_STR) return kv;
+	if (kv->vtype != VTYPE_ARR) return kv;
 	return kv->val.arr.kvs[idx];
 }
 
@@ -98,12 +109,123 @@ kv_iter(struct kv_entry *kv, struct kv_iter *i, struct kv_entry **ent)
 	return kv_iter_next(i, ent);
 }
 
+/*
+ * Operations on TLV-encoded entries (see struct tlv_entry). The
+ * encoding isn't aligned, so the lengths are read a byte at a time.
+ */
+
+#define TLV_MAGIC "cosargs1"
+#define TLV_HDR_SZ 5 /* the type, and the value's length */
+
+static unsigned int
+tlv_u32(unsigned char *p)
+{
+	return p[0] | (p[1] << 8) | (p[2] << 16) | ((unsigned int)p[3] << 24);
+}
+
+static kv_valtype_t
+tlv_vtype(struct tlv_entry *tlv)
+{
+	return (kv_valtype_t)tlv->ent[0];
+}
+
+/* The value follows the key's length, the key, and its '\0' */
+static unsigned char *
+tlv_val(struct tlv_entry *tlv)
+{
+	return tlv->ent + TLV_HDR_SZ + 4 + tlv_u32(tlv->ent + TLV_HDR_SZ) + 1;
+}
+
+static unsigned char *
+tlv_end(struct tlv_entry *tlv)
+{
+	return tlv->ent + TLV_HDR_SZ + tlv_u32(tlv->ent + 1);
+}
+
+static char *
+tlv_key(struct tlv_entry *tlv, int *key_len)
+{
+	*key_len = tlv_u32(tlv->ent + TLV_HDR_SZ);
+	return (char *)tlv->ent + TLV_HDR_SZ + 4;
+}
+
+static char *
+tlv_value(struct tlv_entry *tlv)
+{
+	if (tlv_vtype(tlv) != VTYPE_STR) return NULL;
+	return (char *)tlv_val(tlv);
+}
+
+static unsigned char *
+tlv_blob(struct tlv_entry *tlv, int *sz)
+{
+	if (tlv_vtype(tlv) != VTYPE_BLOB) return NULL;
+	*sz = tlv_end(tlv) - tlv_val(tlv);
+	return tlv_val(tlv);
+}
+
+static args_type_t
+tlv_type(struct tlv_entry *tlv)
+{
+	switch (tlv_vtype(tlv)) {
+	case VTYPE_STR:  return ARGS_VAL;
+	case VTYPE_ARR:  return ARGS_MAP;
+	case VTYPE_BLOB: return ARGS_VAL;
+	default:         return ARGS_ERR;
+	}
+}
+
+static int
+tlv_iter_next(struct tlv_iter *i, struct tlv_entry *ent)
+{
+	if (i->curr >= i->end) return 0;
+	ent->ent = i->curr;
+	i->curr  = tlv_end(ent);
+
+	return 1;
+}
+
+static int
+tlv_iter(struct tlv_entry *tlv, struct tlv_iter *i, struct tlv_entry *ent)
+{
+	if (tlv_vtype(tlv) != VTYPE_ARR) return 0;
+	*i = (struct tlv_iter){
+		.curr = tlv_val(tlv),
+		.end  = tlv_end(tlv)
+	};
+
+	return tlv_iter_next(i, ent);
+}
+
+/* Length of the map (= 1 for a string or blob), found by walking it */
+static int
+tlv_len(struct tlv_entry *tlv)
+{
+	struct tlv_iter i;
+	struct tlv_entry ent;
+	int cont, len = 0;
+
+	if (tlv_vtype(tlv) != VTYPE_ARR) return 1;
+	for (cont = tlv_iter(tlv, &i, &ent); cont; cont = tlv_iter_next(&i, &ent)) len++;
+
+	return len;
+}
+
 char *
 args_key(struct initargs *arg, int *arg_len)
 {
 	switch (arg->type) {
 	case ARGS_IMPL_KV:  return kv_key(arg->d.kv_ent, arg_len);
 	case ARGS_IMPL_TAR: return tar_key(&arg->d.tar_ent, arg_len);
+	case ARGS_IMPL_TLV: return tlv_key(&arg->d.tlv_ent, arg_len);
+	case ARGS_IMPL_FILE: {
+		/* As in a tarball, the key is the last of the path's names */
+		char *key = strrchr(arg->d.file_ent.name, '/');
+
+		key = key ? key + 1 : arg->d.file_ent.name;
+		*arg_len = strlen(key);
+		return key;
+	}
 	default:            return NULL;
 	}
 }
@@ -114,6 +236,25 @@ args_value(struct initargs *arg)
 	switch (arg->type) {
 	case ARGS_IMPL_KV:  return kv_value(arg->d.kv_ent);
 	case ARGS_IMPL_TAR: return tar_value(&arg->d.tar_ent);
+	case ARGS_IMPL_TLV: return tlv_value(&arg->d.tlv_ent);
+	case ARGS_IMPL_FILE: return (char *)arg->d.file_ent.data;
+	default:            return NULL;
+	}
+}
+
+/* Files in the tarball are blobs as well */
+unsigned char *
+args_blob(struct initargs *arg, int *sz)
+{
+	switch (arg->type) {
+	case ARGS_IMPL_KV:  return kv_blob(arg->d.kv_ent, sz);
+	case ARGS_IMPL_TAR:
+		*sz = tar_value_sz(&arg->d.tar_ent);
+		return (unsigned char *)tar_value(&arg->d.tar_ent);
+	case ARGS_IMPL_TLV: return tlv_blob(&arg->d.tlv_ent, sz);
+	case ARGS_IMPL_FILE:
+		*sz = arg->d.file_ent.sz;
+		return arg->d.file_ent.data;
 	default:            return NULL;
 	}
 }
@@ -124,6 +265,8 @@ args_len(struct initargs *arg)
 	switch (arg->type) {
 	case ARGS_IMPL_KV:  return kv_len(arg->d.kv_ent);
 	case ARGS_IMPL_TAR: return tar_len(&arg->d.tar_ent);
+	case ARGS_IMPL_TLV: return tlv_len(&arg->d.tlv_ent);
+	case ARGS_IMPL_FILE: return 1;
 	default:            return 0;
 	}
 }
@@ -135,6 +278,7 @@ args_iter_next(struct initargs_iter *i, struct initargs *arg)
 	switch (i->type) {
 	case ARGS_IMPL_KV:  return kv_iter_next(&i->i.kv_i, &arg->d.kv_ent);
 	case ARGS_IMPL_TAR: return tar_iter_next(&i->i.tar_i, &arg->d.tar_ent);
+	case ARGS_IMPL_TLV: return tlv_iter_next(&i->i.tlv_i, &arg->d.tlv_ent);
 	default:            return 0;
 	}
 }
@@ -146,6 +290,7 @@ args_iter(struct initargs *arg, struct initargs_iter *i, struct initargs *ent)
 	switch (arg->type) {
 	case ARGS_IMPL_KV:  return kv_iter(arg->d.kv_ent, &i->i.kv_i, &ent->d.kv_ent);
 	case ARGS_IMPL_TAR: return tar_iter(&arg->d.tar_ent, &i->i.tar_i, &ent->d.tar_ent);
+	case ARGS_IMPL_TLV: return tlv_iter(&arg->d.tlv_ent, &i->i.tlv_i, &ent->d.tlv_ent);
 	default:            return 0;
 	}
 }
@@ -220,6 +365,8 @@ args_type(struct initargs *ent)
 	switch (ent->type) {
 	case ARGS_IMPL_KV:  return kv_type(ent->d.kv_ent);
 	case ARGS_IMPL_TAR: return tar_is_value(&ent->d.tar_ent) ? ARGS_VAL : ARGS_MAP;
+	case ARGS_IMPL_TLV: return tlv_type(&ent->d.tlv_ent);
+	case ARGS_IMPL_FILE: return ARGS_VAL;
 	default:            return ARGS_ERR;
 	}
 }
@@ -240,27 +387,60 @@ args_get_from(char *path, struct initargs *from)
 	return args_value(&ent);
 }
 
+/*
+ * The root of the TLV-encoded arguments, if the component has them:
+ * __initargs_root's "tlv" names the file in the tarball holding them.
+ */
+static int
+tlv_root(struct initargs *tarroot, struct initargs *root)
+{
+	struct initargs file;
+	char *name, *data;
+
+	name = args_get_from("tlv", &__initargs_root);
+	if (!name || args_get_entry_from(name, tarroot, &file)) return -1;
+	data = args_value(&file);
+	if (!data || tar_value_sz(&file.d.tar_ent) < (int)strlen(TLV_MAGIC) + TLV_HDR_SZ) return -1;
+	if (strncmp(data, TLV_MAGIC, strlen(TLV_MAGIC))) return -1;
+	*root = (struct initargs) {
+		.type = ARGS_IMPL_TLV,
+		.d.tlv_ent.ent = (unsigned char *)data + strlen(TLV_MAGIC)
+	};
+
+	return 0;
+}
+
 /*
  * The "base-case" API where we need to do the initial lookup in the
  * KV map.  This requires basing the search in some structure:
  * __initargs_root.  This supports searching by a "path" through the
  * structure, which is just a /-separated set of keys used to lookup
- * in the corresponding maps.
+ * in the corresponding maps. The TLV-encoded arguments, if any, and
+ * then the tarball (or the cpio or flat archive) are searched next.
  */
 int
 args_get_entry(char *path, struct initargs *ent)
 {
-	struct initargs tarroot;
+	struct initargs tarroot, tlvroot;
 	struct tar_entry *tarent;
+	int ret;
 
 	if (!args_get_entry_from(path, &__initargs_root, ent)) return 0;
 
+	/* Files in a cpio or flat archive are only looked up by path */
+	ret = initfs_file(path, &ent->d.file_ent);
+	if (ret <= 0) {
+		ent->type = ARGS_IMPL_FILE;
+		return ret;
+	}
+
 	tarent = tar_root();
 	if (!tarent) return -1;
 	tarroot = (struct initargs) {
 		.type = ARGS_IMPL_TAR,
 		.d.tar_ent = *tarent
 	};
+	if (!tlv_root(&tarroot, &tlvroot) && !args_get_entry_from(path, &tlvroot, ent)) return 0;
 
 	return args_get_entry_from(path, &tarroot, ent);
 }
@@ -437,6 +617,7 @@ struct initargs __initargs_root __attribute__((weak)) = { type: ARGS_IMPL_KV, d:
 
 COMPDIR=/home/gparmer/research/composite/src/components
 TAR_FILE=crt_init.tar
+LONG70=long_name_long_name_long_name_long_name_long_name_long_name_long_name_
 
 all: tartest argstest
 
@@ -462,7 +643,10 @@ tartest.tar:
 	echo "file3 contents" > binaries/dir2/subdir2/file3
 	echo "file4 contents" > binaries/dir2/subdir2/file4
 	echo "file5 contents" > binaries/dir3/subdir3/file5
-	tar cvf tartest.tar binaries
+	echo "file6 contents" > binaries/dir2/subdir2/$(LONG70)file6__
+	echo "file7 contents" > binaries/dir2/subdir2/$(LONG70)file7____
+	echo "file8 contents" > binaries/dir2/subdir2/$(LONG70)long_name_long_name_long_name_long_name_long_name_file8___
+	tar --format=gnu -cvf tartest.tar binaries
 	rm -rf binaries
 
 clean:
@@ -2,6 +2,7 @@
 #define INITARGS_H
 
 #include <tar.h>
+#include <initfs.h>
 
 /***
  * A very simple nested K/V representation for retrieving initial
@@ -21,12 +22,15 @@ typedef enum {
 
 typedef enum {
 	ARGS_IMPL_KV,
-	ARGS_IMPL_TAR
+	ARGS_IMPL_TAR,
+	ARGS_IMPL_TLV,
+	ARGS_IMPL_FILE
 } args_impltype_t;
 
 typedef enum {
 	VTYPE_STR,
 	VTYPE_ARR,
+	VTYPE_BLOB,
 } kv_valtype_t;
 
 struct kv_entry;
@@ -36,6 +40,10 @@ union kv_val {
 		int sz;
 		struct kv_entry **kvs;
 	} arr;
+	struct {
+		int sz;
+		unsigned char *data;
+	} blob;
 };
 
 struct kv_entry {
@@ -49,6 +57,22 @@ struct kv_iter {
 	int curr, len;
 };
 
+/*
+ * The arguments can instead be encoded as TLV (see the component's
+ * initargs_format), in a file in its tarball. Each entry is its type
+ * (a byte, of kv_valtype_t), the length of its value (4 bytes, as are
+ * all lengths, little endian), and its value: the key's length, the
+ * '\0'-terminated key, and then either the '\0'-terminated string,
+ * the map's entries, or the blob.
+ */
+struct tlv_entry {
+	unsigned char *ent;
+};
+
+struct tlv_iter {
+	unsigned char *curr, *end;
+};
+
 /*
  * This is the structure that holds persistent data that the caller
  * must save to avoid memory allocation.
@@ -58,6 +82,8 @@ struct initargs {
 	union {
 		struct kv_entry *kv_ent;
 		struct tar_entry tar_ent;
+		struct tlv_entry tlv_ent;
+		struct initfs_file file_ent; /* in a cpio or flat archive */
 	} d;
 };
 
@@ -66,6 +92,7 @@ struct initargs_iter {
 	union {
 		struct kv_iter kv_i;
 		struct tar_iter tar_i;
+		struct tlv_iter tlv_i;
 	} i;
 };
 
@@ -79,6 +106,8 @@ int args_get_entry_from(char *path, struct initargs *from, struct initargs *ent)
 /* Access the k/v of a given entry */
 char *args_key(struct initargs *entry, int *str_len);
 char *args_value(struct initargs *entry);
+/* A binary value (NULL if it isn't one), and its size in bytes */
+unsigned char *args_blob(struct initargs *entry, int *sz);
 args_type_t args_type(struct initargs *ent);
 /* Iterate through the entries, particularly in a map. */
 int args_len(struct initargs *kv);
@@ -0,0 +1,130 @@
+#include <initfs.h>
+#include <string.h>
+
+/* The archive is linked in as the tarball would be (see tar.c) */
+extern unsigned char _binary_crt_init_tar_start[] __attribute__((weak));
+extern unsigned char _binary_crt_init_tar_end[] __attribute__((weak));
+
+#define CPIO_HDR_SZ 110
+#define CPIO_FIELD_SZ 8
+#define CPIO_TRAILER "TRAILER!!!"
+#define FLAT_TAIL_SZ 24 /* the index's offset, its length, and the magic */
+
+static unsigned long
+align_up(unsigned long off, unsigned long align)
+{
+	return (off + align - 1) / align * align;
+}
+
+/* The Nth of the cpio header's hex fields, or -1 if it's malformed */
+static long
+cpio_field(unsigned char *hdr, int n)
+{
+	unsigned char *f = hdr + strlen(INITFS_CPIO_MAGIC) + n * CPIO_FIELD_SZ;
+	long val = 0;
+	int i;
+
+	for (i = 0; i < CPIO_FIELD_SZ; i++) {
+		unsigned char c = f[i];
+
+		if (c >= '0' && c <= '9')      val = val * 16 + (c - '0');
+		else if (c >= 'a' && c <= 'f') val = val * 16 + (c - 'a' + 10);
+		else if (c >= 'A' && c <= 'F') val = val * 16 + (c - 'A' + 10);
+		else                           return -1;
+	}
+
+	return val;
+}
+
+static int
+cpio_file(unsigned char *ar, unsigned long sz, char *path, struct initfs_file *f)
+{
+	unsigned long off = 0;
+
+	while (off + CPIO_HDR_SZ <= sz && !memcmp(ar + off, INITFS_CPIO_MAGIC, strlen(INITFS_CPIO_MAGIC))) {
+		long mode = cpio_field(ar + off, 1), fsz = cpio_field(ar + off, 6), namesz = cpio_field(ar + off, 11);
+		unsigned long name = off + CPIO_HDR_SZ, data;
+		char *n = (char *)ar + name;
+
+		/* The sizes are from the archive, so avoid overflows */
+		if (mode < 0 || fsz < 0 || namesz < 1 || (unsigned long)namesz > sz - name) return -1;
+		if (n[namesz - 1] != '\0') return -1;
+		if (!strcmp(n, CPIO_TRAILER)) return -1;
+		data = align_up(name + namesz, 4);
+		if (data > sz || (unsigned long)fsz > sz - data) return -1;
+		/* Only regular files */
+		if ((mode & 0170000) == 0100000 && !strcmp(n, path)) {
+			*f = (struct initfs_file) {
+				.name = n,
+				.data = ar + data,
+				.sz   = fsz
+			};
+			return 0;
+		}
+		off = align_up(data + fsz, 4);
+	}
+
+	return -1;
+}
+
+/* The little-endian integer of len bytes, as the index isn't aligned */
+static unsigned long
+flat_le(unsigned char *p, int len)
+{
+	unsigned long val = 0;
+
+	while (len--) val = (val << 8) | p[len];
+
+	return val;
+}
+
+static int
+flat_file(unsigned char *ar, unsigned long sz, char *path, struct initfs_file *f)
+{
+	unsigned long off, n, i;
+
+	if (sz < strlen(INITFS_FLAT_MAGIC) + FLAT_TAIL_SZ) return -1;
+	off = flat_le(ar + sz - FLAT_TAIL_SZ, 8);
+	n   = flat_le(ar + sz - FLAT_TAIL_SZ + 8, 8);
+	/* Each file's entry is its offset, size, name's length, and name */
+	for (i = 0; i < n; i++) {
+		unsigned long start, fsz, len;
+		char *name;
+
+		/* The offsets are from the archive, so avoid overflows */
+		if (off > sz || sz - off < 20) return -1;
+		start = flat_le(ar + off, 8);
+		fsz   = flat_le(ar + off + 8, 8);
+		len   = flat_le(ar + off + 16, 4);
+		name  = (char *)ar + off + 20;
+		if (len >= sz - off - 20 || start > sz || fsz > sz - start) return -1;
+		if (len == strlen(path) && !strncmp(name, path, len)) {
+			*f = (struct initfs_file) {
+				.name = name,
+				.data = ar + start,
+				.sz   = fsz
+			};
+			return 0;
+		}
+		off = align_up(off + 20 + len + 1, 8);
+	}
+
+	return -1;
+}
+
+int
+initfs_file(char *path, struct initfs_file *f)
+{
+	unsigned char *ar = _binary_crt_init_tar_start;
+	unsigned long sz = _binary_crt_init_tar_end - ar;
+	unsigned long magic = strlen(INITFS_FLAT_MAGIC);
+
+	if (sz >= CPIO_HDR_SZ && !memcmp(ar, INITFS_CPIO_MAGIC, strlen(INITFS_CPIO_MAGIC))) {
+		return cpio_file(ar, sz, path, f);
+	}
+	if (sz >= 2 * magic && !memcmp(ar, INITFS_FLAT_MAGIC, magic) && !memcmp(ar + sz - magic, INITFS_FLAT_MAGIC, magic)) {
+		return flat_file(ar, sz, path, f);
+	}
+
+	return 1;
+}
@@ -0,0 +1,28 @@
+/*
+ * The composer can archive a constructor's files as cpio (newc) or
+ * as a flat, indexed blob instead of as a tarball (see the system's
+ * archive option). These archives hold no directory structure that
+ * the K/V API could walk, so only files are looked up in them, by
+ * their full paths (e.g. binaries/<name>, as the booter does).
+ */
+
+#ifndef INITFS_H
+#define INITFS_H
+
+#define INITFS_CPIO_MAGIC "070701"
+#define INITFS_FLAT_MAGIC "COSFLAT1"
+
+struct initfs_file {
+	char *name;
+	unsigned char *data;
+	int sz;
+};
+
+/*
+ * Look up the file at path. Returns 0 if it's found, -1 if it isn't,
+ * and 1 if the initfs isn't a cpio or flat archive (so it's a
+ * tarball, or there is none).
+ */
+int initfs_file(char *path, struct initfs_file *f);
+
+#endif /* INITFS_H */
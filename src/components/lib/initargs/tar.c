@@ -15,18 +15,6 @@
 #include <consts.h>
 #endif
 
-static inline int
-tar_is_file(struct tar_record *r)
-{
-	return *r->linkflag == '0' || *r->linkflag == '\0';
-}
-
-static inline int
-tar_is_dir(struct tar_record *r)
-{
-	return *r->linkflag == '5';
-}
-
 /* We are at the end of an archive when we have two empty records. */
 static inline int
 tar_end(struct tar_record *r)
@@ -61,18 +49,86 @@ oct2dec(char *oct)
 	return tot;
 }
 
-/* return the next record, or NULL if THIS record doesn't exist. */
+/* The number of records holding this record's data */
+static inline int
+tar_nrecords(struct tar_record *r)
+{
+	int sz = oct2dec(r->size);
+
+	return round_up_to_pow2(sz, TAR_RECORD_SIZE) / TAR_RECORD_SIZE;
+}
+
+/*
+ * A GNU long-name record holds the name of the entry that follows
+ * it, NUL-terminated, as its data.
+ */
+static inline int
+tar_is_longname(struct tar_record *r)
+{
+	return *r->linkflag == 'L';
+}
+
+/*
+ * A pax extended header record holds attributes of the entry that
+ * follows it, which we ignore. The composer pads entries with them,
+ * so that the contents of files start at page boundaries.
+ */
+static inline int
+tar_is_paxhdr(struct tar_record *r)
+{
+	return *r->linkflag == 'x';
+}
+
+/* The entry starting at r, after any pax extended header records */
+static inline struct tar_record *
+tar_skip_pax(struct tar_record *r)
+{
+	while (tar_is_paxhdr(r)) r = &r[tar_nrecords(r) + 1];
+
+	return r;
+}
+
+/* The header of the entry starting at r, after any long-name record */
+static inline struct tar_record *
+tar_hdr(struct tar_record *r)
+{
+	r = tar_skip_pax(r);
+	if (!tar_is_longname(r)) return r;
+
+	return &r[tar_nrecords(r) + 1];
+}
+
+static inline char *
+tar_name(struct tar_record *r)
+{
+	r = tar_skip_pax(r);
+	if (tar_is_longname(r)) return (char *)&r[1];
+
+	return r->name;
+}
+
+static inline int
+tar_is_file(struct tar_record *r)
+{
+	r = tar_hdr(r);
+
+	return *r->linkflag == '0' || *r->linkflag == '\0';
+}
+
+static inline int
+tar_is_dir(struct tar_record *r)
+{
+	return *tar_hdr(r)->linkflag == '5';
+}
+
+/* return the next entry, or NULL if THIS record doesn't exist. */
 static struct tar_record *
 tar_next_record(struct tar_record *r)
 {
-	int sz, n_records;
-
 	if (tar_end(r)) return NULL;
+	r = tar_hdr(r);
 
-	sz = oct2dec(r->size);
-	n_records = round_up_to_pow2(sz, TAR_RECORD_SIZE) / TAR_RECORD_SIZE;
-
-	return &r[n_records + 1];
+	return &r[tar_nrecords(r) + 1];
 }
 
 static inline int
@@ -108,7 +164,7 @@ tar_path(struct tar_entry *ent)
 {
 	if (!tar_valid(ent) || ent->record == NULL) return NULL;
 
-	return ent->record->name;
+	return tar_name(ent->record);
 }
 
 /*
@@ -168,7 +224,7 @@ tar_path_iter_next(struct tar_entry *path, struct tar_record **iter)
 
 	if (!tar_valid(path) || iter == NULL || *iter == NULL) return NULL;
 
-	for (r = *iter; r && !tar_end(r) && tar_pathcmp(path, r->name); r = tar_next_record(r)) ;
+	for (r = *iter; r && !tar_end(r) && tar_pathcmp(path, tar_name(r)); r = tar_next_record(r)) ;
 	if (tar_end(r)) return NULL;
 	*iter = tar_next_record(r);
 
@@ -178,7 +234,7 @@ tar_path_iter_next(struct tar_entry *path, struct tar_record **iter)
 char *
 tar_key(struct tar_entry *ent, int *str_len)
 {
-	return tar_nesting(ent->nesting_lvl, ent->record->name, str_len);
+	return tar_nesting(ent->nesting_lvl, tar_name(ent->record), str_len);
 }
 
 char *
@@ -187,7 +243,7 @@ tar_value(struct tar_entry *ent)
 	if (!tar_valid(ent)) return NULL;
 	if (!tar_is_file(ent->record)) return NULL;
 
-	return (char *)&ent->record[1];
+	return (char *)&tar_hdr(ent->record)[1];
 }
 
 int
@@ -196,7 +252,7 @@ tar_value_sz(struct tar_entry *ent)
 	if (!tar_valid(ent)) return 0;
 	if (!tar_is_file(ent->record)) return 0;
 
-	return oct2dec(ent->record->size);
+	return oct2dec(tar_hdr(ent->record)->size);
 }
 
 int
@@ -299,14 +355,24 @@ foreach(struct tar_entry *root, visitor_fn_t fn, void *data)
 
 #define PATH_LEN 3
 char *path[PATH_LEN] = {"binaries", "dir2", "subdir2"};
+/*
+ * The paths of file6, file7, and file8 are 99 (the longest that the
+ * header holds), 101, and 150 bytes, so the latter two have GNU
+ * long-name records.
+ */
+#define LONG10 "long_name_"
+#define LONG70 LONG10 LONG10 LONG10 LONG10 LONG10 LONG10 LONG10
 struct file_cont {
-	char filename[16];
+	char filename[TAR_NAME_SZ * 2];
 	char contents[32];
 	int found;
 };
 struct file_cont file_contents[] = {
 	{.filename = "file3", .contents = "file3 contents\n", .found = 0},
 	{.filename = "file4", .contents = "file4 contents\n", .found = 0},
+	{.filename = LONG70 "file6__", .contents = "file6 contents\n", .found = 0},
+	{.filename = LONG70 "file7____", .contents = "file7 contents\n", .found = 0},
+	{.filename = LONG70 LONG10 LONG10 LONG10 LONG10 LONG10 "file8___", .contents = "file8 contents\n", .found = 0},
 	{.filename = "", .contents = "", .found = 0}
 };
 int failure = 0;
@@ -344,7 +410,7 @@ dump_tar(struct tar_record *r)
 {
 	printf("Tar dump:\n");
 	for ( ; r ; r = tar_next_record(r)) {
-		printf("\tname: %s [%c]\n", r->name, *r->linkflag);
+		printf("\tname: %s [%c]\n", tar_name(r), *tar_hdr(r)->linkflag);
 	}
 }
 
@@ -1,81 +1,359 @@
-use passes::{AddressAssignmentPass, BuildState, ComponentId, SystemState, Transition};
+use compobject::obj_sizes;
+use passes::{
+    AddrSpcName, AddressAssignmentPass, BuildState, ComponentId, ComponentName, ComposeError,
+    SystemState, Transition, XipLayout,
+};
 use std::collections::HashMap;
+use xip::{xip_table_size, XIP_ALIGN};
 
 pub struct AddressAssignmentx86_64 {
     baseaddrs: HashMap<ComponentId, u64>,
+    dataaddrs: HashMap<ComponentId, u64>, // only with an XIP layout
 }
 
 impl AddressAssignmentPass for AddressAssignmentx86_64 {
     fn component_baseaddr(&self, id: &ComponentId) -> u64 {
         // unwrap as we already sanitized/validated the ids.
-        *self.baseaddrs.get(&id).unwrap()
+        *self.baseaddrs.get(id).unwrap()
+    }
+
+    fn component_dataaddr(&self, id: &ComponentId) -> Option<u64> {
+        self.dataaddrs.get(id).cloned()
     }
 }
 
-impl Transition for AddressAssignmentx86_64 {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+// The sizes of the ranges of virtual addresses spanned by a
+// component's object's loadable segments.
+#[derive(Clone, Copy, Debug)]
+pub struct ObjSize {
+    pub span: u64, // all of them
+    pub text: u64, // the read-only ones
+    pub data: u64, // the writable ones
+}
+
+const PAGE_SZ: u64 = 4096;
+// The flash and RAM that an XIP layout assumes each component that
+// isn't yet built takes.
+const XIP_SIZE_ESTIMATE: u64 = 64 << 10;
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    addr.div_ceil(align) * align
+}
+
+fn overlaps(r: &(u64, u64), used: &Vec<(u64, u64)>) -> bool {
+    used.iter().any(|u| r.0 < u.1 && u.0 < r.1)
+}
+
+// The base address that the spec pins the component at, if any.
+fn pinned_baseaddr(s: &SystemState, c: &ComponentName) -> Result<Option<u64>, ComposeError> {
+    let comp = s.get_spec().component_named(c);
+    let vaddr = match comp.base_vaddr {
+        Some(ref v) => v,
+        None => return Ok(None),
+    };
+    let addr = match u64::from_str_radix(vaddr.trim_start_matches("0x"), 16) {
+        Ok(a) => a,
+        Err(_) => {
+            warn!(
+                r#"(do not ignore) Cannot parse the base address, {}, for component "{}" as hexdecimal. Using default."#,
+                vaddr, c
+            );
+            return Ok(None);
+        }
+    };
+    if addr % PAGE_SZ != 0 {
+        return Err(ComposeError::Spec(format!(
+            r#"Error: The base address, {}, for component "{}" is not page-aligned."#,
+            vaddr, c
+        )));
+    }
+
+    Ok(Some(addr))
+}
+
+// With an XIP layout, each component's read-only segment follows the
+// previous one's in the flash (after the booter's copy table), and
+// its writable segment follows the previous one's in the RAM. There's
+// no virtual memory to give them each the same addresses.
+fn xip_assign(
+    s: &SystemState,
+    x: &XipLayout,
+    sizes: &HashMap<ComponentId, ObjSize>,
+) -> AddressAssignmentx86_64 {
+    let ids = s.get_named().ids();
+    let mut baseaddrs = HashMap::new();
+    let mut dataaddrs = HashMap::new();
+    let mut flash = x.flash_base + align_up(xip_table_size(ids.len()), XIP_ALIGN);
+    let mut ram = x.ram_base;
+    for (id, _) in ids.iter() {
+        let sz = sizes.get(id);
+        baseaddrs.insert(*id, flash);
+        dataaddrs.insert(*id, ram);
+        flash += align_up(sz.map_or(XIP_SIZE_ESTIMATE, |s| s.text).max(1), XIP_ALIGN);
+        ram += align_up(sz.map_or(XIP_SIZE_ESTIMATE, |s| s.data).max(1), XIP_ALIGN);
+    }
+
+    AddressAssignmentx86_64 {
+        baseaddrs,
+        dataaddrs,
+    }
+}
+
+impl AddressAssignmentx86_64 {
+    // Assign the base addresses of the components given the sizes of
+    // their objects. Components without a known size (as they are
+    // not yet built) are assumed to take a single address space
+    // name.
+    pub fn assign(
+        s: &SystemState,
+        sizes: &HashMap<ComponentId, ObjSize>,
+    ) -> Result<AddressAssignmentx86_64, ComposeError> {
+        if let Some(ref x) = s.get_spec().xip() {
+            return Ok(xip_assign(s, x, sizes));
+        }
         let ases = s.get_named();
-        // This is the offset into each address space name that each
-        // component starts at (unless manually overridden). This is a
-        // 4MB offset, mainly just a known value significantly larger
-        // than NULL = 0.
-        let addr_offset = 0x400000;
-        // We're assuming 64 bit systems here, and x86-64
-        // specifically. This is the size of the address range in each
-        // second level nodes of the page-table.
-        //
-        // TODO: make this math architecture-specific by adding a
-        // per-architecture configuration file.
-        //
-        // 2^9 = # of entries per page-table node
-        // (2^9)^3 = # 3 levels of page-tables
-        let addrspc_name_sz = u64::pow(u64::pow(2, 9), 3) * u64::pow(2, 12);
-        // 2^48 is the size of the virtual address space on x86-64, so
-        // a sanity check:
-        assert_eq!(addrspc_name_sz, u64::pow(2, 48) / u64::pow(2, 9));
+        let arch = |c: &ComponentName| s.get_spec().component_named(c).arch;
         let mut baseaddrs = HashMap::new();
         // Track the last assigned address to a VAS, so that parent
-        // relationships know where to start child addresses.
-        let mut lastaddr = HashMap::new();
+        // relationships know where to start child addresses, and the
+        // address ranges used in each VAS, as its descendants share
+        // them.
+        let mut lastaddr: HashMap<&AddrSpcName, u64> = HashMap::new();
+        let mut used_ranges: HashMap<&AddrSpcName, Vec<(u64, u64)>> = HashMap::new();
 
-        for (_, a) in ases.addrspc_components_shared() {
+        for a in ases.addrspc_components_shared().values() {
+            // The components of an address space (and its ancestors)
+            // are validated to be of the same architecture. The
+            // offset into each address space name that each component
+            // starts at (unless manually overridden) is the
+            // architecture's default base address, a known value
+            // significantly larger than NULL = 0. The name size is the
+            // range covered by each entry of the top-level page-table.
+            let as_arch = a.components.first().map_or(s.get_spec().arch(), &arch);
+            let addr_offset = as_arch.default_baseaddr();
+            let addrspc_name_sz = as_arch.addrspc_name_sz();
             let mut offset = addr_offset;
+            let mut used = Vec::new();
             if let Some(ref p) = a.parent {
                 // Lets start our address space name where our parent
                 // left off. Unwrap is OK as we've already validated
                 // the name.
                 offset = *lastaddr.get(p).unwrap();
+                used = used_ranges.get(p).unwrap().clone();
             }
+
+            // Components pinned by the spec take their addresses
+            // first, and the rest are placed around them, each
+            // starting in a separate address space name.
+            let mut unpinned = Vec::new();
             for c in &a.components {
-                let id = s.get_named().rmap().get(&c).unwrap();
+                let id = s.get_named().rmap().get(c).unwrap();
+                let sz = sizes.get(id).map_or(addrspc_name_sz, |s| s.span);
+                match pinned_baseaddr(s, c)? {
+                    Some(addr) => {
+                        baseaddrs.insert(*id, addr);
+                        used.push((addr, addr.saturating_add(sz)));
+                    }
+                    None => unpinned.push((id, align_up(sz.max(1), addrspc_name_sz))),
+                }
+            }
+            for (id, sz) in unpinned {
+                while overlaps(&(offset, offset + sz), &used) {
+                    offset += addrspc_name_sz;
+                }
                 baseaddrs.insert(*id, offset);
-                offset += addrspc_name_sz;
+                used.push((offset, offset + sz));
+                offset += sz;
             }
-            lastaddr.insert(&a.name, offset); // record the next name *past* the parent's
+
+            // record the next name *past* the parent's (and past any
+            // pinned components)
+            let end = used.iter().map(|r| r.1).fold(offset, u64::max);
+            lastaddr.insert(&a.name, align_up(end, PAGE_SZ));
+            used_ranges.insert(&a.name, used);
         }
 
         // All components within their own exclusive address space:
         // use the default base address, or the one that is explicitly
-        // chosen within the composition script
+        // chosen within the composition script. They don't share
+        // their page-tables, so cannot overlap other components.
         for c in ases.addrspc_components_exclusive() {
-            let id = s.get_named().rmap().get(&c).unwrap();
-            let comp = s.get_spec().component_named(&c);
-            let preproc_addr = comp.base_vaddr.trim_start_matches("0x");
-            let addr_wrapped = u64::from_str_radix(preproc_addr, 16);
-            let mut addr = addr_offset;
-
-            if let Ok(a) = addr_wrapped {
-                addr = a;
-            } else {
-                println!(
-                    r#"Warning (do not ignore): Cannot parse the base address, {}, for component "{}" as hexdecimal. Using default."#,
-                    comp.base_vaddr, c
-                );
-            }
+            let id = s.get_named().rmap().get(c).unwrap();
+            let addr = pinned_baseaddr(s, c)?.unwrap_or(arch(c).default_baseaddr());
 
             baseaddrs.insert(*id, addr);
         }
 
-        Ok(Box::new(AddressAssignmentx86_64 { baseaddrs }))
+        Ok(AddressAssignmentx86_64 {
+            baseaddrs,
+            dataaddrs: HashMap::new(),
+        })
+    }
+
+    // The sizes of the (built) objects of the components.
+    pub fn comp_sizes(
+        ids: &Vec<ComponentId>,
+        s: &SystemState,
+        b: &mut dyn BuildState,
+    ) -> Result<HashMap<ComponentId, ObjSize>, ComposeError> {
+        let mut sizes = HashMap::new();
+        for id in ids.iter() {
+            let obj_path = b.comp_build(id, s)?;
+            sizes.insert(*id, obj_sizes(&obj_path)?);
+        }
+
+        Ok(sizes)
+    }
+
+    // Validate that the components' virtual address ranges, given
+    // the sizes of their objects, are within user-level memory, and
+    // don't overlap those of the components they share page-tables
+    // with.
+    pub fn validate(
+        &self,
+        s: &SystemState,
+        sizes: &HashMap<ComponentId, ObjSize>,
+    ) -> Result<(), ComposeError> {
+        if let Some(ref x) = s.get_spec().xip() {
+            return self.xip_validate(s, x, sizes);
+        }
+        let ases = s.get_named();
+        let range = |c: &ComponentName| {
+            let id = ases.rmap().get(c).unwrap();
+            let base = *self.baseaddrs.get(id).unwrap();
+            (
+                base,
+                base.saturating_add(sizes.get(id).map_or(0, |s| s.span)),
+            )
+        };
+        let mut errs = Vec::new();
+
+        for c in ases.ids().values() {
+            let (lo, hi) = range(c);
+            let max = s.get_spec().component_named(c).arch.user_max_vaddr();
+            if hi > max + 1 {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {}'s virtual address range [{:#x}, {:#x}) intrudes on the kernel's reserved region (above {:#x}).",
+                    c, lo, hi, max
+                )));
+            }
+        }
+
+        // Components share page-tables with those in the same, and
+        // in ancestor, address spaces. Parents are ordered before
+        // their children, so each pair is checked once.
+        let mut visible: HashMap<&AddrSpcName, Vec<&ComponentName>> = HashMap::new();
+        for a in ases.addrspc_components_shared().values() {
+            let mut cs = match a.parent {
+                Some(ref p) => visible.get(p).unwrap().clone(),
+                None => Vec::new(),
+            };
+            for c in &a.components {
+                let r = range(c);
+                for other in cs.iter() {
+                    let o = range(other);
+                    if overlaps(&r, &vec![o]) {
+                        errs.push(ComposeError::Spec(format!(
+                            "Error: Components {} [{:#x}, {:#x}) and {} [{:#x}, {:#x}) in address space \"{}\" have overlapping virtual address ranges.",
+                            c, r.0, r.1, other, o.0, o.1, a.name
+                        )));
+                    }
+                }
+                cs.push(c);
+            }
+            visible.insert(&a.name, cs);
+        }
+
+        if !errs.is_empty() {
+            return Err(ComposeError::Invalid(errs));
+        }
+
+        Ok(())
+    }
+
+    // Validate that the components' segments, given the sizes of their
+    // objects, are within the XIP layout's flash and RAM. They're
+    // assigned their addresses one after the other, so don't overlap.
+    fn xip_validate(
+        &self,
+        s: &SystemState,
+        x: &XipLayout,
+        sizes: &HashMap<ComponentId, ObjSize>,
+    ) -> Result<(), ComposeError> {
+        let mut errs = Vec::new();
+        for (id, c) in s.get_named().ids() {
+            let sz = sizes.get(id).cloned().unwrap_or(ObjSize {
+                span: 0,
+                text: 0,
+                data: 0,
+            });
+            for (what, base, len, mem, lo, hi) in [
+                (
+                    "read-only segments",
+                    self.baseaddrs.get(id).cloned().unwrap(),
+                    sz.text,
+                    "flash",
+                    x.flash_base,
+                    x.flash_base + x.flash_size,
+                ),
+                (
+                    "writable segments",
+                    self.dataaddrs.get(id).cloned().unwrap(),
+                    sz.data,
+                    "ram",
+                    x.ram_base,
+                    x.ram_base + x.ram_size,
+                ),
+            ]
+            .iter()
+            {
+                if base + len > *hi || base < lo {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s {} [{:#x}, {:#x}) don't fit in the xip layout's {} [{:#x}, {:#x}).",
+                        c,
+                        what,
+                        base,
+                        base + len,
+                        mem,
+                        lo,
+                        hi
+                    )));
+                }
+            }
+        }
+        if !errs.is_empty() {
+            return Err(ComposeError::Invalid(errs));
+        }
+
+        Ok(())
+    }
+
+    // The components whose base (or, with an XIP layout, data)
+    // address differs from that of the current assignment in the
+    // system.
+    pub fn moved(&self, s: &SystemState) -> Vec<ComponentId> {
+        let cur = s.get_address_assignments();
+        let mut ids: Vec<ComponentId> = self
+            .baseaddrs
+            .iter()
+            .filter(|(id, addr)| {
+                cur.component_baseaddr(id) != **addr
+                    || cur.component_dataaddr(id) != self.dataaddrs.get(id).cloned()
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids
+    }
+}
+
+impl Transition for AddressAssignmentx86_64 {
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
+        // The components aren't built yet, so assume they each fit
+        // into an address space name.
+        Ok(Box::new(AddressAssignmentx86_64::assign(
+            s,
+            &HashMap::new(),
+        )?))
     }
 }
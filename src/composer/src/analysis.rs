@@ -1,5 +1,7 @@
 use ascent::{ascent_run, lattice::Dual};
-use passes::{AnalysisPass, BuildState, ComponentId, Interface, SystemState, Transition};
+use passes::{
+    AnalysisPass, BuildState, ComponentId, ComposeError, Interface, SystemState, Transition,
+};
 use std::collections::HashMap;
 
 // A very simplistic criticality-level specification.
@@ -52,7 +54,7 @@ pub enum Warning {
 //
 // This is hard-coded for now, but should be configured somewhere more
 // reasonable.
-const SERVER_PROPERTIES: &[(&'static str, CompProperties)] = &[
+const SERVER_PROPERTIES: &[(&str, CompProperties)] = &[
     ("sched", CompProperties::Scheduler),
     ("capmgr", CompProperties::CapMgr),
     ("memmgr", CompProperties::MemMgr),
@@ -63,7 +65,7 @@ const SERVER_PROPERTIES: &[(&'static str, CompProperties)] = &[
 //
 // This is hard-coded for now, but should be configured somewhere more
 // reasonable.
-const CLIENT_PROPERTIES: &[(&'static str, CompProperties)] = &[
+const CLIENT_PROPERTIES: &[(&str, CompProperties)] = &[
     ("sched", CompProperties::MutExcl), // TODO: should have a separate blkpt interface for this
     ("sched", CompProperties::CanBlock), // TODO: should have a separate blkpt interface for this
     ("memmgr", CompProperties::DynMem),
@@ -143,6 +145,13 @@ pub struct Analysis {
     pub_warnings: HashMap<ComponentId, Vec<Warning>>,
 }
 
+// The code that ascent_run! generates clones the (Copy) ids, and
+// binds unit values and placeholder names.
+#[allow(
+    clippy::clone_on_copy,
+    clippy::let_unit_value,
+    clippy::just_underscores_and_digits
+)]
 fn analysis_output(i: AnalysisInput) -> Analysis {
     let srv_prop_map: Vec<(String, CompProperties)> = SERVER_PROPERTIES
         .iter()
@@ -242,10 +251,10 @@ fn analysis_output(i: AnalysisInput) -> Analysis {
 
     let mut ws: HashMap<ComponentId, Vec<Warning>> = HashMap::new();
     for (c, w) in &p.warnings {
-        if let Some(e) = ws.get_mut(&c) {
+        if let Some(e) = ws.get_mut(c) {
             e.push(w.clone());
         } else {
-            ws.insert(c.clone(), vec![w.clone()]);
+            ws.insert(*c, vec![w.clone()]);
         }
     }
 
@@ -275,7 +284,7 @@ impl Analysis {
         let dependencies: Vec<(ComponentId, ComponentId, Interface, VirtResAccess)> = cs
             .names()
             .iter()
-            .map(|c| {
+            .flat_map(|c| {
                 cs.deps_named(c)
                     .iter()
                     .filter_map(|d| {
@@ -292,7 +301,6 @@ impl Analysis {
                     })
                     .collect::<Vec<_>>()
             })
-            .flatten()
             .collect();
 
         // Calculate the analysis outputs
@@ -318,7 +326,7 @@ impl AnalysisPass for Analysis {
         let compname = name(id);
         let ws_map = self.warnings();
 
-	let ws = ws_map.get(&id).unwrap();
+        let ws = ws_map.get(&id).unwrap();
         ws.iter().fold(format!("Component {compname} warnings:\n"), |s, w| {
 	    match w {
 		Warning::SharedServiceMultCrit(hi, lo) =>
@@ -337,7 +345,7 @@ impl AnalysisPass for Analysis {
 }
 
 impl Transition for Analysis {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let a = Analysis::build(s);
 
         Ok(Box::new(a))
@@ -0,0 +1,615 @@
+// The archives of files linked into components: a constructor's
+// archive holds the objects of the components it creates (and its
+// own initfs), which the booter loads them from, and the other
+// components' archives hold their initfs. These are tar by default,
+// which the components' initargs read (lib/initargs/tar.c). Boot
+// environments and minimal booters that prefer simpler formats can
+// instead have the constructor's archive in cpio (the "newc" format
+// of Linux's initramfs), or as a flat blob indexed by an offset
+// table:
+//
+// - "COSFLAT1", then the contents of each file, each 8-byte aligned,
+// - the index: for each file, its offset and size (u64s), and the
+//   length of its name (u32), then the NUL-terminated name, padded
+//   to 8 bytes,
+// - the last 24 bytes locate the index: its offset and the number of
+//   files (u64s), then "COSFLAT1".
+//
+// The flat blob's integers are little endian, and its offsets are
+// from its start. Its directories are implicit in its files' names.
+//
+// With the system's page_align, the contents of each file in the
+// constructor's archive start at a page-aligned offset, so that a
+// booter can map the components' objects in place, rather than
+// copying them. The flat blob's files are simply aligned to pages,
+// and the tarball's are padded with pax extended headers holding
+// only a comment, which readers ignore. The offsets are computed
+// (by `archive_offsets`) before the archive is created, so that they
+// can be passed to the constructor in its initargs.
+
+use passes::{ArchiveFormat, ComposeError};
+use std::convert::TryFrom;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+
+pub const CPIO_MAGIC: &[u8] = b"070701";
+pub const CPIO_TRAILER: &str = "TRAILER!!!";
+const CPIO_HEADER_SZ: usize = 110;
+pub const FLAT_MAGIC: &[u8] = b"COSFLAT1";
+
+// The longest name that a tarball's header holds for the components'
+// tar reader, which requires it NUL-terminated within the header's
+// 100 bytes.
+const TAR_NAME_MAX: usize = 99;
+const TAR_BLOCK: u64 = 512;
+const GNU_LONGLINK: &[u8] = b"././@LongLink";
+const PAX_HEADER: &[u8] = b"././@PaxHeader";
+
+// Within page-aligned archives, the files' contents start at
+// multiples of the page size.
+pub const PAGE_SIZE: u64 = 4096;
+const FLAT_ALIGN: u64 = 8;
+
+// Archives of at least this size report their progress as each
+// further PROGRESS_STEP bytes are written.
+const PROGRESS_STEP: u64 = 64 << 20;
+
+// Writes the entries of an archive, in order, each directory (named
+// with a trailing '/') before the entries within it.
+pub trait ArchiveWriter {
+    fn dir(&mut self, name: &String) -> io::Result<()>;
+    fn file(&mut self, name: &String, f: &mut File) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>; // write any trailer, and flush
+}
+
+// Writes the archive, of `total` bytes of files, reporting its
+// progress.
+struct Progress<W: Write> {
+    out: W,
+    path: String,
+    written: u64,
+    reported: u64,
+    total: u64,
+}
+
+impl<W: Write> Write for Progress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.written += n as u64;
+        if self.total >= PROGRESS_STEP && self.written >= self.reported + PROGRESS_STEP {
+            self.reported = self.written;
+            info!(
+                "Writing {}: {} of {} MiB.",
+                self.path,
+                self.written >> 20,
+                self.total >> 20
+            );
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn align_up(off: u64, align: u64) -> u64 {
+    off.div_ceil(align) * align
+}
+
+// The length of the headers of the tar entry named `name`: a
+// long-name entry if the name is too long for the header, then the
+// header.
+fn tar_headers_len(name: &String) -> u64 {
+    if name.len() <= TAR_NAME_MAX {
+        return TAR_BLOCK;
+    }
+
+    2 * TAR_BLOCK + align_up(name.len() as u64 + 1, TAR_BLOCK)
+}
+
+// The padding before the entry of the file named `name`, at `off`,
+// for its contents to start at a page boundary. As `off` is a
+// multiple of the block size, so is the padding, and a pax header
+// holding `padding - TAR_BLOCK` bytes fills it.
+fn tar_padding(off: u64, name: &String) -> u64 {
+    (PAGE_SIZE - (off + tar_headers_len(name)) % PAGE_SIZE) % PAGE_SIZE
+}
+
+struct TarWriter<W: Write> {
+    ar: Builder<W>,
+    dir_template: PathBuf, // just need *some* directory with read/write perms
+    page_align: bool,
+    off: u64, // of the next entry
+}
+
+impl<W: Write> TarWriter<W> {
+    // Append `len` bytes that readers ignore: a pax extended header
+    // holding a comment that fills its data.
+    fn append_pad(&mut self, len: u64) -> io::Result<()> {
+        let sz = (len - TAR_BLOCK) as usize;
+        let data = if sz == 0 {
+            String::new()
+        } else {
+            // The record's length includes its own digits.
+            let fill = sz - sz.to_string().len() - " comment=\n".len();
+            format!("{} comment={}\n", sz, "0".repeat(fill))
+        };
+        let mut h = Header::new_gnu();
+        // unwrap as it is a GNU header
+        h.as_gnu_mut().unwrap().name[..PAX_HEADER.len()].copy_from_slice(PAX_HEADER);
+        h.set_entry_type(EntryType::XHeader);
+        h.set_mode(0o644);
+        h.set_size(sz as u64);
+        h.set_cksum();
+        self.ar.append(&h, data.as_bytes())
+    }
+
+    // Append the entry with the `header` (of a file or directory) and
+    // `data`, named `name`, which is too long for the header. As GNU
+    // tar does, a preceding long-name entry holds the name, and the
+    // header holds as much of it as fits.
+    fn append_long<R: Read>(
+        &mut self,
+        header: &mut Header,
+        name: &String,
+        data: R,
+    ) -> io::Result<()> {
+        let mut long = Header::new_gnu();
+        // unwrap as both are GNU headers
+        long.as_gnu_mut().unwrap().name[..GNU_LONGLINK.len()].copy_from_slice(GNU_LONGLINK);
+        long.set_entry_type(EntryType::GNULongName);
+        long.set_mode(0o644);
+        long.set_size(name.len() as u64 + 1);
+        long.set_cksum();
+        self.ar.append(&long, name.as_bytes().chain(&[0u8][..]))?;
+
+        header.as_gnu_mut().unwrap().name[..TAR_NAME_MAX]
+            .copy_from_slice(&name.as_bytes()[..TAR_NAME_MAX]);
+        header.set_cksum();
+        self.ar.append(header, data)
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn dir(&mut self, name: &String) -> io::Result<()> {
+        self.off += tar_headers_len(name);
+        if name.len() <= TAR_NAME_MAX {
+            return self.ar.append_dir(name, &self.dir_template);
+        }
+        let mut h = Header::new_gnu();
+        h.set_metadata(&fs::metadata(&self.dir_template)?);
+        self.append_long(&mut h, name, io::empty())
+    }
+
+    fn file(&mut self, name: &String, f: &mut File) -> io::Result<()> {
+        let size = f.metadata()?.len();
+        let pad = if self.page_align {
+            tar_padding(self.off, name)
+        } else {
+            0
+        };
+        if pad != 0 {
+            self.append_pad(pad)?;
+        }
+        self.off += pad + tar_headers_len(name) + align_up(size, TAR_BLOCK);
+        if name.len() <= TAR_NAME_MAX {
+            return self.ar.append_file(name, f);
+        }
+        let mut h = Header::new_gnu();
+        h.set_metadata(&f.metadata()?);
+        self.append_long(&mut h, name, f)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.ar.finish()?;
+        self.ar.get_mut().flush()
+    }
+}
+
+// The padding of a cpio archive's headers, names, and contents to 4
+// bytes.
+fn cpio_pad(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+// The length of the header of the cpio entry named `name`, with its
+// name and padding.
+fn cpio_header_len(name: &str) -> u64 {
+    let namesz = name.len() + 1;
+
+    (CPIO_HEADER_SZ + namesz + cpio_pad(CPIO_HEADER_SZ + namesz)) as u64
+}
+
+// The modification times, and owners, of the entries are zero, so
+// that the archive is reproducible.
+struct CpioWriter<W: Write> {
+    out: W,
+    ino: u32, // only distinguishes the entries
+}
+
+impl<W: Write> CpioWriter<W> {
+    fn header(&mut self, name: &str, mode: u32, size: u64) -> io::Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is too large for cpio", name),
+            ));
+        }
+        self.ino += 1;
+        let nlink = if mode & 0o040000 != 0 { 2 } else { 1 };
+        let namesz = name.len() + 1;
+        let fields = [
+            self.ino,
+            mode,
+            0, // uid
+            0, // gid
+            nlink,
+            0, // mtime
+            size as u32,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            namesz as u32,
+            0, // check
+        ];
+        self.out.write_all(CPIO_MAGIC)?;
+        for v in fields.iter() {
+            write!(self.out, "{:08x}", v)?;
+        }
+        self.out.write_all(name.as_bytes())?;
+        self.out
+            .write_all(&[0u8; 4][..1 + cpio_pad(CPIO_HEADER_SZ + namesz)])
+    }
+}
+
+impl<W: Write> ArchiveWriter for CpioWriter<W> {
+    fn dir(&mut self, name: &String) -> io::Result<()> {
+        self.header(name.trim_end_matches('/'), 0o040755, 0)
+    }
+
+    fn file(&mut self, name: &String, f: &mut File) -> io::Result<()> {
+        let size = f.metadata()?.len();
+        self.header(name, 0o100644, size)?;
+        if io::copy(&mut f.take(size), &mut self.out)? != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} changed size while archived", name),
+            ));
+        }
+        self.out.write_all(&[0u8; 4][..cpio_pad(size as usize)])
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.header(CPIO_TRAILER, 0, 0)?;
+        self.out.flush()
+    }
+}
+
+struct FlatWriter<W: Write> {
+    out: W,
+    off: u64,                       // of the next byte written
+    file_align: u64,                // of each file's contents
+    index: Vec<(u64, u64, String)>, // each file's offset, size, and name
+}
+
+impl<W: Write> FlatWriter<W> {
+    fn new(out: W, file_align: u64) -> io::Result<FlatWriter<W>> {
+        let mut w = FlatWriter {
+            out,
+            off: 0,
+            file_align,
+            index: Vec::new(),
+        };
+        w.put(FLAT_MAGIC)?;
+
+        Ok(w)
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.off += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn align(&mut self, align: u64) -> io::Result<()> {
+        let pad = align_up(self.off, align) - self.off;
+        self.off += io::copy(&mut io::repeat(0).take(pad), &mut self.out)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> ArchiveWriter for FlatWriter<W> {
+    fn dir(&mut self, _name: &String) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn file(&mut self, name: &String, f: &mut File) -> io::Result<()> {
+        let align = self.file_align;
+        self.align(align)?;
+        let off = self.off;
+        let size = io::copy(f, &mut self.out)?;
+        self.off += size;
+        self.index.push((off, size, name.clone()));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.align(FLAT_ALIGN)?;
+        let index_off = self.off;
+        let index: Vec<(u64, u64, String)> = self.index.drain(..).collect();
+        for (off, size, name) in index.iter() {
+            self.put(&off.to_le_bytes())?;
+            self.put(&size.to_le_bytes())?;
+            self.put(&(name.len() as u32).to_le_bytes())?;
+            self.put(name.as_bytes())?;
+            self.put(&[0])?;
+            self.align(FLAT_ALIGN)?;
+        }
+        self.put(&index_off.to_le_bytes())?;
+        self.put(&(index.len() as u64).to_le_bytes())?;
+        self.put(FLAT_MAGIC)?;
+        self.out.flush()
+    }
+}
+
+enum Entry {
+    Dir(String), // with a trailing '/'
+    File(usize), // the index of its name
+}
+
+// The entries of an archive of the files named `names`: the
+// directories within the names, as they are first encountered, each
+// before the entries within it, and the files.
+fn archive_entries<'a, I: Iterator<Item = &'a String>>(names: I) -> Vec<Entry> {
+    let mut dirs: Vec<String> = Vec::new();
+    let mut entries = Vec::new();
+
+    for (i, n) in names.enumerate() {
+        let mut dir = String::new();
+        for d in n
+            .split('/')
+            .rev()
+            .skip(1)
+            .collect::<Vec<&str>>()
+            .iter()
+            .rev()
+        {
+            dir.push_str(d);
+            dir.push('/');
+            if !dirs.contains(&dir) {
+                entries.push(Entry::Dir(dir.clone()));
+                dirs.push(dir.clone());
+            }
+        }
+        entries.push(Entry::File(i));
+    }
+
+    entries
+}
+
+// The offsets of the contents of the `files`, each a name and size,
+// within the archive of them in `format`, as
+// `archive_create` writes it.
+pub fn archive_offsets(
+    files: &Vec<(String, u64)>,
+    format: ArchiveFormat,
+    page_align: bool,
+) -> Vec<u64> {
+    let mut offs = Vec::new();
+    let mut off = match format {
+        ArchiveFormat::Flat => FLAT_MAGIC.len() as u64,
+        _ => 0,
+    };
+
+    for e in archive_entries(files.iter().map(|(n, _)| n)) {
+        let (name, size) = match e {
+            Entry::Dir(d) => {
+                off += match format {
+                    ArchiveFormat::Tar => tar_headers_len(&d),
+                    ArchiveFormat::Cpio => cpio_header_len(d.trim_end_matches('/')),
+                    ArchiveFormat::Flat => 0,
+                };
+                continue;
+            }
+            Entry::File(i) => (&files[i].0, files[i].1),
+        };
+        match format {
+            ArchiveFormat::Tar => {
+                if page_align {
+                    off += tar_padding(off, name);
+                }
+                off += tar_headers_len(name);
+                offs.push(off);
+                off += align_up(size, TAR_BLOCK);
+            }
+            ArchiveFormat::Cpio => {
+                off += cpio_header_len(name);
+                offs.push(off);
+                off += size + cpio_pad(size as usize) as u64;
+            }
+            ArchiveFormat::Flat => {
+                off = align_up(off, if page_align { PAGE_SIZE } else { FLAT_ALIGN });
+                offs.push(off);
+                off += size;
+            }
+        }
+    }
+
+    offs
+}
+
+// Write the archive's entries with `w`, streaming the files' contents
+// into it.
+fn archive_write(
+    w: &mut dyn ArchiveWriter,
+    contents: &Vec<(String, String)>,
+    err: &dyn Fn(String, io::Error) -> ComposeError,
+) -> Result<(), ComposeError> {
+    for e in archive_entries(contents.iter().map(|(_, n)| n)) {
+        match e {
+            Entry::Dir(dir) => w
+                .dir(&dir)
+                .map_err(|e| err(format!("add directory {}", dir), e))?,
+            Entry::File(i) => {
+                // file path, and name for the archive
+                let (ref p, ref n) = contents[i];
+                let mut f = File::open(p).map_err(|e| err(format!("open {}", p), e))?;
+                w.file(n, &mut f)
+                    .map_err(|e| err(format!("add {} as {}", p, n), e))?;
+            }
+        }
+    }
+    w.finish().map_err(|e| err(String::from("write it"), e))
+}
+
+// Create the archive at `path`, in `format`, containing the
+// `contents`, each a path to a file, and its name within the archive.
+// Directories within the names are added to it as they are first
+// encountered; return its path.
+// With `page_align`, the files' contents start at page boundaries.
+pub fn archive_create(
+    path: &Path,
+    contents: Vec<(String, String)>,
+    format: ArchiveFormat,
+    page_align: bool,
+) -> Result<PathBuf, ComposeError> {
+    let out_path = path.to_path_buf();
+    // Errors name the operation, and the file within the archive.
+    let err = |op: String, e: io::Error| ComposeError::Tar {
+        path: out_path.display().to_string(),
+        msg: format!("could not {}: {}", op, e),
+    };
+    let dir_template =
+        env::current_dir().map_err(|e| err(String::from("find the current directory"), e))?;
+    let out =
+        BufWriter::new(File::create(&out_path).map_err(|e| err(String::from("create it"), e))?);
+    let total = contents
+        .iter()
+        .filter_map(|(p, _)| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum();
+    let out = Progress {
+        out,
+        path: out_path.display().to_string(),
+        written: 0,
+        reported: 0,
+        total,
+    };
+    let written = match format {
+        ArchiveFormat::Tar => archive_write(
+            &mut TarWriter {
+                ar: Builder::new(out),
+                dir_template,
+                page_align,
+                off: 0,
+            },
+            &contents,
+            &err,
+        ),
+        ArchiveFormat::Cpio => archive_write(&mut CpioWriter { out, ino: 0 }, &contents, &err),
+        ArchiveFormat::Flat => {
+            FlatWriter::new(out, if page_align { PAGE_SIZE } else { FLAT_ALIGN })
+                .map_err(|e| err(String::from("write it"), e))
+                .and_then(|mut w| archive_write(&mut w, &contents, &err))
+        }
+    };
+    written?;
+
+    Ok(out_path)
+}
+
+// The `len` bytes of `data` at `off`, if it holds them. The offsets
+// and sizes are read from the archive, so they can be anything.
+fn bytes(data: &[u8], off: usize, len: usize) -> Option<&[u8]> {
+    data.get(off..off.checked_add(len)?)
+}
+
+fn le(data: &[u8], off: usize, len: usize) -> Option<usize> {
+    let bs = bytes(data, off, len)?;
+    usize::try_from(bs.iter().rev().fold(0, |w, b| (w << 8) | *b as u64)).ok()
+}
+
+// The files of an archive, each its name and contents, or why the
+// archive is malformed.
+pub type ArchiveFiles<'a> = Result<Vec<(String, &'a [u8])>, String>;
+
+fn cpio_files(data: &[u8]) -> ArchiveFiles<'_> {
+    let mut files = Vec::new();
+    let mut off = 0;
+    loop {
+        let malformed = || format!("malformed cpio entry at offset {}", off);
+        if bytes(data, off, CPIO_MAGIC.len()) != Some(CPIO_MAGIC) {
+            return Err(malformed());
+        }
+        // The header is in data, so its fields' offsets can't
+        // overflow.
+        let mut fields = Vec::new();
+        for i in 0..13 {
+            let start = off + CPIO_MAGIC.len() + i * 8;
+            let hex = bytes(data, start, 8)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .ok_or_else(malformed)?;
+            fields.push(usize::from_str_radix(hex, 16).map_err(|_| malformed())?);
+        }
+        let (mode, size, namesz) = (fields[1], fields[6], fields[11]);
+        let name_off = off + CPIO_HEADER_SZ;
+        let name = bytes(data, name_off, namesz.saturating_sub(1)).ok_or_else(malformed)?;
+        let name = String::from_utf8_lossy(name).to_string();
+        if name == CPIO_TRAILER {
+            return Ok(files);
+        }
+        // The name is in data, so its end can't overflow either.
+        let start = name_off + namesz + cpio_pad(CPIO_HEADER_SZ + namesz);
+        let contents = bytes(data, start, size).ok_or_else(malformed)?;
+        if mode & 0o170000 == 0o100000 {
+            files.push((name, contents));
+        }
+        off = start
+            .checked_add(size + cpio_pad(size))
+            .ok_or_else(malformed)?;
+    }
+}
+
+fn flat_files(data: &[u8]) -> ArchiveFiles<'_> {
+    let malformed = || String::from("malformed index");
+    let end = data.len().checked_sub(24).ok_or_else(malformed)?;
+    let mut off = le(data, end, 8).ok_or_else(malformed)?;
+    let n = le(data, end + 8, 8).ok_or_else(malformed)?;
+    // Each file's entry in the index takes at least 24 bytes (its
+    // offset, size, name's length, and NUL-terminated name, aligned).
+    if n > data.len() / 24 {
+        return Err(malformed());
+    }
+    let mut files = Vec::new();
+    for _ in 0..n {
+        // The entry's fields are in data, so their offsets can't
+        // overflow.
+        let start = le(data, off, 8).ok_or_else(malformed)?;
+        let size = le(data, off + 8, 8).ok_or_else(malformed)?;
+        let len = le(data, off + 16, 4).ok_or_else(malformed)?;
+        let name = bytes(data, off + 20, len).ok_or_else(malformed)?;
+        let contents = bytes(data, start, size).ok_or_else(malformed)?;
+        files.push((String::from_utf8_lossy(name).to_string(), contents));
+        off = (off + 20 + len + 1).div_ceil(8) * 8;
+    }
+
+    Ok(files)
+}
+
+// The format of the cpio or flat archive `data`, and
+// its files, or None if it is neither.
+pub fn archive_files(data: &[u8]) -> Option<(ArchiveFormat, ArchiveFiles<'_>)> {
+    if data.starts_with(CPIO_MAGIC) {
+        return Some((ArchiveFormat::Cpio, cpio_files(data)));
+    }
+    if data.starts_with(FLAT_MAGIC) && data.ends_with(FLAT_MAGIC) {
+        return Some((ArchiveFormat::Flat, flat_files(data)));
+    }
+
+    None
+}
@@ -0,0 +1,154 @@
+// Backends generate the commands that build component objects and
+// the kernel image. The builder resolves everything a component's
+// build needs from the specification (its interfaces, libraries,
+// address, and generated files) into a CompTarget, so a backend
+// only decides how to build it. MakeBackend drives composite's
+// Makefiles; others (e.g. ninja, or a remote build service) can be
+// added without changing the resolution.
+
+use compiler_cache::cache_cc;
+use passes::{Arch, CompilerCache, Library, Profile};
+use std::collections::BTreeMap;
+use syshelpers::Cmd;
+
+// A component's build, as resolved from the specification.
+pub struct CompTarget {
+    pub src: String,            // the src/ directory of the composite source tree
+    pub name: String,           // the component's variable name (scope.var)
+    pub interface: String,      // the implementation's interface...
+    pub implementation: String, // ...and name, i.e. interface.implementation
+    pub exports: Vec<String>,   // interface/variant
+    pub deps: Vec<String>,      // interface/variant
+    pub libs: Vec<Library>,
+    pub arch: Arch,
+    pub baseaddr: u64,
+    pub dataaddr: Option<u64>, // of the writable segment, if not following .text
+    pub profile: Option<Profile>,
+    pub compiler_cache: Option<CompilerCache>,
+    pub cc_recorder: Option<String>, // the compilations' recorder, and its directory
+    pub cflags: Option<String>,
+    pub make_vars: BTreeMap<String, String>,
+    pub output: String,
+    pub initargs: String,
+    pub tarball: Option<String>,
+    pub tarball_align: Option<u64>,
+    pub constants_header: String,
+}
+
+pub trait ComponentBuilder: Send + Sync {
+    // Print the (library and interface) directories the
+    // component's build depends on, whitespace-separated.
+    fn deps_cmd(&self, t: &CompTarget) -> Cmd;
+    // Rebuild `dirs` (the output of deps_cmd) with the component's
+    // constants.
+    fn rebuild_cmd(&self, t: &CompTarget, dirs: &String) -> Cmd;
+    // Build the component's object.
+    fn build_cmd(&self, t: &CompTarget) -> Cmd;
+    // Build the kernel image, `output`, containing the system
+    // constructor.
+    fn kernel_cmd(&self, src: &String, arch: Arch, constructor: &String, output: &String) -> Cmd;
+}
+
+pub struct MakeBackend;
+
+// Select the architecture's toolchain. The target platform's
+// configuration (see `make config-*`) provides the rest.
+fn arch_vars(cmd: Cmd, arch: Arch) -> Cmd {
+    let cmd = cmd.var("ARCH", arch.name());
+    match arch.cross_compile() {
+        "" => cmd,
+        prefix => cmd.var("CROSS_COMPILE", prefix),
+    }
+}
+
+// Without a profile, the Makefiles' default flags are used.
+fn profile_vars(cmd: Cmd, profile: Option<Profile>) -> Cmd {
+    match profile {
+        Some(p) => cmd
+            .var("COMP_PROFILE_CFLAGS", p.cflags())
+            .var("COMP_PROFILE_LDFLAGS", p.ldflags()),
+        None => cmd,
+    }
+}
+
+// Compile through the compiler cache, if there is one, and the
+// compilations' recorder, if they're recorded.
+fn cache_vars(cmd: Cmd, t: &CompTarget) -> Cmd {
+    let cc = match t.compiler_cache {
+        Some(c) => cache_cc(c, t.arch),
+        None => format!("{}gcc", t.arch.cross_compile()),
+    };
+    match (&t.cc_recorder, t.compiler_cache) {
+        (Some(r), _) => cmd
+            .var("CC", &format!("{} {}", r, cc))
+            .var("CXX", &format!("{} {}g++", r, t.arch.cross_compile())),
+        (None, Some(_)) => cmd.var("CC", &cc),
+        (None, None) => cmd,
+    }
+}
+
+// The variables that select the component's sources, shared by the
+// dependency and build commands.
+fn comp_vars(cmd: Cmd, t: &CompTarget) -> Cmd {
+    arch_vars(cmd.arg("-C").arg(&t.src), t.arch)
+        .var("COMP_INTERFACES", &t.exports.join("+"))
+        .var("COMP_IFDEPS", &t.deps.join("+"))
+        .var("COMP_LIBDEPS", &t.libs.join("+"))
+        .var("COMP_INTERFACE", &t.interface)
+        .var("COMP_NAME", &t.implementation)
+}
+
+impl ComponentBuilder for MakeBackend {
+    fn deps_cmd(&self, t: &CompTarget) -> Cmd {
+        comp_vars(Cmd::new("make").arg("--quiet"), t).arg("dependencies_info")
+    }
+
+    fn rebuild_cmd(&self, t: &CompTarget, dirs: &String) -> Cmd {
+        profile_vars(
+            cache_vars(arch_vars(Cmd::new("make").arg("-C").arg(&t.src), t.arch), t),
+            t.profile,
+        )
+        .var("REBUILD_DIRS", dirs)
+        .var("COMP_CONST_H", &format!("-include {}", t.constants_header))
+        .arg("component_rebuild")
+    }
+
+    fn build_cmd(&self, t: &CompTarget) -> Cmd {
+        let cmd = comp_vars(Cmd::new("make"), t)
+            .var("COMP_VARNAME", &t.name)
+            .var("COMP_OUTPUT", &t.output)
+            .var("COMP_BASEADDR", &format!("{:#X}", t.baseaddr))
+            .var("COMP_INITARGS_FILE", &t.initargs);
+        let cmd = match t.dataaddr {
+            Some(a) => cmd.var("COMP_DATAADDR", &format!("{:#X}", a)),
+            None => cmd,
+        };
+        let cmd = match t.tarball {
+            Some(ref tar) => cmd.var("COMP_TAR_FILE", tar),
+            None => cmd,
+        };
+        let cmd = match t.tarball_align {
+            Some(a) => cmd.var("COMP_TAR_ALIGN", &a.to_string()),
+            None => cmd,
+        };
+        let cmd = match t.cflags {
+            Some(ref f) => cmd.var("COMP_CFLAGS", f),
+            None => cmd,
+        };
+        // The spec's variables come last so that they can override
+        // the architecture's and the profile's.
+        let cmd = profile_vars(cache_vars(cmd, t), t.profile)
+            .var("COMP_CONST_H", &format!("-include {}", t.constants_header));
+        t.make_vars
+            .iter()
+            .fold(cmd, |cmd, (k, v)| cmd.var(k, v))
+            .arg("component")
+    }
+
+    fn kernel_cmd(&self, src: &String, arch: Arch, constructor: &String, output: &String) -> Cmd {
+        arch_vars(Cmd::new("make").arg("-C").arg(src), arch)
+            .var("KERNEL_OUTPUT", output)
+            .var("CONSTRUCTOR_COMP", constructor)
+            .arg("plat")
+    }
+}
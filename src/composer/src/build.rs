@@ -1,9 +1,36 @@
-use initargs::ArgsKV;
-use passes::{component, deps, exports, AddrSpcName, BuildState, ComponentId, SystemState};
+use archive::{archive_offsets, PAGE_SIZE};
+use backend::{CompTarget, ComponentBuilder, MakeBackend};
+use compile_commands::{
+    recorder_create, records_cache, records_cache_path, records_dir, records_restore,
+};
+use compiler_cache::{cache_stats, CacheStats};
+use exec::{Executor, HostExecutor, Outcome};
+use initargs::{initargs_check, initfs_contents, ArgsKV};
+use interrupt::interrupted;
+use options::Options;
+use passes::{
+    component, deps, exports, libs, AddrSpcName, Arch, BuildState, CompilerCache, ComponentId,
+    ComposeError, Library, Profile, ServiceType, SystemState,
+};
+use plan::{Action, CompPlan, ConstructorPlan, Explanation, KernelPlan, Plan};
+use provenance::{provenance, Provenance};
+use signing::{public_key_hex, signing_key_load, SIGNATURE_SZ};
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use syshelpers::{dir_exists, emit_file, exec_pipeline, reset_dir};
-use tar::Builder;
+use std::fs;
+use std::fs::{File, TryLockError};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use strip::{debug_path, objcopy, stripped_path};
+use syshelpers::{
+    dir_exists, dump_file, emit_file, hash_bytes, hash_dir_sources, io_error, reset_dir, sha256,
+    Cmd,
+};
+use timing::{step_name, Step, StepKind, Timings};
 
 // Interact with the composite build system to "seal" the components.
 // This requires linking them with all dependencies, and with libc,
@@ -18,11 +45,10 @@ use tar::Builder;
 // those dependencies.
 //
 // Note that the Makefiles of components and interfaces include a
-// specification of all of this.  FIXME: We should check the
-// specification in the syspec against these local (and
-// compile-checked) specifications, and bomb out with an error if
-// they don't match.  For now, a disparity will result in compiler
-// errors, instead of errors here.  Thus, FIXME.
+// specification of all of this. The sysspec is checked against these
+// local (and compile-checked) specifications before building (see
+// `comp_verify_makefile`), so that missing dependencies fail here,
+// rather than as compiler or linker errors.
 //
 // The goal of the context is to build up all of this information,
 // then call `make component` with the correct make variables
@@ -33,86 +59,197 @@ use tar::Builder;
 //   or "pong/log" for a log variant
 // - COMP_IFDEPS - list of '+'-separated interface dependencies and
 //   variants, again specified as "if/variant"
-// - COMP_LIBS - list of space separated library dependencies
+// - COMP_LIBDEPS - list of '+'-separated library dependencies
 // - COMP_INTERFACE - this component's interface directory
 // - COMP_NAME - which component implementation to use
 // - COMP_VARNAME - the name of the component's variable in the sysspec
 // - COMP_BASEADDR - the base address of .text for the component
+// - COMP_DATAADDR - the base address of its writable sections, with an XIP layout
 // - COMP_INITARGS_FILE - the path to the generated initial arguments .c file
 // - COMP_TAR_FILE - the path to an initargs tarball to compile into the component
+// - COMP_TAR_ALIGN - the alignment of the tarball within the component, if any
 //
 // In the end, this should result in a command line for each component
 // along these (artificial) lines:
 //
-// `make COMP_INTERFACES="pong/log" COMP_IFDEPS="capmgr/stubs sched/lock" COMP_LIBS="ps heap" COMP_INTERFACE=pong COMP_NAME=pingpong COMP_VARNAME=pongcomp component`
+// `make COMP_INTERFACES="pong/log" COMP_IFDEPS="capmgr/stubs+sched/lock" COMP_LIBDEPS="ps+heap" COMP_INTERFACE=pong COMP_NAME=pingpong COMP_VARNAME=pongcomp component`
 //
 // ...which should output the executable pong.pingpong.pongcomp in the
 // build directory which is the "sealed" version of the component that
-// is ready for loading.
-
-// The key within the initargs for the tarball, the path of the
-// tarball, and the set of paths to the files to include in the
-// tarball and name of them within the tarball.
-fn tarball_create(
-    tarball_key: &String,
-    tar_path: &String,
-    contents: Vec<(String, String)>,
-) -> Result<(), String> {
-    let file = File::create(&tar_path).unwrap();
-    let mut ar = Builder::new(file);
-    let dir_template = env::current_dir().unwrap(); // just need *some* directory with read/write perms
-    let key = format!("{}/", tarball_key);
-
-    ar.append_dir(&key, &dir_template).unwrap(); // FIXME: error handling
-    contents.iter().for_each(|(p, n)| {
-        // file path, and name for the tarball
-        let mut f = File::open(p).unwrap(); //  should not fail: we just built this, TODO: fix race
-        ar.append_file(format!("{}/{}", tarball_key, n), &mut f)
-            .unwrap(); // FIXME: error handling
-    });
-    ar.finish().unwrap(); // FIXME: error handling
-    Ok(())
+// is ready for loading. make runs in the src/ directory of the
+// composite source tree, which is --src-root, $COSROOT, or the
+// current directory, in that order.
+
+// Each build directory that the composer creates holds this file,
+// with the PID of the composer that created it, so that `clean` only
+// removes the composer's own directories, and can tell if they are
+// still in use.
+const BUILD_MARKER: &str = ".compose_build";
+
+// The PID of the composer that created the build directory `dir`, or
+// None if the composer didn't create it.
+fn build_dir_owner(dir: &Path) -> Option<u32> {
+    fs::read_to_string(dir.join(BUILD_MARKER))
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
 }
 
-fn constructor_tarball_create(
-    id: &ComponentId,
-    s: &SystemState,
-    b: &dyn BuildState,
-) -> Result<Option<String>, String> {
-    let me = component(&s, &id);
-    let tar_path = b.comp_file_path(&id, &"initfs_constructor.tar".to_string(), &s)?;
+// Replace the build directory `dir` with an empty one, owned by this
+// composer. As --build-dir (or the specification) can name any
+// directory, only those the composer created, or that are empty, are
+// wiped.
+fn build_dir_reset(dir: &Path) -> Result<(), ComposeError> {
+    let path = dir.display().to_string();
+    let empty = fs::read_dir(dir).map_or(true, |mut es| es.next().is_none());
+    if !empty && build_dir_owner(dir).is_none() {
+        return Err(ComposeError::Io {
+            path,
+            msg: format!(
+                "Exists, but was not created by the composer (there is no {}), so it is not replaced with the build; remove it, or build elsewhere with --build-dir",
+                BUILD_MARKER
+            ),
+        });
+    }
+    reset_dir(&path)?;
 
-    let tar_files: Vec<(String, String)> = s
-        .get_named()
-        .ids()
-        .iter()
-        .filter_map(|(cid, _name)| {
-            let c = component(&s, &cid);
-            // are we the constructor for this component?
-            if me.name != c.constructor {
-                return None;
-            }
+    emit_file(
+        &dir.join(BUILD_MARKER).display().to_string(),
+        format!("{}\n", process::id()).as_bytes(),
+    )
+}
 
-            Some((
-                b.comp_obj_path(&cid, &s).unwrap(),
-                b.comp_obj_file(&cid, &s),
-            ))
-        })
-        .collect();
-    if tar_files.len() == 0 {
-        return Ok(None);
+// Builds in the same source tree share make's targets (e.g. the
+// objects of the libraries and interfaces), so only one can run at a
+// time. The lock file holds the PID and build directory of the build
+// holding the lock.
+const SRC_LOCK: &str = ".compose.lock";
+
+// Lock the source tree `srcdir` for the build in `builddir`, waiting
+// for the build holding it if `wait`. The lock is held until the
+// returned file is closed.
+fn src_lock(srcdir: &Path, builddir: &Path, wait: bool) -> Result<File, ComposeError> {
+    let path = srcdir.join(SRC_LOCK);
+    let io_err = |msg: String| ComposeError::Io {
+        path: path.display().to_string(),
+        msg,
+    };
+    let mut f = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| io_err(format!("Could not open the source tree's lock: {}", e)))?;
+
+    match f.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let _ = f.read_to_string(&mut holder);
+            let holder = match holder
+                .trim()
+                .splitn(2, ' ')
+                .collect::<Vec<&str>>()
+                .as_slice()
+            {
+                [pid, dir] => format!("the build in {} (process {})", dir, pid),
+                _ => String::from("another build"),
+            };
+            if !wait {
+                return Err(io_err(format!(
+                    "The source tree is in use by {}; wait for it to finish with --wait",
+                    holder
+                )));
+            }
+            info!("Waiting for {} to finish.", holder);
+            // Poll, so that the wait can be interrupted.
+            loop {
+                match f.try_lock() {
+                    Ok(()) => break,
+                    Err(TryLockError::WouldBlock) if !interrupted() => {
+                        thread::sleep(Duration::from_millis(100))
+                    }
+                    Err(TryLockError::WouldBlock) => {
+                        return Err(ComposeError::Interrupted {
+                            dir: builddir.display().to_string(),
+                        })
+                    }
+                    Err(TryLockError::Error(e)) => {
+                        return Err(io_err(format!("Could not lock the source tree: {}", e)))
+                    }
+                }
+            }
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(io_err(format!("Could not lock the source tree: {}", e)))
+        }
     }
+    // Only reports the holder, so failing to record it is benign.
+    let _ = fs::write(&path, format!("{} {}\n", process::id(), builddir.display()));
 
-    tarball_create(&"binaries".to_string(), &tar_path, tar_files)?;
+    Ok(f)
+}
 
-    Ok(Some(tar_path))
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).is_dir()
 }
 
-fn constructor_serialize_args(
+// Where the signature of the file at `path` is written.
+fn signature_path(path: &String) -> String {
+    format!("{}.sig", path)
+}
+
+// The name of a constructor's archive, in the specification's format.
+pub fn constructor_archive_file(s: &SystemState) -> String {
+    format!("initfs_constructor.{}", s.get_spec().archive().name())
+}
+
+// The components that the constructor `id` creates.
+fn constructed(id: &ComponentId, s: &SystemState) -> Vec<ComponentId> {
+    let me = component(s, id);
+
+    s.get_named()
+        .ids()
+        .keys()
+        .filter(|cid| component(s, cid).constructor == me.name)
+        .cloned()
+        .collect()
+}
+
+// The path of the constructor's tarball, and the paths and names of
+// the objects of the components it constructs (within "binaries/"),
+// stripped if the build is, to be included in it, along with their
+// signatures (within "signatures/") if the build is signed. The constructor's own
+// initfs is included as well, as a component is linked with a single
+// tarball.
+fn constructor_tarball_contents(
+    id: &ComponentId,
+    s: &SystemState,
+    b: &DefaultBuilder,
+) -> Result<(String, Vec<(String, String)>), ComposeError> {
+    let tar_path = b.comp_file_path(id, &constructor_archive_file(s), s)?;
+
+    let mut tar_files = Vec::new();
+    for cid in constructed(id, s).iter() {
+        let obj = b.comp_packed_path(cid, s)?;
+        let obj_file = b.comp_obj_file(cid, s);
+        tar_files.push((obj.clone(), format!("binaries/{}", obj_file)));
+        if b.sign_key.is_some() {
+            tar_files.push((signature_path(&obj), format!("signatures/{}", obj_file)));
+        }
+    }
+    tar_files.extend(initfs_contents(id, s)?);
+
+    Ok((tar_path, tar_files))
+}
+
+// The constructor's initargs, which describe the components it
+// creates.
+pub fn constructor_args(
     id: &ComponentId,
     s: &SystemState,
-    b: &dyn BuildState,
-) -> Result<String, String> {
+    b: &DefaultBuilder,
+) -> Result<ArgsKV, ComposeError> {
     let mut sinvs = Vec::new();
 
     for s in s.get_invs_id(id).invocations().iter() {
@@ -120,31 +257,31 @@ fn constructor_serialize_args(
         sinv.push(ArgsKV::new_key(String::from("name"), s.symb_name.clone()));
         sinv.push(ArgsKV::new_key(
             String::from("client"),
-            String::from(format!("{}", s.client)),
+            format!("{}", s.client),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("server"),
-            String::from(format!("{}", s.server)),
+            format!("{}", s.server),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("c_fn_addr"),
-            String::from(format!("{}", s.c_fn_addr)),
+            format!("{}", s.c_fn_addr),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("c_fast_callgate_addr"),
-            String::from(format!("{}", s.c_callgate_addr)),
+            format!("{}", s.c_callgate_addr),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("c_ucap_addr"),
-            String::from(format!("{}", s.c_ucap_addr)),
+            format!("{}", s.c_ucap_addr),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("s_fn_addr"),
-            String::from(format!("{}", s.s_fn_addr)),
+            format!("{}", s.s_fn_addr),
         ));
         sinv.push(ArgsKV::new_key(
             String::from("s_altfn_addr"),
-            String::from(format!("{}", s.s_altfn_addr)),
+            format!("{}", s.s_altfn_addr),
         ));
 
         // Just an array of each of the maps for each sinv.  Arrays
@@ -153,12 +290,13 @@ fn constructor_serialize_args(
     }
 
     let mut ids = Vec::new();
-    s.get_named().ids().iter().for_each(|(id, _cname)| {
-        let info_addr = s.get_objs_id(&id).comp_symbs().comp_info;
+    s.get_named().ids().iter().for_each(|(id, cname)| {
+        let info_addr = s.get_objs_id(id).comp_symbs().comp_info;
         let cinfo = ArgsKV::new_arr(
             format!("{}", id),
             vec![
-                ArgsKV::new_key("img".to_string(), b.comp_obj_file(&id, &s)),
+                ArgsKV::new_key("name".to_string(), cname.to_string()),
+                ArgsKV::new_key("img".to_string(), b.comp_obj_file(id, s)),
                 ArgsKV::new_key("info".to_string(), format!("{}", info_addr)),
             ],
         );
@@ -167,6 +305,94 @@ fn constructor_serialize_args(
 
     let ids_copy: Vec<ArgsKV> = ids.into_iter().rev().collect();
 
+    // The resolved dependencies between components, each a (client,
+    // server, interface, variant) edge, so that the synchronous
+    // invocation capabilities can be derived from them. Dependencies
+    // on the kernel are on libraries, so they aren't included.
+    let mut edges = Vec::new();
+    for (cid, _) in s.get_named().ids().iter() {
+        for d in deps(s, cid).iter() {
+            let server = match s.get_named().rmap().get(&d.server) {
+                Some(sid) => sid,
+                None => continue,
+            };
+            edges.push(ArgsKV::new_arr(
+                String::from("_"),
+                vec![
+                    ArgsKV::new_key(String::from("variant"), d.variant.clone()),
+                    ArgsKV::new_key(String::from("interface"), d.interface.clone()),
+                    ArgsKV::new_key(String::from("server"), server.to_string()),
+                    ArgsKV::new_key(String::from("client"), cid.to_string()),
+                ],
+            ));
+        }
+    }
+    edges.reverse();
+
+    // The order in which the components are initialized on each core:
+    // those the specification orders first, then the others with an
+    // affinity for the core, in the order of their ids.
+    let spec = s.get_spec();
+    let mut boot = Vec::new();
+    for core in 0..spec.cores() {
+        let mut order: Vec<ComponentId> = spec
+            .boot_order()
+            .get(&core)
+            .unwrap_or(&Vec::new())
+            .iter()
+            .filter_map(|n| s.get_named().rmap().get(n).cloned())
+            .collect();
+        for (cid, _) in s.get_named().ids().iter() {
+            if component(s, cid).affinity.contains(&core) && !order.contains(cid) {
+                order.push(*cid);
+            }
+        }
+        boot.push(ArgsKV::new_arr(
+            core.to_string(),
+            order
+                .iter()
+                .rev()
+                .map(|cid| ArgsKV::new_key(String::from("_"), cid.to_string()))
+                .collect(),
+        ));
+    }
+    boot.reverse();
+
+    // The SHA-256 of each sealed object in our tarball, keyed by its
+    // name within binaries/, so that its contents can be verified
+    // (and images compared by their contents).
+    let mut checksums = Vec::new();
+    for cid in constructed(id, s).iter() {
+        let obj = dump_file(&b.comp_packed_path(cid, s)?)?;
+        checksums.push(ArgsKV::new_key(b.comp_obj_file(cid, s), sha256(&obj)));
+    }
+
+    // With page_align, the offset of each object within our
+    // (uncompressed) archive, keyed as the checksums are, so that the
+    // booter can map it in place. The objects' signatures are made
+    // after the initargs, but are all of the same size.
+    let mut offsets = Vec::new();
+    if s.get_spec().page_align() {
+        let (_, files) = constructor_tarball_contents(id, s, b)?;
+        let mut sizes = Vec::new();
+        for (p, n) in files.iter() {
+            let size = if n.starts_with("signatures/") {
+                SIGNATURE_SZ
+            } else {
+                fs::metadata(p)
+                    .map_err(|e| io_error(p, "read the size of the file", e))?
+                    .len()
+            };
+            sizes.push((n.clone(), size));
+        }
+        let offs = archive_offsets(&sizes, s.get_spec().archive(), true);
+        for ((n, _), off) in sizes.iter().zip(offs) {
+            if let Some(obj) = n.strip_prefix("binaries/") {
+                offsets.push(ArgsKV::new_key(obj.to_string(), off.to_string()));
+            }
+        }
+    }
+
     // Find the id of the address space with a specific name
     fn addrspc_parent_id(s: &SystemState, asname: &AddrSpcName) -> String {
         s.get_named()
@@ -206,7 +432,7 @@ fn constructor_serialize_args(
                 if let Some(ref p) = a.parent {
                     v.push(ArgsKV::new_key(
                         "parent".to_string(),
-                        addrspc_parent_id(&s, &p),
+                        addrspc_parent_id(s, p),
                     ));
                 }
                 v
@@ -226,143 +452,1522 @@ fn constructor_serialize_args(
         })
         .collect();
 
-    let mut topkv = Vec::new();
-    topkv.push(ArgsKV::new_arr(String::from("sinvs"), sinvs));
-    topkv.push(ArgsKV::new_arr(String::from("components"), ids_copy));
+    let mut topkv = vec![
+        ArgsKV::new_arr(String::from("sinvs"), sinvs),
+        ArgsKV::new_arr(String::from("components"), ids_copy),
+        ArgsKV::new_arr(String::from("deps"), edges),
+        ArgsKV::new_key(String::from("cores"), spec.cores().to_string()),
+        ArgsKV::new_arr(String::from("boot_order"), boot),
+        ArgsKV::new_arr(String::from("checksums"), checksums),
+    ];
+    if s.get_spec().page_align() {
+        topkv.push(ArgsKV::new_arr(String::from("initfs_offsets"), offsets));
+    }
+    // Reserved for the build's provenance, rather than parameters.
+    if let Some(ref p) = b.provenance {
+        topkv.push(p.to_args());
+    }
+    // The key the signatures/ in the tarball can be verified with.
+    if let Some(ref k) = b.public_key {
+        topkv.push(ArgsKV::new_key(String::from("signing_key"), k.clone()));
+    }
     topkv.push(ArgsKV::new_arr(String::from("addrspc_shared"), ases));
     topkv.push(ArgsKV::new_arr(
         String::from("addrspc_exclusive"),
         excl_ases,
     ));
-    s.get_param_id(&id)
+    // The booter must know its archive's format to look up the
+    // binaries in it.
+    topkv.push(ArgsKV::new_key(
+        String::from("initfs_format"),
+        String::from(s.get_spec().archive().name()),
+    ));
+    // Record how the components in the image were built.
+    topkv.push(ArgsKV::new_key(
+        String::from("build_profile"),
+        String::from(b.profile.map_or("default", |p| p.name())),
+    ));
+    s.get_param_id(id)
         .param_list()
         .iter()
         .for_each(|a| topkv.push(a.clone()));
 
-    let top = ArgsKV::new_top(topkv);
+    Ok(ArgsKV::new_top(topkv))
+}
+
+// The path of the constructor's initargs, and their contents.
+fn constructor_serialize_args(
+    id: &ComponentId,
+    s: &SystemState,
+    b: &DefaultBuilder,
+) -> Result<(String, String), ComposeError> {
+    let top = constructor_args(id, s, b)?;
+    initargs_check(component(s, id), &top)?;
     let args = top.serialize();
+    debug_assert_eq!(ArgsKV::deserialize(&args), Ok(top));
 
-    let args_file_path = b.comp_file_path(&id, &"initargs_constructor.c".to_string(), &s)?;
-    emit_file(&args_file_path, args.as_bytes()).unwrap();
+    let args_file_path = b.comp_file_path(id, &"initargs_constructor.c".to_string(), s)?;
 
-    Ok(args_file_path)
+    Ok((args_file_path, args))
 }
 
-enum CmdOpts {
-    DEPINFO,
-    REGULAR,
+// The (whitespace-separated) values assigned to a variable in the
+// Makefile of a component, interface, or library directory. This
+// mirrors the parsing in cidl/calculate_dependencies.py.
+fn makefile_var(dir: &Path, var: &str) -> Vec<String> {
+    let contents = match fs::read_to_string(dir.join("Makefile")) {
+        Ok(c) => c,
+        Err(_) => return Vec::new(),
+    };
+
+    contents
+        .lines()
+        .filter_map(|l| {
+            let data: Vec<&str> = l.split('#').next().unwrap().split('=').collect();
+            if data.len() != 2 || data[0].trim() != var {
+                return None;
+            }
+            Some(data[1].split_whitespace().map(|v| v.to_string()).collect())
+        })
+        .next_back()
+        .unwrap_or(Vec::new())
 }
 
-fn comp_gen_make_cmd(
-    output_name: &String,
-    args_file: &String,
-    tar_file: &Option<String>,
-    header_file: &String,
-    opts: CmdOpts,
-    id: &ComponentId,
-    s: &SystemState,
-) -> String {
-    let c = component(&s, id);
-    let ds = deps(&s, id);
-    let exports = exports(&s, id);
+// The libraries to link into a component: those listed in its
+// specification, and those required by the interfaces it exports
+// and depends on, along with the transitive closure of the
+// libraries they depend on. The interfaces' libraries are required
+// by the variants chosen by the specification, so aren't otherwise
+// found by the build system.
+fn comp_libs(src: &Path, id: &ComponentId, s: &SystemState) -> Result<Vec<Library>, ComposeError> {
+    let mut ls: Vec<Library> = libs(s, id).clone();
+    for i in exports(s, id)
+        .iter()
+        .map(|e| &e.interface)
+        .chain(deps(s, id).iter().map(|d| &d.interface))
+    {
+        for l in makefile_var(
+            &src.join("components/interface").join(i),
+            "LIBRARY_DEPENDENCIES",
+        ) {
+            if !ls.contains(&l) {
+                ls.push(l);
+            }
+        }
+    }
+
+    let mut i = 0;
+    while i < ls.len() {
+        let dir = src.join("components/lib").join(&ls[i]);
+        if !dir.is_dir() {
+            return Err(ComposeError::Spec(format!(
+                "Error: Component {} requires library {}, which is not found in {}/components/lib/.",
+                component(s, id).name,
+                ls[i],
+                src.display()
+            )));
+        }
+        for l in makefile_var(&dir, "LIBRARY_DEPENDENCIES") {
+            if !ls.contains(&l) {
+                ls.push(l);
+            }
+        }
+        i += 1;
+    }
 
-    let (_, if_exp) = exports
+    Ok(ls)
+}
+
+// Check the interfaces that the specification says a component
+// exports and depends on against those declared in its Makefile.
+// Dependencies declared in the Makefile on interfaces that the
+// system serves, but that the specification doesn't provide to the
+// component, cannot be linked, so are errors. Disparities in the
+// exported interfaces are often benign (e.g. interfaces that are
+// implemented, but not declared), so only cause warnings.
+fn comp_verify_makefile(src: &Path, id: &ComponentId, s: &SystemState) -> Result<(), ComposeError> {
+    let c = component(s, id);
+    let decomp: Vec<&str> = c.source.split(".").collect();
+    let dir = src
+        .join("components/implementation")
+        .join(decomp[0])
+        .join(decomp[1]);
+    if !dir.is_dir() {
+        return Err(ComposeError::Spec(format!(
+            "Error: Component {}'s implementation, {}, is not found in {}.",
+            c.name,
+            c.source,
+            dir.display()
+        )));
+    }
+
+    let spec_deps: Vec<&String> = deps(s, id).iter().map(|d| &d.interface).collect();
+    let spec_exps: Vec<&String> = exports(s, id).iter().map(|e| &e.interface).collect();
+    for i in spec_deps.iter().chain(spec_exps.iter()) {
+        if !src.join("components/interface").join(i).is_dir() {
+            return Err(ComposeError::Spec(format!(
+                "Error: Component {} uses interface {}, which is not found in {}/components/interface/.",
+                c.name,
+                i,
+                src.display()
+            )));
+        }
+    }
+    let variants = deps(s, id)
+        .iter()
+        .map(|d| (&d.interface, &d.variant))
+        .chain(exports(s, id).iter().map(|e| (&e.interface, &e.variant)));
+    for (i, v) in variants {
+        if !src.join("components/interface").join(i).join(v).is_dir() {
+            return Err(ComposeError::Spec(format!(
+                "Error: Component {} uses variant {} of interface {}, which is not found in {}/components/interface/{}/.",
+                c.name,
+                v,
+                i,
+                src.display(),
+                i
+            )));
+        }
+    }
+
+    let mk_deps = makefile_var(&dir, "INTERFACE_DEPENDENCIES");
+    let mk_exps = makefile_var(&dir, "INTERFACE_EXPORTS");
+    // Only the interfaces that a component of the system serves can be
+    // dependencies in the specification, so the others (e.g. those
+    // that are only a library, like netshmem) are ignored.
+    let served: Vec<&String> = s
+        .get_named()
+        .ids()
+        .keys()
+        .flat_map(|sid| exports(s, sid).iter().map(|e| &e.interface))
+        .collect();
+    let missing_deps: Vec<&String> = mk_deps
         .iter()
-        .fold((true, String::from("")), |(first, accum), e| {
-            let mut ifpath = accum.clone();
-            if !first {
-                ifpath.push_str("+");
+        .filter(|d| !spec_deps.contains(d) && served.contains(d))
+        .collect();
+    let mut exp_diff = Vec::new();
+    for e in mk_exps.iter().filter(|e| !spec_exps.contains(e)) {
+        exp_diff.push(format!(
+            "\t- {} (exported in the Makefile, not the specification)",
+            e
+        ));
+    }
+    for e in spec_exps.iter().filter(|e| !mk_exps.contains(e)) {
+        exp_diff.push(format!(
+            "\t+ {} (exported in the specification, not the Makefile)",
+            e
+        ));
+    }
+
+    if !exp_diff.is_empty() {
+        warn!(
+            "Component {}'s exported interfaces differ from those in {}/Makefile:\n{}",
+            c.name,
+            dir.display(),
+            exp_diff.join("\n")
+        );
+    }
+    if !missing_deps.is_empty() {
+        return Err(ComposeError::Spec(format!(
+            "Error: Component {}'s dependencies in the specification don't include those in {}/Makefile:\n\tMakefile INTERFACE_DEPENDENCIES: {}\n\tspecification deps: {}\n{}",
+            c.name,
+            dir.display(),
+            mk_deps.join(" "),
+            spec_deps.iter().map(|d| d.as_str()).collect::<Vec<&str>>().join(" "),
+            missing_deps
+                .iter()
+                .map(|d| format!("\t- {} (required by the Makefile, but not a dependency in the specification)", d))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )));
+    }
+
+    Ok(())
+}
+
+pub struct DefaultBuilder {
+    srcdir: PathBuf, // the src/ directory of the composite source tree
+    builddir: PathBuf,
+    cachedir: PathBuf, // persists across builds, unlike builddir
+    jobs: usize,
+    rebuild: bool,                         // ignore cached objects?
+    keep_going: bool,                      // build remaining components after a failure?
+    dry_run: bool,                         // only print the build plan?
+    wait: bool,                            // for another build's lock on the source tree?
+    require_clean: bool,                   // refuse to build from a modified source tree?
+    lock: Option<File>,                    // on the source tree, held while building
+    build_dir: Option<String>,             // from the command line
+    src_root: Option<String>,              // from the command line
+    timeout: Option<Duration>,             // of each component's build
+    profile: Option<Profile>,              // from the command line, then the specification
+    sign_key: Option<String>,              // from the command line, then the specification
+    strip: bool,                           // pack stripped objects?
+    regression_threshold: u64,             // percent growth in a component's build time or size
+    shared_layout: bool,                   // lay out the duplicated read-only contents?
+    public_key: Option<String>,            // of the signing key, in hex, once initialized
+    compiler_cache: Option<CompilerCache>, // from the command line, then the specification
+    toolchain: String,                     // the compiler and make configuration, once initialized
+    compile_commands: bool,                // record the components' compilations?
+    recorder: Option<String>,              // of the compilations, once initialized
+    cache_stats: Option<CacheStats>,       // of the compiler cache, when the build started
+    provenance: Option<Provenance>,        // of the build, once initialized
+    built: HashMap<ComponentId, String>,   // objects already built in a batch
+    timings: Timings,                      // of the build's steps
+    exec: Arc<dyn Executor>,               // performs the build's actions
+    backend: Arc<dyn ComponentBuilder>,    // generates the build commands
+    dir_locks: Arc<DirLocks>,              // on the source directories that builds modify
+}
+
+impl DefaultBuilder {
+    pub fn new(opts: &Options) -> Self {
+        DefaultBuilder {
+            srcdir: PathBuf::from("/dev/null"),
+            builddir: PathBuf::from("/dev/null"), // must initialize, so error out if you don't
+            cachedir: PathBuf::from("/dev/null"),
+            jobs: opts.jobs,
+            rebuild: opts.rebuild,
+            keep_going: opts.keep_going,
+            dry_run: opts.dry_run,
+            wait: opts.wait,
+            require_clean: opts.require_clean,
+            lock: None,
+            build_dir: opts.build_dir.clone(),
+            src_root: opts.src_root.clone(),
+            timeout: opts.timeout.map(Duration::from_secs),
+            profile: opts.profile,
+            sign_key: opts.sign_key.clone(),
+            strip: opts.strip,
+            regression_threshold: opts.regression_threshold,
+            shared_layout: opts.shared_layout,
+            public_key: None,
+            compiler_cache: opts.compiler_cache,
+            toolchain: String::new(),
+            compile_commands: opts.compile_commands,
+            recorder: None,
+            cache_stats: None,
+            provenance: None,
+            built: HashMap::new(),
+            timings: Timings::new(),
+            exec: Arc::new(HostExecutor),
+            backend: Arc::new(MakeBackend),
+            dir_locks: Arc::new(DirLocks::default()),
+        }
+    }
+
+    // Perform the build's actions with `exec`, e.g. a
+    // RecordingExecutor to capture them instead.
+    pub fn with_executor(mut self, exec: Arc<dyn Executor>) -> Self {
+        self.exec = exec;
+        self
+    }
+
+    // Build the components and kernel with `backend`, rather than
+    // with composite's Makefiles.
+    pub fn with_backend(mut self, backend: Arc<dyn ComponentBuilder>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    // Resolve what the build of the component `id` requires from the
+    // specification.
+    #[allow(clippy::too_many_arguments)]
+    fn comp_target(
+        &self,
+        id: &ComponentId,
+        s: &SystemState,
+        output: &String,
+        initargs: &String,
+        tarball: &Option<String>,
+        constants_header: &String,
+        libs: &Vec<Library>,
+        cc_recorder: Option<String>,
+    ) -> CompTarget {
+        let c = component(s, id);
+        let ifs = |ifs: Vec<(&String, &String)>| {
+            ifs.iter()
+                .map(|(i, v)| format!("{}/{}", i, v))
+                .collect::<Vec<String>>()
+        };
+        let decomp: Vec<&str> = c.source.split(".").collect();
+        assert!(decomp.len() == 2);
+
+        CompTarget {
+            src: self.srcdir.display().to_string(),
+            name: c.name.to_string(),
+            interface: decomp[0].to_string(),
+            implementation: decomp[1].to_string(),
+            exports: ifs(exports(s, id)
+                .iter()
+                .map(|e| (&e.interface, &e.variant))
+                .collect()),
+            deps: ifs(deps(s, id)
+                .iter()
+                .map(|d| (&d.interface, &d.variant))
+                .collect()),
+            libs: libs.clone(),
+            arch: c.arch,
+            baseaddr: s.get_address_assignments().component_baseaddr(id),
+            dataaddr: s.get_address_assignments().component_dataaddr(id),
+            profile: self.profile,
+            compiler_cache: self.compiler_cache,
+            cc_recorder,
+            cflags: c.cflags.clone(),
+            make_vars: c.make_vars.clone(),
+            output: output.clone(),
+            initargs: initargs.clone(),
+            tarball: tarball.clone(),
+            // The objects are page-aligned within the constructor's
+            // archive, so the archive must be as well.
+            tarball_align: if tarball.is_some()
+                && s.get_spec().page_align()
+                && s.get_properties()
+                    .service_is_a(id, ServiceType::Constructor)
+            {
+                Some(PAGE_SIZE)
+            } else {
+                None
+            },
+            constants_header: constants_header.clone(),
+        }
+    }
+
+    // The build directory of build `name`: from the command line,
+    // then the specification, and otherwise a default relative to
+    // `pwd`. Builds with different profiles get separate default
+    // directories.
+    fn build_dir_path(&self, name: &String, s: &SystemState, pwd: &Path) -> PathBuf {
+        let default_dir = match self.profile.or(s.get_spec().profile()) {
+            Some(p) => format!("system_binaries/cos_build-{}-{}", name, p.name()),
+            None => format!("system_binaries/cos_build-{}", name),
+        };
+        self.build_dir
+            .as_ref()
+            .or(s.get_spec().build_dir().as_ref())
+            .map(|d| pwd.join(d))
+            .unwrap_or(pwd.join(default_dir))
+    }
+
+    // The build directory of build `name`, whether or not it exists.
+    pub fn dir(&self, name: &String, s: &SystemState) -> Result<String, ComposeError> {
+        Ok(self
+            .build_dir_path(name, s, &current_dir()?)
+            .display()
+            .to_string())
+    }
+
+    // Remove the build directory of build `name`, returning it, or
+    // None if there is none. Directories that the composer didn't
+    // create, or that a running build is using, are left alone.
+    pub fn clean(&self, name: &String, s: &SystemState) -> Result<Option<String>, ComposeError> {
+        let dir = self.build_dir_path(name, s, &current_dir()?);
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let path = dir.display().to_string();
+        match build_dir_owner(&dir) {
+            None => Err(ComposeError::Io {
+                path,
+                msg: format!(
+                    "Not created by the composer (there is no {}), so it is not removed",
+                    BUILD_MARKER
+                ),
+            }),
+            Some(pid) if pid != process::id() && process_alive(pid) => Err(ComposeError::Io {
+                path,
+                msg: format!(
+                    "In use by the build in process {}, so it is not removed",
+                    pid
+                ),
+            }),
+            Some(_) => {
+                fs::remove_dir_all(&dir).map_err(|e| ComposeError::Io {
+                    path: path.clone(),
+                    msg: format!("Could not remove the build directory: {}", e),
+                })?;
+                Ok(Some(path))
+            }
+        }
+    }
+
+    // Remove the build directories (cos_build-*) that the composer
+    // created alongside the --build-dir, or in system_binaries/,
+    // whose builds are no longer running, returning them. Anything
+    // else there, including the build cache, is left alone.
+    pub fn clean_stale(&self) -> Result<Vec<String>, ComposeError> {
+        let pwd = current_dir()?;
+        let parent = match self.build_dir {
+            Some(ref d) => pwd.join(d).parent().unwrap_or(&pwd).to_path_buf(),
+            None => pwd.join("system_binaries"),
+        };
+        let entries = match fs::read_dir(&parent) {
+            Ok(es) => es,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut removed = Vec::new();
+        for e in entries.filter_map(|e| e.ok()) {
+            let dir = e.path();
+            let ours = e.file_name().to_string_lossy().starts_with("cos_build-")
+                && e.file_type().is_ok_and(|t| t.is_dir());
+            match build_dir_owner(&dir) {
+                Some(pid) if ours && !process_alive(pid) => {
+                    fs::remove_dir_all(&dir).map_err(|e| ComposeError::Io {
+                        path: dir.display().to_string(),
+                        msg: format!("Could not remove the build directory: {}", e),
+                    })?;
+                    removed.push(dir.display().to_string());
+                }
+                _ => (),
+            }
+        }
+        removed.sort();
+
+        Ok(removed)
+    }
+
+    // The src/ directory of the composite source tree, from the
+    // command line or $COSROOT (relative to `pwd`), or `pwd` itself.
+    fn src_dir(&self, pwd: &Path) -> Result<PathBuf, ComposeError> {
+        let root = self
+            .src_root
+            .clone()
+            .or(env::var("COSROOT").ok())
+            .map(|r| pwd.join(r))
+            .unwrap_or(pwd.to_path_buf());
+        for d in ["implementation", "interface"].iter() {
+            let p = root.join("src/components").join(d);
+            if !p.is_dir() {
+                return Err(ComposeError::Io {
+                    path: p.display().to_string(),
+                    msg: format!(
+                        "Not found, so {} is not the root of a composite source tree (see --src-root and COSROOT)",
+                        root.display()
+                    ),
+                });
             }
-            ifpath.push_str(&e.interface.clone());
-            ifpath.push_str("/");
-            ifpath.push_str(&e.variant.clone());
-            (false, ifpath)
+        }
+
+        Ok(root.join("src"))
+    }
+
+    // Check each component's implementation, and the interfaces it
+    // uses, against the source tree, without building (or
+    // initializing the build), reporting all of the problems found.
+    pub fn verify_sources(&self, s: &SystemState) -> Result<(), ComposeError> {
+        let srcdir = self.src_dir(&current_dir()?)?;
+        let errs: Vec<ComposeError> = s
+            .get_named()
+            .ids()
+            .keys()
+            .filter_map(|id| comp_verify_makefile(&srcdir, id, s).err())
+            .collect();
+
+        if !errs.is_empty() {
+            return Err(ComposeError::Invalid(errs));
+        }
+
+        Ok(())
+    }
+
+    // Mark the build directory as incomplete, after an interrupt,
+    // and return the error reporting it.
+    pub fn incomplete(&self) -> Result<ComposeError, ComposeError> {
+        let dir = self.builddir.display().to_string();
+        emit_file(
+            &self.file_path(&"INCOMPLETE".to_string())?,
+            b"This build was interrupted, so its objects and image are incomplete.\n",
+        )?;
+
+        Ok(ComposeError::Interrupted { dir })
+    }
+
+    // The build profile, once initialized.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    // The compiler cache, once initialized.
+    pub fn compiler_cache(&self) -> Option<CompilerCache> {
+        self.compiler_cache
+    }
+
+    // Are the objects packed into the image stripped?
+    pub fn strip(&self) -> bool {
+        self.strip
+    }
+
+    // Is the components' duplicated read-only contents' shared layout
+    // generated, along with the report of them?
+    pub fn shared_layout(&self) -> bool {
+        self.shared_layout
+    }
+
+    // Are the components' compilations recorded, for
+    // compile_commands.json?
+    pub fn compile_commands(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // The number of components built concurrently.
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    // The percent that a component's build time, or size, can grow
+    // from its past builds' before it's warned about.
+    pub fn regression_threshold(&self) -> u64 {
+        self.regression_threshold
+    }
+
+    // The time each of the build's steps took.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    // Record the time that performing action `a` took, from `start`.
+    fn step_record(&self, a: &Action, start: Duration) {
+        let (name, kind) = match a {
+            Action::Write { .. } => return,
+            Action::Tarball { path, .. } => (step_name(path, true), StepKind::Tarball),
+            Action::Sign { path, .. } => (step_name(path, false), StepKind::Sign),
+            Action::Strip { path, .. } => (step_name(path, false), StepKind::Strip),
+            Action::Make { name, .. } if name == "kernel" => (name.clone(), StepKind::Kernel),
+            Action::Make { name, .. } => (name.clone(), StepKind::Constructor),
+        };
+        self.timings.record(Step {
+            name,
+            kind,
+            lane: 0,
+            start,
+            end: self.timings.now(),
+            cached: false,
         });
-    let (_, if_deps) = ds
-        .iter()
-        .fold((true, String::from("")), |(first, accum), d| {
-            let mut ifpath = accum.clone();
-            if !first {
-                ifpath.push_str("+");
+    }
+
+    // The object of component `id` that is packed into the image:
+    // the sealed object, or its stripped version.
+    pub fn comp_packed_path(
+        &self,
+        id: &ComponentId,
+        s: &SystemState,
+    ) -> Result<String, ComposeError> {
+        let obj = self.comp_obj_path(id, s)?;
+
+        Ok(if self.strip { stripped_path(&obj) } else { obj })
+    }
+
+    // The action to strip component `id`'s object, if the build is
+    // stripped.
+    fn strip_action(&self, id: &ComponentId, s: &SystemState) -> Result<Vec<Action>, ComposeError> {
+        if !self.strip {
+            return Ok(Vec::new());
+        }
+        let obj = self.comp_obj_path(id, s)?;
+
+        Ok(vec![Action::Strip {
+            objcopy: objcopy(component(s, id).arch),
+            stripped: stripped_path(&obj),
+            debug: debug_path(&obj),
+            path: obj,
+        }])
+    }
+
+    // The compiler cache's hits and misses since the build started,
+    // if the cache is used and its statistics can be read.
+    pub fn compiler_cache_stats(&self) -> Option<(CompilerCache, CacheStats)> {
+        let c = self.compiler_cache?;
+        let before = self.cache_stats?;
+
+        Some((c, cache_stats(c)?.since(&before)))
+    }
+
+    // Where the build came from, once initialized.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    // The src/ directory of the source tree, once initialized.
+    pub fn srcdir(&self) -> &Path {
+        &self.srcdir
+    }
+
+    // The build directory, and the object cache, once initialized.
+    pub fn builddir(&self) -> &Path {
+        &self.builddir
+    }
+
+    pub fn cachedir(&self) -> &Path {
+        &self.cachedir
+    }
+
+    // The libraries that component `id` is linked with, including
+    // those they depend on.
+    pub fn libs(&self, id: &ComponentId, s: &SystemState) -> Result<Vec<Library>, ComposeError> {
+        comp_libs(&self.srcdir, id, s)
+    }
+
+    // Execute the `actions` in order. Each make's output is
+    // recorded in its log, and its failure (or timeout) fails the
+    // remaining actions.
+    fn execute(&self, actions: &Vec<Action>) -> Result<(), ComposeError> {
+        for a in actions.iter() {
+            if interrupted() {
+                return Err(ComposeError::Interrupted {
+                    dir: self.builddir.display().to_string(),
+                });
+            }
+            let start = self.timings.now();
+            let (name, cmd, log) = match a {
+                Action::Make { name, cmd, log } => (name, cmd, log),
+                _ => {
+                    self.exec.execute(a, None)?;
+                    self.step_record(a, start);
+                    continue;
+                }
+            };
+            info!("Compiling {}.", name);
+            debug!("\t{}", cmd);
+            let (out, err, status) = match self.exec.execute(a, self.timeout)? {
+                Outcome::Made(out, err, status) => (out, err, status),
+                _ => {
+                    // unwrap as only builds with a timeout time out
+                    let secs = self.timeout.unwrap().as_secs();
+                    self.exec.execute(
+                        &Action::Write {
+                            path: log.clone(),
+                            contents: format!(
+                                "Command: {}\nKilled after exceeding the build timeout of {} seconds.",
+                                cmd, secs
+                            )
+                            .into_bytes(),
+                        },
+                        None,
+                    )?;
+                    return Err(ComposeError::Timeout {
+                        name: name.clone(),
+                        secs,
+                        log: log.clone(),
+                    });
+                }
+            };
+            let output = format!(
+                "Command: {}\nCompilation output:{}\nCompilation errors:{}",
+                cmd, out, err
+            );
+            trace!("{}: {}", name, output);
+            self.exec.execute(
+                &Action::Write {
+                    path: log.clone(),
+                    contents: output.into_bytes(),
+                },
+                None,
+            )?;
+            if !status.success() {
+                return Err(ComposeError::Make {
+                    name: name.clone(),
+                    status: status.code(),
+                    stderr: err,
+                    log: log.clone(),
+                });
+            }
+            if !err.is_empty() {
+                warn!("{} compiled with warnings. See {}.", name, log);
+            }
+            self.step_record(a, start);
+        }
+
+        Ok(())
+    }
+
+    // The actions to strip the objects of the components that the
+    // constructor `c` creates, if the build is stripped. These precede
+    // its other actions, as its initargs hold the stripped objects'
+    // checksums.
+    pub fn strip_actions(
+        &self,
+        c: &ComponentId,
+        s: &SystemState,
+    ) -> Result<Vec<Action>, ComposeError> {
+        let mut actions = Vec::new();
+        for cid in constructed(c, s).iter() {
+            actions.extend(self.strip_action(cid, s)?);
+        }
+
+        Ok(actions)
+    }
+
+    // The actions to build the constructor `c` once the components
+    // it creates are built (and stripped): generating its initargs
+    // and tarball of their objects, then linking it with them.
+    pub fn constructor_actions(
+        &self,
+        c: &ComponentId,
+        s: &SystemState,
+    ) -> Result<Vec<Action>, ComposeError> {
+        let (argsfile, args) = constructor_serialize_args(c, s, self)?;
+        let mut actions = vec![Action::Write {
+            path: argsfile.clone(),
+            contents: args.into_bytes(),
+        }];
+        if let Some(ref key) = self.sign_key {
+            for cid in constructed(c, s).iter() {
+                let obj = self.comp_packed_path(cid, s)?;
+                actions.push(Action::Sign {
+                    signature: signature_path(&obj),
+                    path: obj,
+                    key: key.clone(),
+                });
             }
-            ifpath.push_str(&d.interface.clone());
-            ifpath.push_str("/");
-            ifpath.push_str(&d.variant.clone());
-            (false, ifpath)
+        }
+        let (tar_path, tar_files) = constructor_tarball_contents(c, s, self)?;
+        let tarfile = if tar_files.is_empty() {
+            None
+        } else {
+            actions.push(Action::Tarball {
+                path: tar_path.clone(),
+                files: tar_files,
+                format: s.get_spec().archive(),
+                page_align: s.get_spec().page_align(),
+            });
+            let tarfile = tar_path.clone();
+            if let Some(ref key) = self.sign_key {
+                actions.push(Action::Sign {
+                    path: tarfile.clone(),
+                    key: key.clone(),
+                    signature: signature_path(&tarfile),
+                });
+            }
+            Some(tarfile)
+        };
+        let header_file_path = self.comp_file_path(c, &"component_constants.h".to_string(), s)?;
+        let cmd = self.backend.build_cmd(&self.comp_target(
+            c,
+            s,
+            &self.comp_obj_path(c, s)?,
+            &argsfile,
+            &tarfile,
+            &header_file_path,
+            &comp_libs(&self.srcdir, c, s)?,
+            None,
+        ));
+        actions.push(Action::Make {
+            name: s.get_named().ids().get(c).unwrap().to_string(),
+            cmd,
+            log: self.comp_file_path(c, &"constructor_compilation.log".to_string(), s)?,
+        });
+
+        Ok(actions)
+    }
+
+    // The action to link the kernel image with the system
+    // constructor. If the build is stripped, the constructor's object
+    // is stripped, as the other components' are, and the kernel is
+    // linked with the stripped object instead.
+    pub fn kernel_actions(
+        &self,
+        kern_output: &String,
+        constructor_input: &String,
+        s: &SystemState,
+    ) -> Result<Vec<Action>, ComposeError> {
+        let mut actions = Vec::new();
+        let mut constructor = constructor_input.clone();
+        let sys_constructor = s
+            .get_named()
+            .ids()
+            .keys()
+            .find(|id| component(s, id).constructor.var_name == "kernel")
+            .cloned();
+        if let (true, Some(id)) = (self.strip, sys_constructor) {
+            actions.extend(self.strip_action(&id, s)?);
+            constructor = self.comp_packed_path(&id, s)?;
+        }
+        actions.push(Action::Make {
+            name: String::from("kernel"),
+            cmd: self.backend.kernel_cmd(
+                &self.srcdir.display().to_string(),
+                s.get_spec().arch(),
+                &constructor,
+                kern_output,
+            ),
+            log: self.file_path(&"kernel_compilation.log".to_string())?,
         });
 
-    let mut optional_cmds = String::from("");
-    optional_cmds.push_str(&format!("COMP_INITARGS_FILE={} ", args_file));
-    if let Some(s) = tar_file {
-        optional_cmds.push_str(&format!("COMP_TAR_FILE={} ", s));
+        Ok(actions)
     }
 
-    optional_cmds.push_str(&format!("COMP_CONST_H=\"-include {}\" ", header_file));
+    // The directories of the sources that a component's build
+    // depends on: its implementation, the interfaces it exports and
+    // depends on, and the kernel's headers shared with components.
+    fn comp_src_dirs(&self, id: &ComponentId, state: &SystemState) -> Vec<PathBuf> {
+        let c = component(state, id);
+        let decomp: Vec<&str> = c.source.split(".").collect();
+        let mut src_dirs = vec![self
+            .srcdir
+            .join("components/implementation")
+            .join(decomp[0])
+            .join(decomp[1])];
+        for i in exports(state, id)
+            .iter()
+            .map(|e| &e.interface)
+            .chain(deps(state, id).iter().map(|d| &d.interface))
+        {
+            src_dirs.push(self.srcdir.join("components/interface").join(i));
+        }
+        src_dirs.push(self.srcdir.join("kernel/include"));
 
-    let decomp: Vec<&str> = c.source.split(".").collect();
-    assert!(decomp.len() == 2);
-    // unwrap as we've already validated the name.
-    let compid = s.get_named().rmap().get(&c.name).unwrap();
-    let baseaddr = s.get_address_assignments().component_baseaddr(compid);
-
-    match opts {
-        CmdOpts::DEPINFO => format!(
-            r#"make --quiet -C src COMP_INTERFACES="{}" COMP_IFDEPS="{}" COMP_LIBDEPS="" COMP_INTERFACE={} COMP_NAME={} dependencies_info"#,
-            if_exp, if_deps, &decomp[0], &decomp[1]
-        ),
-        CmdOpts::REGULAR => format!(
-            r#"make -C src COMP_INTERFACES="{}" COMP_IFDEPS="{}" COMP_LIBDEPS="" COMP_INTERFACE={} COMP_NAME={} COMP_VARNAME={} COMP_OUTPUT={} COMP_BASEADDR={:#X} {} component"#,
-            if_exp, if_deps, &decomp[0], &decomp[1], &c.name, output_name, baseaddr, &optional_cmds
-        ),
+        src_dirs
+    }
+
+    // Compute all of the commands to build a component. This
+    // requires the system state, but the resulting job does not, so
+    // that it can be executed concurrently with other builds.
+    fn comp_build_job(
+        &self,
+        id: &ComponentId,
+        state: &SystemState,
+    ) -> Result<CompBuildJob, ComposeError> {
+        comp_verify_makefile(&self.srcdir, id, state)?;
+        let comp_dir = self.comp_dir_path(id, state)?;
+        compdir_check_build(&comp_dir)?;
+        let p = state.get_param_id(id);
+        let output_path = self.comp_obj_path(id, state)?;
+
+        let header_file_path =
+            self.comp_file_path(id, &"component_constants.h".to_string(), state)?;
+        self.comp_const_header_file(&header_file_path, id, state)?;
+        let libs = comp_libs(&self.srcdir, id, state)?;
+        // The compilations of the component's last build are kept.
+        let (records, cc_recorder) = match self.recorder {
+            Some(ref r) => {
+                let dir = records_dir(self, id, state)?;
+                reset_dir(&dir)?;
+                (Some(dir.clone()), Some(format!("{} {}", r, dir)))
+            }
+            None => (None, None),
+        };
+
+        let target = self.comp_target(
+            id,
+            state,
+            &output_path,
+            p.param_prog(),
+            p.param_fs(),
+            &header_file_path,
+            &libs,
+            cc_recorder,
+        );
+        let dep_cmd = self.backend.deps_cmd(&target);
+        let cmd = self.backend.build_cmd(&target);
+        let name = state.get_named().ids().get(id).unwrap();
+
+        // The inputs to the build that are derived from the
+        // specification. Note that the build directory is not
+        // included, so cached objects can be shared between builds.
+        let c = component(state, id);
+        let mut spec_digest = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:#X}\n{:?}",
+            c,
+            deps(state, id),
+            exports(state, id),
+            libs,
+            state.get_address_assignments().component_baseaddr(id),
+            self.profile
+        );
+        // Only XIP layouts place the writable segments separately.
+        if let Some(a) = state.get_address_assignments().component_dataaddr(id) {
+            spec_digest.push_str(&format!("\n{:#X}", a));
+        }
+        let src_dirs = self.comp_src_dirs(id, state);
+        // comp_src_dirs lists the implementation's first.
+        let impl_dir = src_dirs[0].clone();
+        let mut input_files = vec![
+            p.param_prog().clone(),
+            header_file_path.clone(),
+            p.param_header().clone(),
+        ];
+        if let Some(ref t) = p.param_fs() {
+            input_files.push(t.clone());
+        }
+
+        Ok(CompBuildJob {
+            name: name.to_string(),
+            obj_file: self.comp_obj_file(id, state),
+            output_path,
+            dep_cmd,
+            cmd,
+            log_path: self.comp_file_path(id, &"compilation.log".to_string(), state)?,
+            spec_digest,
+            toolchain: self.toolchain.clone(),
+            impl_dir,
+            src_dirs,
+            input_files,
+            cachedir: self.cachedir.clone(),
+            records,
+            builddir: self.builddir.display().to_string(),
+            target,
+            backend: self.backend.clone(),
+            use_cached: !self.rebuild,
+            timeout: self.timeout,
+            exec: self.exec.clone(),
+            dir_locks: self.dir_locks.clone(),
+        })
     }
 }
 
-fn kern_gen_make_cmd(input_constructor: &String, kern_output: &String, _s: &SystemState) -> String {
-    format!(
-        r#"make -C src KERNEL_OUTPUT="{}" CONSTRUCTOR_COMP="{}" plat"#,
-        kern_output, input_constructor
-    )
+impl DefaultBuilder {
+    // The directories of the sources that the system's components
+    // are built from, each once.
+    pub fn src_dirs(&self, s: &SystemState) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = s
+            .get_named()
+            .ids()
+            .keys()
+            .flat_map(|id| self.comp_src_dirs(id, s))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+
+        dirs
+    }
+
+    // The plan to build the components (`ids`, in build order), their
+    // constructors, and the kernel image, without building anything.
+    pub fn plan(&self, ids: &Vec<ComponentId>, s: &SystemState) -> Result<Plan, ComposeError> {
+        let mut components = Vec::new();
+        for id in ids.iter() {
+            let job = self.comp_build_job(id, s)?;
+            let p = s.get_param_id(id);
+            components.push(CompPlan {
+                name: job.name.clone(),
+                initargs: p.param_prog().clone(),
+                constants_header: job.target.constants_header.clone(),
+                deps_cmd: job.dep_cmd.to_string(),
+                rebuild_cmd: self
+                    .backend
+                    .rebuild_cmd(&job.target, &"<output of dependencies>".to_string())
+                    .to_string(),
+                build_cmd: job.cmd.to_string(),
+                initfs: match p.param_fs() {
+                    Some(t) => Some((t.clone(), initfs_contents(id, s)?)),
+                    None => None,
+                },
+            });
+        }
+
+        let mut constructors = Vec::new();
+        let mut sys_constructor = None;
+        for id in ids.iter().rev() {
+            if !s
+                .get_properties()
+                .service_is_a(id, ServiceType::Constructor)
+            {
+                continue;
+            }
+            let (tar_path, tar_files) = constructor_tarball_contents(id, s, self)?;
+            let argsfile = self.comp_file_path(id, &"initargs_constructor.c".to_string(), s)?;
+            let header_file_path =
+                self.comp_file_path(id, &"component_constants.h".to_string(), s)?;
+            let tarfile = if tar_files.is_empty() {
+                None
+            } else {
+                Some(tar_path.clone())
+            };
+            let cmd = self.backend.build_cmd(&self.comp_target(
+                id,
+                s,
+                &self.comp_obj_path(id, s)?,
+                &argsfile,
+                &tarfile,
+                &header_file_path,
+                &comp_libs(&self.srcdir, id, s)?,
+                None,
+            ));
+
+            constructors.push(ConstructorPlan {
+                name: s.get_named().ids().get(id).unwrap().to_string(),
+                initargs: argsfile,
+                tarball: tarfile.map(|t| (t, tar_files)),
+                build_cmd: cmd.to_string(),
+            });
+            if component(s, id).constructor.var_name == "kernel" {
+                sys_constructor = Some(self.comp_obj_path(id, s)?);
+            }
+        }
+
+        // The kernel is linked with a copy of the system constructor,
+        // or with its stripped object.
+        let constructor = match sys_constructor {
+            Some(ref c) if self.strip => stripped_path(c),
+            _ => self.file_path(&"constructor".to_string())?,
+        };
+        let kernel = match sys_constructor {
+            Some(c) => Some(KernelPlan {
+                build_cmd: self
+                    .backend
+                    .kernel_cmd(
+                        &self.srcdir.display().to_string(),
+                        s.get_spec().arch(),
+                        &constructor,
+                        &self.file_path(&"cos.img".to_string())?,
+                    )
+                    .to_string(),
+                constructor: c,
+            }),
+            None => None,
+        };
+
+        Ok(Plan {
+            spec: s.get_input(),
+            components,
+            constructors,
+            kernel,
+        })
+    }
+
+    // What the build of component `id` was resolved to, without
+    // building it.
+    pub fn explain(&self, id: &ComponentId, s: &SystemState) -> Result<Explanation, ComposeError> {
+        let job = self.comp_build_job(id, s)?;
+        let initargs = s.get_param_id(id).param_prog().clone();
+        let contents = String::from_utf8_lossy(&dump_file(&initargs)?).to_string();
+        let t = &job.target;
+
+        Ok(Explanation {
+            name: job.name.clone(),
+            source: component(s, id).source.clone(),
+            exports: t.exports.clone(),
+            deps: deps(s, id)
+                .iter()
+                .map(|d| {
+                    (
+                        d.server.to_string(),
+                        format!("{}/{}", d.interface, d.variant),
+                    )
+                })
+                .collect(),
+            libs: t.libs.clone(),
+            baseaddr: t.baseaddr,
+            initargs: (initargs, contents),
+            make_vars: job.cmd.vars(),
+            output: t.output.clone(),
+        })
+    }
 }
 
-pub struct DefaultBuilder {
+// Everything required to build a single component, independent of
+// the system state.
+struct CompBuildJob {
+    name: String,
+    obj_file: String,
+    output_path: String,
+    dep_cmd: Cmd,
+    cmd: Cmd,
+    log_path: String,
+    // Inputs that determine if a cached object can be reused
+    spec_digest: String,
+    toolchain: String,
+    impl_dir: PathBuf,
+    src_dirs: Vec<PathBuf>,
+    input_files: Vec<String>,
+    cachedir: PathBuf,
+    records: Option<String>, // the directory of its recorded compilations, if they're recorded
     builddir: String,
+    target: CompTarget,
+    backend: Arc<dyn ComponentBuilder>,
+    use_cached: bool,
+    timeout: Option<Duration>,
+    exec: Arc<dyn Executor>,
+    dir_locks: Arc<DirLocks>,
 }
 
-impl DefaultBuilder {
-    pub fn new() -> Self {
-        DefaultBuilder {
-            builddir: "/dev/null".to_string(), // must initialize, so error out if you don't
+// The source directories that components' builds modify: make
+// builds each component within its implementation's directory, and
+// rebuilds the libraries and interfaces it uses, in theirs, with its
+// constants. Concurrent builds that share any of them take turns.
+#[derive(Default)]
+struct DirLocks {
+    held: Mutex<Vec<PathBuf>>,
+    released: Condvar,
+}
+
+// The directories held by a build, until it's dropped.
+struct DirsHeld<'a> {
+    locks: &'a DirLocks,
+    dirs: Vec<PathBuf>,
+}
+
+impl DirLocks {
+    // Wait until none of `dirs` are held, then hold them all. As
+    // they're all taken at once, builds can't deadlock.
+    fn lock(&self, dirs: Vec<PathBuf>) -> DirsHeld<'_> {
+        let mut held = self.held.lock().unwrap();
+        while dirs.iter().any(|d| held.contains(d)) {
+            held = self.released.wait(held).unwrap();
         }
+        held.extend(dirs.iter().cloned());
+
+        DirsHeld { locks: self, dirs }
+    }
+}
+
+impl Drop for DirsHeld<'_> {
+    fn drop(&mut self) {
+        let mut held = self.locks.held.lock().unwrap();
+        held.retain(|d| !self.dirs.contains(d));
+        self.locks.released.notify_all();
     }
 }
 
-fn compdir_check_build(comp_dir: &String) -> Result<(), String> {
-    if !dir_exists(&comp_dir) {
-        reset_dir(&comp_dir)?;
+struct CompBuildResult {
+    output_path: String,
+    log_path: String,
+    warnings: bool,                         // did the build output errors/warnings...
+    failure: Option<(Option<i32>, String)>, // ...or actually fail (with status and stderr)?
+    timed_out: Option<u64>,                 // ...or get killed after the timeout (in seconds)?
+    cached: bool,
+    log_err: Option<ComposeError>,
+}
+
+impl CompBuildJob {
+    // Hash all of the inputs to the component's build: the spec's
+    // configuration of it, the compiler and Makefiles, the generated
+    // files passed to make, and the sources of the implementation,
+    // interfaces, shared headers, and the libraries (`lib_dirs`,
+    // from the dependency information). `None` if any input cannot
+    // be read.
+    fn inputs_hash(&self, lib_dirs: &String) -> Option<String> {
+        let mut h = hash_bytes(None, self.spec_digest.as_bytes());
+        h = hash_bytes(Some(h), self.toolchain.as_bytes());
+        for f in &self.input_files {
+            h = hash_bytes(Some(h), &dump_file(f).ok()?);
+        }
+        for d in self
+            .src_dirs
+            .iter()
+            .cloned()
+            .chain(lib_dirs.split_whitespace().map(PathBuf::from))
+        {
+            h = hash_dir_sources(h, &d.display().to_string()).ok()?;
+        }
+
+        Some(format!("{:016x}", h))
+    }
+
+    fn cache_path(&self, hash: &String) -> PathBuf {
+        self.cachedir.join(hash).join(&self.obj_file)
+    }
+
+    // Restore the recorded compilations of the `cached` object, if
+    // they're recorded, returning false if they weren't cached with
+    // it.
+    fn cached_records(&self, cached: &Path) -> bool {
+        match self.records {
+            Some(ref r) => records_restore(&records_cache_path(cached), r, &self.builddir).is_ok(),
+            None => true,
+        }
+    }
+
+    // The time left to build before the timeout, if there is one.
+    fn remaining(&self, start: &Instant) -> Option<Duration> {
+        self.timeout.map(|t| {
+            t.checked_sub(start.elapsed())
+                .unwrap_or(Duration::from_secs(0))
+        })
+    }
+
+    // Execute `cmd` with the time left before the timeout, returning
+    // None if it was killed.
+    fn make(&self, cmd: &Cmd, start: &Instant) -> Option<(String, String, ExitStatus)> {
+        let a = Action::Make {
+            name: self.name.clone(),
+            cmd: cmd.clone(),
+            log: self.log_path.clone(),
+        };
+        match self.exec.execute(&a, self.remaining(start)) {
+            Ok(Outcome::Made(out, err, status)) => Some((out, err, status)),
+            _ => None,
+        }
+    }
+
+    fn log(&self, contents: String) -> Option<ComposeError> {
+        trace!("{}: {}", self.name, contents);
+        let a = Action::Write {
+            path: self.log_path.clone(),
+            contents: contents.into_bytes(),
+        };
+        self.exec.execute(&a, None).err()
+    }
+
+    // The result of the build when it was killed, executing `cmd`,
+    // after the timeout.
+    fn timed_out(&self, cmd: &Cmd) -> CompBuildResult {
+        // unwrap as only builds with a timeout time out
+        let secs = self.timeout.unwrap().as_secs();
+        let log_err = self.log(format!(
+            "Command: {}\nKilled after exceeding the build timeout of {} seconds.",
+            cmd, secs
+        ));
+
+        CompBuildResult {
+            output_path: self.output_path.clone(),
+            log_path: self.log_path.clone(),
+            warnings: false,
+            failure: None,
+            timed_out: Some(secs),
+            cached: false,
+            log_err,
+        }
     }
-    assert!(dir_exists(&comp_dir));
+
+    fn run(&self) -> CompBuildResult {
+        let start = Instant::now();
+        //rebuild process starts
+        let (out1, err1, status1) = match self.make(&self.dep_cmd, &start) {
+            Some(r) => r,
+            None => return self.timed_out(&self.dep_cmd),
+        };
+
+        let hash = self.inputs_hash(&out1);
+        if let Some(ref h) = hash {
+            let cached = self.cache_path(h);
+            if self.use_cached
+                && self.cached_records(&cached)
+                && fs::copy(&cached, &self.output_path).is_ok()
+            {
+                let log_err = self.log(format!(
+                    "Inputs unchanged (hash {}): reused cached object {}\nCommand (not executed): {}",
+                    h,
+                    cached.display(),
+                    self.cmd
+                ));
+
+                return CompBuildResult {
+                    output_path: self.output_path.clone(),
+                    log_path: self.log_path.clone(),
+                    warnings: false,
+                    failure: None,
+                    timed_out: None,
+                    cached: true,
+                    log_err,
+                };
+            }
+        }
+
+        // The implementation's directory, and those of the
+        // libraries and interfaces that are rebuilt (`out1`), are
+        // shared with other components' builds. Waiting on them
+        // doesn't count against the timeout.
+        let dirs = std::iter::once(self.impl_dir.clone())
+            .chain(out1.split_whitespace().map(PathBuf::from))
+            .collect();
+        let _held = self.dir_locks.lock(dirs);
+        let start = Instant::now();
+        let rebuild_cmd = self.backend.rebuild_cmd(&self.target, &out1);
+        let (out2, err2, status2) = match self.make(&rebuild_cmd, &start) {
+            Some(r) => r,
+            None => return self.timed_out(&rebuild_cmd),
+        };
+        //rebuild process ends
+        let (out3, err3, status3) = match self.make(&self.cmd, &start) {
+            Some(r) => r,
+            None => return self.timed_out(&self.cmd),
+        };
+        let log_err = self.log(format!(
+            "Dep Command: {}\nCompilation output:{}\ncompilation errors:{}\n
+                 Rebuild Command: {}\nCompilation output:{}\nComponent compilation errors:{}\n
+                 Command: {}\nCompilation output:{}\nComponent compilation errors:{}",
+            self.dep_cmd, out1, err1, rebuild_cmd, out2, err2, self.cmd, out3, err3
+        ));
+        let warnings = !err1.is_empty() || !err2.is_empty() || !err3.is_empty();
+        let failure = vec![(status1, err1), (status2, err2), (status3, err3)]
+            .into_iter()
+            .find(|(status, _)| !status.success())
+            .map(|(status, err)| (status.code(), err));
+
+        // Only cache successful builds. Failing to populate the
+        // cache only costs a future rebuild, so ignore errors.
+        if let (true, Some(ref h)) = (failure.is_none() && !warnings, &hash) {
+            let cached = self.cache_path(h);
+            if fs::create_dir_all(self.cachedir.join(h)).is_ok() {
+                let _ = fs::copy(&self.output_path, &cached);
+                if let Some(ref r) = self.records {
+                    let _ = records_cache(r, &records_cache_path(&cached), &self.builddir);
+                }
+            }
+        }
+
+        CompBuildResult {
+            output_path: self.output_path.clone(),
+            log_path: self.log_path.clone(),
+            warnings,
+            failure,
+            timed_out: None,
+            cached: false,
+            log_err,
+        }
+    }
+}
+
+impl CompBuildResult {
+    fn report(&self, name: &String) -> Result<(), ComposeError> {
+        if let Some(ref e) = self.log_err {
+            return Err(e.clone());
+        }
+        if let Some(secs) = self.timed_out {
+            return Err(ComposeError::Timeout {
+                name: name.clone(),
+                secs,
+                log: self.log_path.clone(),
+            });
+        }
+        if let Some((status, ref stderr)) = self.failure {
+            return Err(ComposeError::Make {
+                name: name.clone(),
+                status,
+                stderr: stderr.clone(),
+                log: self.log_path.clone(),
+            });
+        }
+        if self.warnings {
+            warn!(
+                "Component {} compiled with warnings. See {}.",
+                &self.output_path, self.log_path
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn current_dir() -> Result<PathBuf, ComposeError> {
+    env::current_dir().map_err(|e| ComposeError::Io {
+        path: String::from("."),
+        msg: format!("Could not determine the current directory: {}", e),
+    })
+}
+
+fn compdir_check_build(comp_dir: &String) -> Result<(), ComposeError> {
+    if !dir_exists(comp_dir) {
+        reset_dir(comp_dir)?;
+    }
+    assert!(dir_exists(comp_dir));
 
     Ok(())
 }
 
+// The build configuration that every component's compilation
+// depends on, outside of its sources: the compiler's identity (its
+// version and configuration, as `gcc -v` reports them), and the
+// Makefiles that define the flags (including those that `make
+// config-*` generates, if it has been run).
+fn toolchain_digest(srcdir: &Path, arch: Arch) -> String {
+    let cc = format!("{}gcc", arch.cross_compile());
+    let mut digest = match Command::new(&cc).arg("-v").output() {
+        Ok(out) => String::from_utf8_lossy(&out.stderr).to_string(),
+        Err(e) => format!("{}: {}", cc, e),
+    };
+    for f in [
+        "Makefile.src",
+        "Makefile.cosconfig",
+        "Makefile.arch",
+        "components/Makefile.comp",
+        "components/lib/Makefile.lib",
+    ]
+    .iter()
+    {
+        if let Ok(contents) = fs::read_to_string(srcdir.join(f)) {
+            digest.push_str(&format!("\n{}:\n{}", f, contents));
+        }
+    }
+
+    digest
+}
+
 impl BuildState for DefaultBuilder {
-    fn initialize(&mut self, name: &String, _s: &SystemState) -> Result<(), String> {
-        let pwd = env::current_dir().unwrap();
-        let dir = format!("{}/system_binaries/cos_build-{}", pwd.display(), name);
+    fn initialize(&mut self, name: &String, s: &SystemState) -> Result<(), ComposeError> {
+        self.profile = self.profile.or(s.get_spec().profile());
+        self.sign_key = self.sign_key.take().or(s.get_spec().sign_key().clone());
+        // Load the key now, so that a bad one fails the build early.
+        if let Some(ref k) = self.sign_key {
+            self.public_key = Some(public_key_hex(&signing_key_load(k)?));
+        }
+        self.compiler_cache = self.compiler_cache.or(s.get_spec().compiler_cache());
+        if let (Some(c), false) = (self.compiler_cache, self.dry_run) {
+            self.cache_stats = cache_stats(c);
+        }
+        let pwd = current_dir()?;
+        // The paths must be absolute as make runs in the source tree.
+        let dir = self.build_dir_path(name, s, &pwd);
+        // The cache is shared by the builds alongside this one.
+        let cachedir = dir.parent().unwrap_or(&pwd).join("cos_build_cache");
+
+        let srcdir = self.src_dir(&pwd)?;
+        // make's arguments, and the paths the builder returns, are
+        // Strings.
+        for p in [&dir, &cachedir, &srcdir].iter() {
+            if p.to_str().is_none() {
+                return Err(ComposeError::Io {
+                    path: p.display().to_string(),
+                    msg: String::from("Not valid UTF-8, so cannot be passed to the build"),
+                });
+            }
+        }
+        self.srcdir = srcdir;
+        self.toolchain = toolchain_digest(&self.srcdir, s.get_spec().arch());
+
+        let prov = provenance(&self.srcdir, &s.get_input())?;
+        // A dry run doesn't create an image.
+        if self.require_clean && !self.dry_run {
+            if prov.commit.is_none() {
+                return Err(ComposeError::Io {
+                    path: self.srcdir.display().to_string(),
+                    msg: String::from(
+                        "Not in a git repository, so --require-clean cannot check it for changes",
+                    ),
+                });
+            }
+            if prov.dirty {
+                return Err(ComposeError::Io {
+                    path: self.srcdir.display().to_string(),
+                    msg: String::from("The source tree has uncommitted changes (see git status), and --require-clean was given"),
+                });
+            }
+        }
+        self.provenance = Some(prov);
+
+        if let Err(e) = fs::create_dir_all(&cachedir) {
+            return Err(ComposeError::Io {
+                path: cachedir.display().to_string(),
+                msg: format!("Could not create the build cache directory: {}", e),
+            });
+        }
+        self.cachedir = cachedir;
 
-        reset_dir(&dir)?;
+        // A dry run doesn't run make, so doesn't need the lock.
+        if !self.dry_run {
+            self.lock = Some(src_lock(&self.srcdir, &dir, self.wait)?);
+        }
+        // A dry run shouldn't wipe out a previous build of the same
+        // name; only the generated initargs and headers are written.
+        if !self.dry_run || !dir.is_dir() {
+            build_dir_reset(&dir)?;
+        }
         self.builddir = dir;
+        // make's CC records the compilations that are executed.
+        if self.compile_commands && !self.dry_run {
+            self.recorder = Some(recorder_create(&self.builddir)?);
+        }
 
         Ok(())
     }
 
-    fn file_path(&self, file: &String) -> Result<String, String> {
-        Ok(format!("{}/{}", self.builddir, file))
+    fn file_path(&self, file: &String) -> Result<String, ComposeError> {
+        Ok(self.builddir.join(file).display().to_string())
     }
 
-    fn comp_dir_path(&self, c: &ComponentId, state: &SystemState) -> Result<String, String> {
+    fn comp_dir_path(&self, c: &ComponentId, state: &SystemState) -> Result<String, ComposeError> {
         let name = state.get_named().ids().get(c).unwrap();
-        Ok(self.file_path(&format!("{}.{}", name.scope_name, name.var_name))?)
+        self.file_path(&format!("{}.{}", name.scope_name, name.var_name))
     }
 
     fn comp_file_path(
@@ -370,20 +1975,20 @@ impl BuildState for DefaultBuilder {
         c: &ComponentId,
         file: &String,
         state: &SystemState,
-    ) -> Result<String, String> {
-        let comp_dir = self.comp_dir_path(&c, &state)?;
+    ) -> Result<String, ComposeError> {
+        let comp_dir = self.comp_dir_path(c, state)?;
         compdir_check_build(&comp_dir)?;
 
-        Ok(format!("{}/{}", comp_dir, file))
+        Ok(Path::new(&comp_dir).join(file).display().to_string())
     }
 
     fn comp_obj_file(&self, c: &ComponentId, s: &SystemState) -> String {
-        let comp = component(&s, &c);
+        let comp = component(s, c);
         format!("{}.{}", &comp.source, &comp.name)
     }
 
-    fn comp_obj_path(&self, c: &ComponentId, s: &SystemState) -> Result<String, String> {
-        self.comp_file_path(&c, &self.comp_obj_file(&c, &s), &s)
+    fn comp_obj_path(&self, c: &ComponentId, s: &SystemState) -> Result<String, ComposeError> {
+        self.comp_file_path(c, &self.comp_obj_file(c, s), s)
     }
 
     fn comp_const_header_file(
@@ -391,15 +1996,15 @@ impl BuildState for DefaultBuilder {
         header_file_path: &String,
         id: &ComponentId,
         s: &SystemState,
-    ) -> Result<(), String> {
-        let c = component(&s, id);
-
-        if std::path::Path::new(header_file_path).exists() {
-            std::fs::remove_file(header_file_path).unwrap();
-        }
-
+    ) -> Result<(), ComposeError> {
+        let c = component(s, id);
         let mut header_content =
             String::from("#ifndef COMPONENT_CONSTANTS_H\n#define COMPONENT_CONSTANTS_H\n\n");
+        // The parameters' keys (see args_header_create).
+        header_content.push_str(&format!(
+            "#include \"{}\"\n",
+            s.get_param_id(id).param_header()
+        ));
 
         for constant in &c.constants {
             header_content.push_str(&format!(
@@ -407,122 +2012,216 @@ impl BuildState for DefaultBuilder {
                 constant.variable, constant.value
             ));
         }
+        // The ids of the system's components (e.g. COMPID_PING), as in
+        // the constructor's initargs, so that the component can refer
+        // to (and its logs be correlated with) the others.
+        header_content.push('\n');
+        for (cid, cname) in s.get_named().ids().iter() {
+            header_content.push_str(&format!(
+                "#define COMPID_{} {}\n",
+                cname
+                    .var_name
+                    .to_ascii_uppercase()
+                    .replace(|ch: char| !ch.is_ascii_alphanumeric(), "_"),
+                cid
+            ));
+        }
 
         header_content.push_str("\n#endif /* COMPONENT_CONSTANTS_H */\n");
 
-        emit_file(&header_file_path, header_content.as_bytes()).unwrap();
+        self.exec.execute(
+            &Action::Write {
+                path: header_file_path.clone(),
+                contents: header_content.into_bytes(),
+            },
+            None,
+        )?;
 
         Ok(())
     }
 
-    fn comp_build(&self, id: &ComponentId, state: &SystemState) -> Result<String, String> {
-        let comp_dir = self.comp_dir_path(&id, &state)?;
-        compdir_check_build(&comp_dir)?;
-        let p = state.get_param_id(&id);
-        let output_path = self.comp_obj_path(&id, &state)?;
-
-        //rebuild process starts
-        let header_file_path =
-            self.comp_file_path(&id, &"component_constants.h".to_string(), &state)?;
-        self.comp_const_header_file(&header_file_path, &id, &state)?;
-
-        let dep_cmd = comp_gen_make_cmd(
-            &output_path,
-            p.param_prog(),
-            p.param_fs(),
-            &header_file_path,
-            CmdOpts::DEPINFO,
-            &id,
-            &state,
-        );
-        let (out1, err1) = exec_pipeline(vec![dep_cmd.clone()]);
-
-        let rebuild_cmd = format!(
-            r#"make -C src REBUILD_DIRS="{}" COMP_CONST_H="-include {}" component_rebuild"#,
-            out1, header_file_path
-        );
-        let (out2, err2) = exec_pipeline(vec![rebuild_cmd.clone()]);
-        //rebuild process ends
-        let cmd = comp_gen_make_cmd(
-            &output_path,
-            p.param_prog(),
-            p.param_fs(),
-            &header_file_path,
-            CmdOpts::REGULAR,
-            &id,
-            &state,
-        );
-        let name = state.get_named().ids().get(id).unwrap();
-        println!(
-            "Compiling component {} with the following command line:\n\t{}",
-            name, cmd
-        );
-        let (out3, err3) = exec_pipeline(vec![cmd.clone()]);
-        let comp_log = self.comp_file_path(&id, &"compilation.log".to_string(), &state)?;
-        emit_file(
-            &comp_log,
-            format!(
-                "Dep Command: {}\nCompilation output:{}\ncompilation errors:{}\n
-                 Rebuild Command: {}\nCompilation output:{}\nComponent compilation errors:{}\n
-                 Command: {}\nCompilation output:{}\nComponent compilation errors:{}",
-                dep_cmd, out1, err1, rebuild_cmd, out2, err2, cmd, out3, err3
-            )
-            .as_bytes(),
-        )?;
-        if err1.len() != 0 || err2.len() != 0 || err3.len() != 0 {
-            println!(
-                "Errors in compiling component {}. See {}.",
-                &output_path, comp_log
-            );
+    fn comp_build(&self, id: &ComponentId, state: &SystemState) -> Result<String, ComposeError> {
+        // Already built as part of a batch?
+        if let Some(p) = self.built.get(id) {
+            return Ok(p.clone());
         }
 
-        Ok(output_path)
+        let job = self.comp_build_job(id, state)?;
+        info!("Compiling component {}.", job.name);
+        debug!("\t{}", job.cmd);
+        let start = self.timings.now();
+        let res = job.run();
+        self.timings.record(Step {
+            name: job.name.clone(),
+            kind: StepKind::Component,
+            lane: 1,
+            start,
+            end: self.timings.now(),
+            cached: res.cached,
+        });
+        res.report(&job.name)?;
+
+        Ok(job.output_path)
     }
 
-    fn constructor_build(&self, c: &ComponentId, s: &SystemState) -> Result<String, String> {
-        let comp_dir = self.comp_dir_path(&c, &s)?;
-        compdir_check_build(&comp_dir)?;
+    fn comps_build(
+        &mut self,
+        ids: &Vec<ComponentId>,
+        s: &SystemState,
+    ) -> Result<(), Vec<ComposeError>> {
+        let mut jobs = Vec::new();
+        for id in ids.iter() {
+            // Wait on the servers we depend on, and on our
+            // constructor, so that shared interface and library
+            // objects are built before their clients need them. The
+            // builds that modify the same source directories also
+            // take turns (see DirLocks).
+            let c = component(s, id);
+            let mut waits: Vec<ComponentId> = deps(s, id)
+                .iter()
+                .filter_map(|d| s.get_named().rmap().get(&d.server))
+                .cloned()
+                .collect();
+            if let Some(cons) = s.get_named().rmap().get(&c.constructor) {
+                waits.push(*cons);
+            }
+            waits.retain(|w| ids.contains(w));
 
-        let binary = self.comp_obj_path(&c, &s)?;
-        let argsfile = constructor_serialize_args(&c, &s, self)?;
-        let tarfile = constructor_tarball_create(&c, &s, self)?;
+            let job = self.comp_build_job(id, s).map_err(|e| vec![e])?;
+            jobs.push((*id, job, waits));
+        }
 
-        let header_file_path = self.comp_file_path(&c, &"component_constants.h".to_string(), &s)?;
+        // Build in the total order of the components (lower ids are
+        // depended on by higher ones), which is topological over
+        // their dependencies.
+        jobs.sort_by_key(|(id, _, _)| *id);
 
-        let cmd = comp_gen_make_cmd(
-            &binary,
-            &argsfile,
-            &tarfile,
-            &header_file_path,
-            CmdOpts::REGULAR,
-            &c,
-            &s,
-        );
+        let total = jobs.len();
+        // The components built, and those that failed (or were
+        // skipped), with the component whose build failed.
+        let mut done: Vec<ComponentId> = Vec::new();
+        let mut failed: Vec<(ComponentId, String)> = Vec::new();
+        let mut finished = 0;
+        let mut errors = Vec::new();
+        let mut running = 0;
+        // The jobs building components, for their builds' timing.
+        let mut lanes = vec![false; self.jobs];
+        let epoch = self.timings.epoch();
+        let (tx, rx) = mpsc::channel();
 
-        let name = s.get_named().ids().get(c).unwrap();
-        println!(
-            "Compiling component {}.{} with the following command line:\n\t{}",
-            name.scope_name, name.var_name, cmd
-        );
+        loop {
+            // The components that depend on one that failed can't be
+            // built, so they're skipped, as are those depending on
+            // them.
+            while let Some((idx, cause)) = jobs
+                .iter()
+                .enumerate()
+                .filter(|_| self.keep_going)
+                .find_map(|(idx, (_, _, waits))| {
+                    failed
+                        .iter()
+                        .find(|(f, _)| waits.contains(f))
+                        .map(|(_, cause)| (idx, cause.clone()))
+                })
+            {
+                let (id, job, _) = jobs.remove(idx);
+                finished += 1;
+                let e = ComposeError::Skipped {
+                    name: job.name,
+                    failed: cause.clone(),
+                };
+                error!("[{}/{}] {}", finished, total, e);
+                errors.push(e);
+                failed.push((id, cause));
+            }
+            // Launch all jobs whose dependencies have completed, up
+            // to the job limit. After a failure, only keep going if
+            // asked to.
+            while running < self.jobs && (errors.is_empty() || self.keep_going) && !interrupted() {
+                let ready = jobs
+                    .iter()
+                    .position(|(_, _, waits)| waits.iter().all(|w| done.contains(w)));
+                let (id, job, _) = match ready {
+                    Some(idx) => jobs.remove(idx),
+                    None => break,
+                };
+                info!("Compiling component {}.", job.name);
+                debug!("\t{}", job.cmd);
+                let tx = tx.clone();
+                running += 1;
+                // One is free, as fewer than the limit are running.
+                let lane = lanes.iter().position(|busy| !busy).unwrap();
+                lanes[lane] = true;
+                thread::spawn(move || {
+                    let start = epoch.elapsed();
+                    let res = job.run();
+                    let time = (lane, start, epoch.elapsed());
+                    // The receiver lives until all jobs complete.
+                    tx.send((id, job, res, time)).unwrap();
+                });
+            }
+            if running == 0 {
+                break;
+            }
+            // Each component's output is in its own log, so only
+            // the (atomic) summaries are printed here.
+            let (id, job, res, (lane, start, end)) = rx.recv().unwrap();
+            running -= 1;
+            finished += 1;
+            lanes[lane] = false;
+            self.timings.record(Step {
+                name: job.name.clone(),
+                kind: StepKind::Component,
+                lane: lane + 1,
+                start,
+                end,
+                cached: res.cached,
+            });
+            match res.report(&job.name) {
+                Ok(()) => {
+                    info!(
+                        "[{}/{}] Compiled component {}{}.",
+                        finished,
+                        total,
+                        job.name,
+                        if res.cached { " (cached)" } else { "" }
+                    );
+                    done.push(id);
+                    self.built.insert(id, job.output_path);
+                }
+                Err(e) => {
+                    error!("[{}/{}] {}", finished, total, e);
+                    errors.push(e);
+                    failed.push((id, job.name));
+                }
+            }
+        }
 
-        let (out, err) = exec_pipeline(vec![cmd.clone()]);
-        let comp_log = self.comp_file_path(&c, &"constructor_compilation.log".to_string(), &s)?;
-        emit_file(
-            &comp_log,
-            format!(
-                "Command: {}\nConstructor compilation output:{}\nComponent compilation errors:{}",
-                cmd, out, err
-            )
-            .as_bytes(),
-        )?;
-        if err.len() != 0 {
-            println!(
-                "Errors in compiling component {}. See {}.",
-                &binary, comp_log
-            )
+        if errors.is_empty() && !jobs.is_empty() && interrupted() {
+            errors.push(ComposeError::Interrupted {
+                dir: self.builddir.display().to_string(),
+            });
+        }
+        if errors.is_empty() && !jobs.is_empty() {
+            errors.push(ComposeError::Pass(format!(
+                "Error: Components {:?} cannot be built as they wait on each other's builds.",
+                jobs.iter()
+                    .map(|(_, j, _)| j.name.clone())
+                    .collect::<Vec<_>>()
+            )));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
         }
 
-        Ok(binary.clone())
+        Ok(())
+    }
+
+    fn constructor_build(&self, c: &ComponentId, s: &SystemState) -> Result<String, ComposeError> {
+        self.execute(&self.strip_actions(c, s)?)?;
+        self.execute(&self.constructor_actions(c, s)?)?;
+
+        self.comp_obj_path(c, s)
     }
 
     fn kernel_build(
@@ -530,27 +2229,7 @@ impl BuildState for DefaultBuilder {
         kern_output: &String,
         constructor_input: &String,
         s: &SystemState,
-    ) -> Result<(), String> {
-        let cmd = kern_gen_make_cmd(&constructor_input, &kern_output, &s);
-        println!(
-            "Compiling the kernel the following command line:\n\t{}",
-            cmd
-        );
-
-        let (out, err) = exec_pipeline(vec![cmd.clone()]);
-        let comp_log = self.file_path(&"kernel_compilation.log".to_string())?;
-        emit_file(
-            &comp_log,
-            format!(
-                "Command: {}\nKernel compilation output:{}\nKernel compilation errors:{}",
-                cmd, out, err
-            )
-            .as_bytes(),
-        )?;
-        if err.len() != 0 {
-            println!("Errors in compiling kernel. See {}.", comp_log)
-        }
-
-        Ok(())
+    ) -> Result<(), ComposeError> {
+        self.execute(&self.kernel_actions(kern_output, constructor_input, s)?)
     }
 }
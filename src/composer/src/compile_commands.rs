@@ -0,0 +1,203 @@
+// Recording the components' compilations (--compile-commands), to
+// generate compile_commands.json, the compilation database that
+// clangd (and other tools) read to know how each source is compiled:
+// with which interfaces' and libraries' includes, the component's
+// constants, and the flags that the profile and specification give
+// make. Rather than reproducing the Makefiles' flags, make's CC (and
+// CXX) is a recorder that writes each compilation's directory and
+// arguments into the component's directory, then executes it, so
+// the database has exactly what was compiled.
+//
+// A component whose cached object is reused isn't compiled, so its
+// recorded compilations are cached alongside the object, and it's
+// only reused with them (otherwise it's compiled, and recorded).
+
+use manifest::Json;
+use passes::{BuildState, CompilerCache, ComponentId, ComposeError, SystemState};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use syshelpers::emit_file;
+
+const RECORDER: &str = "cc_record.sh";
+// Each compilation is recorded in a file of its own (as compilations
+// run concurrently), its directory and arguments NUL-terminated, as
+// the arguments can hold anything else.
+const RECORDER_SCRIPT: &str = "#!/bin/sh\n\
+# Generated by the composer: record the compilation (for compile_commands.json), then execute it.\n\
+dir=$1\n\
+shift\n\
+f=$(mktemp \"$dir/cc.XXXXXX\") && printf '%s\\0' \"$PWD\" \"$@\" > \"$f\"\n\
+exec \"$@\"\n";
+const RECORDS_DIR: &str = "compilations";
+// The build directory, in the cached records, as it differs between
+// the builds sharing the cache.
+const BUILD_DIR_VAR: &str = "${BUILD_DIR}";
+
+const SOURCE_EXTS: [&str; 6] = ["c", "S", "s", "cc", "cpp", "cxx"];
+// The options whose value is the following argument.
+const VALUE_OPTS: [&str; 12] = [
+    "-o", "-include", "-imacros", "-isystem", "-iquote", "-I", "-D", "-U", "-MF", "-MT", "-MQ",
+    "-x",
+];
+
+// Write the recorder into the build directory `builddir`, and return
+// its path.
+pub fn recorder_create(builddir: &Path) -> Result<String, ComposeError> {
+    let path = builddir.join(RECORDER).display().to_string();
+    emit_file(&path, RECORDER_SCRIPT.as_bytes())?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).map_err(|e| {
+        ComposeError::Io {
+            path: path.clone(),
+            msg: format!(
+                "Could not make the compilations' recorder executable: {}",
+                e
+            ),
+        }
+    })?;
+
+    Ok(path)
+}
+
+// The directory of the component `id`'s recorded compilations.
+pub fn records_dir(
+    b: &dyn BuildState,
+    id: &ComponentId,
+    s: &SystemState,
+) -> Result<String, ComposeError> {
+    b.comp_file_path(id, &RECORDS_DIR.to_string(), s)
+}
+
+// The cached records of the component's object `obj` (in the cache's
+// directory for its inputs).
+pub fn records_cache_path(obj: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.{}", obj.display(), RECORDS_DIR))
+}
+
+fn record_read(path: &Path) -> io::Result<Vec<String>> {
+    let rec = fs::read(path)?;
+    let mut fields: Vec<String> = rec
+        .split(|b| *b == 0)
+        .map(|f| String::from_utf8_lossy(f).to_string())
+        .collect();
+    // The last field is NUL-terminated as well.
+    fields.pop();
+
+    Ok(fields)
+}
+
+// Replace the records in `to` with those in `from`, with `old`
+// replaced by `new` in each of their fields.
+fn records_copy(from: &Path, to: &Path, old: &str, new: &str) -> io::Result<()> {
+    let recs = fs::read_dir(from)?;
+    match fs::remove_dir_all(to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    fs::create_dir_all(to)?;
+    for e in recs {
+        let e = e?;
+        let mut rec = Vec::new();
+        for f in record_read(&e.path())?.iter() {
+            rec.extend_from_slice(f.replace(old, new).as_bytes());
+            rec.push(0);
+        }
+        fs::write(to.join(e.file_name()), rec)?;
+    }
+
+    Ok(())
+}
+
+// Cache the `records` of a build in `builddir` at `cached`.
+pub fn records_cache(records: &String, cached: &Path, builddir: &str) -> io::Result<()> {
+    records_copy(Path::new(records), cached, builddir, BUILD_DIR_VAR)
+}
+
+// Restore the `cached` records into `records`, of the build in
+// `builddir`.
+pub fn records_restore(cached: &Path, records: &String, builddir: &str) -> io::Result<()> {
+    records_copy(cached, Path::new(records), BUILD_DIR_VAR, builddir)
+}
+
+// The source file that a recorded compilation (its directory, then
+// the compiler and its arguments) compiles, and its entry in the
+// database, if it compiles one (rather than, e.g., generating
+// dependencies, or linking). The compiler cache, if any, is dropped,
+// as the tools expect the compiler.
+fn command_entry(fields: Vec<String>) -> Option<(String, Json)> {
+    let mut fields = fields.into_iter();
+    let dir = fields.next()?;
+    let mut args: Vec<String> = fields.collect();
+    let caches = [CompilerCache::Ccache, CompilerCache::Sccache];
+    if args
+        .first()
+        .is_some_and(|a| caches.iter().any(|c| c.name() == a))
+    {
+        args.remove(0);
+    }
+    if !args.iter().any(|a| a == "-c") {
+        return None;
+    }
+    let file = (1..args.len())
+        .find(|i| {
+            let a = &args[*i];
+            !a.starts_with('-')
+                && !VALUE_OPTS.contains(&args[*i - 1].as_str())
+                && Path::new(a)
+                    .extension()
+                    .is_some_and(|e| SOURCE_EXTS.contains(&&*e.to_string_lossy()))
+        })
+        .map(|i| Path::new(&dir).join(&args[i]).display().to_string())?;
+
+    let entry = Json::obj(vec![
+        ("directory", Json::str(&dir)),
+        ("file", Json::str(&file)),
+        (
+            "arguments",
+            Json::Arr(args.iter().map(|a| Json::str(a)).collect()),
+        ),
+    ]);
+    Some((file, entry))
+}
+
+// Write compile_commands.json, of the components' recorded
+// compilations, into the build directory, and return its path. The
+// libraries' sources are compiled for each of the components using
+// them (with its constants), but the tools expect a compilation of
+// each source, so the first component's is kept.
+pub fn compile_commands_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+) -> Result<String, ComposeError> {
+    let mut files = BTreeSet::new();
+    let mut entries = Vec::new();
+    for id in s.get_named().ids().keys() {
+        let dir = records_dir(b, id, s)?;
+        let io_err = |e: io::Error| ComposeError::Io {
+            path: dir.clone(),
+            msg: format!(
+                "Could not read the component's recorded compilations: {}",
+                e
+            ),
+        };
+        let mut recs = Vec::new();
+        for e in fs::read_dir(&dir).map_err(io_err)? {
+            recs.push(e.map_err(io_err)?.path());
+        }
+        recs.sort();
+        for r in recs.iter() {
+            if let Some((file, entry)) = command_entry(record_read(r).map_err(io_err)?) {
+                if files.insert(file) {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+
+    let path = b.file_path(&"compile_commands.json".to_string())?;
+    emit_file(&path, format!("{}\n", Json::Arr(entries)).as_bytes())?;
+
+    Ok(path)
+}
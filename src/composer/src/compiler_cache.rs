@@ -0,0 +1,75 @@
+// Compiling the components through a compiler cache (ccache or
+// sccache): make's CC is the cache wrapping the architecture's
+// compiler, so that sources compiled by a previous build (of any
+// system) aren't recompiled. The cache's statistics are read before
+// and after the build to report its hits and misses, which include
+// those of any other builds using the cache at the same time.
+
+use manifest::Json;
+use passes::{Arch, CompilerCache};
+use std::process::Command;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// The compiler, as make's CC, for `arch`.
+pub fn cache_cc(c: CompilerCache, arch: Arch) -> String {
+    format!("{} {}gcc", c.name(), arch.cross_compile())
+}
+
+// The value of the statistic on the line starting with `key` (e.g.
+// "Cache hits" in "Cache hits     12").
+fn stat(out: &str, key: &str, sep: char) -> Option<u64> {
+    out.lines()
+        .filter_map(|l| l.strip_prefix(key))
+        .filter(|v| v.starts_with(sep))
+        .filter_map(|v| v.trim().parse().ok())
+        .next()
+}
+
+// The cache's statistics, if they can be read: ccache (4.0 and later)
+// prints them as tab-separated keys and values, and sccache as text.
+pub fn cache_stats(c: CompilerCache) -> Option<CacheStats> {
+    let args: &[&str] = match c {
+        CompilerCache::Ccache => &["--print-stats"],
+        CompilerCache::Sccache => &["--show-stats"],
+    };
+    let out = Command::new(c.name()).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let out = String::from_utf8_lossy(&out.stdout);
+
+    match c {
+        CompilerCache::Ccache => Some(CacheStats {
+            hits: stat(&out, "direct_cache_hit", '\t')?
+                + stat(&out, "preprocessed_cache_hit", '\t')?,
+            misses: stat(&out, "cache_miss", '\t')?,
+        }),
+        CompilerCache::Sccache => Some(CacheStats {
+            hits: stat(&out, "Cache hits", ' ')?,
+            misses: stat(&out, "Cache misses", ' ')?,
+        }),
+    }
+}
+
+impl CacheStats {
+    // The statistics since `before`, unless the cache was cleared.
+    pub fn since(&self, before: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits.saturating_sub(before.hits),
+            misses: self.misses.saturating_sub(before.misses),
+        }
+    }
+
+    pub fn to_json(&self, c: CompilerCache) -> Json {
+        Json::obj(vec![
+            ("cache", Json::str(c.name())),
+            ("hits", Json::Num(self.hits)),
+            ("misses", Json::Num(self.misses)),
+        ])
+    }
+}
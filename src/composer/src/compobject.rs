@@ -1,18 +1,20 @@
-use xmas_elf::sections::SectionData;
+use xmas_elf::program;
+use xmas_elf::sections::{SectionData, ShType, SHF_ALLOC};
 use xmas_elf::symbol_table::{Binding, Entry, Type};
 use xmas_elf::ElfFile;
 
+use address_assignment::ObjSize;
 use itertools::Itertools;
 use passes::{
-    component, BuildState, ClientSymb, CompSymbs, ComponentId, ComponentName, ConstructorPass,
-    ObjectsPass, ServerSymb, SystemState, Transition, TransitionIter,
+    component, Arch, BuildState, ClientSymb, CompSymbs, ComponentId, ComponentName, ComposeError,
+    ConstructorPass, ObjectsPass, ServerSymb, SystemState, Transition, TransitionIter,
 };
 use std::collections::HashMap;
 use symbols::{Symb, SymbType};
 use syshelpers::{dump_file, exec_pipeline};
 
 impl SymbType {
-    fn new<'a>(symb: &'a dyn Entry) -> Self {
+    fn new(symb: &dyn Entry) -> Self {
         match symb.get_binding() {
             Ok(Binding::Global) => match symb.get_type() {
                 Ok(Type::NoType) | Ok(Type::Object) => SymbType::GlobalData,
@@ -40,13 +42,15 @@ struct ServerSymbol {
 struct CompObject {
     dep_symbs: Vec<ClientSymbol>,
     exp_symbs: Vec<ServerSymbol>,
+    undef_symbs: Vec<String>,
     compinfo_symb: u64,
     entryfn_symb: u64,
 }
 
 impl CompObject {
-    pub fn parse(_name: &String, obj: &Vec<u8>) -> Result<CompObject, String> {
-        let elf_file = ElfFile::new(obj).unwrap();
+    pub fn parse(name: &String, obj: &Vec<u8>) -> Result<CompObject, String> {
+        let elf_file = ElfFile::new(obj)
+            .map_err(|e| format!("Cannot parse the object {} as an ELF file: {}", name, e))?;
         let symbs = symbs_retrieve(&elf_file)?;
 
         let compinfo = compinfo_addr(&symbs)?.addr();
@@ -59,11 +63,16 @@ impl CompObject {
         Ok(CompObject {
             dep_symbs: deps,
             exp_symbs: exps,
+            undef_symbs: undefined_symbs(&elf_file),
             compinfo_symb: compinfo,
             entryfn_symb: entryfn,
         })
     }
 
+    pub fn undefined(&self) -> &Vec<String> {
+        &self.undef_symbs
+    }
+
     pub fn exported(&self) -> &Vec<ServerSymbol> {
         &self.exp_symbs
     }
@@ -112,7 +121,7 @@ fn symbol_prefix_filter<'a>(
 ) -> Vec<Symb<'a>> {
     symb_filter(symbs)
         .iter()
-        .filter_map(|ref symb| {
+        .filter_map(|symb| {
             if symb.name().starts_with(prefix) {
                 Symb::new(&symb.name()[prefix.len()..], symb.addr(), symb.stype())
             } else {
@@ -146,11 +155,7 @@ fn unique_symbol<T>(symbs: &mut Vec<T>) -> Option<T> {
     if symbs.len() != 1 {
         return None;
     }
-    if let Some(s) = symbs.pop() {
-        Some(s)
-    } else {
-        None
-    }
+    symbs.pop()
 }
 
 fn compinfo_addr<'a>(symbs: &Vec<Symb<'a>>) -> Result<Symb<'a>, String> {
@@ -187,19 +192,22 @@ fn defcli_stub_addr<'a>(symbs: &Vec<Symb<'a>>) -> Result<Symb<'a>, String> {
 }
 
 fn symbs_retrieve<'a>(e: &ElfFile<'a>) -> Result<Vec<Symb<'a>>, String> {
-    match e.find_section_by_name(".symtab").unwrap().get_data(&e) {
+    let symtab = e
+        .find_section_by_name(".symtab")
+        .ok_or(String::from("The object has no symbol table."))?;
+    match symtab.get_data(e) {
         //Ok(SectionData::DynSymbolTable32(sts)) => section_symbols_print(e, sts),
-        Ok(SectionData::SymbolTable32(ref sts)) => Ok(sts
+        Ok(SectionData::SymbolTable32(sts)) => Ok(sts
             .iter()
-            .filter_map(|s| match s.get_name(&e) {
-                Ok(n) => Symb::new(n, symb_address(&e, s), SymbType::new(s)),
+            .filter_map(|s| match s.get_name(e) {
+                Ok(n) => Symb::new(n, symb_address(e, s), SymbType::new(s)),
                 _ => None,
             })
             .collect()),
-        Ok(SectionData::SymbolTable64(ref sts)) => Ok(sts
+        Ok(SectionData::SymbolTable64(sts)) => Ok(sts
             .iter()
-            .filter_map(|s| match s.get_name(&e) {
-                Ok(n) => Symb::new(n, symb_address(&e, s), SymbType::new(s)),
+            .filter_map(|s| match s.get_name(e) {
+                Ok(n) => Symb::new(n, symb_address(e, s), SymbType::new(s)),
                 _ => None,
             })
             .collect()),
@@ -207,6 +215,24 @@ fn symbs_retrieve<'a>(e: &ElfFile<'a>) -> Result<Vec<Symb<'a>>, String> {
     }
 }
 
+// The symbols that remain undefined in the linked object, which can
+// only be weak references (that are 0).
+fn undefined_symbs<'a>(e: &ElfFile<'a>) -> Vec<String> {
+    fn undef<'a, E: Entry>(e: &ElfFile<'a>, sts: &'a [E]) -> Vec<String> {
+        sts.iter()
+            .filter(|s| s.shndx() == 0) // SHN_UNDEF
+            .filter_map(|s| s.get_name(e).ok())
+            .filter(|n| !n.is_empty())
+            .map(|n| n.to_string())
+            .collect()
+    }
+    match e.find_section_by_name(".symtab").map(|s| s.get_data(e)) {
+        Some(Ok(SectionData::SymbolTable32(sts))) => undef(e, sts),
+        Some(Ok(SectionData::SymbolTable64(sts))) => undef(e, sts),
+        _ => Vec::new(),
+    }
+}
+
 fn compute_dependencies<'a>(symbs: &Vec<Symb<'a>>) -> Result<Vec<ClientSymbol>, String> {
     let defstub = defcli_stub_addr(symbs)?;
     let ucap_symbs = client_caps(symbs);
@@ -275,17 +301,200 @@ pub struct ElfObject {
     obj_path: String,
     client_symbs: HashMap<String, ClientSymb>,
     server_symbs: HashMap<String, ServerSymb>,
+    undef_symbs: Vec<String>,
     comp_symbs: CompSymbs,
 }
 
+// The sizes of the ranges of virtual addresses spanned by the
+// loadable segments of a component's object: all of them, its
+// read-only ones, and its writable ones.
+pub fn obj_sizes(obj_path: &String) -> Result<ObjSize, ComposeError> {
+    let obj = dump_file(obj_path)?;
+    let elf_file = ElfFile::new(&obj).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the object {} as an ELF file: {}",
+            obj_path, e
+        ))
+    })?;
+    let span = |write: Option<bool>| {
+        let (lo, hi) = elf_file
+            .program_iter()
+            .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+            .filter(|ph| write.is_none_or(|w| ph.flags().is_write() == w))
+            .fold((u64::MAX, 0), |(lo, hi), ph| {
+                (
+                    lo.min(ph.virtual_addr()),
+                    hi.max(ph.virtual_addr() + ph.mem_size()),
+                )
+            });
+        hi.saturating_sub(lo)
+    };
+
+    Ok(ObjSize {
+        span: span(None),
+        text: span(Some(false)),
+        data: span(Some(true)),
+    })
+}
+
+// Read the 16 bit field at `off` in the ELF object, in its byte
+// order (e_ident[EI_DATA]).
+fn elf_u16(obj: &Vec<u8>, off: usize) -> Option<u16> {
+    obj.get(off..off + 2).map(|f| match obj.get(5) {
+        Some(2) => u16::from_be_bytes([f[0], f[1]]),
+        _ => u16::from_le_bytes([f[0], f[1]]),
+    })
+}
+
+// Check that the object was built for the component's architecture
+// using the ELF header's class (e_ident[EI_CLASS]) and machine
+// (e_machine). A mismatch means that the wrong toolchain was used.
+fn obj_arch_check(obj_path: &String, obj: &Vec<u8>, arch: Arch) -> Result<(), ComposeError> {
+    let class = obj.get(4).cloned();
+    let machine = elf_u16(obj, 18);
+    let expected_class = if arch.is_64bit() { 2 } else { 1 };
+
+    if class != Some(expected_class) || machine != Some(arch.elf_machine()) {
+        return Err(ComposeError::Pass(format!(
+            "Error: The object {} is not a {} bit ELF object for {} (ELF class {:?}, machine {:#x?}); was it built with the wrong toolchain?",
+            obj_path,
+            if arch.is_64bit() { 64 } else { 32 },
+            arch.name(),
+            class,
+            machine
+        )));
+    }
+
+    Ok(())
+}
+
+const ET_EXEC: u16 = 2;
+
+// Check that the sealed object is one that its constructor can load
+// (rather than packing whatever make produced into the tarball): a
+// statically linked executable for the component's architecture,
+// that starts at __cosrt_upcall_entry, and whose .text is at the
+// base address it was assigned (COMP_BASEADDR).
+fn obj_validate(
+    obj_path: &String,
+    obj: &Vec<u8>,
+    arch: Arch,
+    baseaddr: u64,
+) -> Result<(), ComposeError> {
+    obj_arch_check(obj_path, obj, arch)?;
+    let err = |msg: String| ComposeError::Pass(format!("Error: The object {} {}", obj_path, msg));
+    let elf_file = ElfFile::new(obj).map_err(|e| err(format!("is not a valid ELF file: {}", e)))?;
+
+    let etype = elf_u16(obj, 16);
+    if etype != Some(ET_EXEC) {
+        return Err(err(format!(
+            "is not an executable (ELF type {:?}), so was not linked as a component.",
+            etype
+        )));
+    }
+    let dynamic = elf_file.program_iter().any(|ph| {
+        matches!(
+            ph.get_type(),
+            Ok(program::Type::Dynamic) | Ok(program::Type::Interp)
+        )
+    });
+    if dynamic {
+        return Err(err(String::from(
+            "is dynamically linked, but components must be statically linked.",
+        )));
+    }
+    // The constructor loads the segments as they are, so cannot
+    // apply relocations.
+    let relocs = elf_file.section_iter().find(|sh| {
+        let rel = matches!(sh.get_type(), Ok(ShType::Rel) | Ok(ShType::Rela));
+        rel && sh.flags() & SHF_ALLOC != 0
+    });
+    if let Some(sh) = relocs {
+        return Err(err(format!(
+            "has dynamic relocations (in {}), which its constructor cannot apply.",
+            sh.get_name(&elf_file).unwrap_or("an unnamed section")
+        )));
+    }
+
+    let symbs = symbs_retrieve(&elf_file).map_err(&err)?;
+    let entry = entry_addr(&symbs).map_err(&err)?.addr();
+    if elf_file.header.pt2.entry_point() != entry {
+        return Err(err(format!(
+            "has the entry point {:#x}, not __cosrt_upcall_entry ({:#x}).",
+            elf_file.header.pt2.entry_point(),
+            entry
+        )));
+    }
+    let text = elf_file
+        .find_section_by_name(".text")
+        .map(|sh| sh.address());
+    if text != Some(baseaddr) {
+        return Err(err(format!(
+            "has its .text at {:#x?}, not at its base address (COMP_BASEADDR) {:#x}; was it built with stale addresses?",
+            text, baseaddr
+        )));
+    }
+
+    Ok(())
+}
+
+// The magic number that starts the multiboot2 header, which must be
+// 8 byte aligned within the first 32KB of the image.
+const MULTIBOOT2_MAGIC: u32 = 0xe85250d6;
+const MULTIBOOT2_SEARCH: usize = 32768;
+
+// Check that the kernel image is bootable: that it is for the
+// system's architecture, includes the constructor (the initial
+// component), and that x86 images can be loaded by multiboot2
+// bootloaders (i.e. grub).
+fn image_check(img_path: &String, arch: Arch) -> Result<(), ComposeError> {
+    let img = dump_file(img_path)?;
+    obj_arch_check(img_path, &img, arch)?;
+    let elf_file = ElfFile::new(&img).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the system image {} as an ELF file: {}",
+            img_path, e
+        ))
+    })?;
+
+    let constructor_sz = elf_file
+        .find_section_by_name(".initial_component")
+        .map_or(0, |s| s.size());
+    if constructor_sz == 0 {
+        return Err(ComposeError::Pass(format!(
+            "Error: The system image {} does not include the constructor in its .initial_component section.",
+            img_path
+        )));
+    }
+
+    let x86 = arch == Arch::X86_64 || arch == Arch::I386;
+    let multiboot = img[..img.len().min(MULTIBOOT2_SEARCH)]
+        .chunks(8)
+        .any(|w| w.len() >= 4 && u32::from_le_bytes([w[0], w[1], w[2], w[3]]) == MULTIBOOT2_MAGIC);
+    if x86 && !multiboot {
+        return Err(ComposeError::Pass(format!(
+            "Error: The system image {} does not have a multiboot2 header in its first {} bytes, so cannot be booted.",
+            img_path, MULTIBOOT2_SEARCH
+        )));
+    }
+
+    Ok(())
+}
+
 fn compute_elfobj(
-    _id: &ComponentId,
+    id: &ComponentId,
     obj_path: &String,
-    _s: &SystemState,
+    s: &SystemState,
     _b: &mut dyn BuildState,
-) -> Result<Box<ElfObject>, String> {
-    let obj_contents = dump_file(&obj_path)?;
-    let obj = CompObject::parse(&obj_path, &obj_contents)?;
+) -> Result<Box<ElfObject>, ComposeError> {
+    let obj_contents = dump_file(obj_path)?;
+    obj_validate(
+        obj_path,
+        &obj_contents,
+        component(s, id).arch,
+        s.get_address_assignments().component_baseaddr(id),
+    )?;
+    let obj = CompObject::parse(obj_path, &obj_contents)?;
 
     let mut client_symbs = HashMap::new();
     let mut server_symbs = HashMap::new();
@@ -315,6 +524,7 @@ fn compute_elfobj(
         obj_path: obj_path.to_string(),
         client_symbs,
         server_symbs,
+        undef_symbs: obj.undefined().clone(),
         comp_symbs: CompSymbs {
             entry: obj.entryfn_addr(),
             comp_info: obj.compinfo_addr(),
@@ -327,10 +537,10 @@ impl TransitionIter for ElfObject {
         id: &ComponentId,
         s: &SystemState,
         b: &mut dyn BuildState,
-    ) -> Result<Box<Self>, String> {
-        let obj_path = b.comp_build(&id, &s)?;
+    ) -> Result<Box<Self>, ComposeError> {
+        let obj_path = b.comp_build(id, s)?;
 
-        compute_elfobj(&id, &obj_path, &s, b)
+        compute_elfobj(id, &obj_path, s, b)
     }
 }
 
@@ -347,6 +557,10 @@ impl ObjectsPass for ElfObject {
         &self.comp_symbs
     }
 
+    fn undefined_symbs(&self) -> &Vec<String> {
+        &self.undef_symbs
+    }
+
     fn comp_path(&self) -> &String {
         &self.obj_path
     }
@@ -357,7 +571,7 @@ pub struct Constructor {
 }
 
 impl Transition for Constructor {
-    fn transition(s: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(s: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let spec = s.get_spec();
         let mut sys_constructor = "".to_string();
         let constructors: Vec<&ComponentName> = spec
@@ -386,42 +600,43 @@ impl Transition for Constructor {
                 .find(|(_id, name)| *name == *c_name)
                 .unwrap();
 
-            let obj_path = b.constructor_build(&id, &s)?;
-            let obj = compute_elfobj(&id, &obj_path, &s, b)?;
+            let obj_path = b.constructor_build(id, s)?;
+            let obj = compute_elfobj(id, &obj_path, s, b)?;
 
             for (name, symb) in obj.server_symbs().iter() {
                 if symb.func_addr
-                    != s.get_objs_id(&id)
+                    != s.get_objs_id(id)
                         .server_symbs()
                         .get(name)
                         .unwrap() // this really should not fail! How could the object have, then not have the symbol?
                         .func_addr
                 {
-                    return Err(format!("Constructor {:?} creation error: Between when the object's synchronous invocations were generated, and when the constructor was synthesized, the code layout changed. This is an internal error, but we cannot proceed.", c_name));
+                    return Err(ComposeError::Pass(format!("Constructor {:?} creation error: Between when the object's synchronous invocations were generated, and when the constructor was synthesized, the code layout changed. This is an internal error, but we cannot proceed.", c_name)));
                 }
             }
 
-            if component(&s, &id).constructor.var_name == "kernel" {
+            if component(s, id).constructor.var_name == "kernel" {
                 sys_constructor = obj_path;
             }
         }
 
         // If we didn't find the core system constructor, something is very wrong.
-        if sys_constructor == "" {
-            return Err(format!("Error: Could not find the system constructor with \"kernel\" as its own constructor. Error copying into the final constructor."));
+        if sys_constructor.is_empty() {
+            return Err(ComposeError::Pass("Error: Could not find the system constructor with \"kernel\" as its own constructor. Error copying into the final constructor.".to_string()));
         }
 
         let constructor_path = b.file_path(&"constructor".to_string())?;
         let cp_cmd = format!("cp {} {}", sys_constructor, constructor_path);
         let (_out, err) = exec_pipeline(vec![cp_cmd.clone()]);
-        if err.len() != 0 {
-            return Err(format!(
-                "Errors copying image (in cmd {}):\n{}",
-                cp_cmd, err
-            ));
+        if !err.is_empty() {
+            return Err(ComposeError::Io {
+                path: constructor_path,
+                msg: format!("Errors copying image (in cmd {}):\n{}", cp_cmd, err),
+            });
         }
         let kern_path = b.file_path(&"cos.img".to_string())?;
-        b.kernel_build(&kern_path, &constructor_path, &s)?;
+        b.kernel_build(&kern_path, &constructor_path, s)?;
+        image_check(&kern_path, s.get_spec().arch())?;
 
         Ok(Box::new(Constructor {
             obj_path: kern_path,
@@ -1,27 +1,43 @@
-use std::collections::{HashMap, HashSet};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use syshelpers::dump_file;
 use toml;
+use xip::XIP_ALIGN;
 
-use initargs::ArgsKV;
+use diagnostic::{similar, SpecLoc};
+use disk::BOOT_MIN_SIZE;
+use initargs::{ArgsKV, ArgsValType};
 use passes::{
-    AddrSpace, AddrSpaces, AddrSpcName, BuildState, Component, ComponentName, Dependency, Export,
-    Library, SpecificationPass, SystemState, Transition,
+    AddrSpace, AddrSpaces, AddrSpcName, Arch, ArchiveFormat, BuildState, CapDelegations,
+    CompilerCache, Component, ComponentId, ComponentName, ComposeError, Dependency, DiskLayout,
+    Export, FlashProfile, FlashTool, InitargsFormat, Library, MemoryRegion, NetbootLayout, Profile,
+    RegionKind, SpecificationPass, SystemState, Transition, XipLayout,
 };
 
 #[derive(Debug, Deserialize)]
 pub struct Dep {
-    pub srv: String,
+    pub srv: String, // resolved from the interface if omitted
     pub interface: String,
-    pub variant: Option<String>, // should only be used when srv == "kernel", i.e. a library variant
+    pub variant: Option<String>, // one the server builds (required when srv == "kernel", i.e. a library variant)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Parameters {
     pub key: String,
-    pub value: Option<String>, // optional as we might provide simple keys without values.
+    pub value: Option<toml::Value>, // optional as we might provide simple keys without values.
+    // Binary values, instead of a value: the contents of a host file
+    // (relative to the specification's directory), or a literal.
+    pub file: Option<String>,
+    pub hex: Option<String>,
+    pub base64: Option<String>,
     pub at: Option<String>,
 }
 
+// A server can build several variants of an interface, each listed
+// separately, from which its clients choose.
 #[derive(Debug, Deserialize)]
 pub struct InterfaceVariant {
     pub interface: String,
@@ -34,6 +50,47 @@ pub struct ConstantVal {
     pub value: String,
 }
 
+// Host files to include in the component's initfs tarball. `path` is
+// a file, a directory (included recursively), or a glob (with `*` and
+// `?` in its last component), relative to the specification's
+// directory. `as` is the path within the tarball, where a trailing
+// '/' places the files within that directory.
+#[derive(Debug, Deserialize, Clone)]
+pub struct InitfsEntry {
+    pub path: String,
+    #[serde(rename = "as")]
+    pub dest: Option<String>,
+}
+
+// How a component's threads are scheduled, passed to its scheduler:
+// their priority, their budget of execution within each period (in
+// microseconds), and the core they run on.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SchedParams {
+    pub prio: Option<u64>,
+    pub budget_us: Option<u64>,
+    pub period_us: Option<u64>,
+    pub core: Option<u64>,
+}
+
+// A range of physical memory, e.g. { base = "0x80000000", size = "4K" }.
+#[derive(Debug, Deserialize)]
+pub struct TomlMemRange {
+    base: String,
+    size: String,
+}
+
+// The capabilities that a component's capability manager delegates
+// to it: ranges of physical memory, cores, interrupt lines, and a
+// quota of untyped memory (e.g. "16M").
+#[derive(Debug, Deserialize)]
+pub struct TomlCaps {
+    memory: Option<Vec<TomlMemRange>>,
+    cores: Option<Vec<u64>>,
+    irqs: Option<Vec<u64>>,
+    untyped: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TomlComponent {
     name: String,
@@ -43,14 +100,46 @@ pub struct TomlComponent {
     params: Option<Vec<Parameters>>,
     constants: Option<Vec<ConstantVal>>,
     implements: Option<Vec<InterfaceVariant>>,
-    initfs: Option<String>,
+    libs: Option<Vec<String>>, // names of libraries in src/components/lib/
+    initfs: Option<Vec<InitfsEntry>>,
+    arch: Option<String>, // overrides the system's architecture
+    cflags: Option<String>,
+    make_vars: Option<BTreeMap<String, String>>,
+    max_size: Option<String>, // budget for text + data + bss, e.g. "64K"
+    initargs_format: Option<String>, // "c" (default) or "tlv"
+    max_initargs: Option<String>, // budget for the serialized initargs, e.g. "4K"
+    sched: Option<SchedParams>,
+    caps: Option<TomlCaps>,
+    id: Option<u64>, // pins the component's id, otherwise assigned in dependency order
+    affinity: Option<Vec<u64>>, // the cores the component is initialized on (all by default)
     constructor: String, // the booter
 }
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct SysInfo {
-    description: String, // comment
+    description: String,             // comment
+    archive: Option<String>,         // of its initfs: "tar" (default), "cpio", or "flat"
+    page_align: Option<bool>,        // the objects within its initfs
+    build_dir: Option<String>,       // relative to the specification's directory
+    arch: Option<String>,            // "x86_64" (default), "i386", "armv7a", or "riscv64"
+    profile: Option<String>,         // "debug" or "release"; the Makefiles' flags by default
+    max_initfs_size: Option<String>, // budget for the constructor's archive
+    sign_key: Option<String>,        // ed25519 key (PEM), relative to the specification's directory
+    dtb: Option<String>, // the board's device-tree (.dts or .dtb), relative to the specification's directory
+    dtb_addr: Option<String>, // the physical address to embed the device-tree at, e.g. "0x2000000"
+    board: Option<String>, // the board's configuration (for mkimg flash), relative to the specification's directory
+    compiler_cache: Option<String>, // "ccache" or "sccache"
+    cores: Option<u64>,    // the number of cores (1 by default)
+}
+
+// The order in which components are initialized on a core: those in
+// `order` first, then the others with an affinity for the core, in
+// the order of their ids.
+#[derive(Debug, Deserialize)]
+pub struct TomlBoot {
+    core: u64,
+    order: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -60,79 +149,2236 @@ pub struct TomlAddrSpace {
     parent: Option<String>,  // which vas contains this one
 }
 
+// What a test run of the system must print on its serial console.
+// `expect` holds (extended) regular expressions that must each match
+// some line of the output within `timeout` seconds of booting.
+#[derive(Debug, Deserialize)]
+pub struct TomlTest {
+    expect: Option<Vec<String>>,
+    timeout: Option<u64>,
+}
+
+// The partitions of the disk image that `mkimg disk` creates: the
+// sizes of its boot partition, and of its data partition (if any),
+// and the files (relative to the specification's directory) copied
+// into the boot partition along with the system's image.
+#[derive(Debug, Deserialize)]
+pub struct TomlDisk {
+    boot_size: Option<String>,
+    data_size: Option<String>,
+    boot_files: Option<Vec<String>>,
+}
+
+// Where `mkimg pxe` places the system's image, within the directory
+// that is served to network-booted machines, and the (HTTP) URL
+// that directory is served from.
+#[derive(Debug, Deserialize)]
+pub struct TomlNetboot {
+    dir: Option<String>,
+    url: Option<String>,
+}
+
+// The flash and RAM that an execute-in-place layout places the
+// components' read-only, and writable, segments in.
+#[derive(Debug, Deserialize)]
+pub struct TomlXip {
+    flash_base: String, // hexadecimal addresses
+    flash_size: String, // sizes, optionally with a K, M, or G suffix
+    ram_base: String,
+    ram_size: String,
+}
+
+// A region of the board's memory, which `mkimg split` emits an image
+// of.
+#[derive(Debug, Deserialize)]
+pub struct TomlRegion {
+    name: String,
+    kind: String, // "flash", or "ram"
+    base: String, // a hexadecimal address
+    size: String, // optionally with a K, M, or G suffix
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TomlMemory {
+    regions: Vec<TomlRegion>,
+}
+
+// A profile for programming the board, in its configuration.
+#[derive(Debug, Deserialize)]
+pub struct TomlFlash {
+    name: String,
+    tool: String,              // "openocd", "probe-rs", or "dfu-util"
+    args: Option<Vec<String>>, // the tool's, e.g. selecting the probe and the target
+    region: Option<String>,    // the memory region whose image is written, not the kernel image
+    verify: Option<bool>,      // after writing (by default)
+}
+
+// A board's configuration (system.board), shared by the
+// specifications of the systems that run on it.
+#[derive(Debug, Deserialize)]
+pub struct TomlBoard {
+    #[serde(default)]
+    flash: Vec<TomlFlash>,
+}
+
+// Parse the board's configuration, at `path`.
+fn board_parse(path: &Path) -> Result<TomlBoard, ComposeError> {
+    let p = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| ComposeError::Io {
+        path: p.clone(),
+        msg: format!("Could not read the board's configuration: {}", e),
+    })?;
+    toml::from_str(&source).map_err(|e| ComposeError::Parse {
+        path: p,
+        msg: e.to_string(),
+    })
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct TomlSpecification {
     system: SysInfo,
+    #[serde(default)]
     components: Vec<TomlComponent>,
     address_spaces: Option<Vec<TomlAddrSpace>>, //aggregates: Vec<TomlComponent>  For components of components
+    test: Option<TomlTest>,
+    disk: Option<TomlDisk>,
+    netboot: Option<TomlNetboot>,
+    xip: Option<TomlXip>,
+    memory: Option<TomlMemory>,
+    params: Option<toml::value::Table>, // global parameters, passed to every component
+    boot: Option<Vec<TomlBoot>>,
+    #[serde(skip)]
+    source: String, // the text of the specification, for error context
+    #[serde(skip)]
+    path: String,
 }
 
-impl Dep {
-    pub fn get_name(&self) -> String {
-        self.srv.clone()
+// The text of the specification (or included file) at `path`.
+fn spec_read(path: &String) -> Result<String, ComposeError> {
+    let conf = dump_file(path).map_err(|e| match e {
+        ComposeError::Io { path, msg } => ComposeError::Parse { path, msg },
+        e => e,
+    })?;
+
+    String::from_utf8(conf).map_err(|_| ComposeError::Parse {
+        path: path.clone(),
+        msg: String::from("Not valid UTF-8"),
+    })
+}
+
+fn toml_parse(path: &String, source: &String) -> Result<toml::Value, ComposeError> {
+    toml::from_str(source).map_err(|e| {
+        let err = ComposeError::Parse {
+            path: path.clone(),
+            msg: e.to_string(),
+        };
+        // The parser's position is of the character it rejected.
+        match e.line_col().and_then(|(l, c)| {
+            SpecLoc::find(path, source, l, "").map(|loc| SpecLoc {
+                col: c + 1,
+                len: 1,
+                ..loc
+            })
+        }) {
+            Some(loc) => ComposeError::Located(Box::new(err), loc),
+            None => err,
+        }
+    })
+}
+
+// The names defined by the array of tables `key` (e.g. "components")
+// of a specification (or included file).
+fn toml_names(v: &toml::Value, key: &str) -> Vec<String> {
+    v.get(key)
+        .and_then(|a| a.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|t| t.get("name").and_then(|n| n.as_str()))
+                .map(|n| n.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Append `v` to the array `key` of the table `top`.
+fn toml_push(top: &mut toml::value::Table, key: &str, v: toml::Value) {
+    if let toml::Value::Array(ref mut a) = *top
+        .entry(key.to_string())
+        .or_insert(toml::Value::Array(Vec::new()))
+    {
+        a.push(v);
     }
 }
 
-impl TomlComponent {
-    fn update_options(&mut self) -> () {
-        if self.deps.is_none() {
-            let vs = Vec::new();
-            self.deps = Some(vs);
+// Initfs paths (and the files of parameters) are relative to the
+// file defining the component (or template), so join them to `dir`,
+// that file's directory.
+fn paths_rebase(t: &mut toml::Value, dir: &Path) {
+    for (array, field) in [("initfs", "path"), ("params", "file")].iter() {
+        if let Some(es) = t.get_mut(array).and_then(|es| es.as_array_mut()) {
+            for e in es.iter_mut().filter_map(|e| e.as_table_mut()) {
+                if let Some(p) = e.get(*field).and_then(|p| p.as_str()).map(|p| dir.join(p)) {
+                    e.insert(
+                        field.to_string(),
+                        toml::Value::String(p.display().to_string()),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Record that `name` is defined in `file`, returning false (and
+// the error) if another file already defines it.
+fn name_define(
+    origins: &mut HashMap<String, String>,
+    errs: &mut Vec<ComposeError>,
+    name: String,
+    file: &String,
+) -> bool {
+    match origins.get(&name) {
+        // Duplicates within a file are found by validation.
+        Some(f) if f != file => {
+            errs.push(ComposeError::Spec(format!(
+                "Error: {} is defined in both {} and {}.",
+                name, f, file
+            )));
+            false
+        }
+        _ => {
+            origins.insert(name, file.clone());
+            true
+        }
+    }
+}
+
+// The arrays of named tables that included files contribute, and
+// what they name.
+const INCLUDED_ARRAYS: [(&str, &str); 2] = [
+    ("components", "Component"),
+    ("address_spaces", "Address space"),
+];
+
+// Files included by the specification (or by other included files)
+// contribute their components, address spaces, and templates, but
+// only the specification describes the system. Merge them into
+// `spec`, the specification at `path`, in order. Included paths are
+// relative to the including file. Each file is merged once, even if
+// it is included several times, and a name can only be defined in
+// one file.
+fn includes_merge(spec: &mut toml::Value, path: &String) -> Result<(), ComposeError> {
+    let mut origins: HashMap<String, String> = HashMap::new();
+    let mut errs = Vec::new();
+    let template_names = |v: &toml::Value| -> Vec<String> {
+        v.get("template")
+            .and_then(|t| t.as_table())
+            .map(|t| t.keys().map(|n| format!("Template {}", n)).collect())
+            .unwrap_or_default()
+    };
+    for (key, kind) in INCLUDED_ARRAYS.iter() {
+        for n in toml_names(spec, key) {
+            name_define(&mut origins, &mut errs, format!("{} {}", kind, n), path);
+        }
+    }
+    for n in template_names(spec) {
+        name_define(&mut origins, &mut errs, n, path);
+    }
+
+    let includes = |v: &toml::Value, from: &String| -> Vec<(String, String)> {
+        v.get("include")
+            .and_then(|is| is.as_array())
+            .map(|is| {
+                is.iter()
+                    .filter_map(|i| i.as_str())
+                    .map(|i| (from.clone(), i.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut merged: HashSet<PathBuf> = fs::canonicalize(path).into_iter().collect();
+    let mut pending = includes(spec, path);
+    let mut i = 0;
+    while i < pending.len() {
+        let (from, inc) = pending[i].clone();
+        i += 1;
+        let file = Path::new(&from)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&inc);
+        let file = fs::canonicalize(&file).map_err(|e| ComposeError::Parse {
+            path: file.display().to_string(),
+            msg: format!("Cannot include the file from {}: {}", from, e),
+        })?;
+        if !merged.insert(file.clone()) {
+            continue;
+        }
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let file = file.display().to_string();
+        let mut incl = toml_parse(&file, &spec_read(&file)?)?;
+        for t in ["system", "vars", "params", "boot", "subsystems"]
+            .iter()
+            .filter(|t| incl.get(**t).is_some())
+        {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The included file {} has a {} table, but only the specification ({}) can.",
+                file, t, path
+            )));
+        }
+
+        // unwrap as the specification is a table
+        let top = spec.as_table_mut().unwrap();
+        for (key, kind) in INCLUDED_ARRAYS.iter() {
+            let ts = match incl.get_mut(key).and_then(|ts| ts.as_array_mut()) {
+                Some(ts) => ts,
+                None => continue,
+            };
+            for mut t in ts.drain(..) {
+                let name = t
+                    .get("name")
+                    .and_then(|n| n.as_str())
+                    .map(|n| n.to_string());
+                if let Some(n) = name {
+                    if !name_define(&mut origins, &mut errs, format!("{} {}", kind, n), &file) {
+                        continue;
+                    }
+                }
+                paths_rebase(&mut t, &dir);
+                toml_push(top, key, t);
+            }
+        }
+        if let Some(ts) = incl.get("template").and_then(|t| t.as_table()) {
+            for (n, t) in ts.iter() {
+                if !name_define(&mut origins, &mut errs, format!("Template {}", n), &file) {
+                    continue;
+                }
+                let mut t = t.clone();
+                paths_rebase(&mut t, &dir);
+                if let toml::Value::Table(ref mut all) = *top
+                    .entry(String::from("template"))
+                    .or_insert(toml::Value::Table(toml::value::Table::new()))
+                {
+                    all.insert(n.clone(), t);
+                }
+            }
+        }
+        pending.extend(includes(&incl, &file));
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The fields that can be overridden in components, and in the
+// system table.
+const COMP_FIELDS: [&str; 17] = [
+    "img",
+    "baseaddr",
+    "deps",
+    "params",
+    "constants",
+    "implements",
+    "libs",
+    "initfs",
+    "arch",
+    "cflags",
+    "make_vars",
+    "max_size",
+    "initargs_format",
+    "max_initargs",
+    "sched",
+    "caps",
+    "constructor",
+];
+const SYSTEM_FIELDS: [&str; 12] = [
+    "description",
+    "archive",
+    "page_align",
+    "build_dir",
+    "arch",
+    "profile",
+    "max_initfs_size",
+    "sign_key",
+    "dtb",
+    "dtb_addr",
+    "board",
+    "compiler_cache",
+];
+
+// The value of an override: TOML (e.g. 3, true, or [80, 443]), or
+// otherwise the string itself (e.g. release). The fields that aren't
+// arrays, tables, or booleans are all strings, so `field` values are
+// only TOML if they are one of those (e.g. 0x1600000 is a string).
+fn override_value(v: &str, field: bool) -> toml::Value {
+    match toml::from_str::<toml::Value>(&format!("v = {}", v)) {
+        // unwrap as the table has the one key
+        Ok(mut t) => match t.as_table_mut().unwrap().remove("v").unwrap() {
+            tv @ toml::Value::Array(_)
+            | tv @ toml::Value::Table(_)
+            | tv @ toml::Value::Boolean(_) => tv,
+            _ if field => toml::Value::String(v.to_string()),
+            tv => tv,
+        },
+        Err(_) => toml::Value::String(v.to_string()),
+    }
+}
+
+// Apply each override of a component's field (component.field), a
+// system field (system.field), a component's parameter
+// (component.params.key), or a global parameter (params.key), in
+// order.
+fn overrides_apply(
+    spec: &mut toml::Value,
+    overrides: &Vec<(String, String)>,
+) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let top = spec.as_table_mut().unwrap();
+    let mut errs = Vec::new();
+
+    for (path, val) in overrides.iter() {
+        let ps: Vec<&str> = path.splitn(3, '.').collect();
+        let invalid = || {
+            ComposeError::Spec(format!(
+                "Error: Cannot override {}; expected <component>.<field> (one of {}), <component>.params.<key>, system.<field> (one of {}), or params.<key>.",
+                path,
+                COMP_FIELDS.join(", "),
+                SYSTEM_FIELDS.join(", ")
+            ))
+        };
+
+        if ps[0] == "system" {
+            match top.get_mut("system").and_then(|s| s.as_table_mut()) {
+                Some(sys) if ps.len() == 2 && SYSTEM_FIELDS.contains(&ps[1]) => {
+                    sys.insert(ps[1].to_string(), override_value(val, true));
+                }
+                _ => errs.push(invalid()),
+            }
+            continue;
+        }
+        if ps[0] == "params" {
+            match *top
+                .entry(String::from("params"))
+                .or_insert(toml::Value::Table(toml::value::Table::new()))
+            {
+                toml::Value::Table(ref mut params) if ps.len() == 2 => {
+                    params.insert(ps[1].to_string(), override_value(val, false));
+                }
+                _ => errs.push(invalid()),
+            }
+            continue;
+        }
+        let comp = top
+            .get_mut("components")
+            .and_then(|cs| cs.as_array_mut())
+            .and_then(|cs| {
+                cs.iter_mut()
+                    .filter_map(|c| c.as_table_mut())
+                    .find(|c| c.get("name").and_then(|n| n.as_str()) == Some(ps[0]))
+            });
+        let comp = match comp {
+            Some(c) => c,
+            None => {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Cannot override {}, as there is no component named {}.",
+                    path, ps[0]
+                )));
+                continue;
+            }
+        };
+        match (ps.get(1), ps.get(2)) {
+            (Some(&"params"), Some(key)) => {
+                let mut param = toml::value::Table::new();
+                param.insert(String::from("key"), toml::Value::String(key.to_string()));
+                param.insert(String::from("value"), override_value(val, false));
+                if let toml::Value::Array(ref mut params) = *comp
+                    .entry(String::from("params"))
+                    .or_insert(toml::Value::Array(Vec::new()))
+                {
+                    // The component's own parameter, not one it
+                    // directs at another.
+                    let own = params.iter_mut().find(|p| {
+                        p.get("key").and_then(|k| k.as_str()) == Some(key) && p.get("at").is_none()
+                    });
+                    match own {
+                        Some(p) => *p = toml::Value::Table(param),
+                        None => params.push(toml::Value::Table(param)),
+                    }
+                }
+            }
+            (Some(f), None) if COMP_FIELDS.contains(f) => {
+                comp.insert(f.to_string(), override_value(val, true));
+            }
+            _ => errs.push(invalid()),
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// Replace each ${name} in `s` with the variable's value, where $${
+// is a literal ${.
+fn vars_subst(s: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = s;
+
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("$${") {
+            out.push_str("${");
+            rest = &rest["$${".len()..];
+        } else if rest.starts_with("${") {
+            let end = rest
+                .find('}')
+                .ok_or(format!("unterminated ${{ in \"{}\"", s))?;
+            let name = &rest["${".len()..end];
+            match vars.get(name) {
+                Some(v) => out.push_str(v),
+                None => return Err(format!("undefined variable {} in \"{}\"", name, s)),
+            }
+            rest = &rest[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+// Substitute the variables in all of the strings within `v`.
+fn value_subst(v: &mut toml::Value, vars: &BTreeMap<String, String>) -> Result<(), String> {
+    match *v {
+        toml::Value::String(ref mut s) => *s = vars_subst(s, vars)?,
+        toml::Value::Array(ref mut a) => {
+            for e in a.iter_mut() {
+                value_subst(e, vars)?;
+            }
+        }
+        toml::Value::Table(ref mut t) => {
+            for (_, e) in t.iter_mut() {
+                value_subst(e, vars)?;
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+// The fields of components that can use variables.
+const VAR_FIELDS: [&str; 5] = ["img", "baseaddr", "params", "initfs", "caps"];
+
+// Substitute the variables of the specification's [vars] table,
+// overridden by those in `external`, in the components' VAR_FIELDS.
+fn vars_resolve(
+    spec: &mut toml::Value,
+    external: &BTreeMap<String, String>,
+) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let top = spec.as_table_mut().unwrap();
+    let mut errs = Vec::new();
+    let mut vars = BTreeMap::new();
+
+    match top.remove("vars") {
+        None => (),
+        Some(toml::Value::Table(t)) => {
+            for (k, v) in t {
+                match v {
+                    toml::Value::String(s) => {
+                        vars.insert(k, s);
+                    }
+                    toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                        vars.insert(k, v.to_string());
+                    }
+                    _ => errs.push(ComposeError::Spec(format!(
+                        "Error: Variable {} must be a string, number, or boolean.",
+                        k
+                    ))),
+                }
+            }
+        }
+        Some(_) => {
+            return Err(ComposeError::Spec(String::from(
+                "Error: vars must be a table of variables, e.g. [vars] name = \"value\".",
+            )))
+        }
+    }
+    vars.extend(external.clone());
+
+    if let Some(Err(e)) = top.get_mut("params").map(|v| value_subst(v, &vars)) {
+        errs.push(ComposeError::Spec(format!(
+            "Error: The global params have an {}.",
+            e
+        )));
+    }
+    if let Some(cs) = top.get_mut("components").and_then(|cs| cs.as_array_mut()) {
+        for c in cs.iter_mut().filter_map(|c| c.as_table_mut()) {
+            let name = c
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("")
+                .to_string();
+            for f in VAR_FIELDS.iter() {
+                if let Some(Err(e)) = c.get_mut(*f).map(|v| value_subst(v, &vars)) {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s {} has an {}.",
+                        name, f, e
+                    )));
+                }
+            }
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The conditions of a `when` table, which must all hold: the
+// system's architecture and build profile must each be one of those
+// listed, and each listed feature must be enabled.
+const WHEN_KEYS: [&str; 3] = ["arch", "profile", "feature"];
+
+// The names (a string, or an array of them) of a condition.
+fn when_names(key: &str, v: &toml::Value) -> Result<Vec<String>, String> {
+    let names = match *v {
+        toml::Value::String(ref s) => Some(vec![s.clone()]),
+        toml::Value::Array(ref a) => a
+            .iter()
+            .map(|n| n.as_str().map(|n| n.to_string()))
+            .collect(),
+        _ => None,
+    };
+
+    names.ok_or(format!("{} must be a name, or an array of names", key))
+}
+
+// What the conditions are evaluated against.
+struct Selection<'a> {
+    arch: String,
+    profile: Option<String>,
+    features: &'a BTreeSet<String>,
+}
+
+// Does the `when` table hold for the selection?
+fn when_holds(when: &toml::Value, sel: &Selection) -> Result<bool, String> {
+    let conds = when
+        .as_table()
+        .ok_or(String::from("when must be a table of conditions"))?;
+    let mut holds = true;
+
+    for (k, v) in conds.iter() {
+        if !WHEN_KEYS.contains(&k.as_str()) {
+            return Err(format!(
+                "unknown condition {} (expected one of {})",
+                k,
+                WHEN_KEYS.join(", ")
+            ));
+        }
+        let names = when_names(k, v)?;
+        holds &= match k.as_str() {
+            "arch" => {
+                if let Some(a) = names.iter().find(|a| Arch::from_name(a).is_none()) {
+                    return Err(format!("unknown architecture \"{}\"", a));
+                }
+                names.contains(&sel.arch)
+            }
+            "profile" => {
+                if let Some(p) = names.iter().find(|p| Profile::from_name(p).is_none()) {
+                    return Err(format!("unknown build profile \"{}\"", p));
+                }
+                names.iter().any(|p| Some(p) == sel.profile.as_ref())
+            }
+            _ => names.iter().all(|f| sel.features.contains(f)),
+        };
+    }
+
+    Ok(holds)
+}
+
+// Remove the tables within the arrays of `v` (e.g. components, or
+// their deps) whose `when` doesn't hold, and the `when` of those
+// that remain. `ctx` names `v` for errors.
+fn when_filter(v: &mut toml::Value, ctx: &String, sel: &Selection, errs: &mut Vec<ComposeError>) {
+    match *v {
+        toml::Value::Array(ref mut a) => {
+            // Named tables (e.g. components) by their name.
+            let elem_ctx = |e: &toml::Value| match e.get("name").and_then(|n| n.as_str()) {
+                Some(n) => format!("{}.{}", ctx, n),
+                None => ctx.clone(),
+            };
+            a.retain(|e| match e.get("when") {
+                None => true,
+                Some(w) => match when_holds(w, sel) {
+                    Ok(holds) => holds,
+                    Err(msg) => {
+                        errs.push(ComposeError::Spec(format!(
+                            "Error: Invalid condition in {}: {}.",
+                            elem_ctx(e),
+                            msg
+                        )));
+                        false
+                    }
+                },
+            });
+            for e in a.iter_mut() {
+                let ctx = elem_ctx(e);
+                if let Some(t) = e.as_table_mut() {
+                    t.remove("when");
+                }
+                when_filter(e, &ctx, sel, errs);
+            }
+        }
+        toml::Value::Table(ref mut t) => {
+            for (k, e) in t.iter_mut() {
+                let ctx = if ctx.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", ctx, k)
+                };
+                when_filter(e, &ctx, sel, errs);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Select the sections of the specification whose conditions hold
+// for the system's architecture and profile, and the `features`
+// enabled on the command line.
+fn conditions_resolve(
+    spec: &mut toml::Value,
+    features: &BTreeSet<String>,
+) -> Result<(), ComposeError> {
+    let sys_field = |f: &str| {
+        spec.get("system")
+            .and_then(|s| s.get(f))
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+    let sel = Selection {
+        arch: sys_field("arch").unwrap_or(String::from(Arch::X86_64.name())),
+        profile: sys_field("profile"),
+        features,
+    };
+    let mut errs = Vec::new();
+
+    when_filter(spec, &String::new(), &sel, &mut errs);
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The parameters of instance `i` of a replicated component: those
+// with `instance = i` replace the shared ones with the same key (and
+// target), and those of other instances are dropped.
+fn instance_params(params: &Vec<toml::Value>, i: i64) -> Vec<toml::Value> {
+    let target = |p: &toml::Value| {
+        (
+            p.get("key").and_then(|k| k.as_str()).map(|k| k.to_string()),
+            p.get("at").and_then(|a| a.as_str()).map(|a| a.to_string()),
+        )
+    };
+    let own: Vec<&toml::Value> = params
+        .iter()
+        .filter(|p| p.get("instance").and_then(|n| n.as_integer()) == Some(i))
+        .collect();
+
+    params
+        .iter()
+        .filter(|p| match p.get("instance") {
+            Some(_) => false,
+            None => !own.iter().any(|o| target(o) == target(p)),
+        })
+        .chain(own.iter().cloned())
+        .map(|p| {
+            let mut p = p.clone();
+            if let Some(t) = p.as_table_mut() {
+                t.remove("instance");
+            }
+            p
+        })
+        .collect()
+}
+
+// Expand each component with `instances = N` into N components,
+// named name.0 to name.N-1, each with its own parameters, and
+// replace it with them in the address spaces that include it. As
+// their base addresses aren't pinned, they are each assigned their
+// own.
+fn instances_expand(spec: &mut toml::Value) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let top = spec.as_table_mut().unwrap();
+    let mut errs = Vec::new();
+    let mut replicated: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    if let Some(cs) = top.get_mut("components").and_then(|cs| cs.as_array_mut()) {
+        let mut expanded = Vec::new();
+        for c in cs.drain(..) {
+            let name = c
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("")
+                .to_string();
+            let params = c
+                .get("params")
+                .and_then(|ps| ps.as_array())
+                .cloned()
+                .unwrap_or(Vec::new());
+            let n = match c.get("instances") {
+                None => {
+                    if params.iter().any(|p| p.get("instance").is_some()) {
+                        errs.push(ComposeError::Spec(format!(
+                            "Error: Component {} has parameters for an instance, but isn't replicated with instances = N.",
+                            name
+                        )));
+                    }
+                    expanded.push(c);
+                    continue;
+                }
+                Some(toml::Value::Integer(n)) if *n > 0 => *n,
+                Some(_) => {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s instances must be a positive integer.",
+                        name
+                    )));
+                    continue;
+                }
+            };
+            if c.get("baseaddr").is_some() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {} is replicated, so its instances' base addresses are assigned, and it cannot have a baseaddr.",
+                    name
+                )));
+            }
+            if c.get("id").is_some() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {} is replicated, so its instances' ids are assigned, and it cannot have an id.",
+                    name
+                )));
+            }
+            if let Some(p) = params.iter().find(|p| match p.get("instance") {
+                Some(i) => i.as_integer().is_none_or(|i| i < 0 || i >= n),
+                None => false,
+            }) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {}'s parameter {} is for instance {}, but it has instances 0 to {}.",
+                    name,
+                    p.get("key").and_then(|k| k.as_str()).unwrap_or(""),
+                    p.get("instance").unwrap(),
+                    n - 1
+                )));
+            }
+
+            let mut names = Vec::new();
+            for i in 0..n {
+                let mut inst = c.clone();
+                // unwrap as `instances` was found in the table
+                let t = inst.as_table_mut().unwrap();
+                let iname = format!("{}.{}", name, i);
+                t.remove("instances");
+                t.insert(String::from("name"), toml::Value::String(iname.clone()));
+                if t.contains_key("params") {
+                    t.insert(
+                        String::from("params"),
+                        toml::Value::Array(instance_params(&params, i)),
+                    );
+                }
+                names.push(iname);
+                expanded.push(inst);
+            }
+            replicated.insert(name, names);
+        }
+        *cs = expanded;
+    }
+    // Clients must choose an instance to depend on.
+    for c in top
+        .get("components")
+        .and_then(|cs| cs.as_array())
+        .unwrap_or(&Vec::new())
+    {
+        let name = c.get("name").and_then(|n| n.as_str()).unwrap_or("");
+        let deps = c.get("deps").and_then(|ds| ds.as_array());
+        for srv in deps
+            .unwrap_or(&Vec::new())
+            .iter()
+            .filter_map(|d| d.get("srv").and_then(|s| s.as_str()))
+        {
+            if let Some(insts) = replicated.get(srv) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {} depends on {}, which is replicated; it must depend on one of its instances ({}).",
+                    name,
+                    srv,
+                    insts.join(", ")
+                )));
+            }
+        }
+    }
+
+    if let Some(ases) = top.get_mut("address_spaces").and_then(|a| a.as_array_mut()) {
+        for cs in ases
+            .iter_mut()
+            .filter_map(|a| a.get_mut("components"))
+            .filter_map(|cs| cs.as_array_mut())
+        {
+            let names = cs
+                .iter()
+                .flat_map(|c| match c.as_str().and_then(|c| replicated.get(c)) {
+                    Some(insts) => insts
+                        .iter()
+                        .map(|i| toml::Value::String(i.clone()))
+                        .collect(),
+                    None => vec![c.clone()],
+                })
+                .collect();
+            *cs = names;
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The components that export `interface`.
+fn interface_servers(comps: &Vec<toml::Value>, interface: &str) -> Vec<String> {
+    comps
+        .iter()
+        .filter(|c| {
+            c.get("implements")
+                .and_then(|is| is.as_array())
+                .is_some_and(|is| {
+                    is.iter()
+                        .any(|i| i.get("interface").and_then(|i| i.as_str()) == Some(interface))
+                })
+        })
+        .filter_map(|c| c.get("name").and_then(|n| n.as_str()))
+        .map(|n| n.to_string())
+        .collect()
+}
+
+// Resolve the server of each dependency that only names an
+// interface, e.g. { interface = "sched" }, to the component that
+// exports it. If several do, the [providers] table (interface =
+// "component") chooses between them.
+fn deps_resolve(spec: &mut toml::Value) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let top = spec.as_table_mut().unwrap();
+    let mut errs = Vec::new();
+    let mut providers = BTreeMap::new();
+
+    match top.remove("providers") {
+        None => (),
+        Some(toml::Value::Table(t)) => {
+            for (i, v) in t {
+                match v {
+                    toml::Value::String(s) => {
+                        providers.insert(i, s);
+                    }
+                    _ => errs.push(ComposeError::Spec(format!(
+                        "Error: The provider of interface {} must be the name of a component.",
+                        i
+                    ))),
+                }
+            }
+        }
+        Some(_) => {
+            return Err(ComposeError::Spec(String::from(
+                "Error: providers must be a table of interfaces and the components that provide them, e.g. [providers] sched = \"sched\".",
+            )))
+        }
+    }
+    let comps = match top.get_mut("components").and_then(|cs| cs.as_array_mut()) {
+        Some(cs) => cs,
+        None => return Ok(()),
+    };
+    let all = comps.clone();
+    for (i, p) in providers.iter() {
+        if !interface_servers(&all, i).contains(p) {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The provider of interface {}, {}, is not a component that exports it.",
+                i, p
+            )));
+        }
+    }
+
+    for c in comps.iter_mut() {
+        let name = c
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or("")
+            .to_string();
+        let deps = match c.get_mut("deps").and_then(|ds| ds.as_array_mut()) {
+            Some(ds) => ds,
+            None => continue,
+        };
+        for d in deps.iter_mut().filter_map(|d| d.as_table_mut()) {
+            if d.contains_key("srv") {
+                continue;
+            }
+            let interface = match d.get("interface").and_then(|i| i.as_str()) {
+                Some(i) => i.to_string(),
+                None => continue, // reported when deserializing
+            };
+            // A component doesn't depend on itself.
+            let srvs: Vec<String> = interface_servers(&all, &interface)
+                .into_iter()
+                .filter(|s| *s != name)
+                .collect();
+            let srv = match (srvs.len(), providers.get(&interface)) {
+                (_, Some(p)) => p.clone(),
+                (1, None) => srvs[0].clone(),
+                (0, None) => {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {} depends on interface {}, but no component exports it.",
+                        name, interface
+                    )));
+                    continue;
+                }
+                (_, None) => {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {} depends on interface {}, which is exported by several components ({}); choose one with srv, or for all of its clients in [providers].",
+                        name,
+                        interface,
+                        srvs.join(", ")
+                    )));
+                    continue;
+                }
+            };
+            d.insert(String::from("srv"), toml::Value::String(srv));
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The tables that only the system's own specification can have, and
+// not the specifications of its sub-systems.
+const SUBSYSTEM_EXCLUDED: [&str; 8] = [
+    "system", "params", "boot", "test", "disk", "netboot", "xip", "memory",
+];
+
+// Parse the specification of a sub-system at `path`, resolving it as
+// the system's own specification is, but with the system's `system`
+// table (e.g. its architecture and profile).
+fn subsystem_parse(
+    path: &String,
+    system: Option<&toml::Value>,
+    features: &BTreeSet<String>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<toml::Value, ComposeError> {
+    let mut sub = toml_parse(path, &spec_read(path)?)?;
+    let errs: Vec<ComposeError> = SUBSYSTEM_EXCLUDED
+        .iter()
+        .filter(|t| sub.get(**t).is_some())
+        .map(|t| {
+            ComposeError::Spec(format!(
+                "Error: The sub-system specification {} has a {} table, but only the system's specification can.",
+                path, t
+            ))
+        })
+        .collect();
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+    if let Some(s) = system {
+        // unwrap as the specification is a table
+        sub.as_table_mut()
+            .unwrap()
+            .insert(String::from("system"), s.clone());
+    }
+
+    includes_merge(&mut sub, path)?;
+    templates_resolve(&mut sub)?;
+    vars_resolve(&mut sub, &BTreeMap::new())?;
+    conditions_resolve(&mut sub, features)?;
+    instances_expand(&mut sub)?;
+    deps_resolve(&mut sub)?;
+    subsystems_merge(&mut sub, path, features, stack)?;
+    sub.as_table_mut().unwrap().remove("system");
+
+    Ok(sub)
+}
+
+// Merge each sub-system, [[subsystems]] with a `name`, the path of its
+// `spec` (relative to the including file), and the `constructor`
+// that boots it, into `spec`, the specification at `path`. The
+// sub-system's names are prefixed with its name (e.g. sub.booter),
+// and its booter (the component the kernel constructs) is instead
+// constructed by `constructor`, so the sub-system is sealed into the
+// booter's tarball, which is embedded in the constructor's as a
+// single component.
+fn subsystems_merge(
+    spec: &mut toml::Value,
+    path: &String,
+    features: &BTreeSet<String>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let subs =
+        match spec.as_table_mut().unwrap().remove("subsystems") {
+            None => return Ok(()),
+            Some(toml::Value::Array(subs)) => subs,
+            Some(_) => return Err(ComposeError::Spec(String::from(
+                "Error: subsystems must be an array of tables, e.g. [[subsystems]] name = \"sub\".",
+            ))),
+        };
+    let system = spec.get("system").cloned();
+    let mut errs = Vec::new();
+
+    for s in subs.iter() {
+        let field = |f: &str| s.get(f).and_then(|v| v.as_str()).map(|v| v.to_string());
+        let (name, file, cons) = match (field("name"), field("spec"), field("constructor")) {
+            (Some(n), Some(f), Some(c)) => (n, f, c),
+            _ => {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Each sub-system in {} must have a name, spec, and constructor.",
+                    path
+                )));
+                continue;
+            }
+        };
+        let file = Path::new(&path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&file);
+        let file = fs::canonicalize(&file).map_err(|e| ComposeError::Parse {
+            path: file.display().to_string(),
+            msg: format!(
+                "Cannot read the specification of sub-system {}: {}",
+                name, e
+            ),
+        })?;
+        if stack.contains(&file) {
+            errs.push(ComposeError::Spec(format!(
+                "Error: Sub-system {} ({}) includes itself.",
+                name,
+                file.display()
+            )));
+            continue;
+        }
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        stack.push(file.clone());
+        let mut sub = subsystem_parse(
+            &file.display().to_string(),
+            system.as_ref(),
+            features,
+            stack,
+        )?;
+        stack.pop();
+
+        let prefixed = |n: &str| toml::Value::String(format!("{}.{}", name, n));
+        let roots = sub
+            .get("components")
+            .and_then(|cs| cs.as_array())
+            .map_or(0, |cs| {
+                cs.iter()
+                    .filter(|c| c.get("constructor").and_then(|c| c.as_str()) == Some("kernel"))
+                    .count()
+            });
+        if roots != 1 {
+            errs.push(ComposeError::Spec(format!(
+                "Error: Sub-system {} must have a single booter (with constructor = \"kernel\"), but has {}.",
+                name, roots
+            )));
+            continue;
+        }
+
+        // unwrap as the specification is a table
+        let top = spec.as_table_mut().unwrap();
+        let sub = sub.as_table_mut().unwrap();
+        for mut c in sub
+            .remove("components")
+            .and_then(|cs| cs.as_array().cloned())
+            .unwrap_or(Vec::new())
+        {
+            paths_rebase(&mut c, &dir);
+            let t = match c.as_table_mut() {
+                Some(t) => t,
+                None => continue,
+            };
+            let cname = t
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("")
+                .to_string();
+            if t.contains_key("id") {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {} of sub-system {} cannot pin its id, as ids are assigned across the system.",
+                    cname, name
+                )));
+            }
+            t.insert(String::from("name"), prefixed(&cname));
+            let booter = match t.get("constructor").and_then(|c| c.as_str()) {
+                Some("kernel") => toml::Value::String(cons.clone()),
+                Some(c) => prefixed(c),
+                None => continue,
+            };
+            t.insert(String::from("constructor"), booter);
+            for d in t
+                .get_mut("deps")
+                .and_then(|ds| ds.as_array_mut())
+                .into_iter()
+                .flat_map(|ds| ds.iter_mut())
+                .filter_map(|d| d.as_table_mut())
+            {
+                match d.get("srv").and_then(|s| s.as_str()).map(|s| s.to_string()) {
+                    Some(ref s) if s != "kernel" => {
+                        d.insert(String::from("srv"), prefixed(s));
+                    }
+                    _ => (),
+                }
+            }
+            for p in t
+                .get_mut("params")
+                .and_then(|ps| ps.as_array_mut())
+                .into_iter()
+                .flat_map(|ps| ps.iter_mut())
+                .filter_map(|p| p.as_table_mut())
+            {
+                if let Some(at) = p.get("at").and_then(|a| a.as_str()).map(|a| a.to_string()) {
+                    p.insert(String::from("at"), prefixed(&at));
+                }
+            }
+            toml_push(top, "components", c);
+        }
+        for mut a in sub
+            .remove("address_spaces")
+            .and_then(|a| a.as_array().cloned())
+            .unwrap_or(Vec::new())
+        {
+            if let Some(t) = a.as_table_mut() {
+                for f in ["name", "parent"].iter() {
+                    if let Some(n) = t.get(*f).and_then(|n| n.as_str()).map(|n| n.to_string()) {
+                        t.insert(f.to_string(), prefixed(&n));
+                    }
+                }
+                if let Some(cs) = t.get_mut("components").and_then(|cs| cs.as_array_mut()) {
+                    for c in cs.iter_mut() {
+                        if let Some(n) = c.as_str().map(|n| n.to_string()) {
+                            *c = prefixed(&n);
+                        }
+                    }
+                }
+            }
+            toml_push(top, "address_spaces", a);
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The fields of template `name`, including those of the templates it
+// extends. `chain` holds the templates (after the component `comp`)
+// that extend it, to catch cycles.
+fn template_fields(
+    templates: &toml::value::Table,
+    name: &String,
+    comp: &String,
+    chain: &mut Vec<String>,
+) -> Result<toml::value::Table, String> {
+    if chain.contains(name) {
+        return Err(format!(
+            "Error: Template {} extends itself (via {}).",
+            name,
+            chain.join(" -> ")
+        ));
+    }
+    let mut fields = match templates.get(name).and_then(|t| t.as_table()) {
+        Some(t) => t.clone(),
+        None => {
+            return Err(format!(
+                "Error: {} extends template {}, which is not defined.",
+                chain
+                    .last()
+                    .map_or(format!("Component {}", comp), |t| format!("Template {}", t)),
+                name
+            ))
+        }
+    };
+
+    match fields.remove("extends") {
+        None => Ok(fields),
+        Some(toml::Value::String(parent)) => {
+            chain.push(name.clone());
+            let mut base = template_fields(templates, &parent, comp, chain)?;
+            base.extend(fields);
+            Ok(base)
+        }
+        Some(_) => Err(format!(
+            "Error: Template {}'s extends must be the name of a template.",
+            name
+        )),
+    }
+}
+
+// Replace each component that `extends` a template with the
+// template's fields, overridden by those the component sets itself
+// (arrays and tables included, so they replace rather than extend
+// the template's). Templates can extend other templates.
+fn templates_resolve(spec: &mut toml::Value) -> Result<(), ComposeError> {
+    // unwrap as the specification is a table
+    let top = spec.as_table_mut().unwrap();
+    let templates = match top.remove("template") {
+        None => toml::value::Table::new(),
+        Some(toml::Value::Table(t)) => t,
+        Some(_) => {
+            return Err(ComposeError::Spec(String::from(
+                "Error: template must be a table of templates, e.g. [template.name].",
+            )))
+        }
+    };
+    let mut errs = Vec::new();
+
+    if let Some(cs) = top.get_mut("components").and_then(|cs| cs.as_array_mut()) {
+        for c in cs.iter_mut() {
+            let c = match c.as_table_mut() {
+                Some(c) => c,
+                None => continue, // deserialization reports it
+            };
+            let name = c
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("")
+                .to_string();
+            let base = match c.remove("extends") {
+                None => continue,
+                Some(toml::Value::String(t)) => {
+                    template_fields(&templates, &t, &name, &mut Vec::new())
+                }
+                Some(_) => Err(format!(
+                    "Error: Component {}'s extends must be the name of a template.",
+                    name
+                )),
+            };
+            match base {
+                Ok(mut base) => {
+                    base.extend(c.clone());
+                    *c = base;
+                }
+                // Components sharing a broken template report it once.
+                Err(e) => {
+                    if !errs.contains(&e) {
+                        errs.push(e)
+                    }
+                }
+            }
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(
+            errs.into_iter().map(ComposeError::Spec).collect(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Parse a size in bytes, in decimal or hex (0x...), optionally with
+// a K, M, or G suffix (e.g. "64K" or "0x100000").
+fn size_parse(sz: &str) -> Option<u64> {
+    let sz = sz.trim();
+    let (num, mult) = match sz.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&sz[..sz.len() - 1], 1 << 10),
+        Some('M') => (&sz[..sz.len() - 1], 1 << 20),
+        Some('G') => (&sz[..sz.len() - 1], 1 << 30),
+        _ => (sz, 1),
+    };
+    let n = if let Some(hex) = num.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()?
+    } else {
+        num.parse::<u64>().ok()?
+    };
+
+    n.checked_mul(mult)
+}
+
+// Convert a parameter's value into its initargs representation.
+// Scalars are passed as strings (booleans as "1" or "0" so they can be
+// read with atoi), arrays as maps with each key set to "_", and tables
+// as maps.
+fn param_val(v: &toml::Value) -> Result<ArgsValType, String> {
+    Ok(match v {
+        toml::Value::String(s) => ArgsValType::Str(s.clone()),
+        toml::Value::Integer(i) => ArgsValType::Str(i.to_string()),
+        toml::Value::Float(f) => ArgsValType::Str(f.to_string()),
+        toml::Value::Boolean(b) => ArgsValType::Str(String::from(if *b { "1" } else { "0" })),
+        toml::Value::Datetime(d) => ArgsValType::Str(d.to_string()),
+        // Serialization emits each map's entries in reverse, so
+        // reverse the array to preserve its order for iteration.
+        toml::Value::Array(vs) => ArgsValType::Arr(
+            vs.iter()
+                .rev()
+                .map(|v| {
+                    param_val(v).map(|val| match val {
+                        ArgsValType::Str(s) => ArgsKV::new_key(String::from("_"), s),
+                        ArgsValType::Arr(a) => ArgsKV::new_arr(String::from("_"), a),
+                        ArgsValType::Blob(b) => ArgsKV::new_blob(String::from("_"), b),
+                    })
+                })
+                .collect::<Result<Vec<ArgsKV>, String>>()?,
+        ),
+        toml::Value::Table(t) => {
+            let mut kvs = Vec::new();
+            for (k, v) in t.iter() {
+                if k.contains('/') {
+                    return Err(format!("the parameter key \"{}\" includes a '/'", k));
+                }
+                ArgsKV::insert_path(&mut kvs, k, param_val(v)?)?;
+            }
+            ArgsValType::Arr(kvs)
+        }
+    })
+}
+
+// Decode a parameter's hex literal, e.g. "00 1b 44 11 3a b7", where
+// whitespace between the bytes is ignored.
+fn hex_decode(h: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = h.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("\"{}\" is not a hex literal", h));
+    }
+
+    // unwrap as the digits are valid
+    Ok(digits
+        .chunks(2)
+        .map(|d| u8::from_str_radix(&d.iter().collect::<String>(), 16).unwrap())
+        .collect())
+}
+
+impl Dep {
+    pub fn get_name(&self) -> String {
+        self.srv.clone()
+    }
+}
+
+impl TomlComponent {
+    fn update_options(&mut self) {
+        if self.deps.is_none() {
+            let vs = Vec::new();
+            self.deps = Some(vs);
+        }
+
+        if self.implements.is_none() {
+            let vs = Vec::new();
+            self.implements = Some(vs);
+        }
+
+        if self.constants.is_none() {
+            let vs = Vec::new();
+            self.constants = Some(vs);
+        }
+
+        if self.libs.is_none() {
+            let vs = Vec::new();
+            self.libs = Some(vs);
+        }
+        // TODO: should fill in the default variants here
+    }
+
+    pub fn deps(&self) -> &Vec<Dep> {
+        self.deps.as_ref().unwrap()
+    }
+
+    pub fn interfaces(&self) -> &Vec<InterfaceVariant> {
+        self.implements.as_ref().unwrap()
+    }
+
+    pub fn libs(&self) -> &Vec<String> {
+        self.libs.as_ref().unwrap()
+    }
+}
+
+impl TomlSpecification {
+    fn comp(&self, cname: String) -> Option<&TomlComponent> {
+        self.comps().iter().find(|c| c.name == cname)
+    }
+
+    fn comp_exists(&self, cname: String) -> bool {
+        self.comp(cname).is_some()
+    }
+
+    // The format of the constructor's initfs, or None if it isn't one
+    // we support.
+    fn archive(&self) -> Option<ArchiveFormat> {
+        match self.system.archive {
+            Some(ref a) => ArchiveFormat::from_name(a),
+            None => Some(ArchiveFormat::Tar),
+        }
+    }
+
+    // The format of component `c`'s initargs, or None if it isn't one
+    // we support.
+    fn initargs_format(&self, c: &TomlComponent) -> Option<InitargsFormat> {
+        match c.initargs_format {
+            Some(ref f) => InitargsFormat::from_name(f),
+            None => Some(InitargsFormat::C),
+        }
+    }
+
+    // The architecture of component `c` (or of the system if `c` is
+    // None), or None if it isn't one we support.
+    fn arch(&self, c: Option<&TomlComponent>) -> Option<Arch> {
+        match c
+            .and_then(|c| c.arch.as_ref())
+            .or(self.system.arch.as_ref())
+        {
+            Some(a) => Arch::from_name(a),
+            None => Some(Arch::X86_64),
+        }
+    }
+
+    // The parameters passed to component `c`. Each key is a path
+    // (e.g. "net/ip") into nested arrays of parameters. Parameters
+    // that another component directs "at" `c` are nested under that
+    // component's name, so `c` can tell who configured it.
+    fn param_tree(&self, c: &TomlComponent) -> Result<Vec<ArgsKV>, String> {
+        let mut kvs = Vec::new();
+
+        for other in self.comps() {
+            for p in other.params.as_ref().unwrap_or(&Vec::new()) {
+                if *p.at.as_ref().unwrap_or(&other.name) != c.name {
+                    continue;
+                }
+                let path = if other.name == c.name {
+                    p.key.clone()
+                } else {
+                    format!("{}/{}", other.name, p.key)
+                };
+                let val = match (&p.value, &p.file, &p.hex, &p.base64) {
+                    (None, None, None, None) => ArgsValType::Str(String::from("")),
+                    (Some(v), None, None, None) => param_val(v)?,
+                    (None, Some(f), None, None) => {
+                        let path = Path::new(&self.path)
+                            .parent()
+                            .unwrap_or(Path::new(""))
+                            .join(f);
+                        ArgsValType::Blob(fs::read(&path).map_err(|e| {
+                            format!(
+                                "could not read \"{}\" for \"{}\": {}",
+                                path.display(),
+                                p.key,
+                                e
+                            )
+                        })?)
+                    }
+                    (None, None, Some(h), None) => ArgsValType::Blob(hex_decode(h)?),
+                    (None, None, None, Some(b)) => ArgsValType::Blob(
+                        STANDARD
+                            .decode(b.trim())
+                            .map_err(|e| format!("\"{}\" is not valid base64: {}", p.key, e))?,
+                    ),
+                    _ => {
+                        return Err(format!(
+                        "the parameter \"{}\" has more than one of value, file, hex, and base64",
+                        p.key
+                    ))
+                    }
+                };
+                ArgsKV::insert_path(&mut kvs, &path, val)?;
+            }
+        }
+
+        Ok(kvs)
+    }
+
+    // The global parameters, from the [params] table, passed to
+    // component `c` under the reserved "global" key. A parameter of
+    // `c`'s own with the same key as a global one overrides it, so
+    // that, for example, one component can log more verbosely.
+    fn global_tree(&self, c: &TomlComponent) -> Result<Vec<ArgsKV>, String> {
+        let globals = match self.params {
+            Some(ref g) => g,
+            None => return Ok(Vec::new()),
+        };
+        let own = self.param_tree(c)?;
+        let mut kvs = Vec::new();
+        for (k, v) in globals.iter() {
+            if k.contains('/') {
+                return Err(format!("the global parameter key \"{}\" includes a '/'", k));
+            }
+            let val = match own.iter().find(|kv| kv.key() == k) {
+                Some(kv) => kv.val().clone(),
+                None => param_val(v)?,
+            };
+            ArgsKV::insert_path(&mut kvs, k, val)?;
+        }
+
+        Ok(kvs)
+    }
+
+    // The capabilities delegated to component `c`, if it has any.
+    fn caps(&self, c: &TomlComponent) -> Result<Option<CapDelegations>, String> {
+        let caps = match c.caps {
+            Some(ref caps) => caps,
+            None => return Ok(None),
+        };
+        let mut memory = Vec::new();
+        for m in caps.memory.as_ref().unwrap_or(&Vec::new()) {
+            let base = u64::from_str_radix(m.base.trim_start_matches("0x"), 16).map_err(|_| {
+                format!(
+                    "the memory base \"{}\" is not a hexadecimal address",
+                    m.base
+                )
+            })?;
+            let size = match size_parse(&m.size) {
+                Some(sz) if sz > 0 => sz,
+                _ => {
+                    return Err(format!(
+                        "the memory size \"{}\" is not a (positive) number of bytes",
+                        m.size
+                    ))
+                }
+            };
+            if base.checked_add(size).is_none() {
+                return Err(format!(
+                    "the memory at {:#x} of {} bytes is beyond the address space",
+                    base, size
+                ));
+            }
+            memory.push((base, size));
+        }
+        let untyped = match caps.untyped {
+            Some(ref u) => Some(size_parse(u).ok_or(format!(
+                "the untyped quota \"{}\" is not a number of bytes",
+                u
+            ))?),
+            None => None,
+        };
+
+        Ok(Some(CapDelegations {
+            memory,
+            cores: caps.cores.clone().unwrap_or(Vec::new()),
+            irqs: caps.irqs.clone().unwrap_or(Vec::new()),
+            untyped,
+        }))
+    }
+
+    // The capabilities that are delegated to more than one component
+    // (or twice to one): overlapping ranges of memory, and interrupt
+    // lines. Cores are shared.
+    fn cap_conflicts(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let mut memory = Vec::new();
+        let mut irqs: BTreeMap<u64, Vec<&String>> = BTreeMap::new();
+
+        for c in self.comps() {
+            let caps = match self.caps(c) {
+                Ok(Some(caps)) => caps,
+                _ => continue,
+            };
+            memory.extend(caps.memory.iter().map(|(b, sz)| (*b, *sz, &c.name)));
+            for i in caps.irqs.iter() {
+                irqs.entry(*i).or_default().push(&c.name);
+            }
+        }
+        memory.sort();
+        for (i, (b, sz, name)) in memory.iter().enumerate() {
+            for (b2, sz2, name2) in memory[i + 1..].iter().take_while(|(b2, _, _)| *b2 < b + sz) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Memory [{:#x}, {:#x}) delegated to component {} overlaps [{:#x}, {:#x}) delegated to component {}.",
+                    b,
+                    b + sz,
+                    name,
+                    b2,
+                    b2 + sz2,
+                    name2
+                )));
+            }
+        }
+        for (i, names) in irqs.iter().filter(|(_, ns)| ns.len() > 1) {
+            errs.push(ComposeError::Spec(format!(
+                "Error: Interrupt {} is delegated more than once, to components {}.",
+                i,
+                names
+                    .iter()
+                    .map(|n| n.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )));
+        }
+
+        errs
+    }
+
+    fn cores(&self) -> u64 {
+        self.system.cores.unwrap_or(1)
+    }
+
+    // The cores that component `c` is initialized on.
+    fn affinity(&self, c: &TomlComponent) -> Vec<u64> {
+        match c.affinity {
+            Some(ref a) => a.clone(),
+            None => (0..self.cores()).collect(),
+        }
+    }
+
+    // The cores referenced by the components (their affinity, sched
+    // core, and delegated cores) and by the boot orders must be among
+    // those declared, and each boot order must initialize a
+    // component's servers (on that core) before it.
+    fn disk_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let d = match self.disk {
+            Some(ref d) => d,
+            None => return errs,
+        };
+        // The boot partition's FAT32 filesystem has a minimum size.
+        let boot_min = format!("at least {}M", BOOT_MIN_SIZE >> 20);
+        for (field, sz, min, why) in [
+            ("boot_size", &d.boot_size, BOOT_MIN_SIZE, boot_min.as_str()),
+            ("data_size", &d.data_size, 1, "positive"),
+        ]
+        .iter()
+        {
+            let sz = match sz {
+                Some(sz) => sz,
+                None => continue,
+            };
+            match size_parse(sz) {
+                None => errs.push(ComposeError::Spec(format!(
+                    "Error: Invalid {} \"{}\" for the disk; expected a number of bytes, optionally with a K, M, or G suffix.",
+                    field, sz
+                ))),
+                Some(n) if n < *min => errs.push(ComposeError::Spec(format!(
+                    "Error: The disk's {} \"{}\" must be {}.",
+                    field, sz, why
+                ))),
+                _ => (),
+            }
+        }
+        for f in d.boot_files.iter().flatten() {
+            let path = Path::new(&self.path)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(f);
+            if !path.is_file() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The disk's boot file \"{}\" ({}) doesn't exist.",
+                    f,
+                    path.display()
+                )));
+            }
+        }
+
+        errs
+    }
+
+    // The device-tree describes the hardware of ARM and RISC-V boards;
+    // x86 systems discover theirs from ACPI.
+    fn dtb_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let dtb = match self.system.dtb {
+            Some(ref d) => d,
+            None => {
+                if self.system.dtb_addr.is_some() {
+                    errs.push(ComposeError::Spec(String::from(
+                        "Error: The system's dtb_addr requires a dtb to embed at it.",
+                    )));
+                }
+                return errs;
+            }
+        };
+        let arch = self.arch(None);
+        if arch != Some(Arch::Armv7a) && arch != Some(Arch::Riscv64) {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The system's dtb is only for armv7a and riscv64 systems, which discover their hardware from it.",
+            )));
+        }
+        let path = Path::new(&self.path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(dtb);
+        if !dtb.ends_with(".dts") && !dtb.ends_with(".dtb") {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The system's dtb \"{}\" must be a device-tree's source (.dts), or blob (.dtb).",
+                dtb
+            )));
+        } else if !path.is_file() {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The system's dtb \"{}\" ({}) doesn't exist.",
+                dtb,
+                path.display()
+            )));
+        }
+        // Device-trees are 8-byte aligned, and armv7a's are within
+        // its 32 bit physical addresses.
+        if let Some(ref a) = self.system.dtb_addr {
+            match u64::from_str_radix(a.trim_start_matches("0x"), 16) {
+                Ok(addr) if addr % 8 == 0 && (arch != Some(Arch::Armv7a) || addr >> 32 == 0) => (),
+                Ok(_) => errs.push(ComposeError::Spec(format!(
+                    "Error: The system's dtb_addr, \"{}\", must be 8-byte aligned (and for armv7a, a 32 bit address).",
+                    a
+                ))),
+                Err(_) => errs.push(ComposeError::Spec(format!(
+                    "Error: The system's dtb_addr, \"{}\", is not a hexadecimal address.",
+                    a
+                ))),
+            }
+        }
+
+        errs
+    }
+
+    // The XIP layout's regions, and which systems it's for.
+    fn xip_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let x = match self.xip {
+            Some(ref x) => x,
+            None => return errs,
+        };
+        // Only armv7a's components are linked for XIP.
+        if self.arch(None) != Some(Arch::Armv7a) {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The xip layout is only for armv7a systems.",
+            )));
+        }
+        let mut regions = Vec::new();
+        for (name, base, size) in [
+            ("flash", &x.flash_base, &x.flash_size),
+            ("ram", &x.ram_base, &x.ram_size),
+        ]
+        .iter()
+        {
+            let b = u64::from_str_radix(base.trim_start_matches("0x"), 16).ok();
+            let sz = size_parse(size).filter(|sz| *sz != 0);
+            if b.is_none() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The xip layout's {}_base, \"{}\", is not a hexadecimal address.",
+                    name, base
+                )));
+            }
+            if sz.is_none() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Invalid {}_size \"{}\" for the xip layout; expected a positive number of bytes, optionally with a K, M, or G suffix.",
+                    name, size
+                )));
+            }
+            if let (Some(b), Some(sz)) = (b, sz) {
+                if b % XIP_ALIGN != 0 || b + sz > 1 << 32 {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: The xip layout's {} [{:#x}, {:#x}) must be {}-byte aligned, and within 32 bit addresses.",
+                        name,
+                        b,
+                        b + sz,
+                        XIP_ALIGN
+                    )));
+                }
+                regions.push((b, b + sz));
+            }
+        }
+        if regions.len() == 2 && regions[0].0 < regions[1].1 && regions[1].0 < regions[0].1 {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The xip layout's flash and ram overlap.",
+            )));
+        }
+        // Components are placed one after the other in the flash.
+        for c in self.comps().iter().filter(|c| c.baseaddr.is_some()) {
+            errs.push(ComposeError::Spec(format!(
+                "Error: Component {} has a baseaddr, but the xip layout assigns the components' addresses in the flash.",
+                c.name
+            )));
+        }
+
+        errs
+    }
+
+    fn board_errors(&self) -> Vec<ComposeError> {
+        let board = match self.system.board {
+            Some(ref b) => b,
+            None => return Vec::new(),
+        };
+        let path = Path::new(&self.path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(board);
+        let b = match board_parse(&path) {
+            Ok(b) => b,
+            Err(e) => return vec![e],
+        };
+        let mut errs = Vec::new();
+        if b.flash.is_empty() {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The board's configuration {} has no flash profiles.",
+                path.display()
+            )));
+        }
+        let mut names = HashSet::new();
+        for f in b.flash.iter() {
+            if f.name.is_empty() || !names.insert(&f.name) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The board's flash profile name \"{}\" must be non-empty, and unique.",
+                    f.name
+                )));
+            }
+            let tool = FlashTool::from_name(&f.tool);
+            if tool.is_none() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Unknown tool \"{}\" for flash profile {}; expected \"openocd\", \"probe-rs\", or \"dfu-util\".",
+                    f.tool, f.name
+                )));
+            }
+            match f.region {
+                // dfu-util writes raw images, at the region's base.
+                None if tool == Some(FlashTool::DfuUtil) => errs.push(ComposeError::Spec(format!(
+                    "Error: Flash profile {} uses dfu-util, so must name the memory region to program.",
+                    f.name
+                ))),
+                Some(ref r)
+                    if !self
+                        .memory
+                        .as_ref()
+                        .is_some_and(|m| m.regions.iter().any(|mr| &mr.name == r)) =>
+                {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Flash profile {}'s region {} is not in the memory table.",
+                        f.name, r
+                    )))
+                }
+                _ => (),
+            }
+        }
+
+        errs
+    }
+
+    fn memory_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let m = match self.memory {
+            Some(ref m) => m,
+            None => return errs,
+        };
+        if m.regions.is_empty() {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The memory table must have at least one region.",
+            )));
+        }
+        let mut names = HashSet::new();
+        let mut regions = Vec::new();
+        for r in m.regions.iter() {
+            // Each region's image is named after it.
+            let valid = !r.name.is_empty()
+                && r.name
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-');
+            if !valid {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Invalid memory region name \"{}\"; it must be composed of letters, digits, '_', and '-'.",
+                    r.name
+                )));
+            }
+            if !names.insert(&r.name) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Memory region {} is defined more than once.",
+                    r.name
+                )));
+            }
+            let kind = match r.kind.as_str() {
+                "flash" => Some(RegionKind::Flash),
+                "ram" => Some(RegionKind::Ram),
+                _ => {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Memory region {}'s kind \"{}\" must be \"flash\" or \"ram\".",
+                        r.name, r.kind
+                    )));
+                    None
+                }
+            };
+            let base = u64::from_str_radix(r.base.trim_start_matches("0x"), 16).ok();
+            if base.is_none() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Memory region {}'s base, \"{}\", is not a hexadecimal address.",
+                    r.name, r.base
+                )));
+            }
+            let size = size_parse(&r.size).filter(|sz| *sz != 0);
+            if size.is_none() {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Invalid size \"{}\" for memory region {}; expected a positive number of bytes, optionally with a K, M, or G suffix.",
+                    r.size, r.name
+                )));
+            }
+            if let (Some(kind), Some(base), Some(size)) = (kind, base, size) {
+                if let Some((other, _, _, _)) = regions
+                    .iter()
+                    .find(|(_, _, b, e)| base < *e && *b < base + size)
+                {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Memory regions {} and {} overlap.",
+                        other, r.name
+                    )));
+                }
+                regions.push((&r.name, kind, base, base + size));
+            }
+        }
+        if !m.regions.is_empty() && !m.regions.iter().any(|r| r.kind == "ram") {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The memory table must have a ram region, for the components to run in.",
+            )));
+        }
+        // The XIP layout's flash and RAM must be regions of the board's.
+        if let Some(ref x) = self.xip {
+            let addr = |a: &String| u64::from_str_radix(a.trim_start_matches("0x"), 16).ok();
+            for (kind, base, size) in [
+                (RegionKind::Flash, &x.flash_base, &x.flash_size),
+                (RegionKind::Ram, &x.ram_base, &x.ram_size),
+            ]
+            .iter()
+            {
+                let (b, e) = match (addr(base), size_parse(size)) {
+                    (Some(b), Some(sz)) => (b, b + sz),
+                    _ => continue, // reported with the xip layout's errors
+                };
+                if !regions
+                    .iter()
+                    .any(|(_, k, rb, re)| k == kind && *rb <= b && e <= *re)
+                {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: The xip layout's {} [{:#x}, {:#x}) is not within one of the memory table's {} regions.",
+                        kind.name(),
+                        b,
+                        e,
+                        kind.name()
+                    )));
+                }
+            }
+        }
+
+        errs
+    }
+
+    fn netboot_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let n = match self.netboot {
+            Some(ref n) => n,
+            None => return errs,
+        };
+        if let Some(ref d) = n.dir {
+            let p = Path::new(d);
+            if p.is_absolute() || p.components().any(|c| c.as_os_str() == "..") {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The netboot dir \"{}\" must be relative to (and within) the served directory.",
+                    d
+                )));
+            }
+        }
+        if let Some(ref u) = n.url {
+            if !u.starts_with("http://") && !u.starts_with("https://") {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The netboot url \"{}\" must be an http:// or https:// URL.",
+                    u
+                )));
+            }
+        }
+
+        errs
+    }
+
+    fn core_errors(&self) -> Vec<ComposeError> {
+        let mut errs = Vec::new();
+        let cores = self.cores();
+        if cores == 0 {
+            errs.push(ComposeError::Spec(String::from(
+                "Error: The system must have at least one core.",
+            )));
+            return errs;
+        }
+
+        for c in self.comps() {
+            let mut used = Vec::new();
+            if let Some(ref a) = c.affinity {
+                if a.is_empty() {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s affinity must include at least one core.",
+                        c.name
+                    )));
+                }
+                used.extend(a.iter().map(|k| ("affinity", *k)));
+            }
+            if let Some(k) = c.sched.as_ref().and_then(|s| s.core) {
+                used.push(("sched core", k));
+            }
+            if let Some(ref caps) = c.caps {
+                used.extend(
+                    caps.cores
+                        .iter()
+                        .flat_map(|ks| ks.iter())
+                        .map(|k| ("caps cores", *k)),
+                );
+            }
+            for (field, k) in used.iter().filter(|(_, k)| *k >= cores) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Component {}'s {} includes core {}, but the system has {} core(s).",
+                    c.name, field, k, cores
+                )));
+            }
+        }
+
+        let mut ordered = HashSet::new();
+        for b in self.boot.as_ref().unwrap_or(&Vec::new()) {
+            if b.core >= cores {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: The boot order is for core {}, but the system has {} core(s).",
+                    b.core, cores
+                )));
+                continue;
+            }
+            if !ordered.insert(b.core) {
+                errs.push(ComposeError::Spec(format!(
+                    "Error: Core {} has more than one boot order.",
+                    b.core
+                )));
+            }
+            let mut prior: Vec<&String> = Vec::new();
+            for name in b.order.iter() {
+                let c = match self.comp(name.clone()) {
+                    Some(c) => c,
+                    None => {
+                        let mut msg = format!(
+                            "Error: The boot order for core {} includes {}, which is not a component.",
+                            b.core, name
+                        );
+                        if let Some(s) = similar(name, &self.comp_names()) {
+                            msg.push_str(&format!(" Did you mean {}?", s));
+                        }
+                        errs.push(ComposeError::Spec(msg));
+                        continue;
+                    }
+                };
+                if prior.contains(&name) {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: The boot order for core {} includes {} more than once.",
+                        b.core, name
+                    )));
+                    continue;
+                }
+                if !self.affinity(c).contains(&b.core) {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: The boot order for core {} includes {}, but its affinity doesn't include the core.",
+                        b.core, name
+                    )));
+                }
+                let mut servers: Vec<&String> = c.deps().iter().map(|d| &d.srv).collect();
+                servers.sort();
+                servers.dedup();
+                for srv in servers {
+                    let on_core = self
+                        .comp(srv.clone())
+                        .is_some_and(|s| self.affinity(s).contains(&b.core));
+                    if on_core && !prior.contains(&srv) {
+                        errs.push(ComposeError::Spec(format!(
+                            "Error: The boot order for core {} initializes {} before {}, which it depends on.",
+                            b.core, name, srv
+                        )));
+                    }
+                }
+                prior.push(name);
+            }
         }
 
-        if self.implements.is_none() {
-            let vs = Vec::new();
-            self.implements = Some(vs);
-        }
+        errs
+    }
 
-        if self.constants.is_none() {
-            let vs = Vec::new();
-            self.constants = Some(vs);
+    // The location in the specification of the (quoted) `focus`, on
+    // the first line within component `cname`'s table that includes
+    // all of the (quoted) `values`. The TOML parser doesn't track
+    // positions, so this provides the context for errors. Components
+    // defined in included files aren't found.
+    fn comp_loc(&self, cname: &String, values: &[&String], focus: &String) -> Option<SpecLoc> {
+        let name = format!("\"{}\"", cname);
+        let quoted: Vec<String> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+        let mut in_comp = false;
+
+        for (n, l) in self.source.lines().enumerate() {
+            let t = l.trim();
+            if t.starts_with("[") {
+                in_comp = false;
+            } else if t.starts_with("name") && t.contains(&name) {
+                in_comp = true;
+            }
+            if in_comp && quoted.iter().all(|v| l.contains(v.as_str())) {
+                return SpecLoc::find(&self.path, &self.source, n, &format!("\"{}\"", focus));
+            }
         }
-        // TODO: should fill in the default variants here
-    }
 
-    pub fn deps(&self) -> &Vec<Dep> {
-        self.deps.as_ref().unwrap()
+        None
     }
 
-    pub fn interfaces(&self) -> &Vec<InterfaceVariant> {
-        self.implements.as_ref().unwrap()
-    }
-}
+    // The location of the `nth` (from 0) definition of a component
+    // (or address space) named `name`.
+    fn name_loc(&self, name: &String, nth: usize) -> Option<SpecLoc> {
+        let quoted = format!("\"{}\"", name);
+        let n = self
+            .source
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| l.trim().starts_with("name") && l.contains(&quoted))
+            .map(|(n, _)| n)
+            .nth(nth)?;
 
-impl TomlSpecification {
-    fn comp(&self, cname: String) -> Option<&TomlComponent> {
-        self.comps().iter().find(|c| c.name == cname)
+        SpecLoc::find(&self.path, &self.source, n, &quoted)
     }
 
-    fn comp_exists(&self, cname: String) -> bool {
-        self.comp(cname).is_some()
+    // Point the error `e` at `loc`, if it was found.
+    fn located(e: ComposeError, loc: Option<SpecLoc>) -> ComposeError {
+        match loc {
+            Some(l) => ComposeError::Located(Box::new(e), l),
+            None => e,
+        }
     }
 
     // This MUST be called before anything else in the API
-    fn validate(&mut self) -> Result<(), String> {
+    fn validate(&mut self) -> Result<(), Vec<ComposeError>> {
         self.comps_mut().iter_mut().for_each(|c| c.update_options());
-        let mut fail = false;
-        let mut err_accum = String::new();
+        let mut err_accum = Vec::new();
 
         // Validate that we don't repeat components.
         //
         // TODO: This should really aggregate error strings with fold,
         // and return them as part of the function's Err, but I
         // appearantly can't rust.
-        self.comps().iter().for_each(|c| {
+        self.comps().iter().enumerate().for_each(|(i, c)| {
             if 1 < self
                 .comps()
                 .iter()
                 .fold(0, |n, c2| if c.name != c2.name { n } else { n + 1 })
             {
-                err_accum.push_str(&format!(
-                    "Error: TomlComponent name {} is defined multiple times.",
-                    c.name
+                // Which of the definitions is this?
+                let nth = self.comps()[..i]
+                    .iter()
+                    .filter(|c2| c2.name == c.name)
+                    .count();
+                err_accum.push(TomlSpecification::located(
+                    ComposeError::Spec(format!(
+                        "Error: TomlComponent name {} is defined multiple times.",
+                        c.name
+                    )),
+                    self.name_loc(&c.name, nth),
                 ));
-                fail = true;
             }
         });
 
@@ -150,18 +2396,19 @@ impl TomlSpecification {
             }
 
             let ds: Vec<_> = c.deps().iter().filter(|d| d.srv != "kernel").collect();
-            if ds.len() == 0 {
+            if ds.is_empty() {
                 true
             } else {
-                err_accum.push_str(&format!(
+                err_accum.push(ComposeError::Spec(format!(
                     "Error: Base constructor {} has dependencies.",
                     c.name
-                ));
+                )));
                 false
             }
         }) {
-            err_accum.push_str(&format!("Error: Appropriate system constructor not found."));
-            fail = true;
+            err_accum.push(ComposeError::Spec(
+                "Error: Appropriate system constructor not found.".to_string(),
+            ));
         }
 
         // Check that 1. each address space includes components that
@@ -183,33 +2430,30 @@ impl TomlSpecification {
                         .iter()
                         .fold(false, |found, c| found | (c.name == *as_c))
                     {
-                        err_accum.push_str(&format!("Error: Address space \"{}\" includes component \"{}\" that is not found in the list of components.\n", addrspc.name, as_c));
-                        fail = true;
+                        err_accum.push(ComposeError::Spec(format!("Error: Address space \"{}\" includes component \"{}\" that is not found in the list of components.", addrspc.name, as_c)));
                     }
 
                     // 2. Test that the components are only referenced
                     // a single time in address spaces.
                     if referenced_components.contains(&as_c) {
-                        err_accum.push_str(&format!("Error: Address space \"{}\" includes component {} that is already found in another address space.\n", addrspc.name, as_c));
-                        fail = true;
+                        err_accum.push(ComposeError::Spec(format!("Error: Address space \"{}\" includes component {} that is already found in another address space.", addrspc.name, as_c)));
                     } else {
                         referenced_components.push(as_c);
                     }
                 }
 
                 // 3. ensure that address spaces have non-empty names.
-                if addrspc.name.len() == 0 {
-                    err_accum.push_str(&format!(
-                        "Error: Address space has empty name. Must provide a non-empty name.\n"
+                if addrspc.name.is_empty() {
+                    err_accum.push(ComposeError::Spec(
+                        "Error: Address space has empty name. Must provide a non-empty name."
+                            .to_string(),
                     ));
-                    fail = true;
                     continue;
                 }
 
                 // 4. Make sure that address spaces have unique names.
                 if addrspc_names.contains(&addrspc.name) {
-                    err_accum.push_str(&format!("Error: Address space \"{}\" name is used by multiple address spaces; address spaces must have unique names.\n", addrspc.name));
-                    fail = true;
+                    err_accum.push(ComposeError::Spec(format!("Error: Address space \"{}\" name is used by multiple address spaces; address spaces must have unique names.", addrspc.name)));
                 } else {
                     addrspc_names.push(addrspc.name.clone());
                 }
@@ -226,9 +2470,8 @@ impl TomlSpecification {
             // named address spaces.
             for (addrspc, parent) in &as_and_parents {
                 if let Some(p) = parent {
-                    if !addrspc_names.contains(&p) {
-                        err_accum.push_str(&format!("Error: Address space \"{}\" has parent \"{}\" where that name is not found among the names of address spaces.\n", addrspc, p));
-                        fail = true;
+                    if !addrspc_names.contains(p) {
+                        err_accum.push(ComposeError::Spec(format!("Error: Address space \"{}\" has parent \"{}\" where that name is not found among the names of address spaces.", addrspc, p)));
                     }
                 }
             }
@@ -253,7 +2496,7 @@ impl TomlSpecification {
             );
             // Iterate while we have more ASes to process, or until
             // there are no changes to the sets.
-            while as_and_parents.len() > 0 && parents.len() != parents_len {
+            while !as_and_parents.is_empty() && parents.len() != parents_len {
                 parents_len = parents.len();
                 let tmp = as_and_parents.iter().fold(
                     (parents, Vec::new()),
@@ -275,8 +2518,7 @@ impl TomlSpecification {
                 as_and_parents = tmp.1;
             }
             for (as_spc, p) in &as_and_parents {
-                err_accum.push_str(&format!("Error: Address spaces \"{}\" and \"{}\" are involved in a cycle of parent dependencies. Cycles are not allowed.\n", as_spc, p.as_ref().unwrap()));
-                fail = true;
+                err_accum.push(ComposeError::Spec(format!("Error: Address spaces \"{}\" and \"{}\" are involved in a cycle of parent dependencies. Cycles are not allowed.", as_spc, p.as_ref().unwrap())));
             }
         }
 
@@ -289,34 +2531,97 @@ impl TomlSpecification {
             for d in c.deps() {
                 if d.get_name() == "kernel" {
                     if d.variant.is_none() {
-                        err_accum.push_str(&format!(
+                        err_accum.push(ComposeError::Spec(format!(
                             "Error: Component {}'s dependency on the kernel for interface {} must specify a variant.",
                             c.name, d.interface
-                        ));
-                        fail = true;
+                        )));
                     }
-                } else if let Some(ref s) = self.comp(d.get_name()) {
+                } else if let Some(s) = self.comp(d.get_name()) {
                     if s.interfaces()
                         .iter()
                         .find(|i| i.interface == d.interface)
                         .is_none()
                     {
-                        err_accum.push_str(&format!(
-                            "Error: Component {}'s dependency on {} is not exported by any depended on components.",
-                            c.name, d.interface
+                        err_accum.push(TomlSpecification::located(
+                            ComposeError::MissingInterface {
+                                client: c.name.clone(),
+                                interface: d.interface.clone(),
+                                server: s.name.clone(),
+                                exports: s
+                                    .interfaces()
+                                    .iter()
+                                    .map(|i| i.interface.clone())
+                                    .collect(),
+                            },
+                            self.comp_loc(&c.name, &[&d.srv, &d.interface], &d.interface),
                         ));
-                        fail = true;
+                    } else if let Some(ref v) = d.variant {
+                        // The client chose one of the variants that
+                        // the server builds.
+                        let variants: Vec<&str> = s
+                            .interfaces()
+                            .iter()
+                            .filter(|i| i.interface == d.interface)
+                            .map(|i| i.variant.as_ref().map_or("stubs", |v| v.as_str()))
+                            .collect();
+                        if !variants.contains(&v.as_str()) {
+                            err_accum.push(TomlSpecification::located(
+                                ComposeError::Spec(format!(
+                                    "Error: Component {} depends on variant {} of interface {}, but server {} only builds [{}].",
+                                    c.name,
+                                    v,
+                                    d.interface,
+                                    s.name,
+                                    variants.join(", ")
+                                )),
+                                self.comp_loc(&c.name, &[&d.srv, &d.interface, v], v),
+                            ));
+                        }
                     }
                 } else {
-                    err_accum.push_str(&format!(
-                        "Error: Cannot find component referenced by dependency {} in component {}.",
-                        d.get_name(),
-                        c.name
+                    err_accum.push(TomlSpecification::located(
+                        ComposeError::MissingComponent {
+                            name: d.get_name(),
+                            referrer: c.name.clone(),
+                            reference: String::from("dependency"),
+                            candidates: self.comp_names(),
+                        },
+                        self.comp_loc(&c.name, &[&d.srv, &d.interface], &d.srv),
                     ));
-                    fail = true;
                 }
             }
         }
+        err_accum.extend(self.export_conflicts());
+        err_accum.extend(self.cap_conflicts());
+        // Ids are dense, from 1, so pins must be within the
+        // components' ids.
+        let mut pinned = BTreeMap::new();
+        for c in self.comps() {
+            if let Some(id) = c.id {
+                if id == 0 || id > self.comps().len() as u64 {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s id, {}, must be between 1 and the number of components ({}).",
+                        c.name,
+                        id,
+                        self.comps().len()
+                    )));
+                } else if let Some(other) = pinned.insert(id, &c.name) {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Components {} and {} are both pinned to id {}.",
+                        other, c.name, id
+                    )));
+                }
+            }
+        }
+        // Dependency cycles prevent the components from being
+        // ordered for construction, so report the exact paths.
+        for cycle in self.dep_cycles() {
+            err_accum.push(ComposeError::Spec(format!(
+                "Error: Component dependencies form a cycle: {}.",
+                cycle.join(" -> ")
+            )));
+        }
+
         // validate that all directed params are to declared
         // components
         for c in self.comps() {
@@ -324,59 +2629,462 @@ impl TomlSpecification {
                 for ia in args.iter() {
                     if let Some(ref name) = ia.at {
                         if !self.comp_exists(name.to_string()) {
-                            err_accum.push_str(&format!("Error: Cannot find component referenced by directed params {} in component {}.",
-                                                        name, c.name));
-                            fail = true;
+                            err_accum.push(TomlSpecification::located(
+                                ComposeError::MissingComponent {
+                                    name: name.clone(),
+                                    referrer: c.name.clone(),
+                                    reference: String::from("directed params"),
+                                    candidates: self.comp_names(),
+                                },
+                                self.comp_loc(&c.name, &[&ia.key, name], name),
+                            ));
                         }
                     }
                 }
             }
+            if let Err(e) = self.param_tree(c).and_then(|_| self.global_tree(c)) {
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Invalid parameters for component {}: {}.",
+                    c.name, e
+                )));
+            }
+        }
+
+        if self.archive().is_none() {
+            err_accum.push(ComposeError::Spec(format!(
+                "Error: Unknown archive format \"{}\" for the system; expected \"tar\", \"cpio\", or \"flat\".",
+                self.system.archive.as_ref().unwrap()
+            )));
+        }
+        // cpio places each file's contents after its header and name,
+        // so they can't be aligned without padding entries that its
+        // readers would see as files.
+        if self.system.page_align == Some(true) && self.archive() == Some(ArchiveFormat::Cpio) {
+            err_accum.push(ComposeError::Spec(String::from(
+                "Error: The system's page_align requires its archive to be \"tar\" or \"flat\", not \"cpio\".",
+            )));
+        }
+
+        if self.arch(None).is_none() {
+            err_accum.push(ComposeError::Spec(format!(
+                "Error: Unknown architecture \"{}\" for the system; expected \"x86_64\", \"i386\", \"armv7a\", or \"riscv64\".",
+                self.system.arch.as_ref().unwrap()
+            )));
+        }
+        err_accum.extend(self.dtb_errors());
+        if let Some(ref p) = self.system.profile {
+            if Profile::from_name(p).is_none() {
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Unknown build profile \"{}\" for the system; expected \"debug\" or \"release\".",
+                    p
+                )));
+            }
+        }
+        if let Some(ref c) = self.system.compiler_cache {
+            if CompilerCache::from_name(c).is_none() {
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Unknown compiler cache \"{}\" for the system; expected \"ccache\" or \"sccache\".",
+                    c
+                )));
+            }
+        }
+        if let Some(ref sz) = self.system.max_initfs_size {
+            if size_parse(sz).is_none() {
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Invalid max_initfs_size \"{}\" for the system; expected a number of bytes, optionally with a K, M, or G suffix.",
+                    sz
+                )));
+            }
+        }
+        err_accum.extend(self.disk_errors());
+        err_accum.extend(self.netboot_errors());
+        err_accum.extend(self.xip_errors());
+        err_accum.extend(self.memory_errors());
+        err_accum.extend(self.board_errors());
+        err_accum.extend(self.core_errors());
+        if let Some(0) = self.test.as_ref().and_then(|t| t.timeout) {
+            err_accum.push(ComposeError::Spec(String::from(
+                "Error: The test's timeout must be a positive number of seconds.",
+            )));
+        }
+        for c in self.comps() {
+            if let Some(ref sz) = c.max_size {
+                if size_parse(sz).is_none() {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Invalid max_size \"{}\" for component {}; expected a number of bytes, optionally with a K, M, or G suffix.",
+                        sz, c.name
+                    )));
+                }
+            }
+            if let Some(ref sz) = c.max_initargs {
+                if size_parse(sz).is_none() {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Invalid max_initargs \"{}\" for component {}; expected a number of bytes, optionally with a K, M, or G suffix.",
+                        sz, c.name
+                    )));
+                }
+            }
+            match self.initargs_format(c) {
+                None => err_accum.push(ComposeError::Spec(format!(
+                    "Error: Unknown initargs_format \"{}\" for component {}; expected \"c\" or \"tlv\".",
+                    c.initargs_format.as_ref().unwrap(),
+                    c.name
+                ))),
+                // A constructor's initargs describe how to read its
+                // archive (e.g. its format and checksums), so
+                // they can't be within it.
+                Some(InitargsFormat::Tlv) if self.comps().iter().any(|o| o.constructor == c.name) => {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {} is a constructor, so its initargs_format must be \"c\".",
+                        c.name
+                    )))
+                }
+                _ => (),
+            }
+            if let (Some(a), None) = (&c.arch, self.arch(Some(c))) {
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Unknown architecture \"{}\" for component {}.",
+                    a, c.name
+                )));
+            }
+            if let Some(ref sched) = c.sched {
+                if !c
+                    .deps()
+                    .iter()
+                    .any(|d| d.interface == "init" && d.srv != "kernel")
+                {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {} has sched parameters, but no scheduler to pass them to (it must depend on one for the init interface).",
+                        c.name
+                    )));
+                }
+                match (sched.budget_us, sched.period_us) {
+                    (None, None) => (),
+                    (Some(b), Some(p)) if b > 0 && b <= p => (),
+                    _ => err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s sched budget_us and period_us must be provided together, with 0 < budget_us <= period_us.",
+                        c.name
+                    ))),
+                }
+            }
+            if c.caps.is_some() {
+                if !c.deps().iter().any(|d| {
+                    (d.interface == "capmgr" || d.interface == "capmgr_create") && d.srv != "kernel"
+                }) {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {} has caps, but no capability manager to delegate them (it must depend on one for the capmgr or capmgr_create interface).",
+                        c.name
+                    )));
+                }
+                if let Err(e) = self.caps(c) {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Invalid caps for component {}: {}.",
+                        c.name, e
+                    )));
+                }
+            }
+        }
+
+        // Components that share page-tables, in an address space or
+        // its parent, must be of the same architecture.
+        for a in self.ases().as_ref().unwrap_or(&Vec::new()) {
+            let parent = self
+                .ases()
+                .as_ref()
+                .unwrap()
+                .iter()
+                .find(|p| Some(&p.name) == a.parent.as_ref());
+            let names = a
+                .components
+                .iter()
+                .chain(parent.iter().flat_map(|p| p.components.iter()));
+            let arches: HashSet<&'static str> = names
+                .filter_map(|n| self.comp(n.clone()))
+                .filter_map(|c| self.arch(Some(c)))
+                .map(|a| a.name())
+                .collect();
+            if arches.len() > 1 {
+                let mut arches: Vec<&str> = arches.into_iter().collect();
+                arches.sort();
+                err_accum.push(ComposeError::Spec(format!(
+                    "Error: Address space \"{}\" shares page-tables between components of different architectures ({}).",
+                    a.name,
+                    arches.join(", ")
+                )));
+            }
+        }
+
+        // The initfs must name host files, and place them within the
+        // tarball.
+        for c in self.comps() {
+            for e in c.initfs.as_ref().unwrap_or(&Vec::new()) {
+                let outside = e
+                    .dest
+                    .as_ref()
+                    .is_some_and(|d| d.starts_with('/') || d.split('/').any(|p| p == ".."));
+                if e.path.is_empty() || outside {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {} has an invalid initfs entry (path = \"{}\", as = \"{}\"); the path must be non-empty, and \"as\" must be relative to the tarball's root.",
+                        c.name,
+                        e.path,
+                        e.dest.as_ref().unwrap_or(&String::new())
+                    )));
+                }
+            }
+        }
+
+        // Base addresses are hexadecimal, e.g. "0x1600000".
+        for c in self.comps() {
+            if let Some(ref a) = c.baseaddr {
+                if u64::from_str_radix(a.trim_start_matches("0x"), 16).is_err() {
+                    err_accum.push(TomlSpecification::located(
+                        ComposeError::Spec(format!(
+                            "Error: Component {}'s base address, \"{}\", is not a hexadecimal address.",
+                            c.name, a
+                        )),
+                        self.comp_loc(&c.name, &[a], a),
+                    ));
+                }
+            }
+        }
+
+        // The composer controls the COMP_* variables of the build.
+        for c in self.comps() {
+            for var in c.make_vars.as_ref().unwrap_or(&BTreeMap::new()).keys() {
+                let valid = !var.is_empty()
+                    && var
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+                if !valid || var.starts_with("COMP_") {
+                    err_accum.push(ComposeError::Spec(format!(
+                        "Error: Component {} has an invalid make variable \"{}\"; it must be composed of letters, digits, and '_', and cannot start with COMP_.",
+                        c.name, var
+                    )));
+                }
+            }
         }
 
         for c in self.comps() {
             if let Some(constants) = &c.constants {
                 for constant in constants {
                     if constant.variable.is_empty() || constant.value.is_empty() {
-                        err_accum.push_str(&format!(
+                        err_accum.push(ComposeError::Spec(format!(
                             "Error: Component {} has an invalid constant with an empty variable or value.",
                             c.name
-                        ));
-                        fail = true;
+                        )));
                     }
                 }
             }
         }
 
         for c in self.comps() {
-            if !self.comps().iter().fold(false, |accum, c2| {
-                c.constructor == "kernel" || c.constructor == c2.name || accum
-            }) {
-                err_accum.push_str(&format!(
-                    "Error: Component {}'s stated constructor ({}) is not a valid component.",
-                    c.name, c.constructor
+            if !self
+                .comps()
+                .iter()
+                .any(|c2| c.constructor == "kernel" || c.constructor == c2.name)
+            {
+                err_accum.push(TomlSpecification::located(
+                    ComposeError::MissingComponent {
+                        name: c.constructor.clone(),
+                        referrer: c.name.clone(),
+                        reference: String::from("constructor"),
+                        // The booter is constructed by the kernel.
+                        candidates: self
+                            .comp_names()
+                            .into_iter()
+                            .chain(Some(String::from("kernel")))
+                            .collect(),
+                    },
+                    self.comp_loc(&c.name, &[&c.constructor], &c.constructor),
                 ));
-                fail = true;
             }
         }
 
-        if self
+        let booters: Vec<&String> = self
             .comps()
             .iter()
             .filter(|c| c.constructor == "kernel")
-            .count()
-            != 1
-        {
-            err_accum.push_str(&format!("Error: the number of base constructors (with constructor = \"kernel\") is not singular."));
-            fail = true;
+            .map(|c| &c.name)
+            .collect();
+        match booters.len() {
+            0 => err_accum.push(ComposeError::Spec(format!(
+                "Error: No component is the booter, with constructor = \"kernel\", so the system cannot be loaded. (The components are [{}].)",
+                self.comp_names().join(", ")
+            ))),
+            1 => (),
+            _ => err_accum.push(ComposeError::Spec(format!(
+                "Error: Only one component can be the booter, with constructor = \"kernel\", but [{}] are.",
+                booters
+                    .iter()
+                    .map(|b| b.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))),
         }
 
-        if fail {
+        if !err_accum.is_empty() {
             Err(err_accum)
         } else {
             Ok(())
         }
     }
 
+    // Find the cycles in the dependency relation between components
+    // via a depth-first search, each returned as the path of
+    // component names that starts and ends with the same component.
+    // Dependencies on the kernel, or on missing components, are
+    // ignored as they are reported elsewhere.
+    fn dep_cycles(&self) -> Vec<Vec<String>> {
+        fn visit<'a>(
+            spec: &'a TomlSpecification,
+            c: &'a TomlComponent,
+            path: &mut Vec<&'a String>,
+            done: &mut HashSet<&'a String>,
+            cycles: &mut Vec<Vec<String>>,
+        ) {
+            if done.contains(&c.name) {
+                return;
+            }
+            if let Some(start) = path.iter().position(|n| **n == c.name) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(c.name.clone());
+                cycles.push(cycle);
+                return;
+            }
+            path.push(&c.name);
+            let mut srvs: Vec<&String> = Vec::new();
+            for d in c.deps() {
+                if !srvs.contains(&&d.srv) {
+                    srvs.push(&d.srv);
+                }
+            }
+            for srv in srvs {
+                if let Some(s) = spec.comps().iter().find(|s| s.name == *srv) {
+                    visit(spec, s, path, done, cycles);
+                }
+            }
+            path.pop();
+            done.insert(&c.name);
+        }
+
+        let mut cycles = Vec::new();
+        let mut done = HashSet::new();
+        for c in self.comps() {
+            visit(self, c, &mut Vec::new(), &mut done, &mut cycles);
+        }
+        cycles
+    }
+
+    // The exports (and the dependencies on them) that are ambiguous:
+    // a component exporting the same variant of an interface twice, a
+    // dependency that doesn't choose between the several variants its
+    // server builds, and a component depending on the same interface
+    // of a server more than once (e.g. as different variants).
+    // Otherwise one of them would be silently chosen.
+    fn export_conflicts(&self) -> Vec<ComposeError> {
+        let variant = |v: &Option<String>| v.clone().unwrap_or(String::from("stubs"));
+        let mut errs = Vec::new();
+
+        for c in self.comps() {
+            let mut exported = HashSet::new();
+            for i in c.interfaces() {
+                if !exported.insert((&i.interface, variant(&i.variant))) {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {} exports variant {} of interface {} more than once.",
+                        c.name,
+                        variant(&i.variant),
+                        i.interface
+                    )));
+                }
+            }
+
+            let mut depended = HashSet::new();
+            for d in c.deps().iter().filter(|d| d.srv != "kernel") {
+                if !depended.insert((&d.srv, &d.interface)) {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {} depends on interface {} of {} more than once; it can only use one of its variants.",
+                        c.name, d.interface, d.srv
+                    )));
+                }
+                if d.variant.is_some() {
+                    continue;
+                }
+                let mut variants: Vec<String> = Vec::new();
+                for i in self.comp(d.srv.clone()).iter().flat_map(|s| s.interfaces()) {
+                    if i.interface == d.interface && !variants.contains(&variant(&i.variant)) {
+                        variants.push(variant(&i.variant));
+                    }
+                }
+                if variants.len() > 1 {
+                    errs.push(ComposeError::Spec(format!(
+                        "Error: Component {}'s dependency on interface {} of {} is ambiguous, as it builds several variants ({}); choose one with variant.",
+                        c.name,
+                        d.interface,
+                        d.srv,
+                        variants.join(", ")
+                    )));
+                }
+            }
+        }
+
+        errs
+    }
+
+    // The components that the system doesn't need. Applications
+    // (components that export no interfaces) and the booter are
+    // needed, as are the servers and constructors that needed
+    // components (transitively) depend on. The rest are servers that
+    // no needed component uses.
+    fn unreachable(&self) -> Vec<String> {
+        let mut pending: Vec<&TomlComponent> = self
+            .comps()
+            .iter()
+            .filter(|c| c.constructor == "kernel" || c.interfaces().is_empty())
+            .collect();
+        let mut reached = HashSet::new();
+
+        while let Some(c) = pending.pop() {
+            if !reached.insert(&c.name) {
+                continue;
+            }
+            for n in c.deps().iter().map(|d| &d.srv).chain(Some(&c.constructor)) {
+                if let Some(s) = self.comps().iter().find(|s| s.name == *n) {
+                    pending.push(s);
+                }
+            }
+        }
+
+        self.comps()
+            .iter()
+            .filter(|c| !reached.contains(&c.name))
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    // Remove the unreachable components (from the system, and so from
+    // the constructor's tarball), returning their names.
+    pub fn prune_unreachable(&mut self) -> Vec<String> {
+        let pruned = self.unreachable();
+
+        self.components.retain(|c| !pruned.contains(&c.name));
+        if let Some(ref mut ases) = self.address_spaces {
+            for a in ases.iter_mut() {
+                a.components.retain(|c| !pruned.contains(c));
+            }
+        }
+
+        pruned
+    }
+
+    // The names of the components, sorted.
+    fn comp_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.comps().iter().map(|c| c.name.clone()).collect();
+        names.sort();
+        names.dedup();
+
+        names
+    }
+
     pub fn comps(&self) -> &Vec<TomlComponent> {
         &self.components
     }
@@ -389,29 +3097,115 @@ impl TomlSpecification {
         &mut self.components
     }
 
-    pub fn parse(sysspec_path: &String) -> Result<TomlSpecification, String> {
-        let conf = dump_file(&sysspec_path)?;
-        // This is BRAIN DEAD.  There has to be a better way to get a str
-        let cossys_pre: Result<TomlSpecification, _> =
-            toml::from_str(String::from_utf8(conf).unwrap().as_str());
-
-        if let Err(cs) = cossys_pre {
-            let mut e = String::from("Error when parsing TOML:\n");
-            e.push_str(&format!("{:?}", cs));
-            return Err(e);
-        }
+    // Parse the specification at `sysspec_path`, with the `vars`
+    // defined, the fields `overrides`, and the `features` enabled
+    // from outside of it.
+    pub fn parse(
+        sysspec_path: &String,
+        vars: &BTreeMap<String, String>,
+        overrides: &Vec<(String, String)>,
+        features: &BTreeSet<String>,
+    ) -> Result<TomlSpecification, ComposeError> {
+        let source = spec_read(sysspec_path)?;
+        let mut spec = toml_parse(sysspec_path, &source)?;
+        includes_merge(&mut spec, sysspec_path)?;
+        templates_resolve(&mut spec)?;
+        // Overridden values can use variables, too.
+        overrides_apply(&mut spec, overrides)?;
+        vars_resolve(&mut spec, vars)?;
+        // After the overrides, which can change the architecture.
+        conditions_resolve(&mut spec, features)?;
+        instances_expand(&mut spec)?;
+        deps_resolve(&mut spec)?;
+        let mut stack = fs::canonicalize(sysspec_path).into_iter().collect();
+        subsystems_merge(&mut spec, sysspec_path, features, &mut stack)?;
 
-        let mut cossys = cossys_pre.unwrap();
-        if let Err(s) = cossys.validate() {
-            let mut e = String::from("Error in system specification:\n");
-            e.push_str(&format!("{}", s));
-            return Err(e);
+        let mut cossys: TomlSpecification = spec.try_into().map_err(|e| ComposeError::Parse {
+            path: sysspec_path.clone(),
+            msg: format!("{:?}", e),
+        })?;
+        cossys.source = source;
+        cossys.path = sysspec_path.clone();
+        if let Err(es) = cossys.validate() {
+            return Err(ComposeError::Invalid(es));
         }
 
         Ok(cossys)
     }
 }
 
+// A workspace lists the compositions built together (`build --all`),
+// each with its `name` (by default, its specification's file stem)
+// and the path of its `spec`, relative to the workspace. They share
+// component definitions by including the same files, and share the
+// cache of built objects.
+#[derive(Debug, Deserialize)]
+pub struct TomlComposition {
+    name: Option<String>,
+    spec: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TomlWorkspace {
+    compositions: Vec<TomlComposition>,
+}
+
+// The compositions of the workspace at `path`, as (name,
+// specification path) pairs, in order.
+pub fn workspace_parse(path: &String) -> Result<Vec<(String, String)>, ComposeError> {
+    let ws: TomlWorkspace = toml_parse(path, &spec_read(path)?)?
+        .try_into()
+        .map_err(|e| ComposeError::Parse {
+            path: path.clone(),
+            msg: format!("{:?}", e),
+        })?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let mut errs = Vec::new();
+    if ws.compositions.is_empty() {
+        errs.push(ComposeError::Spec(format!(
+            "Error: The workspace {} lists no compositions.",
+            path
+        )));
+    }
+    let mut comps: Vec<(String, String)> = Vec::new();
+    for c in ws.compositions.iter() {
+        let spec = dir.join(&c.spec);
+        let name = match c.name {
+            Some(ref n) => n.clone(),
+            None => spec
+                .file_stem()
+                .map_or(String::new(), |s| s.to_string_lossy().to_string()),
+        };
+        // Names become build directories.
+        if name.is_empty() || name.contains('/') {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The composition of {} in workspace {} has the invalid name \"{}\".",
+                c.spec, path, name
+            )));
+        }
+        if comps.iter().any(|(n, _)| *n == name) {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The workspace {} lists several compositions named {}.",
+                path, name
+            )));
+        }
+        if !spec.is_file() {
+            errs.push(ComposeError::Spec(format!(
+                "Error: The specification {} of composition {} (in workspace {}) does not exist.",
+                spec.display(),
+                name,
+                path
+            )));
+        }
+        comps.push((name, spec.display().to_string()));
+    }
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(comps)
+}
+
 pub struct SystemSpec {
     ids: Vec<ComponentName>,
     components: HashMap<ComponentName, Component>,
@@ -419,6 +3213,25 @@ pub struct SystemSpec {
     libs: HashMap<ComponentName, Vec<Library>>,
     exports: HashMap<ComponentName, Vec<Export>>,
     address_spaces: HashMap<AddrSpcName, AddrSpace>,
+    archive: ArchiveFormat,
+    page_align: bool,
+    arch: Arch,
+    profile: Option<Profile>,
+    build_dir: Option<String>,
+    sign_key: Option<String>,
+    dtb: Option<String>,
+    dtb_addr: Option<u64>,
+    disk: DiskLayout,
+    netboot: NetbootLayout,
+    xip: Option<XipLayout>,
+    memory: Vec<MemoryRegion>,
+    flash_profiles: Vec<FlashProfile>,
+    compiler_cache: Option<CompilerCache>,
+    max_initfs_size: Option<u64>,
+    test_expect: Vec<String>,
+    test_timeout: u64,
+    cores: u64,
+    boot_order: BTreeMap<u64, Vec<ComponentName>>,
 }
 
 // Helper functions to compute components in an address space, and
@@ -436,7 +3249,7 @@ fn addrspc_parent_closure<'a>(
             let children_set: HashSet<&'a AddrSpcName> = a.children.iter().collect();
             cs = cs
                 .union(&addrspc_parent_closure(children_set, all))
-                .map(|c| *c)
+                .copied()
                 .collect();
         }
     }
@@ -458,7 +3271,7 @@ fn addrspc_components<'a>(
         .iter()
         .fold(HashSet::new(), |agg, a| {
             let comps_as_set = all.get(*a).unwrap().components.iter().collect();
-            agg.union(&comps_as_set).map(|c| *c).collect()
+            agg.union(&comps_as_set).copied().collect()
         })
         .into_iter()
         .collect();
@@ -466,38 +3279,83 @@ fn addrspc_components<'a>(
     (parent_comps, descendent_comps)
 }
 
+// Seconds a test run has to print its expected output, by default.
+const TEST_TIMEOUT: u64 = 60;
+// The size of the disk image's boot partition, by default.
+const DISK_BOOT_SIZE: u64 = 64 << 20;
+// Where the system's image is in the netboot directory, by default.
+const NETBOOT_DIR: &str = "composite";
+
+// The patterns a test run's output must match: those of the spec's
+// `test` table, followed by those in the specification's sibling
+// `.expected` file (e.g. ping_pong.expected for ping_pong.toml), one
+// per line, ignoring empty lines and `#` comments.
+fn test_expect(input: &String, spec: &TomlSpecification) -> Result<Vec<String>, ComposeError> {
+    let mut pats = spec
+        .test
+        .as_ref()
+        .and_then(|t| t.expect.clone())
+        .unwrap_or_default();
+    let expected = Path::new(input).with_extension("expected");
+    if expected.exists() {
+        let contents = dump_file(&expected.display().to_string())?;
+        pats.extend(
+            String::from_utf8_lossy(&contents)
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with("#"))
+                .map(|l| l.to_string()),
+        );
+    }
+
+    Ok(pats)
+}
+
 impl Transition for SystemSpec {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
-        let spec_err = TomlSpecification::parse(&s.get_input());
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
+        let spec_err = TomlSpecification::parse(
+            &s.get_input(),
+            s.get_vars(),
+            s.get_overrides(),
+            s.get_features(),
+        );
         if let Err(e) = spec_err {
             return Err(e);
         }
 
-        let spec = spec_err.unwrap();
+        let mut spec = spec_err.unwrap();
+        if s.get_prune_unreachable() {
+            let pruned = spec.prune_unreachable();
+            if !pruned.is_empty() {
+                warn!(
+                    "Pruning components that no application, nor the booter, depends on: {}.",
+                    pruned.join(", ")
+                );
+            }
+        }
         let ids = spec
             .comps()
             .iter()
             .map(|c| ComponentName::new(&c.name, &String::from("global")))
             .collect();
-        // TODO: no libs currently considered
-        let libs: HashMap<ComponentName, Vec<Library>> = HashMap::new();
+        let mut libs: HashMap<ComponentName, Vec<Library>> = HashMap::new();
         let mut components: HashMap<ComponentName, Component> = HashMap::new();
         let mut deps: HashMap<ComponentName, Vec<Dependency>> = HashMap::new();
         let mut exports: HashMap<ComponentName, Vec<Export>> = HashMap::new();
 
         for c in spec.comps().iter() {
-            // TODO: assuming no use of "at" currently
             let ds: Vec<Dependency> = c
                 .deps()
                 .iter()
                 .map(|d| Dependency {
                     server: ComponentName::new(&d.srv, &String::from("global")),
                     interface: d.interface.clone(),
-                    // The variant is associated with the server,
-                    // so we have to find the correct server,
-                    // then the correct interface to find the
-                    // variant. Note: the unwraps here are valid
-                    // as they are checked in the validation step
+                    // Unless the client chooses one, the variant
+                    // is the (first) one the server builds, so we
+                    // have to find the correct server, then the
+                    // correct interface to find the variant. Note:
+                    // the unwraps here are valid as they are
+                    // checked in the validation step
                     variant: d.variant.clone().unwrap_or_else(|| {
                         spec.comp(d.srv.clone())
                             .unwrap()
@@ -536,29 +3394,33 @@ impl Transition for SystemSpec {
                 constructor: ComponentName::new(&c.constructor, &String::from("global")),
                 scheduler: sched_name,
                 source: c.img.clone(),
-                base_vaddr: c
-                    .baseaddr
-                    .as_ref()
-                    .unwrap_or(&String::from("0x00400000"))
-                    .clone(),
-                params: c
-                    .params
-                    .as_ref()
-                    .unwrap_or(&Vec::new())
-                    .iter()
-                    .map(|p| {
-                        ArgsKV::new_key(
-                            p.key.clone(),
-                            p.value.as_ref().unwrap_or(&String::from("")).clone(),
-                        )
-                    })
-                    .collect(),
-                fsimg: c.initfs.clone(),
+                // unwrap valid as the architectures are validated
+                arch: spec.arch(Some(c)).unwrap(),
+                base_vaddr: c.baseaddr.clone(),
+                // unwrap valid as the parameters are validated
+                params: spec.param_tree(c).unwrap(),
+                globals: spec.global_tree(c).unwrap(),
+                initfs: c.initfs.as_ref().unwrap_or(&Vec::new()).clone(),
                 constants: c.constants.as_ref().unwrap_or(&Vec::new()).clone(),
+                cflags: c.cflags.clone(),
+                make_vars: c.make_vars.clone().unwrap_or(BTreeMap::new()),
+                max_size: c.max_size.as_ref().and_then(|sz| size_parse(sz)),
+                // unwrap valid as the formats are validated
+                initargs_format: spec.initargs_format(c).unwrap(),
+                max_initargs: c.max_initargs.as_ref().and_then(|sz| size_parse(sz)),
+                sched: c.sched.clone(),
+                // unwrap valid as the capabilities are validated
+                caps: spec.caps(c).unwrap(),
+                pinned_id: c.id.map(|id| id as ComponentId),
+                affinity: spec.affinity(c),
             };
             components.insert(ComponentName::new(&c.name, &String::from("global")), comp);
             deps.insert(ComponentName::new(&c.name, &String::from("global")), ds);
             exports.insert(ComponentName::new(&c.name, &String::from("global")), es);
+            libs.insert(
+                ComponentName::new(&c.name, &String::from("global")),
+                c.libs().clone(),
+            );
         }
 
         // Create the address spaces structure
@@ -570,7 +3432,7 @@ impl Transition for SystemSpec {
                 let components = addrspc
                     .components
                     .iter()
-                    .map(|c| ComponentName::new(&c, &String::from("global")))
+                    .map(|c| ComponentName::new(c, &String::from("global")))
                     .collect();
                 let children = ases
                     .iter()
@@ -599,6 +3461,164 @@ impl Transition for SystemSpec {
             libs,
             exports,
             address_spaces,
+            // unwrap valid as the archive format is validated
+            archive: spec.archive().unwrap(),
+            page_align: spec.system.page_align.unwrap_or(false),
+            arch: spec.arch(None).unwrap(),
+            profile: spec
+                .system
+                .profile
+                .as_ref()
+                .and_then(|p| Profile::from_name(p)),
+            build_dir: spec.system.build_dir.as_ref().map(|d| {
+                Path::new(&s.get_input())
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(d)
+                    .display()
+                    .to_string()
+            }),
+            sign_key: spec.system.sign_key.as_ref().map(|k| {
+                Path::new(&s.get_input())
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(k)
+                    .display()
+                    .to_string()
+            }),
+            dtb: spec.system.dtb.as_ref().map(|d| {
+                Path::new(&s.get_input())
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(d)
+                    .display()
+                    .to_string()
+            }),
+            dtb_addr: spec
+                .system
+                .dtb_addr
+                .as_ref()
+                .and_then(|a| u64::from_str_radix(a.trim_start_matches("0x"), 16).ok()),
+            disk: DiskLayout {
+                boot_size: spec
+                    .disk
+                    .as_ref()
+                    .and_then(|d| d.boot_size.as_ref())
+                    .and_then(|sz| size_parse(sz))
+                    .unwrap_or(DISK_BOOT_SIZE),
+                data_size: spec
+                    .disk
+                    .as_ref()
+                    .and_then(|d| d.data_size.as_ref())
+                    .and_then(|sz| size_parse(sz)),
+                boot_files: spec
+                    .disk
+                    .as_ref()
+                    .and_then(|d| d.boot_files.as_ref())
+                    .unwrap_or(&Vec::new())
+                    .iter()
+                    .map(|f| {
+                        Path::new(&s.get_input())
+                            .parent()
+                            .unwrap_or(Path::new(""))
+                            .join(f)
+                            .display()
+                            .to_string()
+                    })
+                    .collect(),
+            },
+            netboot: NetbootLayout {
+                dir: spec
+                    .netboot
+                    .as_ref()
+                    .and_then(|n| n.dir.clone())
+                    .unwrap_or(String::from(NETBOOT_DIR)),
+                url: spec.netboot.as_ref().and_then(|n| n.url.clone()),
+            },
+            xip: spec.xip.as_ref().and_then(|x| {
+                let addr = |a: &String| u64::from_str_radix(a.trim_start_matches("0x"), 16).ok();
+                Some(XipLayout {
+                    flash_base: addr(&x.flash_base)?,
+                    flash_size: size_parse(&x.flash_size)?,
+                    ram_base: addr(&x.ram_base)?,
+                    ram_size: size_parse(&x.ram_size)?,
+                })
+            }),
+            memory: spec.memory.as_ref().map_or(Vec::new(), |m| {
+                m.regions
+                    .iter()
+                    .filter_map(|r| {
+                        Some(MemoryRegion {
+                            name: r.name.clone(),
+                            kind: if r.kind == "flash" {
+                                RegionKind::Flash
+                            } else {
+                                RegionKind::Ram
+                            },
+                            base: u64::from_str_radix(r.base.trim_start_matches("0x"), 16).ok()?,
+                            size: size_parse(&r.size)?,
+                        })
+                    })
+                    .collect()
+            }),
+            flash_profiles: spec
+                .system
+                .board
+                .as_ref()
+                .and_then(|b| {
+                    board_parse(
+                        &Path::new(&s.get_input())
+                            .parent()
+                            .unwrap_or(Path::new(""))
+                            .join(b),
+                    )
+                    .ok()
+                })
+                .map_or(Vec::new(), |b| {
+                    b.flash
+                        .into_iter()
+                        .filter_map(|f| {
+                            Some(FlashProfile {
+                                tool: FlashTool::from_name(&f.tool)?,
+                                name: f.name,
+                                args: f.args.unwrap_or(Vec::new()),
+                                region: f.region,
+                                verify: f.verify.unwrap_or(true),
+                            })
+                        })
+                        .collect()
+                }),
+            compiler_cache: spec
+                .system
+                .compiler_cache
+                .as_ref()
+                .and_then(|c| CompilerCache::from_name(c)),
+            max_initfs_size: spec
+                .system
+                .max_initfs_size
+                .as_ref()
+                .and_then(|sz| size_parse(sz)),
+            test_expect: test_expect(&s.get_input(), &spec)?,
+            test_timeout: spec
+                .test
+                .as_ref()
+                .and_then(|t| t.timeout)
+                .unwrap_or(TEST_TIMEOUT),
+            cores: spec.cores(),
+            boot_order: spec
+                .boot
+                .as_ref()
+                .unwrap_or(&Vec::new())
+                .iter()
+                .map(|b| {
+                    let order = b
+                        .order
+                        .iter()
+                        .map(|n| ComponentName::new(n, &String::from("global")))
+                        .collect();
+                    (b.core, order)
+                })
+                .collect(),
         });
 
         // Check that the address spaces are formed such that there
@@ -607,9 +3627,9 @@ impl Transition for SystemSpec {
         // spaces. This should be done in `validate`, but it requires
         // the children to be solved.
         let ases = spec.address_spaces();
-        let mut errs = String::new();
-        for (_, a) in ases {
-            let (parent_comps, child_comps) = addrspc_components(&a.name, &ases);
+        let mut errs = Vec::new();
+        for a in ases.values() {
+            let (parent_comps, child_comps) = addrspc_components(&a.name, ases);
 
             // Iterate through parent components, and ensure that they
             // do not depend on descendent address space components.
@@ -618,16 +3638,15 @@ impl Transition for SystemSpec {
                     .deps_named(pc)
                     .iter()
                     .find(|&d| child_comps.contains(&&d.server));
-                if backward_dep.is_some() {
-                    let bd = backward_dep.unwrap();
-                    errs.push_str(&format!(
+                if let Some(bd) = backward_dep {
+                    errs.push(ComposeError::Spec(format!(
 			"Error: Dependency exists in address space \"{}\" from component \"{}\" to \"{}\" which is in a descendant address space; dependencies can only go from descendants to ancestors.",
-			a.name, pc, bd.server));
+			a.name, pc, bd.server)));
                 }
             }
         }
-        if errs.len() != 0 {
-            return Err(errs);
+        if !errs.is_empty() {
+            return Err(ComposeError::Invalid(errs));
         }
 
         Ok(spec)
@@ -640,22 +3659,98 @@ impl SpecificationPass for SystemSpec {
     }
 
     fn component_named(&self, id: &ComponentName) -> &Component {
-        &self.components.get(id).unwrap()
+        self.components.get(id).unwrap()
     }
 
     fn deps_named(&self, id: &ComponentName) -> &Vec<Dependency> {
-        &self.deps.get(id).unwrap()
+        self.deps.get(id).unwrap()
     }
 
     fn libs_named(&self, id: &ComponentName) -> &Vec<Library> {
-        &self.libs.get(id).unwrap()
+        self.libs.get(id).unwrap()
     }
 
     fn exports_named(&self, id: &ComponentName) -> &Vec<Export> {
-        &self.exports.get(id).unwrap()
+        self.exports.get(id).unwrap()
     }
 
     fn address_spaces(&self) -> &HashMap<AddrSpcName, AddrSpace> {
         &self.address_spaces
     }
+
+    fn archive(&self) -> ArchiveFormat {
+        self.archive
+    }
+
+    fn page_align(&self) -> bool {
+        self.page_align
+    }
+
+    fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    fn build_dir(&self) -> &Option<String> {
+        &self.build_dir
+    }
+
+    fn sign_key(&self) -> &Option<String> {
+        &self.sign_key
+    }
+
+    fn dtb(&self) -> &Option<String> {
+        &self.dtb
+    }
+
+    fn dtb_addr(&self) -> Option<u64> {
+        self.dtb_addr
+    }
+
+    fn disk(&self) -> &DiskLayout {
+        &self.disk
+    }
+
+    fn netboot(&self) -> &NetbootLayout {
+        &self.netboot
+    }
+
+    fn xip(&self) -> &Option<XipLayout> {
+        &self.xip
+    }
+
+    fn memory(&self) -> &Vec<MemoryRegion> {
+        &self.memory
+    }
+
+    fn flash_profiles(&self) -> &Vec<FlashProfile> {
+        &self.flash_profiles
+    }
+
+    fn compiler_cache(&self) -> Option<CompilerCache> {
+        self.compiler_cache
+    }
+
+    fn max_initfs_size(&self) -> Option<u64> {
+        self.max_initfs_size
+    }
+
+    fn test_expect(&self) -> &Vec<String> {
+        &self.test_expect
+    }
+
+    fn test_timeout(&self) -> u64 {
+        self.test_timeout
+    }
+
+    fn cores(&self) -> u64 {
+        self.cores
+    }
+
+    fn boot_order(&self) -> &BTreeMap<u64, Vec<ComponentName>> {
+        &self.boot_order
+    }
 }
@@ -0,0 +1,314 @@
+// The read-only code and data that the components duplicate. Each
+// component is linked with its own copy of libc, and of the libraries
+// it uses, so their sealed objects' .text and .rodata hold many
+// identical functions and constants. The read-only sections of the
+// objects, and the functions and data within them, are compared by
+// their contents, and dedup.txt reports those identical across (or
+// within) components, and how much of the image sharing them would
+// reclaim. The objects are linked at different addresses, so code
+// that refers to absolute addresses, or to functions at different
+// offsets from it, differs between them and isn't counted; the
+// report is a lower bound.
+//
+// With --shared-layout, dedup.json also lays the duplicated contents
+// out in a single shared segment, each at an offset aligned as its
+// copies are, along with the address of each copy in its component,
+// for a loader (or a later layout) to map the segment in their place.
+
+use build::DefaultBuilder;
+use manifest::Json;
+use passes::{component, BuildState, ComposeError, SystemState};
+use std::collections::BTreeMap;
+use syshelpers::{dump_file, emit_file, sha256};
+use xmas_elf::header::{Class, Data};
+use xmas_elf::sections::{SectionData, ShType, SHF_ALLOC, SHF_WRITE};
+use xmas_elf::symbol_table::{Entry, Type};
+use xmas_elf::ElfFile;
+
+// The alignment of the functions and data laid out in the shared
+// segment; sections are aligned as they are in the objects.
+const SYMBOL_ALIGN: u64 = 16;
+
+// Contents of an object's read-only section: the section, or a
+// function or datum within it.
+struct Chunk {
+    section: bool,
+    name: String,
+    comp: usize, // index of the component
+    addr: u64,
+    size: u64,
+    align: u64,
+    hash: String,
+}
+
+// The functions and data (of non-zero sizes) within the section
+// at `addr`, with the contents `data`.
+fn symb_chunks<'a, E: Entry>(
+    e: &ElfFile<'a>,
+    sts: &[E],
+    shndx: u16,
+    addr: u64,
+    data: &[u8],
+    comp: usize,
+) -> Vec<Chunk> {
+    sts.iter()
+        .filter(|s| s.shndx() == shndx && s.size() != 0)
+        .filter(|s| matches!(s.get_type(), Ok(Type::Func) | Ok(Type::Object)))
+        .filter_map(|s| {
+            let start = s.value().checked_sub(addr)? as usize;
+            let bytes = data.get(start..start + s.size() as usize)?;
+            Some(Chunk {
+                section: false,
+                name: s.get_name(e).ok()?.to_string(),
+                comp,
+                addr: s.value(),
+                size: s.size(),
+                align: SYMBOL_ALIGN,
+                hash: sha256(bytes),
+            })
+        })
+        .collect()
+}
+
+// The alignment of section `i` of the object `obj`. xmas-elf doesn't
+// expose it, so it's read from the section's raw header (sh_addralign).
+fn sh_align(obj: &[u8], e: &ElfFile, i: usize) -> u64 {
+    let (off, width) = match e.header.pt1.class() {
+        Class::ThirtyTwo => (0x20, 4),
+        _ => (0x30, 8),
+    };
+    let start = e.header.pt2.sh_offset() as usize + i * e.header.pt2.sh_entry_size() as usize + off;
+    let bytes = match obj.get(start..start + width) {
+        Some(b) => b,
+        None => return 1,
+    };
+    let byte = |j: usize| match e.header.pt1.data() {
+        Data::BigEndian => bytes[width - 1 - j] as u64,
+        _ => bytes[j] as u64,
+    };
+
+    (0..width).fold(0, |a, j| a | byte(j) << (8 * j)).max(1)
+}
+
+// The read-only bytes of the object at `path`, and its chunks.
+fn obj_chunks(path: &String, comp: usize) -> Result<(u64, Vec<Chunk>), ComposeError> {
+    let obj = dump_file(path)?;
+    let elf_file = ElfFile::new(&obj).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the object {} as an ELF file: {}",
+            path, e
+        ))
+    })?;
+    let symtab = elf_file
+        .find_section_by_name(".symtab")
+        .and_then(|s| s.get_data(&elf_file).ok());
+
+    let mut ro = 0;
+    let mut chunks = Vec::new();
+    for (i, sh) in elf_file.section_iter().enumerate() {
+        if sh.flags() & SHF_ALLOC == 0
+            || sh.flags() & SHF_WRITE != 0
+            || sh.get_type() != Ok(ShType::ProgBits)
+            || sh.size() == 0
+        {
+            continue;
+        }
+        let data = match obj.get(sh.offset() as usize..(sh.offset() + sh.size()) as usize) {
+            Some(d) => d,
+            None => continue,
+        };
+        ro += sh.size();
+        chunks.push(Chunk {
+            section: true,
+            name: sh.get_name(&elf_file).unwrap_or("?").to_string(),
+            comp,
+            addr: sh.address(),
+            size: sh.size(),
+            align: sh_align(&obj, &elf_file, i),
+            hash: sha256(data),
+        });
+        chunks.extend(match symtab {
+            Some(SectionData::SymbolTable32(sts)) => {
+                symb_chunks(&elf_file, sts, i as u16, sh.address(), data, comp)
+            }
+            Some(SectionData::SymbolTable64(sts)) => {
+                symb_chunks(&elf_file, sts, i as u16, sh.address(), data, comp)
+            }
+            _ => Vec::new(), // stripped
+        });
+    }
+
+    Ok((ro, chunks))
+}
+
+// The total length of the `ranges`, counting their overlaps once.
+fn covered(mut ranges: Vec<(u64, u64)>) -> u64 {
+    ranges.sort();
+    let mut total = 0;
+    let mut end = 0;
+    for (s, e) in ranges.into_iter() {
+        let s = s.max(end);
+        if e > s {
+            total += e - s;
+            end = e;
+        }
+    }
+
+    total
+}
+
+fn hex(a: u64) -> Json {
+    Json::Str(format!("{:#x}", a))
+}
+
+// Write dedup.txt, and with `layout`, dedup.json, into the build
+// directory, and return their paths.
+pub fn dedup_create(
+    s: &SystemState,
+    b: &DefaultBuilder,
+    layout: bool,
+) -> Result<(String, Option<String>), ComposeError> {
+    let txt_path = b.file_path(&"dedup.txt".to_string())?;
+
+    let mut names = Vec::new();
+    let mut ro = Vec::new();
+    let mut chunks = Vec::new();
+    for (i, (id, _)) in s.get_named().ids().iter().enumerate() {
+        let (bytes, cs) = obj_chunks(s.get_objs_id(id).comp_path(), i)?;
+        names.push(component(s, id).name.to_string());
+        ro.push(bytes);
+        chunks.extend(cs);
+    }
+
+    // Chunks with the same contents, ordered by the bytes that
+    // sharing them would reclaim, then by their contents.
+    let mut by_hash: BTreeMap<&String, Vec<&Chunk>> = BTreeMap::new();
+    for c in chunks.iter() {
+        by_hash.entry(&c.hash).or_default().push(c);
+    }
+    let mut groups: Vec<Vec<&Chunk>> = by_hash.into_values().collect();
+    groups.retain(|g| g.len() > 1);
+    for g in groups.iter_mut() {
+        g.sort_by_key(|c| (c.comp, c.addr));
+    }
+    groups.sort_by(|a, b| {
+        let saved = |g: &Vec<&Chunk>| g[0].size * (g.len() as u64 - 1);
+        saved(b).cmp(&saved(a)).then(a[0].hash.cmp(&b[0].hash))
+    });
+
+    // Each group's first copy is kept, and the others reclaimed.
+    let mut dup: Vec<Vec<(u64, u64)>> = vec![Vec::new(); names.len()];
+    for g in groups.iter() {
+        for c in g.iter().skip(1) {
+            dup[c.comp].push((c.addr, c.addr + c.size));
+        }
+    }
+    let dup: Vec<u64> = dup.into_iter().map(covered).collect();
+    let (total_ro, total_dup): (u64, u64) = (ro.iter().sum(), dup.iter().sum());
+
+    let mut txt = format!(
+        "Of the components' {} read-only bytes, {} ({:.1}%) are identical to others, and could be shared.\n\n",
+        total_ro,
+        total_dup,
+        if total_ro == 0 {
+            0.0
+        } else {
+            total_dup as f64 * 100.0 / total_ro as f64
+        }
+    );
+    txt.push_str(&format!(
+        "{:<32} {:>12} {:>12}\n",
+        "component", "read-only", "duplicated"
+    ));
+    for (i, n) in names.iter().enumerate() {
+        txt.push_str(&format!("{:<32} {:>12} {:>12}\n", n, ro[i], dup[i]));
+    }
+    txt.push_str(
+        "\nIdentical sections, and functions and data, by the bytes sharing them would reclaim:\n",
+    );
+    let group_names = |g: &Vec<&Chunk>| {
+        let mut ns: Vec<String> = g
+            .iter()
+            .map(|c| {
+                format!(
+                    "{} {}",
+                    if c.section { "section" } else { "symbol" },
+                    c.name
+                )
+            })
+            .collect();
+        ns.dedup();
+        ns.join(", ")
+    };
+    for g in groups.iter() {
+        let mut comps: Vec<&str> = g.iter().map(|c| names[c.comp].as_str()).collect();
+        comps.dedup();
+        txt.push_str(&format!(
+            "{:>10} bytes ({} x {}) {} in {}\n",
+            g[0].size * (g.len() as u64 - 1),
+            g[0].size,
+            g.len(),
+            group_names(g),
+            comps.join(", ")
+        ));
+    }
+    emit_file(&txt_path, txt.as_bytes())?;
+
+    if !layout {
+        return Ok((txt_path, None));
+    }
+
+    // The largest contents are laid out first; those whose copies are
+    // all within contents already laid out (e.g. the functions of a
+    // section) are shared with them.
+    let json_path = b.file_path(&"dedup.json".to_string())?;
+    let mut placed: Vec<Vec<(u64, u64)>> = vec![Vec::new(); names.len()];
+    let mut off: u64 = 0;
+    let mut entries = Vec::new();
+    let mut by_size: Vec<&Vec<&Chunk>> = groups.iter().collect();
+    by_size.sort_by(|a, b| b[0].size.cmp(&a[0].size).then(a[0].hash.cmp(&b[0].hash)));
+    for g in by_size.into_iter() {
+        let within = |c: &Chunk| {
+            placed[c.comp]
+                .iter()
+                .any(|(s, e)| *s <= c.addr && c.addr + c.size <= *e)
+        };
+        if g.iter().all(|c| within(c)) {
+            continue;
+        }
+        let align = g.iter().map(|c| c.align).max().unwrap_or(1);
+        off = off.div_ceil(align) * align;
+        entries.push(Json::obj(vec![
+            ("offset", hex(off)),
+            ("size", Json::Num(g[0].size)),
+            ("sha256", Json::str(&g[0].hash)),
+            ("names", Json::str(&group_names(g))),
+            (
+                "copies",
+                Json::Arr(
+                    g.iter()
+                        .map(|c| {
+                            Json::obj(vec![
+                                ("component", Json::str(&names[c.comp])),
+                                ("address", hex(c.addr)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]));
+        off += g[0].size;
+        for c in g.iter() {
+            placed[c.comp].push((c.addr, c.addr + c.size));
+        }
+    }
+    let json = Json::obj(vec![
+        ("read_only", Json::Num(total_ro)),
+        ("duplicated", Json::Num(total_dup)),
+        ("segment_size", Json::Num(off)),
+        ("segment", Json::Arr(entries)),
+    ]);
+    emit_file(&json_path, format!("{}\n", json).as_bytes())?;
+
+    Ok((txt_path, Some(json_path)))
+}
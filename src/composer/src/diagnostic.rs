@@ -0,0 +1,138 @@
+// Errors in the specification are shown with the line that causes
+// them, and the offending value underlined, as compilers do:
+//
+// Error: Cannot find component referenced by dependency pnog in component ping.
+//   --> ping.toml:21:17
+//    |
+// 21 | deps = [{srv = "pnog", interface = "pong"}]
+//    |                 ^^^^
+//
+// They are colored when the compose binary prints to a terminal.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+// Color the diagnostics (e.g. if printing to a terminal).
+pub fn diagnostics_color(on: bool) {
+    COLOR.store(on, Ordering::Relaxed);
+}
+
+// A span within a line of the specification.
+#[derive(Clone, Debug)]
+pub struct SpecLoc {
+    pub path: String,
+    pub line: usize,  // from 1
+    pub col: usize,   // from 1
+    pub len: usize,   // of the span underlined
+    pub text: String, // the line
+}
+
+impl SpecLoc {
+    // The location of the first occurrence of `focus` in line `n`
+    // (from 0) of `source`, or the start of the line without it.
+    pub fn find(path: &String, source: &String, n: usize, focus: &str) -> Option<SpecLoc> {
+        let text = source.lines().nth(n)?.to_string();
+        let (col, len) = match text.find(focus) {
+            Some(c) if !focus.is_empty() => (c + 1, focus.len()),
+            _ => (text.len() - text.trim_start().len() + 1, text.trim().len()),
+        };
+
+        Some(SpecLoc {
+            path: path.clone(),
+            line: n + 1,
+            col,
+            len,
+            text,
+        })
+    }
+}
+
+// The number of single-character insertions, deletions,
+// substitutions, and transpositions of adjacent characters that turn
+// `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j] is the distance between a[..i] and b[..j]
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, dist) in d[0].iter_mut().enumerate() {
+        *dist = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+// The candidate closest to `name`, if it is close enough to be a
+// likely misspelling of it.
+pub fn similar<'a>(name: &str, candidates: &'a [String]) -> Option<&'a String> {
+    let max = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn paint(s: &str, code: &str) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, s)
+    } else {
+        s.to_string()
+    }
+}
+
+const RED: &str = "1;31";
+const BLUE: &str = "1;34";
+
+// The message `msg` (starting with "Error:"), followed by the line
+// of the specification at `loc`.
+pub fn diagnostic_render(msg: &String, loc: &SpecLoc) -> String {
+    let msg = match msg.find("Error:") {
+        Some(0) => format!("{}{}", paint("Error:", RED), &msg["Error:".len()..]),
+        _ => msg.clone(),
+    };
+    let num = loc.line.to_string();
+    let pad = " ".repeat(num.len());
+    // Tabs keep the underline aligned with the text.
+    let indent: String = loc
+        .text
+        .chars()
+        .take(loc.col - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!(
+        "{}\n{}{} {}:{}:{}\n{} {}\n{} {} {}\n{} {} {}{}",
+        msg,
+        pad,
+        paint("-->", BLUE),
+        loc.path,
+        loc.line,
+        loc.col,
+        pad,
+        paint("|", BLUE),
+        paint(&num, BLUE),
+        paint("|", BLUE),
+        loc.text,
+        pad,
+        paint("|", BLUE),
+        indent,
+        paint(&"^".repeat(loc.len.max(1)), RED)
+    )
+}
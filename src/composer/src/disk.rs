@@ -0,0 +1,404 @@
+// Disk images (`mkimg disk`) to write (e.g. with dd) onto an SD card
+// or a USB stick, for boards whose firmware (or U-Boot) loads the
+// system from a FAT partition, such as the Raspberry Pi. The image is
+// partitioned with an MBR, which such firmware requires (rather than
+// a GPT). Its first, bootable, partition is a FAT32 filesystem that
+// holds the system's image (its FIT image on armv7a, for U-Boot, and
+// otherwise the kernel image), the board's device-tree (system.dtb),
+// and the specification's disk.boot_files (e.g. the firmware, its
+// config.txt, and U-Boot), each under its file name in the root
+// directory. The optional second partition (of disk.data_size) is
+// left empty, for the system's data. The partitions are aligned to 1
+// MiB, as SD cards' erase blocks expect. The composer generates the
+// filesystem itself, so that the image is reproducible, and doesn't
+// require mkfs.
+
+use dtb::dtb_path;
+use passes::{BuildState, ComposeError, DiskLayout, SystemState};
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use syshelpers::{dump_file, io_error, sha256};
+
+const SECTOR: u64 = 512;
+const PART_ALIGN: u64 = 1 << 20;
+const MBR_PARTS: usize = 0x1be;
+const MBR_DISK_ID: usize = 0x1b8;
+const PART_BOOTABLE: u8 = 0x80;
+const PART_FAT32_LBA: u8 = 0x0c;
+const PART_NON_FS: u8 = 0xda; // data, without a filesystem
+
+const FAT_RESERVED: u64 = 32; // sectors, before the FATs
+const FAT_COPIES: u64 = 2;
+const FAT_FSINFO: u64 = 1; // its sector
+const FAT_BACKUP_BOOT: u64 = 6;
+const FAT_ROOT_CLUSTER: u32 = 2;
+const FAT_EOC: u32 = 0x0fff_ffff; // the end of a cluster chain
+const FAT_MEDIA: u8 = 0xf8; // a fixed disk
+const FAT32_MIN_CLUSTERS: u64 = 65525; // fewer are FAT16
+const DIRENT: usize = 32;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LFN: u8 = 0x0f;
+const LFN_CHARS: usize = 13;
+// The name's base, and extension, are in lowercase.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+const FAT_DATE: u16 = (1 << 5) | 1; // 1980-01-01, for reproducibility
+const VOLUME_LABEL: &[u8; 11] = b"COMPOSITE  ";
+
+// The smallest boot partition, whose FAT32 filesystem has enough
+// (single sector) clusters not to be FAT16.
+pub const BOOT_MIN_SIZE: u64 = 33 << 20;
+
+fn put16(b: &mut [u8], off: usize, v: u16) {
+    b[off..off + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put32(b: &mut [u8], off: usize, v: u32) {
+    b[off..off + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn align_up(n: u64, align: u64) -> u64 {
+    n.div_ceil(align) * align
+}
+
+// The sectors per cluster for a FAT32 filesystem of `sectors`, as
+// Microsoft's format does.
+fn cluster_sectors(sectors: u64) -> u64 {
+    match sectors * SECTOR {
+        sz if sz <= 260 << 20 => 1,
+        sz if sz <= 8 << 30 => 8,
+        sz if sz <= 16 << 30 => 16,
+        _ => 32,
+    }
+}
+
+// Is `part` (a name's base or extension) valid in a short (8.3)
+// name, and in a single case? Returns whether it's in lowercase.
+fn short_part(part: &str, max: usize) -> Option<bool> {
+    let valid = part.len() <= max
+        && part
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c));
+    let lower = part.chars().any(|c| c.is_ascii_lowercase());
+    let upper = part.chars().any(|c| c.is_ascii_uppercase());
+    if !valid || (lower && upper) {
+        return None;
+    }
+
+    Some(lower)
+}
+
+// The short (8.3) name of the file `name`, its case flags, and
+// whether it requires a long name. The `n`th file requiring a long
+// name is named (uniquely) with the ~n suffix.
+fn short_name(name: &str, n: usize) -> ([u8; 11], u8, bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i != 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+    let mut short = [b' '; 11];
+    if !base.is_empty() {
+        if let (Some(lb), Some(le)) = (short_part(base, 8), short_part(ext, 3)) {
+            short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+            short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+            let case = if lb { NT_LOWER_BASE } else { 0 } | if le { NT_LOWER_EXT } else { 0 };
+            return (short, case, false);
+        }
+    }
+
+    let strip = |s: &str, max: usize| -> Vec<u8> {
+        s.to_ascii_uppercase()
+            .bytes()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .take(max)
+            .collect()
+    };
+    let tail = format!("~{}", n);
+    let base = strip(base, 8 - tail.len());
+    let ext = strip(ext, 3);
+    short[..base.len()].copy_from_slice(&base);
+    short[base.len()..base.len() + tail.len()].copy_from_slice(tail.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+
+    (short, 0, true)
+}
+
+// The directory entries (its long name's, then its short name's) of
+// the file `name`, of `size` bytes, starting at `cluster`.
+fn dirents(name: &str, n: usize, cluster: u32, size: u32) -> Vec<u8> {
+    let (short, case, long) = short_name(name, n);
+    let mut ents = Vec::new();
+    if long {
+        let sum = short.iter().fold(0u8, |s, c| {
+            ((s & 1) << 7).wrapping_add(s >> 1).wrapping_add(*c)
+        });
+        // UCS-2, nul-terminated (unless it fills its last entry), and
+        // padded with 0xffff.
+        let mut chars: Vec<u16> = name.encode_utf16().collect();
+        if !chars.len().is_multiple_of(LFN_CHARS) {
+            chars.push(0);
+        }
+        while !chars.len().is_multiple_of(LFN_CHARS) {
+            chars.push(0xffff);
+        }
+        let nents = chars.len() / LFN_CHARS;
+        // The entries are in reverse order, the last flagged.
+        for i in (0..nents).rev() {
+            let mut e = [0u8; DIRENT];
+            e[0] = (i + 1) as u8 | if i == nents - 1 { 0x40 } else { 0 };
+            e[11] = ATTR_LFN;
+            e[13] = sum;
+            let part = &chars[i * LFN_CHARS..(i + 1) * LFN_CHARS];
+            for (j, off) in [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30]
+                .iter()
+                .enumerate()
+            {
+                put16(&mut e, *off, part[j]);
+            }
+            ents.extend_from_slice(&e);
+        }
+    }
+    let mut e = [0u8; DIRENT];
+    e[..11].copy_from_slice(&short);
+    e[11] = ATTR_ARCHIVE;
+    e[12] = case;
+    put16(&mut e, 16, FAT_DATE); // created
+    put16(&mut e, 18, FAT_DATE); // accessed
+    put16(&mut e, 20, (cluster >> 16) as u16);
+    put16(&mut e, 24, FAT_DATE); // modified
+    put16(&mut e, 26, cluster as u16);
+    put32(&mut e, 28, size);
+    ents.extend_from_slice(&e);
+
+    ents
+}
+
+// Write a FAT32 filesystem, of `sectors` at sector `start` of the
+// `disk`, whose root directory holds the `files` (their names and
+// contents).
+fn fat32_write(
+    disk: &fs::File,
+    path: &String,
+    start: u64,
+    sectors: u64,
+    files: &[(String, Vec<u8>)],
+) -> Result<(), ComposeError> {
+    let spc = cluster_sectors(sectors);
+    let cluster_sz = (spc * SECTOR) as usize;
+    // The FATs' size is that for the clusters if they weren't there.
+    let fat_sectors = align_up(((sectors - FAT_RESERVED) / spc + 2) * 4, SECTOR) / SECTOR;
+    let data_start = FAT_RESERVED + FAT_COPIES * fat_sectors;
+    let clusters = (sectors - data_start) / spc;
+    if clusters < FAT32_MIN_CLUSTERS {
+        return Err(ComposeError::Io {
+            path: path.clone(),
+            msg: format!(
+                "The boot partition ({} bytes) is too small for FAT32; it must be at least {}M.",
+                sectors * SECTOR,
+                BOOT_MIN_SIZE >> 20
+            ),
+        });
+    }
+
+    // The root directory (with its volume label), then each file, in
+    // consecutive clusters.
+    let mut root = Vec::new();
+    let mut label = [0u8; DIRENT];
+    label[..11].copy_from_slice(VOLUME_LABEL);
+    label[11] = ATTR_VOLUME_ID;
+    root.extend_from_slice(&label);
+    let root_clusters = |ents: usize| ents.div_ceil(cluster_sz);
+    let mut next = FAT_ROOT_CLUSTER as usize;
+    let mut chains = Vec::new(); // the first cluster, and the number of them
+    let mut nlong = 0;
+    let ents: usize = files
+        .iter()
+        .enumerate()
+        .map(|(i, (n, _))| dirents(n, i + 1, 0, 0).len())
+        .sum::<usize>()
+        + DIRENT;
+    chains.push((next, root_clusters(ents).max(1)));
+    next += chains[0].1;
+    for (name, contents) in files.iter() {
+        let n = contents.len().div_ceil(cluster_sz);
+        let first = if n == 0 { 0 } else { next };
+        if short_name(name, 0).2 {
+            nlong += 1;
+        }
+        root.extend(dirents(name, nlong, first as u32, contents.len() as u32));
+        chains.push((first, n));
+        next += n;
+    }
+    if (next - FAT_ROOT_CLUSTER as usize) as u64 > clusters {
+        return Err(ComposeError::Io {
+            path: path.clone(),
+            msg: format!(
+                "The boot partition's files don't fit in it ({} bytes); increase disk.boot_size.",
+                sectors * SECTOR
+            ),
+        });
+    }
+
+    let mut fat = vec![0u8; (fat_sectors * SECTOR) as usize];
+    put32(&mut fat, 0, 0x0fff_ff00 | FAT_MEDIA as u32);
+    put32(&mut fat, 4, FAT_EOC);
+    for (first, n) in chains.iter().filter(|(_, n)| *n != 0) {
+        for c in *first..first + n {
+            let link = if c == first + n - 1 {
+                FAT_EOC
+            } else {
+                c as u32 + 1
+            };
+            put32(&mut fat, c * 4, link);
+        }
+    }
+
+    let mut boot = [0u8; SECTOR as usize];
+    boot[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"COMPOSIT");
+    put16(&mut boot, 11, SECTOR as u16);
+    boot[13] = spc as u8;
+    put16(&mut boot, 14, FAT_RESERVED as u16);
+    boot[16] = FAT_COPIES as u8;
+    boot[21] = FAT_MEDIA;
+    put16(&mut boot, 24, 63); // sectors per track
+    put16(&mut boot, 26, 255); // heads
+    put32(&mut boot, 28, start as u32); // hidden sectors
+    put32(&mut boot, 32, sectors as u32);
+    put32(&mut boot, 36, fat_sectors as u32);
+    put32(&mut boot, 44, FAT_ROOT_CLUSTER);
+    put16(&mut boot, 48, FAT_FSINFO as u16);
+    put16(&mut boot, 50, FAT_BACKUP_BOOT as u16);
+    boot[64] = 0x80; // drive number
+    boot[66] = 0x29; // the volume's id, label, and type follow
+    let id = sha256(path.as_bytes());
+    put32(&mut boot, 67, u32::from_str_radix(&id[..8], 16).unwrap());
+    boot[71..82].copy_from_slice(VOLUME_LABEL);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510] = 0x55;
+    boot[511] = 0xaa;
+
+    let mut fsinfo = [0u8; SECTOR as usize];
+    put32(&mut fsinfo, 0, 0x4161_5252);
+    put32(&mut fsinfo, 484, 0x6141_7272);
+    put32(&mut fsinfo, 488, (clusters as usize + 2 - next) as u32); // free
+    put32(&mut fsinfo, 492, next as u32);
+    put32(&mut fsinfo, 508, 0xaa55_0000);
+
+    let cluster_off = |c: usize| (start + data_start + (c as u64 - 2) * spc) * SECTOR;
+    let mut writes: Vec<(u64, &[u8])> = vec![
+        (start * SECTOR, &boot),
+        ((start + FAT_FSINFO) * SECTOR, &fsinfo),
+        ((start + FAT_BACKUP_BOOT) * SECTOR, &boot),
+        ((start + FAT_BACKUP_BOOT + FAT_FSINFO) * SECTOR, &fsinfo),
+        (cluster_off(chains[0].0), &root),
+    ];
+    for i in 0..FAT_COPIES {
+        writes.push(((start + FAT_RESERVED + i * fat_sectors) * SECTOR, &fat));
+    }
+    for ((_, contents), (first, _)) in files.iter().zip(chains.iter().skip(1)) {
+        if !contents.is_empty() {
+            writes.push((cluster_off(*first), contents));
+        }
+    }
+    for (off, data) in writes.iter() {
+        disk.write_all_at(data, *off)
+            .map_err(|e| io_error(path, "write the disk image", e))?;
+    }
+
+    Ok(())
+}
+
+// An MBR partition table entry for the partition of `sectors` at
+// sector `start`. The CHS addresses are unused (the maximum), as the
+// partitions are addressed by LBA.
+fn mbr_part(mbr: &mut [u8], i: usize, ty: u8, bootable: bool, start: u64, sectors: u64) {
+    let e = MBR_PARTS + i * 16;
+    mbr[e] = if bootable { PART_BOOTABLE } else { 0 };
+    mbr[e + 1..e + 4].copy_from_slice(&[0xfe, 0xff, 0xff]);
+    mbr[e + 4] = ty;
+    mbr[e + 5..e + 8].copy_from_slice(&[0xfe, 0xff, 0xff]);
+    put32(mbr, e + 8, start as u32);
+    put32(mbr, e + 12, sectors as u32);
+}
+
+// Create disk.img, in the build directory, whose boot partition holds
+// the system's `image`, and return its path.
+pub fn disk_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+    image: &str,
+) -> Result<String, ComposeError> {
+    let path = b.file_path(&"disk.img".to_string())?;
+    let layout = s.get_spec().disk();
+
+    let mut srcs = vec![image.to_string()];
+    srcs.extend(dtb_path(s, b)?);
+    srcs.extend(layout.boot_files.iter().cloned());
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for src in srcs.iter() {
+        let name = Path::new(src)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(src.clone());
+        // FAT's names are case-insensitive.
+        if files.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+            return Err(ComposeError::Io {
+                path: src.clone(),
+                msg: format!("The boot partition already has a file named {}", name),
+            });
+        }
+        files.push((name, dump_file(src)?));
+    }
+    disk_write(&path, layout, &files)?;
+
+    Ok(path)
+}
+
+// Write the disk image, partitioned as the `layout`, with the `files`
+// (their names and contents) in its boot partition, to `path`.
+fn disk_write(
+    path: &String,
+    layout: &DiskLayout,
+    files: &[(String, Vec<u8>)],
+) -> Result<(), ComposeError> {
+    let boot_start = PART_ALIGN / SECTOR;
+    let boot_sectors = align_up(layout.boot_size, PART_ALIGN) / SECTOR;
+    let data_start = boot_start + boot_sectors;
+    let data_sectors = layout
+        .data_size
+        .map_or(0, |sz| align_up(sz, PART_ALIGN) / SECTOR);
+    let total = data_start + data_sectors;
+    if total > u32::MAX as u64 {
+        return Err(ComposeError::Io {
+            path: path.clone(),
+            msg: String::from("The disk image is too large for an MBR (at most 2 TiB)."),
+        });
+    }
+
+    // Truncating the file zeroes the partitions.
+    let disk = fs::File::create(path).map_err(|e| io_error(path, "create the disk image", e))?;
+    disk.set_len(total * SECTOR)
+        .map_err(|e| io_error(path, "size the disk image", e))?;
+    let mut mbr = [0u8; SECTOR as usize];
+    let id = sha256(path.as_bytes());
+    put32(
+        &mut mbr,
+        MBR_DISK_ID,
+        u32::from_str_radix(&id[8..16], 16).unwrap(),
+    );
+    mbr_part(&mut mbr, 0, PART_FAT32_LBA, true, boot_start, boot_sectors);
+    if data_sectors != 0 {
+        mbr_part(&mut mbr, 1, PART_NON_FS, false, data_start, data_sectors);
+    }
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+    disk.write_all_at(&mbr, 0)
+        .map_err(|e| io_error(path, "write the disk image", e))?;
+    // Don't leave a partial image behind.
+    fat32_write(&disk, path, boot_start, boot_sectors, files).inspect_err(|_| {
+        let _ = fs::remove_file(path);
+    })
+}
@@ -0,0 +1,271 @@
+// The board's device-tree (system.dtb), for ARM and RISC-V systems to
+// discover their hardware from, rather than from constants compiled
+// into their components. It's compiled from its source (.dts) with
+// dtc, or taken as is (.dtb), and checked to be a well-formed
+// flattened device-tree, into the build directory's system.dtb.
+//
+// With system.dtb_addr, it's embedded in the kernel image, as a
+// loadable segment of its own at that physical address, so that any
+// ELF loader (e.g. qemu's) loads it with the kernel. FIT images
+// instead hold it separately, for U-Boot to load at the address. The
+// booter's initargs ("dtb") hold the address, and the device-tree's
+// header holds its size.
+
+use passes::{BuildState, ComposeError, ImageFormat, SystemState};
+use std::path::Path;
+use std::process::Command;
+use syshelpers::{dump_file, emit_file};
+use xmas_elf::program;
+use xmas_elf::ElfFile;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_HEADER_SZ: usize = 40;
+const FDT_VERSION_MIN: u32 = 16;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+const PT_LOAD: u32 = 1;
+const PF_R: u32 = 4;
+const DTB_ALIGN: usize = 8;
+
+fn be32(b: &[u8], off: usize) -> Option<u32> {
+    b.get(off..off + 4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]))
+}
+
+// Is `dtb` a well-formed flattened device-tree? Its header's blocks
+// must be within it, and its structure block a single (root) node.
+fn fdt_check(dtb: &[u8]) -> Result<(), String> {
+    let field = |i: usize| be32(dtb, i * 4).ok_or("its header is truncated");
+    if field(0)? != FDT_MAGIC {
+        return Err(String::from("it doesn't start with the magic number"));
+    }
+    let total = field(1)? as usize;
+    let (off_struct, off_strings, off_rsvmap) =
+        (field(2)? as usize, field(3)? as usize, field(4)? as usize);
+    let (version, size_strings, size_struct) = (field(5)?, field(8)? as usize, field(9)? as usize);
+    if version < FDT_VERSION_MIN {
+        return Err(format!(
+            "its version ({}) is older than {}",
+            version, FDT_VERSION_MIN
+        ));
+    }
+    if total > dtb.len() || total < FDT_HEADER_SZ {
+        return Err(format!(
+            "its size ({} bytes) doesn't match the file's ({} bytes)",
+            total,
+            dtb.len()
+        ));
+    }
+    if off_rsvmap % 8 != 0
+        || off_struct % 4 != 0
+        || off_rsvmap < FDT_HEADER_SZ
+        || off_struct + size_struct > total
+        || off_strings + size_strings > total
+    {
+        return Err(String::from("its blocks aren't within it"));
+    }
+
+    let st = &dtb[off_struct..off_struct + size_struct];
+    let mut off = 0;
+    let mut depth = 0;
+    let mut roots = 0;
+    loop {
+        let tok = be32(st, off).ok_or("its structure block isn't terminated")?;
+        off += 4;
+        match tok {
+            FDT_BEGIN_NODE => {
+                if depth == 0 && roots != 0 {
+                    return Err(String::from("it has more than one root node"));
+                }
+                let len = st[off.min(st.len())..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or("a node's name isn't terminated")?;
+                off = (off + len + 1 + 3) & !3;
+                if depth == 0 {
+                    roots += 1;
+                }
+                depth += 1;
+            }
+            FDT_END_NODE if depth > 0 => depth -= 1,
+            FDT_PROP if depth > 0 => {
+                let len = be32(st, off).ok_or("a property is truncated")? as usize;
+                let name = be32(st, off + 4).ok_or("a property is truncated")? as usize;
+                if name >= size_strings {
+                    return Err(String::from("a property's name isn't in its strings"));
+                }
+                off = (off + 8 + len + 3) & !3;
+                if off > st.len() {
+                    return Err(String::from("a property's value is truncated"));
+                }
+            }
+            FDT_NOP => (),
+            FDT_END if depth == 0 && roots == 1 => return Ok(()),
+            _ => {
+                return Err(format!(
+                    "its structure block has an unexpected token ({}) at offset {}",
+                    tok,
+                    off - 4
+                ))
+            }
+        }
+    }
+}
+
+fn dtc(src: &String, out: &String) -> Result<(), ComposeError> {
+    let res = Command::new("dtc")
+        .args(["-I", "dts", "-O", "dtb", "-o"])
+        .arg(out)
+        .arg(src)
+        .output()
+        .map_err(|e| ComposeError::Io {
+            path: src.clone(),
+            msg: format!("Could not execute dtc to compile the device-tree: {}", e),
+        })?;
+    if !res.status.success() {
+        return Err(ComposeError::Spec(format!(
+            "Error: dtc could not compile the system's dtb {}:\n{}",
+            src,
+            String::from_utf8_lossy(&res.stderr)
+        )));
+    }
+
+    Ok(())
+}
+
+// The kernel image `obj`, with the `dtb` embedded in a loadable
+// segment at (the physical and virtual) `addr`. The device-tree and a
+// copy of the program headers, with the segment added, are appended
+// to the image, which then refers to the copy.
+fn elf_embed(obj: &[u8], dtb: &[u8], addr: u64) -> Result<Vec<u8>, String> {
+    let elf_file = ElfFile::new(obj)?;
+    let end = addr + dtb.len() as u64;
+    for ph in elf_file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+    {
+        let (start, len) = (ph.physical_addr(), ph.mem_size());
+        // The kernel image was already built with it.
+        if start == addr && ph.file_size() == dtb.len() as u64 {
+            let off = ph.offset() as usize;
+            if obj.get(off..off + dtb.len()) == Some(dtb) {
+                return Ok(obj.to_vec());
+            }
+        }
+        if start < end && addr < start + len {
+            return Err(format!(
+                "it overlaps the kernel's segment at {:#x}-{:#x}",
+                start,
+                start + len
+            ));
+        }
+    }
+
+    let is64 = obj.get(4) == Some(&2);
+    if obj.get(5) != Some(&1) {
+        return Err(String::from(
+            "only little-endian kernel images are supported",
+        ));
+    }
+    // The header's e_phoff, e_phentsize, and e_phnum, and the size of
+    // a program header.
+    let (phoff_at, phent_at, phnum_at, phent) = if is64 {
+        (0x20, 0x36, 0x38, 56)
+    } else {
+        (0x1c, 0x2a, 0x2c, 32)
+    };
+    let le = |at: usize, n: usize| {
+        obj[at..at + n]
+            .iter()
+            .rev()
+            .fold(0, |v, b| v << 8 | *b as usize)
+    };
+    let phoff = le(phoff_at, if is64 { 8 } else { 4 });
+    let phnum = le(phnum_at, 2);
+    if le(phent_at, 2) != phent || phoff + phnum * phent > obj.len() {
+        return Err(String::from("its program headers are malformed"));
+    }
+
+    let mut out = obj.to_vec();
+    let pad = |v: &mut Vec<u8>| v.resize(v.len().div_ceil(DTB_ALIGN) * DTB_ALIGN, 0);
+    pad(&mut out);
+    let dtb_off = out.len() as u64;
+    out.extend_from_slice(dtb);
+    pad(&mut out);
+    let new_phoff = out.len() as u64;
+    out.extend_from_slice(&obj[phoff..phoff + phnum * phent]);
+    let (sz, align) = (dtb.len() as u64, DTB_ALIGN as u64);
+    if is64 {
+        out.extend_from_slice(&PT_LOAD.to_le_bytes());
+        out.extend_from_slice(&PF_R.to_le_bytes());
+        for f in [dtb_off, addr, addr, sz, sz, align].iter() {
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        out[phoff_at..phoff_at + 8].copy_from_slice(&new_phoff.to_le_bytes());
+    } else {
+        out.extend_from_slice(&PT_LOAD.to_le_bytes());
+        for f in [dtb_off, addr, addr, sz, sz, PF_R as u64, align].iter() {
+            out.extend_from_slice(&(*f as u32).to_le_bytes());
+        }
+        out[phoff_at..phoff_at + 4].copy_from_slice(&(new_phoff as u32).to_le_bytes());
+    }
+    out[phnum_at..phnum_at + 2].copy_from_slice(&((phnum + 1) as u16).to_le_bytes());
+
+    Ok(out)
+}
+
+// The path of the system's device-tree, in the build directory, if it
+// has one.
+pub fn dtb_path(s: &SystemState, b: &dyn BuildState) -> Result<Option<String>, ComposeError> {
+    match s.get_spec().dtb() {
+        Some(_) => Ok(Some(b.file_path(&"system.dtb".to_string())?)),
+        None => Ok(None),
+    }
+}
+
+// Create system.dtb, and (with a dtb_addr, unless the `image` is a FIT
+// image) embed it in the kernel image. Returns its path, if the
+// system has a device-tree.
+pub fn dtb_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+    image: ImageFormat,
+) -> Result<Option<String>, ComposeError> {
+    let (src, path) = match (s.get_spec().dtb(), dtb_path(s, b)?) {
+        (Some(src), Some(path)) => (src, path),
+        _ => return Ok(None),
+    };
+    if Path::new(src).extension().is_some_and(|e| e == "dts") {
+        dtc(src, &path)?;
+    } else {
+        emit_file(&path, &dump_file(src)?)?;
+    }
+    let dtb = dump_file(&path)?;
+    fdt_check(&dtb).map_err(|e| {
+        ComposeError::Spec(format!(
+            "Error: The system's dtb {} is not a valid device-tree: {}.",
+            src, e
+        ))
+    })?;
+
+    if let Some(addr) = s.get_spec().dtb_addr() {
+        let kern = s.get_constructor().image_path();
+        let obj = dump_file(kern)?;
+        let embedded = elf_embed(&obj, &dtb, addr).map_err(|e| {
+            ComposeError::Pass(format!(
+                "Error: Cannot embed the system's dtb at {:#x} in the kernel image {}: {}.",
+                addr, kern, e
+            ))
+        })?;
+        // U-Boot loads the FIT image's device-tree itself.
+        if image != ImageFormat::Fit {
+            emit_file(kern, &embedded)?;
+        }
+    }
+
+    Ok(Some(path))
+}
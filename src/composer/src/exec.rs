@@ -0,0 +1,201 @@
+// Executors perform the Actions that the builder resolves: they
+// write the generated files, create archives, sign and strip
+// objects, and run make. The
+// HostExecutor does so on this machine. The RecordingExecutor only
+// records the actions, and reports that they succeeded, so that
+// tools (and tests) can check what a build would do without the
+// toolchain or the source tree.
+
+use archive::archive_create;
+use passes::ComposeError;
+use plan::Action;
+use signing::file_sign;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::Duration;
+use strip::obj_strip;
+use syshelpers::emit_file;
+
+pub enum Outcome {
+    Done,
+    Made(String, String, ExitStatus), // make's stdout, stderr, and status
+    TimedOut,                         // make was killed after the timeout
+}
+
+pub trait Executor: Send + Sync {
+    // `timeout` only applies to make.
+    fn execute(&self, a: &Action, timeout: Option<Duration>) -> Result<Outcome, ComposeError>;
+}
+
+pub struct HostExecutor;
+
+impl Executor for HostExecutor {
+    fn execute(&self, a: &Action, timeout: Option<Duration>) -> Result<Outcome, ComposeError> {
+        match a {
+            Action::Write { path, contents } => emit_file(path, contents).map(|_| Outcome::Done),
+            Action::Tarball {
+                path,
+                files,
+                format,
+                page_align,
+            } => archive_create(Path::new(&path), files.clone(), *format, *page_align)
+                .map(|_| Outcome::Done),
+            Action::Sign {
+                path,
+                key,
+                signature,
+            } => file_sign(key, path, signature).map(|_| Outcome::Done),
+            Action::Strip {
+                objcopy,
+                path,
+                stripped,
+                debug,
+            } => obj_strip(objcopy, path, stripped, debug).map(|_| Outcome::Done),
+            Action::Make { cmd, .. } => Ok(match cmd.exec_timeout(timeout) {
+                Some((out, err, status)) => Outcome::Made(out, err, status),
+                None => Outcome::TimedOut,
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RecordingExecutor {
+    actions: Mutex<Vec<Action>>,
+}
+
+impl RecordingExecutor {
+    pub fn new() -> RecordingExecutor {
+        RecordingExecutor::default()
+    }
+
+    // The actions executed so far, in order.
+    pub fn actions(&self) -> Vec<Action> {
+        self.actions.lock().unwrap().clone()
+    }
+}
+
+impl Executor for RecordingExecutor {
+    fn execute(&self, a: &Action, _timeout: Option<Duration>) -> Result<Outcome, ComposeError> {
+        self.actions.lock().unwrap().push(a.clone());
+        Ok(match a {
+            Action::Make { .. } => {
+                Outcome::Made(String::new(), String::new(), ExitStatus::from_raw(0))
+            }
+            _ => Outcome::Done,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options::Options;
+    use passes::{BuildState, ComponentId, SystemState};
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use system_resolve;
+    use DefaultBuilder;
+
+    // Resolve the ping pong system of the source tree that this
+    // crate is in, building into a temporary directory, with its
+    // actions recorded by `rec`.
+    fn ping_pong(
+        name: &str,
+        rec: &Arc<RecordingExecutor>,
+    ) -> (SystemState, DefaultBuilder, Vec<ComponentId>) {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let spec = root
+            .join("composition_scripts/ping_pong.toml")
+            .display()
+            .to_string();
+        let mut opts = Options::new(&spec, &name.to_string());
+        opts.src_root = Some(root.display().to_string());
+        opts.build_dir = Some(
+            env::temp_dir()
+                .join(format!("compose-exec-{}", std::process::id()))
+                .join(name)
+                .display()
+                .to_string(),
+        );
+        // Don't take the source tree's lock.
+        opts.dry_run = true;
+        let (sys, build, ids, _) = system_resolve(&opts, &spec, &opts.name).unwrap();
+
+        (sys, build.with_executor(rec.clone()), ids)
+    }
+
+    // The names of the recorded makes, and their targets.
+    fn makes(rec: &RecordingExecutor) -> Vec<(String, String)> {
+        rec.actions()
+            .iter()
+            .filter_map(|a| match a {
+                Action::Make { name, cmd, .. } => Some((
+                    name.clone(),
+                    cmd.to_string().rsplit(' ').next().unwrap().to_string(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn components_make() {
+        let rec = Arc::new(RecordingExecutor::default());
+        let (sys, mut build, ids) = ping_pong("components", &rec);
+        build.comps_build(&ids, &sys).unwrap();
+
+        // Each component's dependencies are found, and rebuilt,
+        // before it is built, after the servers it depends on.
+        let targets = ["dependencies_info", "component_rebuild", "component"];
+        let expected: Vec<(String, String)> = ["booter", "pong", "ping"]
+            .iter()
+            .flat_map(|c| {
+                targets
+                    .iter()
+                    .map(move |t| (format!("global.{}", c), t.to_string()))
+            })
+            .collect();
+        assert_eq!(makes(&rec), expected);
+        // Each build's output is logged.
+        let logs = rec
+            .actions()
+            .iter()
+            .filter(|a| match a {
+                Action::Write { path, .. } => path.ends_with("/compilation.log"),
+                _ => false,
+            })
+            .count();
+        assert_eq!(logs, ids.len());
+    }
+
+    #[test]
+    fn kernel_make_plat() {
+        let rec = Arc::new(RecordingExecutor::default());
+        let (sys, build, _) = ping_pong("kernel", &rec);
+        let output = build.file_path(&"cos.img".to_string()).unwrap();
+        let constructor = build.file_path(&"booter.o".to_string()).unwrap();
+        build.kernel_build(&output, &constructor, &sys).unwrap();
+
+        assert_eq!(
+            makes(&rec),
+            vec![(String::from("kernel"), String::from("plat"))]
+        );
+        match rec.actions().first() {
+            Some(Action::Make { cmd, .. }) => {
+                let vars = cmd.vars();
+                assert!(vars.contains(&(String::from("KERNEL_OUTPUT"), output)));
+                assert!(vars.contains(&(String::from("CONSTRUCTOR_COMP"), constructor)));
+            }
+            _ => panic!("the kernel isn't made first"),
+        }
+        // Followed by writing its log.
+        match rec.actions().last() {
+            Some(Action::Write { path, .. }) => assert!(path.ends_with("kernel_compilation.log")),
+            _ => panic!("the kernel's build isn't logged"),
+        }
+    }
+}
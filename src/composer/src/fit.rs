@@ -0,0 +1,270 @@
+// A U-Boot FIT image (Flattened Image Tree) of the kernel image, so
+// that ARM boards boot the system with `bootm` (from TFTP, or an SD
+// card) without mkimage. The FIT is a device-tree blob whose images
+// node holds the kernel, as the binary that its loadable segments
+// would be loaded as, and optionally the board's device-tree
+// (system.dtb), loaded at system.dtb_addr if it has one; its default
+// configuration boots the kernel with that device-tree. The
+// constructor, and so its initfs, are linked into the kernel image,
+// so they are loaded with it, rather than as a separate ramdisk.
+//
+// The kernel is loaded at the physical address of its first segment,
+// and entered at the physical address of its entry point, from its
+// ELF object. U-Boot boots it as a Linux kernel: with the MMU and
+// caches disabled, and the device-tree's address in r2.
+
+use passes::ComposeError;
+use std::collections::BTreeMap;
+use syshelpers::sha256;
+use xmas_elf::program;
+use xmas_elf::ElfFile;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SZ: usize = 40;
+const FDT_RSVMAP_SZ: usize = 16; // only its terminating entry
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+// U-Boot's name for the architecture of the (armv7a) kernel.
+const FIT_ARCH: &str = "arm";
+
+// A device-tree node: its properties, then its subnodes.
+struct Node {
+    name: String,
+    props: Vec<(String, Vec<u8>)>,
+    nodes: Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &str) -> Node {
+        Node {
+            name: name.to_string(),
+            props: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    fn prop(mut self, name: &str, val: Vec<u8>) -> Node {
+        self.props.push((name.to_string(), val));
+        self
+    }
+
+    // Strings are nul-terminated.
+    fn str(self, name: &str, val: &str) -> Node {
+        let mut v = val.as_bytes().to_vec();
+        v.push(0);
+        self.prop(name, v)
+    }
+
+    // Cells are big-endian 32 bit words.
+    fn cell(self, name: &str, val: u32) -> Node {
+        self.prop(name, val.to_be_bytes().to_vec())
+    }
+
+    fn node(mut self, n: Node) -> Node {
+        self.nodes.push(n);
+        self
+    }
+}
+
+fn push_u32(v: &mut Vec<u8>, n: u32) {
+    v.extend_from_slice(&n.to_be_bytes());
+}
+
+fn pad4(v: &mut Vec<u8>) {
+    while !v.len().is_multiple_of(4) {
+        v.push(0);
+    }
+}
+
+// Append the `node` to the structure block, adding the names of its
+// properties to the strings block.
+fn fdt_node(n: &Node, st: &mut Vec<u8>, strings: &mut Vec<u8>, offs: &mut BTreeMap<String, u32>) {
+    push_u32(st, FDT_BEGIN_NODE);
+    st.extend_from_slice(n.name.as_bytes());
+    st.push(0);
+    pad4(st);
+    for (name, val) in n.props.iter() {
+        let off = *offs.entry(name.clone()).or_insert_with(|| {
+            let off = strings.len() as u32;
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+            off
+        });
+        push_u32(st, FDT_PROP);
+        push_u32(st, val.len() as u32);
+        push_u32(st, off);
+        st.extend_from_slice(val);
+        pad4(st);
+    }
+    for sub in n.nodes.iter() {
+        fdt_node(sub, st, strings, offs);
+    }
+    push_u32(st, FDT_END_NODE);
+}
+
+// The device-tree blob of the `root` node.
+fn fdt(root: &Node) -> Vec<u8> {
+    let mut st = Vec::new();
+    let mut strings = Vec::new();
+    fdt_node(root, &mut st, &mut strings, &mut BTreeMap::new());
+    push_u32(&mut st, FDT_END);
+
+    let off_rsvmap = FDT_HEADER_SZ;
+    let off_struct = off_rsvmap + FDT_RSVMAP_SZ;
+    let off_strings = off_struct + st.len();
+    let total = off_strings + strings.len();
+    let mut blob = Vec::with_capacity(total);
+    for f in [
+        FDT_MAGIC,
+        total as u32,
+        off_struct as u32,
+        off_strings as u32,
+        off_rsvmap as u32,
+        FDT_VERSION,
+        FDT_LAST_COMP_VERSION,
+        0, // the boot cpu
+        strings.len() as u32,
+        st.len() as u32,
+    ]
+    .iter()
+    {
+        push_u32(&mut blob, *f);
+    }
+    blob.extend_from_slice(&[0; FDT_RSVMAP_SZ]);
+    blob.extend_from_slice(&st);
+    blob.extend_from_slice(&strings);
+
+    blob
+}
+
+// The hash node, with the sha256 of the `data`, that U-Boot checks
+// the image against.
+fn hash_node(data: &[u8]) -> Node {
+    let hex = sha256(data);
+    let digest = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+
+    Node::new("hash-1")
+        .str("algo", "sha256")
+        .prop("value", digest)
+}
+
+// The kernel object at `path`, as the binary its loadable segments
+// are loaded as, its load address, and its entry point.
+pub fn kernel_binary(path: &String, obj: &[u8]) -> Result<(Vec<u8>, u32, u32), ComposeError> {
+    let elf_file = ElfFile::new(obj).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the kernel image {} as an ELF file: {}",
+            path, e
+        ))
+    })?;
+    let segs: Vec<(u64, u64, u64, u64)> = elf_file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load) && ph.mem_size() != 0)
+        .map(|ph| {
+            (
+                ph.physical_addr(),
+                ph.virtual_addr(),
+                ph.offset(),
+                ph.file_size(),
+            )
+        })
+        .collect();
+    let entry = elf_file.header.pt2.entry_point();
+    let phys_entry = segs
+        .iter()
+        .find(|(_, v, _, sz)| *v <= entry && entry < v + sz)
+        .map(|(p, v, _, _)| entry - v + p);
+    let load = segs
+        .iter()
+        .filter(|(_, _, _, sz)| *sz != 0)
+        .map(|(p, _, _, _)| *p)
+        .min();
+    let (load, entry) = match (load, phys_entry) {
+        (Some(l), Some(e)) if l >> 32 == 0 && e >> 32 == 0 => (l, e),
+        _ => {
+            return Err(ComposeError::Pass(format!(
+                "Error: The kernel image {} has no loadable segments containing its entry point ({:#x}) at 32 bit physical addresses.",
+                path, entry
+            )))
+        }
+    };
+
+    // The bss is zeroed by the kernel, so only the segments' contents
+    // are in the binary.
+    let end = segs
+        .iter()
+        .filter(|(_, _, _, sz)| *sz != 0)
+        .map(|(p, _, _, sz)| p + sz)
+        .max()
+        .unwrap_or(load);
+    let mut bin = vec![0; (end - load) as usize];
+    for (p, _, off, sz) in segs.iter().filter(|(_, _, _, sz)| *sz != 0) {
+        let contents = obj
+            .get(*off as usize..(off + sz) as usize)
+            .ok_or(ComposeError::Pass(format!(
+                "Error: The kernel image {} is truncated.",
+                path
+            )))?;
+        let at = (p - load) as usize;
+        bin[at..at + contents.len()].copy_from_slice(contents);
+    }
+
+    Ok((bin, load as u32, entry as u32))
+}
+
+// The FIT image of the `kernel` (its binary, load address and entry
+// point), with the `dtb` (and the address to load it at) if the board
+// requires one.
+pub fn fit_image(
+    desc: &str,
+    kernel: (Vec<u8>, u32, u32),
+    dtb: Option<(Vec<u8>, Option<u32>)>,
+) -> Vec<u8> {
+    let (bin, load, entry) = kernel;
+    let mut images = Node::new("images").node(
+        Node::new("kernel-1")
+            .str("description", "composite kernel")
+            .str("type", "kernel")
+            .str("arch", FIT_ARCH)
+            .str("os", "linux")
+            .str("compression", "none")
+            .cell("load", load)
+            .cell("entry", entry)
+            .node(hash_node(&bin))
+            .prop("data", bin),
+    );
+    let mut conf = Node::new("conf-1")
+        .str("description", desc)
+        .str("kernel", "kernel-1");
+    if let Some((d, load)) = dtb {
+        let mut fdt = Node::new("fdt-1")
+            .str("description", "device-tree")
+            .str("type", "flat_dt")
+            .str("arch", FIT_ARCH)
+            .str("compression", "none");
+        if let Some(l) = load {
+            fdt = fdt.cell("load", l);
+        }
+        images = images.node(fdt.node(hash_node(&d)).prop("data", d));
+        conf = conf.str("fdt", "fdt-1");
+    }
+    let root = Node::new("")
+        .str("description", desc)
+        .cell("#address-cells", 1)
+        .node(images)
+        .node(
+            Node::new("configurations")
+                .str("default", "conf-1")
+                .node(conf),
+        );
+
+    fdt(&root)
+}
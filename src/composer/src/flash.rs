@@ -0,0 +1,209 @@
+// Programming the system onto a board (`mkimg flash`), with one of
+// the profiles of the board's configuration (system.board), so that
+// editing, building, and flashing the system is one command. Each
+// profile drives a tool (openocd, probe-rs, or dfu-util) with its
+// board-specific arguments (e.g. the probe, and the target), to write
+// the kernel image, or a memory region's image (from `mkimg split`)
+// at the region's base, then (unless the profile says otherwise) to
+// verify that the board's flash holds the image, and to reset the
+// board, so that it runs the new system.
+
+use passes::{BuildState, ComposeError, FlashProfile, FlashTool, SystemState};
+use regions::region_image_path;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use syshelpers::dump_file;
+
+// The board's flash profile `name`, or without one, its first.
+pub fn flash_profile<'a>(
+    s: &'a SystemState,
+    name: &Option<String>,
+) -> Result<&'a FlashProfile, ComposeError> {
+    let profiles = s.get_spec().flash_profiles();
+    let profile = match name {
+        Some(n) => profiles.iter().find(|p| &p.name == n),
+        None => profiles.first(),
+    };
+
+    profile.ok_or_else(|| match name {
+        Some(n) if !profiles.is_empty() => ComposeError::Spec(format!(
+            "Error: The board has no flash profile {}; its profiles are {}.",
+            n,
+            profiles
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+        _ => ComposeError::Spec(String::from(
+            "Error: mkimg flash requires the board's configuration (system.board), with a flash profile.",
+        )),
+    })
+}
+
+// Run the profile's tool with `args`, after its own (and its
+// subcommand, `sub`, before them).
+fn tool_run(p: &FlashProfile, sub: Option<&str>, args: Vec<String>) -> Result<(), ComposeError> {
+    let prog = p.tool.name();
+    let mut all: Vec<String> = sub.iter().map(|s| s.to_string()).collect();
+    all.extend(p.args.iter().cloned());
+    all.extend(args);
+    debug!("\t{} {}", prog, all.join(" "));
+
+    let status = Command::new(prog)
+        .args(&all)
+        .status()
+        .map_err(|e| ComposeError::Io {
+            path: prog.to_string(),
+            msg: format!("could not execute {}: {}", prog, e),
+        })?;
+    if !status.success() {
+        return Err(ComposeError::Pass(format!(
+            "Error: {} exited with {}, programming the board with flash profile {}.",
+            prog, status, p.name
+        )));
+    }
+
+    Ok(())
+}
+
+// probe-rs's arguments for a raw image at `addr` (rather than an ELF
+// object).
+fn probe_rs_format(addr: Option<u64>) -> Vec<String> {
+    match addr {
+        Some(a) => vec![
+            String::from("--binary-format"),
+            String::from("bin"),
+            String::from("--base-address"),
+            format!("{:#x}", a),
+        ],
+        None => Vec::new(),
+    }
+}
+
+// dfu-util can't write an image and read it back in one invocation,
+// so the image is read back into the build directory and compared.
+fn dfu_verify(
+    p: &FlashProfile,
+    b: &dyn BuildState,
+    img: &String,
+    addr: u64,
+) -> Result<(), ComposeError> {
+    let contents = dump_file(img)?;
+    let readback = b.file_path(&"flash-readback.bin".to_string())?;
+    // dfu-util won't overwrite a file.
+    match fs::remove_file(&readback) {
+        Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(ComposeError::Io {
+                path: readback.clone(),
+                msg: format!("Could not remove the previous read-back image: {}", e),
+            })
+        }
+        _ => (),
+    }
+    tool_run(
+        p,
+        None,
+        vec![
+            String::from("-s"),
+            format!("{:#x}:{}", addr, contents.len()),
+            String::from("-U"),
+            readback.clone(),
+        ],
+    )?;
+    let back = dump_file(&readback)?;
+    if let Some(off) = (0..contents.len()).find(|i| back.get(*i) != Some(&contents[*i])) {
+        return Err(ComposeError::Pass(format!(
+            "Error: Verifying the board's flash failed: it differs from {} at {:#x} (offset {}).",
+            img,
+            addr + off as u64,
+            off
+        )));
+    }
+
+    Ok(())
+}
+
+// Program the board with its flash profile `name` (or its first).
+pub fn board_flash(
+    s: &SystemState,
+    b: &dyn BuildState,
+    name: &Option<String>,
+) -> Result<(), ComposeError> {
+    let p = flash_profile(s, name)?;
+    let (img, addr) =
+        match p.region {
+            Some(ref r) => {
+                let region = s.get_spec().memory().iter().find(|m| &m.name == r).ok_or(
+                    ComposeError::Spec(format!(
+                        "Error: Flash profile {}'s region {} is not in the memory table.",
+                        p.name, r
+                    )),
+                )?;
+                (region_image_path(b, r)?, Some(region.base))
+            }
+            None => (s.get_constructor().image_path().clone(), None),
+        };
+    if !Path::new(&img).is_file() {
+        return Err(ComposeError::Pass(format!(
+            "Error: Flash profile {}'s region has no contents to program (there's no {}).",
+            p.name, img
+        )));
+    }
+    info!(
+        "Programming the board with {} (flash profile {}), from {}.",
+        p.tool.name(),
+        p.name,
+        img
+    );
+
+    match p.tool {
+        // openocd's program command writes, verifies, and resets.
+        FlashTool::Openocd => {
+            let mut cmd = format!("program {{{}}}", img);
+            if let Some(a) = addr {
+                cmd.push_str(&format!(" {:#x}", a));
+            }
+            if p.verify {
+                cmd.push_str(" verify");
+            }
+            cmd.push_str(" reset exit");
+            tool_run(p, None, vec![String::from("-c"), cmd])?;
+        }
+        FlashTool::ProbeRs => {
+            for (sub, run) in [("download", true), ("verify", p.verify)].iter() {
+                if *run {
+                    let mut args = probe_rs_format(addr);
+                    args.push(img.clone());
+                    tool_run(p, Some(sub), args)?;
+                }
+            }
+            tool_run(p, Some("reset"), Vec::new())?;
+        }
+        FlashTool::DfuUtil => {
+            // dfu-util profiles are validated to name a region.
+            let a = addr.unwrap_or(0);
+            // Once written (and not to be read back), it leaves DFU
+            // mode, to run the system.
+            let target = if p.verify {
+                format!("{:#x}", a)
+            } else {
+                format!("{:#x}:leave", a)
+            };
+            tool_run(
+                p,
+                None,
+                vec![String::from("-s"), target, String::from("-D"), img.clone()],
+            )?;
+            if p.verify {
+                dfu_verify(p, b, &img, a)?;
+                tool_run(p, None, vec![String::from("-e")])?;
+            }
+        }
+    }
+    info!("Programmed the board with {}.", img);
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+// Debugging support for the system image. Each component is linked
+// at its own base address, so gdb must be told where each one's
+// symbols are. gdbinit loads the kernel's symbols, adds those of
+// every sealed component, and attaches to qemu's gdbstub. symbols.map
+// aggregates the symbols of all components in address order (as nm
+// would print them) to resolve the addresses in faults and traces.
+// If the build is stripped, the symbols are those kept in each
+// object's .debug file.
+
+use passes::{BuildState, ComposeError, SystemState};
+use qemu::QEMU_GDB_PORT;
+use strip::debug_path;
+use syshelpers::{dump_file, emit_file};
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::{Binding, Entry, Type};
+use xmas_elf::ElfFile;
+
+struct MapSymb {
+    addr: u64,
+    size: u64,
+    kind: char, // nm's: T/t for functions, D/d for data
+    name: String,
+}
+
+fn map_symb<'a>(e: &ElfFile<'a>, s: &'a dyn Entry) -> Option<MapSymb> {
+    let kind = match s.get_type() {
+        Ok(Type::Func) => 't',
+        Ok(Type::Object) => 'd',
+        _ => return None,
+    };
+    let name = s.get_name(e).ok()?;
+    if s.value() == 0 || name.is_empty() {
+        return None;
+    }
+
+    Some(MapSymb {
+        addr: s.value(),
+        size: s.size(),
+        kind: match s.get_binding() {
+            Ok(Binding::Global) | Ok(Binding::Weak) => kind.to_ascii_uppercase(),
+            _ => kind,
+        },
+        name: name.to_string(),
+    })
+}
+
+// The address of the object's .text section, and its function and
+// data symbols.
+fn obj_symbs(obj_path: &String) -> Result<(u64, Vec<MapSymb>), ComposeError> {
+    let obj = dump_file(obj_path)?;
+    let elf_file = ElfFile::new(&obj).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the object {} as an ELF file: {}",
+            obj_path, e
+        ))
+    })?;
+    let text = elf_file
+        .find_section_by_name(".text")
+        .map(|s| s.address())
+        .ok_or(ComposeError::Pass(format!(
+            "Error: The object {} has no .text section to load symbols for.",
+            obj_path
+        )))?;
+    let symbs = match elf_file
+        .find_section_by_name(".symtab")
+        .map(|s| s.get_data(&elf_file))
+    {
+        Some(Ok(SectionData::SymbolTable32(sts))) => {
+            sts.iter().filter_map(|s| map_symb(&elf_file, s)).collect()
+        }
+        Some(Ok(SectionData::SymbolTable64(sts))) => {
+            sts.iter().filter_map(|s| map_symb(&elf_file, s)).collect()
+        }
+        _ => Vec::new(), // stripped, as in release builds
+    };
+
+    Ok((text, symbs))
+}
+
+// Write gdbinit and symbols.map into the build directory, and return
+// their paths.
+pub fn gdb_bundle_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+    stripped: bool,
+) -> Result<(String, String), ComposeError> {
+    let gdbinit = b.file_path(&"gdbinit".to_string())?;
+    let map = b.file_path(&"symbols.map".to_string())?;
+
+    // add-symbol-file would otherwise ask to confirm each object.
+    let mut cmds = format!(
+        "set confirm off\nfile {}\n",
+        s.get_constructor().image_path()
+    );
+    let mut all: Vec<(MapSymb, String)> = Vec::new();
+    for (id, cname) in s.get_named().ids() {
+        let name = cname.to_string();
+        let obj = s.get_objs_id(id).comp_path();
+        let obj = if stripped {
+            debug_path(obj)
+        } else {
+            obj.clone()
+        };
+        let (text, symbs) = obj_symbs(&obj)?;
+
+        cmds.push_str(&format!(
+            "# {} at COMP_BASEADDR {:#x}\nadd-symbol-file {} {:#x}\n",
+            name,
+            s.get_address_assignments().component_baseaddr(id),
+            obj,
+            text
+        ));
+        all.extend(symbs.into_iter().map(|sy| (sy, name.clone())));
+    }
+    cmds.push_str(&format!("target remote :{}\n", QEMU_GDB_PORT));
+
+    all.sort_by(|(a, an), (b, bn)| (a.addr, an).cmp(&(b.addr, bn)));
+    let lines: String = all
+        .iter()
+        .map(|(sy, c)| {
+            format!(
+                "{:016x} {:8x} {} {} [{}]\n",
+                sy.addr, sy.size, sy.kind, sy.name, c
+            )
+        })
+        .collect();
+
+    emit_file(&gdbinit, cmds.as_bytes())?;
+    emit_file(&map, lines.as_bytes())?;
+
+    Ok((gdbinit, map))
+}
@@ -1,6 +1,7 @@
+use manifest::Json;
 use passes::{
-    component, BuildState, ComponentId, ComponentName, Dependency, GraphPass, Interface,
-    ServiceType, SystemState, Transition, Variant,
+    component, deps, exports, BuildState, ComponentId, ComponentName, ComposeError, Dependency,
+    GraphPass, Interface, ServiceType, SystemState, Transition, Variant,
 };
 use petgraph::dot::Dot;
 use petgraph::graph::{DefaultIx, NodeIndex};
@@ -15,6 +16,7 @@ struct ComponentNode {
     id: ComponentId,
     name: ComponentName,
     properties: Vec<ServiceType>,
+    booter: bool, // the system constructor, loaded by the kernel
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
@@ -42,7 +44,7 @@ enum GraphNode {
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum GraphEdge {
-    CompDep,
+    CompDep(Vec<(Interface, Variant)>), // the interfaces the client depends on
     IfDep,
     IfExp,
 }
@@ -91,7 +93,17 @@ impl fmt::Display for GraphNode {
 
 impl fmt::Display for GraphEdge {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "")
+        match self {
+            GraphEdge::CompDep(ifs) => write!(
+                f,
+                "{}",
+                ifs.iter()
+                    .map(|(i, v)| format!("{}/{}", i, v))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            _ => write!(f, ""),
+        }
     }
 }
 
@@ -110,8 +122,8 @@ impl Graph {
         let comps: Vec<_> = s
             .get_named()
             .ids()
-            .iter()
-            .map(|(id, _)| component(s, id))
+            .keys()
+            .map(|id| component(s, id))
             .collect();
         let mut g_comp_map: HashMap<ComponentName, NodeIndex<DefaultIx>> = HashMap::new();
         let mut g_if_map: HashMap<InterfaceNode, NodeIndex<DefaultIx>> = HashMap::new();
@@ -123,10 +135,20 @@ impl Graph {
                 .get(&c.name)
                 .expect("Didn't find a component in the named rmap that must be in it!");
             // Create a graph node for a component
+            let properties = [
+                ServiceType::Scheduler,
+                ServiceType::CapMgr,
+                ServiceType::Constructor,
+            ]
+            .iter()
+            .filter(|t| s.get_properties().service_is_a(&id, (*t).clone()))
+            .cloned()
+            .collect();
             let gid = g.add_node(GraphNode::Comp(ComponentNode {
                 name: c.name.clone(),
                 id,
-                properties: Vec::new(), // TODO: add in the properties
+                properties,
+                booter: c.constructor.var_name == "kernel",
             }));
             // ...and make sure that we can look up the node successful to create edges.
             g_comp_map.insert(c.name.clone(), gid);
@@ -135,7 +157,7 @@ impl Graph {
             for dep in s.get_spec().deps_named(&c.name) {
                 let ifnode = InterfaceNode::from(dep);
                 // If we haven't yet added the interface, throw it in!
-                if g_if_map.get(&ifnode).is_none() || dep.variant == "kernel" {
+                if !g_if_map.contains_key(&ifnode) || dep.variant == "kernel" {
                     let g_if_id = g.add_node(GraphNode::Interface(ifnode.clone()));
                     g_if_map.insert(ifnode, g_if_id);
                 }
@@ -154,9 +176,19 @@ impl Graph {
                 let to_id = g_comp_map.get(&dep.server).expect(err);
                 let if_id = g_if_map.get(&InterfaceNode::from(dep)).expect(err);
 
-                if !g.contains_edge(*from_id, *to_id) {
-                    // Not saving the edge id as we can look it up with `find_edge`
-                    g.add_edge(*from_id, *to_id, GraphEdge::CompDep);
+                // Not saving the edge id as we can look it up with
+                // `find_edge`. Each component dependency edge
+                // aggregates all interfaces between client and server.
+                let iv = (dep.interface.clone(), dep.variant.clone());
+                match g.find_edge(*from_id, *to_id) {
+                    Some(e) => {
+                        if let Some(GraphEdge::CompDep(ref mut ifs)) = g.edge_weight_mut(e) {
+                            ifs.push(iv);
+                        }
+                    }
+                    None => {
+                        g.add_edge(*from_id, *to_id, GraphEdge::CompDep(vec![iv]));
+                    }
                 }
                 if !g.contains_edge(*from_id, *if_id) {
                     g.add_edge(*from_id, *if_id, GraphEdge::IfDep);
@@ -188,7 +220,7 @@ impl Graph {
                     },
                     |_, e| {
                         match e {
-                            &GraphEdge::CompDep if output_interfaces => None,
+                            &GraphEdge::CompDep(_) if output_interfaces => None,
                             _ => Some(e),
                         }
                     },
@@ -196,6 +228,9 @@ impl Graph {
                 &[],
                 &|_, _| "".to_string(),
                 &|_, n| match n.weight() {
+                    GraphNode::Comp(c) if c.booter => {
+                        "style=filled fillcolor=\"orange\" penwidth=3".to_string()
+                    }
                     GraphNode::Comp(_) => "style=filled fillcolor=\"skyblue\"".to_string(),
                     GraphNode::Interface(_) => "shape=rect".to_string(),
                 }
@@ -204,21 +239,89 @@ impl Graph {
     }
 }
 
+impl Graph {
+    // The component dependency graph, with edges labeled with the
+    // interfaces and variants, in DOT format. This only requires the
+    // specification, ordering, and properties passes.
+    pub fn export_dot(s: &SystemState) -> String {
+        Graph::build(s).render(&[])
+    }
+
+    // The same dependencies, as JSON: each component (in build
+    // order), its services, and the interfaces it exports, and
+    // depends on from which servers.
+    pub fn export_json(s: &SystemState) -> Json {
+        let services = [
+            (ServiceType::Scheduler, "scheduler"),
+            (ServiceType::CapMgr, "capmgr"),
+            (ServiceType::Constructor, "constructor"),
+        ];
+        let comps = s
+            .get_named()
+            .ids()
+            .iter()
+            .map(|(id, name)| {
+                let c = component(s, id);
+                Json::obj(vec![
+                    ("name", Json::Str(name.to_string())),
+                    ("id", Json::Num(*id as u64)),
+                    ("source", Json::str(&c.source)),
+                    ("booter", Json::Bool(c.constructor.var_name == "kernel")),
+                    (
+                        "services",
+                        Json::Arr(
+                            services
+                                .iter()
+                                .filter(|(t, _)| s.get_properties().service_is_a(id, t.clone()))
+                                .map(|(_, n)| Json::str(n))
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "exports",
+                        Json::Arr(
+                            exports(s, id)
+                                .iter()
+                                .map(|e| {
+                                    Json::obj(vec![
+                                        ("interface", Json::str(&e.interface)),
+                                        ("variant", Json::str(&e.variant)),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "deps",
+                        Json::Arr(
+                            deps(s, id)
+                                .iter()
+                                .map(|d| {
+                                    Json::obj(vec![
+                                        ("server", Json::Str(d.server.to_string())),
+                                        ("interface", Json::str(&d.interface)),
+                                        ("variant", Json::str(&d.variant)),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                ])
+            })
+            .collect();
+
+        Json::obj(vec![("components", Json::Arr(comps))])
+    }
+}
+
 impl Transition for Graph {
-    fn transition(c: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(c: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let g = Graph::build(c);
         let dotpath_comp = b.file_path(&"component_graph.dot".to_string())?;
         let dotpath_if = b.file_path(&"interfaces_graph.dot".to_string())?;
 
-        if let Err(s) = emit_file(&dotpath_comp, g.render(&[]).as_bytes()) {
-            return Err(s);
-        }
-        if let Err(s) = emit_file(
-            &dotpath_if,
-            g.render(&[GraphOutput::Interfaces]).as_bytes(),
-        ) {
-            return Err(s);
-        }
+        emit_file(&dotpath_comp, g.render(&[]).as_bytes())?;
+        emit_file(&dotpath_if, g.render(&[GraphOutput::Interfaces]).as_bytes())?;
 
         let comp_cmd = format!("dot -Tpdf -O {}", dotpath_comp);
         let _ = exec_pipeline(vec![comp_cmd]);
@@ -0,0 +1,237 @@
+// The build times and sizes of the components in past builds, so
+// that regressions are noticed: a component whose build takes much
+// longer than it used to (e.g. as it's now built with link-time
+// optimization), or whose object grows much larger (e.g. as it's now
+// built with -O0, or with debugging code), is warned about, when it
+// grows by more than --regression-threshold percent. The history is
+// kept beside the object cache (build_history.toml), as it persists
+// across builds, and is of each specification in each configuration
+// (as in its lockfile), as the components, and how they're built,
+// differ between them.
+//
+// Build times are noisy, so a build is compared to the median of the
+// last few that compiled the component (rather than reusing its
+// cached object), and small differences, however large relatively,
+// are ignored, as are those in size.
+
+use build::DefaultBuilder;
+use lockfile::config;
+use passes::SystemState;
+use sizes::SizeReport;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use syshelpers::{dump_file, emit_file};
+use timing::{StepKind, TimingReport};
+use toml;
+
+// Concurrent builds (of a matrix's configurations) update the same
+// history.
+static HISTORY: Mutex<()> = Mutex::new(());
+
+const HISTORY_FILE: &str = "build_history.toml";
+const HEADER: &str =
+    "# The components' build times and sizes in past builds, recorded by the composer.\n";
+// The runs kept of each component, and those whose build times are
+// compared against.
+const HISTORY_RUNS: usize = 20;
+const HISTORY_BASELINE: usize = 5;
+const REGRESSION_MIN_MS: u64 = 1000;
+const REGRESSION_MIN_BYTES: u64 = 4096;
+
+// A component's build (`build_ms` is None if its cached object was
+// reused), and its object's size.
+struct Run {
+    time: u64, // seconds since the epoch
+    build_ms: Option<u64>,
+    size: u64,
+}
+
+fn run_parse(v: &toml::Value) -> Option<Run> {
+    let int = |k: &str| v.get(k).and_then(|i| i.as_integer()).map(|i| i as u64);
+
+    Some(Run {
+        time: int("time")?,
+        build_ms: int("build_ms"),
+        size: int("size")?,
+    })
+}
+
+fn run_value(r: &Run) -> toml::Value {
+    let mut t = toml::value::Table::new();
+    t.insert(String::from("time"), toml::Value::Integer(r.time as i64));
+    if let Some(ms) = r.build_ms {
+        t.insert(String::from("build_ms"), toml::Value::Integer(ms as i64));
+    }
+    t.insert(String::from("size"), toml::Value::Integer(r.size as i64));
+
+    toml::Value::Table(t)
+}
+
+// Is `new` larger than `old` by more than `threshold` percent, and by
+// more than `min`?
+fn regressed(old: u64, new: u64, threshold: u64, min: u64) -> bool {
+    new > old + min && new * 100 > old * (100 + threshold)
+}
+
+fn growth(old: u64, new: u64) -> u64 {
+    (new - old) * 100 / old.max(1)
+}
+
+fn median(mut v: Vec<u64>) -> Option<u64> {
+    v.sort();
+    v.get(v.len() / 2).cloned()
+}
+
+// The history, or an empty one if it can't be read (which only loses
+// what the builds are compared against).
+fn history_read(path: &String) -> Vec<toml::Value> {
+    if !Path::new(path).exists() {
+        return Vec::new();
+    }
+    let contents = match dump_file(path) {
+        Ok(c) => String::from_utf8_lossy(&c).to_string(),
+        Err(_) => return Vec::new(),
+    };
+    match toml::from_str::<toml::Value>(&contents) {
+        Ok(h) => h
+            .get("system")
+            .and_then(|s| s.as_array())
+            .cloned()
+            .unwrap_or(Vec::new()),
+        Err(e) => {
+            warn!(
+                "The build history {} cannot be parsed ({}), so it is replaced.",
+                path, e
+            );
+            Vec::new()
+        }
+    }
+}
+
+// Record the components' build times (from `timing`) and sizes in the
+// history, and return the regressions from their past builds, each
+// of which is warned about.
+pub fn history_update(
+    s: &SystemState,
+    b: &DefaultBuilder,
+    sizes: &SizeReport,
+    timing: &TimingReport,
+) -> Vec<String> {
+    let path = b.cachedir().join(HISTORY_FILE).display().to_string();
+    let spec = fs::canonicalize(s.get_input())
+        .map(|p| p.display().to_string())
+        .unwrap_or(s.get_input().clone());
+    let cfg = config(s, b);
+    let threshold = b.regression_threshold();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    // The lock is only held for the history, so poisoning is benign.
+    let _guard = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut systems = history_read(&path);
+    let is_sys = |v: &toml::Value| {
+        v.get("spec").and_then(|s| s.as_str()) == Some(&spec)
+            && v.get("config").and_then(|c| c.as_str()) == Some(&cfg)
+    };
+    let prev: Vec<toml::Value> = systems
+        .iter()
+        .find(|v| is_sys(v))
+        .and_then(|v| v.get("component"))
+        .and_then(|c| c.as_array())
+        .cloned()
+        .unwrap_or(Vec::new());
+    let prev_runs = |name: &String| -> Vec<Run> {
+        prev.iter()
+            .find(|c| c.get("name").and_then(|n| n.as_str()) == Some(name))
+            .and_then(|c| c.get("runs"))
+            .and_then(|r| r.as_array())
+            .map(|rs| rs.iter().filter_map(run_parse).collect())
+            .unwrap_or(Vec::new())
+    };
+
+    let mut regressions = Vec::new();
+    let mut comps = Vec::new();
+    for (name, size) in sizes.comp_totals().into_iter() {
+        // The last time it was compiled, if it was.
+        let build_ms = timing
+            .steps()
+            .iter()
+            .filter(|st| st.kind == StepKind::Component && st.name == name && !st.cached)
+            .max_by_key(|st| st.start)
+            .map(|st| (st.end - st.start).as_millis() as u64);
+        let mut runs = prev_runs(&name);
+
+        let past: Vec<u64> = runs
+            .iter()
+            .rev()
+            .filter_map(|r| r.build_ms)
+            .take(HISTORY_BASELINE)
+            .collect();
+        let n = past.len();
+        if let (Some(ms), Some(base)) = (build_ms, median(past)) {
+            if regressed(base, ms, threshold, REGRESSION_MIN_MS) {
+                regressions.push(format!(
+                    "Component {}'s build took {:.1}s, {}% longer than the median ({:.1}s) of its last {} builds.",
+                    name,
+                    ms as f64 / 1000.0,
+                    growth(base, ms),
+                    base as f64 / 1000.0,
+                    n
+                ));
+            }
+        }
+        if let Some(last) = runs.last() {
+            if regressed(last.size, size, threshold, REGRESSION_MIN_BYTES) {
+                regressions.push(format!(
+                    "Component {}'s object is {} bytes, {}% larger than in the last build ({} bytes); is it now built unoptimized (-O0), or with debugging code?",
+                    name,
+                    size,
+                    growth(last.size, size),
+                    last.size
+                ));
+            }
+        }
+
+        runs.push(Run {
+            time: now,
+            build_ms,
+            size,
+        });
+        let skip = runs.len().saturating_sub(HISTORY_RUNS);
+        let mut c = toml::value::Table::new();
+        c.insert(String::from("name"), toml::Value::String(name));
+        c.insert(
+            String::from("runs"),
+            toml::Value::Array(runs.iter().skip(skip).map(run_value).collect()),
+        );
+        comps.push(toml::Value::Table(c));
+    }
+    for r in regressions.iter() {
+        warn!("{}", r);
+    }
+
+    let mut sys = toml::value::Table::new();
+    sys.insert(String::from("spec"), toml::Value::String(spec.clone()));
+    sys.insert(String::from("config"), toml::Value::String(cfg.clone()));
+    sys.insert(String::from("component"), toml::Value::Array(comps));
+    systems.retain(|v| !is_sys(v));
+    systems.push(toml::Value::Table(sys));
+    let mut h = toml::value::Table::new();
+    h.insert(String::from("system"), toml::Value::Array(systems));
+    // Failing to record the history only loses what later builds are
+    // compared against.
+    let res = toml::to_string(&toml::Value::Table(h))
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            emit_file(&path, format!("{}{}", HEADER, contents).as_bytes())
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = res {
+        warn!("Could not record the build history in {}: {}", path, e);
+    }
+
+    regressions
+}
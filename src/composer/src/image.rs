@@ -0,0 +1,224 @@
+// Package the kernel image so that it can be booted by grub, which
+// loads it using multiboot2. The grub directory holds the
+// configuration and image in the layout grub-mkrescue expects for an
+// ISO.
+//
+// grub-mkrescue generates the ISO with whichever of grub's platforms
+// are installed, and (without complaint) one that a BIOS cannot boot
+// if grub's BIOS modules aren't. As VMware's and VirtualBox's VMs,
+// and many real machines, boot ISOs from their BIOS (or its
+// emulation), the generated ISO is checked to be an ISO9660 image
+// with an El Torito boot entry for x86 BIOSes, and a hybrid one (with
+// an MBR) that boots from a USB stick it's written to.
+
+use dtb::dtb_path;
+use fit::{fit_image, kernel_binary};
+use passes::{Arch, BuildState, ComposeError, ImageFormat, SystemState};
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::process::{Command, Output};
+use syshelpers::{dump_file, emit_file, io_error, reset_dir};
+
+const GRUB_CFG: &str = "set timeout=0
+set default=0
+menuentry \"composite\" {
+  multiboot2 /boot/cos.img
+}
+";
+
+// grub's BIOS modules, when installed (e.g. Ubuntu's grub-pc-bin).
+pub const GRUB_BIOS_DIR: &str = "/usr/lib/grub/i386-pc";
+
+const ISO_SECTOR: u64 = 2048;
+// The volume descriptors start at the 16th sector, and end with the
+// terminator (of type 255).
+const ISO_VD_START: u64 = 16;
+const ISO_VD_MAX: u64 = 64;
+const ISO_VD_ID: &[u8] = b"CD001";
+const ELTORITO_ID: &[u8] = b"EL TORITO SPECIFICATION";
+const ELTORITO_X86: u8 = 0;
+const ELTORITO_BOOTABLE: u8 = 0x88;
+
+fn iso_read(iso: &fs::File, path: &String, off: u64, len: usize) -> Result<Vec<u8>, ComposeError> {
+    let mut buf = vec![0; len];
+    iso.read_exact_at(&mut buf, off)
+        .map_err(|e| io_error(path, "read the ISO image", e))?;
+
+    Ok(buf)
+}
+
+// The reasons that the ISO at `path` cannot be booted from a BIOS.
+fn iso_unbootable(path: &String) -> Result<Vec<String>, ComposeError> {
+    let iso = fs::File::open(path).map_err(|e| io_error(path, "open the ISO image", e))?;
+    let mut problems = Vec::new();
+
+    let mbr = iso_read(&iso, path, 0, 512)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        problems.push(String::from(
+            "it isn't a hybrid image (with an MBR), so it cannot boot from a USB stick",
+        ));
+    }
+
+    let mut primary = false;
+    let mut catalog = None;
+    for sector in ISO_VD_START..ISO_VD_MAX {
+        let vd = iso_read(&iso, path, sector * ISO_SECTOR, ISO_SECTOR as usize)?;
+        if &vd[1..6] != ISO_VD_ID || vd[0] == 255 {
+            break;
+        }
+        match vd[0] {
+            // The boot record's catalog sector is at offset 0x47.
+            0 if vd[7..7 + ELTORITO_ID.len()] == *ELTORITO_ID => {
+                let mut lba = [0; 4];
+                lba.copy_from_slice(&vd[0x47..0x4b]);
+                catalog = Some(u32::from_le_bytes(lba) as u64);
+            }
+            1 => primary = true,
+            _ => (),
+        }
+    }
+    if !primary {
+        return Ok(vec![String::from("it isn't an ISO9660 image")]);
+    }
+
+    // The catalog's validation entry names the platform of its
+    // initial entry, which follows it.
+    match catalog {
+        None => problems.push(String::from("it has no El Torito boot catalog")),
+        Some(lba) => {
+            let c = iso_read(&iso, path, lba * ISO_SECTOR, 64)?;
+            if c[0] != 1 || c[30..32] != [0x55, 0xaa] {
+                problems.push(String::from("its El Torito boot catalog is invalid"));
+            } else if c[1] != ELTORITO_X86 || c[32] != ELTORITO_BOOTABLE {
+                problems.push(String::from(
+                    "it has no El Torito boot entry for x86 BIOSes, so only UEFI firmware can boot it",
+                ));
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+fn mkrescue(iso_path: &String, dir: &String) -> Result<Output, ComposeError> {
+    // distributions name grub-mkrescue differently
+    for prog in ["grub-mkrescue", "grub2-mkrescue"].iter() {
+        let mut cmd = Command::new(prog);
+        if Path::new(GRUB_BIOS_DIR).is_dir() {
+            cmd.arg("-d").arg(GRUB_BIOS_DIR);
+        }
+        match cmd.arg("-o").arg(iso_path).arg(dir).output() {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(ComposeError::Io {
+                    path: iso_path.clone(),
+                    msg: format!("could not execute {}: {}", prog, e),
+                })
+            }
+            Ok(o) => return Ok(o),
+        }
+    }
+
+    Err(ComposeError::Io {
+        path: iso_path.clone(),
+        msg: String::from("Cannot find grub-mkrescue or grub2-mkrescue to generate the ISO image"),
+    })
+}
+
+// The image that boards' loaders (U-Boot, or on x86, grub) load from
+// a disk, or from the network: U-Boot boots armv7a boards' FIT images.
+pub fn loader_image_format(s: &SystemState) -> ImageFormat {
+    match s.get_spec().arch() {
+        Arch::Armv7a => ImageFormat::Fit,
+        _ => ImageFormat::Kernel,
+    }
+}
+
+// Can the system's images be booted in `format`? Checked before
+// building so that we don't build a system we can't package.
+pub fn image_supported(format: ImageFormat, s: &SystemState) -> Result<(), ComposeError> {
+    let arch = s.get_spec().arch();
+    if format == ImageFormat::Fit {
+        if arch != Arch::Armv7a {
+            return Err(ComposeError::Spec(format!(
+                "Error: Only armv7a system images can be packaged as FIT images for U-Boot, not {} images.",
+                arch.name()
+            )));
+        }
+    } else if format != ImageFormat::Kernel && arch != Arch::X86_64 && arch != Arch::I386 {
+        return Err(ComposeError::Spec(format!(
+            "Error: Only x86 system images can be booted by grub (via multiboot2), not {} images.",
+            arch.name()
+        )));
+    }
+
+    Ok(())
+}
+
+// Generate the images for the `format` beyond the kernel image, and
+// return their paths.
+pub fn image_create(
+    format: ImageFormat,
+    s: &SystemState,
+    b: &dyn BuildState,
+) -> Result<Vec<String>, ComposeError> {
+    image_supported(format, s)?;
+    if format == ImageFormat::Kernel {
+        return Ok(Vec::new());
+    }
+    if format == ImageFormat::Fit {
+        let kern = s.get_constructor().image_path();
+        let dtb = match dtb_path(s, b)? {
+            Some(d) => Some((dump_file(&d)?, s.get_spec().dtb_addr().map(|a| a as u32))),
+            None => None,
+        };
+        let itb = b.file_path(&"cos.itb".to_string())?;
+        let fit = fit_image("composite", kernel_binary(kern, &dump_file(kern)?)?, dtb);
+        emit_file(&itb, &fit)?;
+        return Ok(vec![itb]);
+    }
+
+    let dir = b.file_path(&"grub".to_string())?;
+    let img = format!("{}/boot/cos.img", dir);
+    let cfg = format!("{}/boot/grub/grub.cfg", dir);
+    reset_dir(&dir)?;
+    fs::create_dir_all(format!("{}/boot/grub", dir)).map_err(|e| ComposeError::Io {
+        path: cfg.clone(),
+        msg: format!("Could not create the grub directory: {}", e),
+    })?;
+    fs::copy(s.get_constructor().image_path(), &img).map_err(|e| ComposeError::Io {
+        path: img.clone(),
+        msg: format!("Could not copy the system image: {}", e),
+    })?;
+    emit_file(&cfg, GRUB_CFG.as_bytes())?;
+    if format == ImageFormat::Grub {
+        return Ok(vec![img, cfg]);
+    }
+
+    let iso = b.file_path(&"cos.iso".to_string())?;
+    let out = mkrescue(&iso, &dir)?;
+    if !out.status.success() {
+        return Err(ComposeError::Io {
+            path: iso,
+            msg: format!(
+                "grub-mkrescue failed to generate the ISO image:\n{}",
+                String::from_utf8_lossy(&out.stderr)
+            ),
+        });
+    }
+    let problems = iso_unbootable(&iso)?;
+    if !problems.is_empty() {
+        return Err(ComposeError::Io {
+            path: iso,
+            msg: format!(
+                "grub-mkrescue generated an ISO image that BIOSes cannot boot, as {}. Install grub's BIOS modules (e.g. grub-pc-bin, or grub2-pc-modules), in {}.",
+                problems.join(", and "),
+                GRUB_BIOS_DIR
+            ),
+        });
+    }
+
+    Ok(vec![img, cfg, iso])
+}
@@ -1,13 +1,22 @@
-use passes::{component, BuildState, ComponentId, InitParamPass, SystemState, TransitionIter};
+use archive::archive_create;
+use passes::{
+    component, ArchiveFormat, BuildState, Component, ComponentId, ComposeError, InitParamPass,
+    InitargsFormat, SystemState, TransitionIter,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use syshelpers::emit_file;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ArgsValType {
     Str(String),
     Arr(Vec<ArgsKV>),
+    Blob(Vec<u8>), // binary data, e.g. keys, that can include '\0's
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ArgsKV {
     key: String,
     val: ArgsValType,
@@ -24,11 +33,27 @@ impl VarNamespace {
 
     fn fresh_name(&mut self) -> String {
         let id = self.id;
-        self.id = self.id + 1;
+        self.id += 1;
         format!("__initargs_autogen_{}", id)
     }
 }
 
+// Escape a string for inclusion in a C string literal. Parameter
+// values come from the specification, so can include quotes and
+// newlines.
+fn c_escape(s: &str) -> String {
+    s.chars().fold(String::new(), |mut acc, c| {
+        match c {
+            '"' => acc.push_str("\\\""),
+            '\\' => acc.push_str("\\\\"),
+            '\n' => acc.push_str("\\n"),
+            '\t' => acc.push_str("\\t"),
+            c => acc.push(c),
+        }
+        acc
+    })
+}
+
 impl ArgsKV {
     pub fn new_key(key: String, val: String) -> ArgsKV {
         ArgsKV {
@@ -44,6 +69,13 @@ impl ArgsKV {
         }
     }
 
+    pub fn new_blob(key: String, val: Vec<u8>) -> ArgsKV {
+        ArgsKV {
+            key,
+            val: ArgsValType::Blob(val),
+        }
+    }
+
     pub fn new_top(val: Vec<ArgsKV>) -> ArgsKV {
         ArgsKV {
             key: String::from("_"),
@@ -51,6 +83,55 @@ impl ArgsKV {
         }
     }
 
+    pub fn key(&self) -> &String {
+        &self.key
+    }
+
+    pub fn val(&self) -> &ArgsValType {
+        &self.val
+    }
+
+    // Add `val` into `kvs` at the '/'-separated `path` (e.g.
+    // "net/ip"), creating, or adding to, the nested arrays named by
+    // each of the path's prefixes. A path cannot both hold a value
+    // and be the prefix of another path.
+    pub fn insert_path(kvs: &mut Vec<ArgsKV>, path: &str, val: ArgsValType) -> Result<(), String> {
+        let mut keys = path.splitn(2, '/');
+        let key = keys.next().unwrap_or("");
+        let rest = keys.next();
+        if key.is_empty() || rest == Some("") {
+            return Err(format!("the parameter path \"{}\" has an empty key", path));
+        }
+
+        let existing = kvs.iter_mut().find(|kv| kv.key == key);
+        match (existing, rest) {
+            (None, None) => kvs.push(ArgsKV {
+                key: key.to_string(),
+                val,
+            }),
+            (None, Some(r)) => {
+                let mut nested = Vec::new();
+                ArgsKV::insert_path(&mut nested, r, val)?;
+                kvs.push(ArgsKV::new_arr(key.to_string(), nested));
+            }
+            (
+                Some(ArgsKV {
+                    val: ArgsValType::Arr(ref mut nested),
+                    ..
+                }),
+                Some(r),
+            ) => ArgsKV::insert_path(nested, r, val)?,
+            (Some(_), _) => {
+                return Err(format!(
+                    "the parameter \"{}\" is provided multiple times, or both as a value and as a path to nested parameters",
+                    key
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
     // This provides code generation for the data-structure containing
     // the initial arguments for the component.  Return a string
     // accumulating new definitions, and another accumulating arrays.
@@ -66,7 +147,33 @@ impl ArgsKV {
                     format!(
                         r#"static struct kv_entry {} = {{ key: "{}", vtype: VTYPE_STR, val: {{ str: "{}" }} }};
 "#,
-                        kv_name, k, s
+                        kv_name,
+                        c_escape(k),
+                        c_escape(s)
+                    ),
+                    vec![format!("&{}", kv_name)],
+                )
+            }
+            ArgsKV {
+                key: k,
+                val: ArgsValType::Blob(ref b),
+            } => {
+                let kv_name = ns.fresh_name();
+                let data_name = ns.fresh_name(); // the blob's bytes
+                (
+                    format!(
+                        r#"static unsigned char {}[] = {{{}}};
+static struct kv_entry {} = {{ key: "{}", vtype: VTYPE_BLOB, val: {{ blob: {{ sz: {}, data: {} }} }} }};
+"#,
+                        data_name,
+                        b.iter()
+                            .map(|b| format!("{:#04x}", b))
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                        kv_name,
+                        c_escape(k),
+                        b.len(),
+                        data_name
                     ),
                     vec![format!("&{}", kv_name)],
                 )
@@ -98,7 +205,7 @@ static struct kv_entry {} = {{ key: "{}", vtype: VTYPE_ARR, val: {{ arr: {{ sz:
                         arr_name,
                         strs.1.join(", "),
                         arr_val_name,
-                        k,
+                        c_escape(k),
                         kvs.len(),
                         arr_name
                     ),
@@ -117,46 +224,587 @@ static struct kv_entry {} = {{ key: "{}", vtype: VTYPE_ARR, val: {{ arr: {{ sz:
 {}
 struct initargs __initargs_root = {{ type: ARGS_IMPL_KV, d: {{ kv_ent: &__initargs_autogen_0 }} }};", self.serialize_rec(&mut ns).0)
     }
+
+    // Parse the c data-structure generated by serialize back into the
+    // initial arguments, so that generated initargs (e.g. those in a
+    // build directory) can be read, and checked against those they
+    // were generated from.
+    pub fn deserialize(c: &str) -> Result<ArgsKV, String> {
+        let mut defs = CDefs {
+            entries: HashMap::new(),
+            arrays: HashMap::new(),
+            blobs: HashMap::new(),
+        };
+        let mut root = None;
+        let toks = c_tokens(c)?;
+        for stmt in toks.split(|t| *t == CTok::Punct(';')) {
+            let idents: Vec<&String> = stmt
+                .iter()
+                .filter_map(|t| match t {
+                    CTok::Ident(i) => Some(i),
+                    _ => None,
+                })
+                .collect();
+            // The value following `field:` in an initializer.
+            let field = |f: &str| {
+                stmt.windows(3)
+                    .find(|w| w[0] == CTok::Ident(f.to_string()) && w[1] == CTok::Punct(':'))
+                    .map(|w| &w[2])
+            };
+            match (idents.as_slice(), stmt.get(3)) {
+                ([], _) => (),
+                ([s, st, kv, name, ..], Some(CTok::Punct('*')))
+                    if *s == "static" && *st == "struct" && *kv == "kv_entry" =>
+                {
+                    // The array of an array value: each of its
+                    // expressions is &name.
+                    defs.arrays.insert(name.to_string(), idents[4..].to_vec());
+                }
+                ([s, u, ch, name, ..], _)
+                    if *s == "static" && *u == "unsigned" && *ch == "char" =>
+                {
+                    // A blob's bytes, in hex.
+                    let bytes = idents[4..]
+                        .iter()
+                        .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16))
+                        .collect::<Result<Vec<u8>, _>>()
+                        .map_err(|_| format!("blob {} has a malformed byte", name))?;
+                    defs.blobs.insert(name.to_string(), bytes);
+                }
+                ([s, st, kv, name, ..], _)
+                    if *s == "static" && *st == "struct" && *kv == "kv_entry" =>
+                {
+                    let key = match field("key") {
+                        Some(CTok::Str(k)) => k.clone(),
+                        _ => return Err(format!("entry {} has no key", name)),
+                    };
+                    let sz = match field("sz") {
+                        Some(CTok::Ident(sz)) => sz.parse::<usize>().ok(),
+                        _ => None,
+                    };
+                    let val = match (
+                        field("vtype"),
+                        field("str"),
+                        field("kvs"),
+                        field("data"),
+                        sz,
+                    ) {
+                        (Some(CTok::Ident(t)), Some(CTok::Str(v)), _, _, _) if t == "VTYPE_STR" => {
+                            CEntry::Str(v.clone())
+                        }
+                        (Some(CTok::Ident(t)), _, Some(CTok::Ident(arr)), _, Some(sz))
+                            if t == "VTYPE_ARR" =>
+                        {
+                            CEntry::Arr(sz, arr.clone())
+                        }
+                        (Some(CTok::Ident(t)), _, _, Some(CTok::Ident(data)), Some(sz))
+                            if t == "VTYPE_BLOB" =>
+                        {
+                            CEntry::Blob(sz, data.clone())
+                        }
+                        _ => return Err(format!("entry {} has a malformed value", name)),
+                    };
+                    defs.entries.insert(name.to_string(), (key, val));
+                }
+                ([st, ia, r, .., name], _)
+                    if *st == "struct" && *ia == "initargs" && *r == "__initargs_root" =>
+                {
+                    root = Some(name.to_string());
+                }
+                _ => return Err(String::from("unexpected definition")),
+            }
+        }
+
+        let root = root.ok_or(String::from("there is no __initargs_root"))?;
+        ArgsKV::deserialize_rec(&root, &defs, 0)
+    }
+
+    // The binary TLV encoding of the initial arguments: TLV_MAGIC,
+    // then the top entry. Each entry is its type (a byte: 0 for a
+    // string, 1 for an array, 2 for a blob), the length of its value
+    // (4 bytes, as are all lengths, little endian), and its value:
+    // the key's length, the NUL-terminated key, and then either the
+    // NUL-terminated string, the array's entries, or the blob. The
+    // NULs let the component use the keys and strings in place (see
+    // lib/initargs). As with serialize, arrays are in the reverse
+    // order, which is the order in which the component iterates
+    // them.
+    pub fn tlv(&self) -> Vec<u8> {
+        let mut out = TLV_MAGIC.to_vec();
+        self.tlv_rec(&mut out);
+        out
+    }
+
+    fn tlv_rec(&self, out: &mut Vec<u8>) {
+        let mut val = (self.key.len() as u32).to_le_bytes().to_vec();
+        val.extend(self.key.as_bytes());
+        val.push(0);
+        let vtype = match self.val {
+            ArgsValType::Str(ref s) => {
+                val.extend(s.as_bytes());
+                val.push(0);
+                0
+            }
+            ArgsValType::Arr(ref kvs) => {
+                kvs.iter().rev().for_each(|kv| kv.tlv_rec(&mut val));
+                1
+            }
+            ArgsValType::Blob(ref b) => {
+                val.extend(b);
+                2
+            }
+        };
+        out.push(vtype);
+        out.extend(&(val.len() as u32).to_le_bytes());
+        out.extend(val);
+    }
+
+    // The size of the entry's TLV encoding.
+    fn tlv_size(&self) -> u64 {
+        let hdr = 1 + 4 + 4 + self.key.len() as u64 + 1;
+        match self.val {
+            ArgsValType::Str(ref s) => hdr + s.len() as u64 + 1,
+            ArgsValType::Blob(ref b) => hdr + b.len() as u64,
+            ArgsValType::Arr(ref kvs) => kvs.iter().fold(hdr, |sz, kv| sz + kv.tlv_size()),
+        }
+    }
+
+    // Decode the TLV encoding generated by tlv.
+    pub fn from_tlv(tlv: &[u8]) -> Result<ArgsKV, String> {
+        if !tlv.starts_with(TLV_MAGIC) {
+            return Err(String::from("the initargs are not in the TLV format"));
+        }
+        let (kv, rest) = ArgsKV::from_tlv_rec(&tlv[TLV_MAGIC.len()..])?;
+        if !rest.is_empty() {
+            return Err(String::from("the initargs have trailing data"));
+        }
+
+        Ok(kv)
+    }
+
+    // Decode the entry at the start of `tlv`, returning it, and the
+    // data that follows it.
+    fn from_tlv_rec(tlv: &[u8]) -> Result<(ArgsKV, &[u8]), String> {
+        let len = |bs: &[u8]| -> Result<usize, String> {
+            match bs.get(..4) {
+                Some(l) => Ok(u32::from_le_bytes([l[0], l[1], l[2], l[3]]) as usize),
+                None => Err(String::from("an entry is truncated")),
+            }
+        };
+        let vtype = *tlv.first().ok_or(String::from("an entry is truncated"))?;
+        let vlen = len(&tlv[1..])?;
+        let val = tlv
+            .get(5..5 + vlen)
+            .ok_or(String::from("an entry is truncated"))?;
+        let klen = len(val)?;
+        let key = match val.get(4..4 + klen + 1) {
+            Some([key @ .., 0]) => String::from_utf8_lossy(key).to_string(),
+            _ => return Err(String::from("a key is truncated")),
+        };
+        let mut rest = &val[4 + klen + 1..];
+        let kv = match vtype {
+            0 => match rest {
+                [s @ .., 0] => ArgsKV::new_key(key, String::from_utf8_lossy(s).to_string()),
+                _ => return Err(format!("entry {}'s string isn't terminated", key)),
+            },
+            1 => {
+                let mut kvs = Vec::new();
+                while !rest.is_empty() {
+                    let (kv, r) = ArgsKV::from_tlv_rec(rest)?;
+                    kvs.push(kv);
+                    rest = r;
+                }
+                kvs.reverse();
+                ArgsKV::new_arr(key, kvs)
+            }
+            2 => ArgsKV::new_blob(key, rest.to_vec()),
+            t => return Err(format!("entry {} has an unknown type {}", key, t)),
+        };
+
+        Ok((kv, &tlv[5 + vlen..]))
+    }
+
+    // The size of the data-structures that serialize generates, for
+    // pointers of `ptr` bytes: each kv_entry is the size of 4
+    // pointers (with padding), each array holds a pointer per entry,
+    // and the strings are null-terminated.
+    pub fn c_size(&self, ptr: u64) -> u64 {
+        let strs = self.key.len() as u64 + 1;
+        match self.val {
+            ArgsValType::Str(ref s) => 4 * ptr + strs + s.len() as u64 + 1,
+            ArgsValType::Blob(ref b) => 4 * ptr + strs + b.len() as u64,
+            ArgsValType::Arr(ref kvs) => kvs
+                .iter()
+                .fold(4 * ptr + strs, |sz, kv| sz + ptr + kv.c_size(ptr)),
+        }
+    }
+
+    fn deserialize_rec(name: &String, defs: &CDefs, depth: usize) -> Result<ArgsKV, String> {
+        // Nesting deeper than there are entries must be a cycle.
+        if depth > defs.entries.len() {
+            return Err(format!("entry {} is nested within itself", name));
+        }
+        let (key, val) = defs
+            .entries
+            .get(name)
+            .ok_or(format!("entry {} is not defined", name))?;
+        match val {
+            CEntry::Str(s) => Ok(ArgsKV::new_key(key.clone(), s.clone())),
+            CEntry::Blob(sz, data) => match defs.blobs.get(data) {
+                Some(b) if b.len() == *sz => Ok(ArgsKV::new_blob(key.clone(), b.clone())),
+                Some(_) => Err(format!("entry {}'s size doesn't match its blob", name)),
+                None => Err(format!("blob {} is not defined", data)),
+            },
+            CEntry::Arr(sz, arr) => {
+                let elems = defs
+                    .arrays
+                    .get(arr)
+                    .ok_or(format!("array {} is not defined", arr))?;
+                if elems.len() != *sz {
+                    return Err(format!(
+                        "entry {} has size {}, but its array has {} entries",
+                        name,
+                        sz,
+                        elems.len()
+                    ));
+                }
+                // The arrays are serialized in reverse.
+                let kvs = elems
+                    .iter()
+                    .rev()
+                    .map(|e| ArgsKV::deserialize_rec(e, defs, depth + 1))
+                    .collect::<Result<Vec<ArgsKV>, String>>()?;
+                Ok(ArgsKV::new_arr(key.clone(), kvs))
+            }
+        }
+    }
+}
+
+// The start of the TLV encoding, the file holding it in the
+// component's build directory, and its path within the tarball of a
+// component with initargs_format = "tlv". lib/initargs can only look
+// up files in a tarball that starts with a directory, so it's in
+// one, first.
+const TLV_MAGIC: &[u8] = b"cosargs1";
+pub const TLV_FILE: &str = "initargs.tlv";
+pub const TLV_PATH: &str = "initargs/initargs.tlv";
+
+// The value of a kv_entry in the generated initargs: either a string,
+// or the size and name of its array or blob.
+enum CEntry {
+    Str(String),
+    Arr(usize, String),
+    Blob(usize, String),
+}
+
+// The definitions in the generated initargs, by name: the entries,
+// the arrays (of the names of entries), and the blobs.
+struct CDefs<'a> {
+    entries: HashMap<String, (String, CEntry)>,
+    arrays: HashMap<String, Vec<&'a String>>,
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum CTok {
+    Ident(String), // identifiers and numbers
+    Str(String),   // string literals, unescaped
+    Punct(char),
+}
+
+// The tokens of the generated initargs, skipping the preprocessor
+// directives, and undoing c_escape within string literals.
+fn c_tokens(c: &str) -> Result<Vec<CTok>, String> {
+    let mut toks = Vec::new();
+    let mut cs = c
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .chars()
+        .collect::<Vec<char>>()
+        .into_iter()
+        .peekable();
+    while let Some(c) = cs.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut id = c.to_string();
+                while let Some(c) = cs.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    id.push(c);
+                }
+                toks.push(CTok::Ident(id));
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match (cs.next(), cs.peek()) {
+                        (Some('"'), _) => break,
+                        (Some('\\'), Some('n')) => s.push('\n'),
+                        (Some('\\'), Some('t')) => s.push('\t'),
+                        (Some('\\'), Some(&c)) if c == '"' || c == '\\' => s.push(c),
+                        (Some('\\'), _) => return Err(String::from("unknown escape in a string")),
+                        (Some(c), _) => {
+                            s.push(c);
+                            continue;
+                        }
+                        (None, _) => return Err(String::from("unterminated string")),
+                    }
+                    // The escaped character.
+                    cs.next();
+                }
+                toks.push(CTok::Str(s));
+            }
+            c => toks.push(CTok::Punct(c)),
+        }
+    }
+
+    Ok(toks)
+}
+
+// Does the file `name` match the glob `pat`, where `*` matches any
+// sequence of characters, and `?` any single character?
+fn glob_match(pat: &[char], name: &[char]) -> bool {
+    match (pat.first(), name.first()) {
+        (None, None) => true,
+        (Some(&'*'), _) => {
+            glob_match(&pat[1..], name) || (!name.is_empty() && glob_match(pat, &name[1..]))
+        }
+        (Some(&'?'), Some(_)) => glob_match(&pat[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pat[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+// The entries of directory `dir`, sorted so that tarballs are
+// reproducible.
+fn dir_entries(dir: &Path) -> Result<Vec<PathBuf>, ComposeError> {
+    let io_err = |e: io::Error| ComposeError::Io {
+        path: dir.display().to_string(),
+        msg: e.to_string(),
+    };
+    let mut es = Vec::new();
+    for e in fs::read_dir(dir).map_err(io_err)? {
+        es.push(e.map_err(io_err)?.path());
+    }
+    es.sort();
+
+    Ok(es)
+}
+
+// Add the file or directory (recursively) at `path` into the
+// `contents` with the name `name` within the tarball.
+fn initfs_add(
+    path: &Path,
+    name: String,
+    contents: &mut Vec<(String, String)>,
+) -> Result<(), ComposeError> {
+    if !path.is_dir() {
+        contents.push((path.display().to_string(), name));
+        return Ok(());
+    }
+    for e in dir_entries(path)? {
+        // unwrap as directory entries always have a name
+        let n = e.file_name().unwrap().to_string_lossy().to_string();
+        let child = if name.is_empty() {
+            n
+        } else {
+            format!("{}/{}", name, n)
+        };
+        initfs_add(&e, child, contents)?;
+    }
+
+    Ok(())
+}
+
+// The host files, and their names within the tarball, of component
+// `id`'s initfs. Paths are relative to the specification's directory.
+pub fn initfs_contents(
+    id: &ComponentId,
+    s: &SystemState,
+) -> Result<Vec<(String, String)>, ComposeError> {
+    let c = component(s, id);
+    let input = s.get_input();
+    let spec_dir = Path::new(&input).parent().unwrap_or(Path::new(""));
+    let mut contents = Vec::new();
+
+    for e in c.initfs.iter() {
+        let path = spec_dir.join(&e.path);
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if name.contains('*') || name.contains('?') {
+            let pat: Vec<char> = name.chars().collect();
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let mut found = false;
+            for m in dir_entries(dir)? {
+                // unwrap as directory entries always have a name
+                let n = m.file_name().unwrap().to_string_lossy().to_string();
+                if !glob_match(&pat, &n.chars().collect::<Vec<char>>()) {
+                    continue;
+                }
+                found = true;
+                // glob matches are placed within the destination directory
+                let dest = match e.dest {
+                    Some(ref d) if !d.is_empty() => format!("{}/{}", d.trim_end_matches('/'), n),
+                    _ => n,
+                };
+                initfs_add(&m, dest, &mut contents)?;
+            }
+            if !found {
+                return Err(ComposeError::Spec(format!(
+                    "Error: The initfs path \"{}\" of component {} matches no files.",
+                    e.path, c.name
+                )));
+            }
+        } else if path.exists() {
+            let dest = match e.dest {
+                None => name,
+                Some(ref d) if path.is_dir() => d.trim_end_matches('/').to_string(),
+                Some(ref d) if d.is_empty() || d.ends_with('/') => format!("{}{}", d, name),
+                Some(ref d) => d.clone(),
+            };
+            initfs_add(&path, dest, &mut contents)?;
+        } else {
+            return Err(ComposeError::Io {
+                path: path.display().to_string(),
+                msg: format!("initfs file of component {} not found", c.name),
+            });
+        }
+    }
+
+    Ok(contents)
+}
+
+// The size of the initargs entry `kv` of component `c` (the `top`
+// entry, or one within it) in its format. Within an array, each
+// entry is also referenced by a pointer in the C format.
+fn entry_size(c: &Component, kv: &ArgsKV, top: bool) -> u64 {
+    let ptr = if c.arch.is_64bit() { 8 } else { 4 };
+    match (c.initargs_format, top) {
+        (InitargsFormat::C, true) => kv.c_size(ptr),
+        (InitargsFormat::C, false) => kv.c_size(ptr) + ptr,
+        (InitargsFormat::Tlv, true) => TLV_MAGIC.len() as u64 + kv.tlv_size(),
+        (InitargsFormat::Tlv, false) => kv.tlv_size(),
+    }
+}
+
+// The size of the serialized initargs, `top`, of component `c`.
+pub fn initargs_size(c: &Component, top: &ArgsKV) -> u64 {
+    entry_size(c, top, true)
 }
 
-// The key within the initargs for the tarball, the path of the
-// tarball, and the set of paths to the files to include in the
-// tarball and name of them within the tarball.
-// use tar::Builder;
-// fn tarball_create(
-//     tarball_key: &String,
-//     tar_path: &String,
-//     contents: Vec<(String, String)>,
-// ) -> Result<(), String> {
-//     let file = File::create(&tar_path).unwrap();
-//     let mut ar = Builder::new(file);
-//     let key = format!("{}/", tarball_key);
-
-//     ar.append_dir(&key, &key).unwrap(); // FIXME: error handling
-//     contents.iter().for_each(|(p, n)| {
-//         // file path, and name for the tarball
-//         let mut f = File::open(p).unwrap(); //  should not fail: we just built this, TODO: fix race
-//         ar.append_file(format!("{}/{}", tarball_key, n), &mut f)
-//             .unwrap(); // FIXME: error handling
-//     });
-//     ar.finish().unwrap(); // FIXME: error handling
-//     Ok(())
-// }
-
-fn initargs_create(initargs_path: &String, kvs: &Vec<ArgsKV>) -> Result<(), String> {
+// Fail, with the size of each of their keys, if the initargs `top`
+// of component `c` exceed its max_initargs.
+pub fn initargs_check(c: &Component, top: &ArgsKV) -> Result<(), ComposeError> {
+    let size = initargs_size(c, top);
+    let max = match c.max_initargs {
+        Some(max) if size > max => max,
+        _ => return Ok(()),
+    };
+    let mut keys = match top.val {
+        ArgsValType::Arr(ref kvs) => kvs.iter().collect(),
+        _ => Vec::new(),
+    };
+    keys.sort_by_key(|kv| std::cmp::Reverse(entry_size(c, kv, false)));
+    let breakdown: Vec<String> = keys
+        .iter()
+        .map(|kv| match kv.val {
+            ArgsValType::Arr(ref a) => format!(
+                "\t{}: {} bytes ({} {})",
+                kv.key,
+                entry_size(c, kv, false),
+                a.len(),
+                if a.len() == 1 { "entry" } else { "entries" }
+            ),
+            _ => format!("\t{}: {} bytes", kv.key, entry_size(c, kv, false)),
+        })
+        .collect();
+
+    Err(ComposeError::Pass(format!(
+        "Error: The initargs of component {} are {} bytes (as {}), which exceeds its max_initargs of {} bytes. By key:\n{}",
+        c.name,
+        size,
+        c.initargs_format.name(),
+        max,
+        breakdown.join("\n")
+    )))
+}
+
+fn initargs_create(initargs_path: &String, kvs: &Vec<ArgsKV>) -> Result<(), ComposeError> {
     let top = ArgsKV::new_top(kvs.clone());
     let args = top.serialize();
+    debug_assert_eq!(ArgsKV::deserialize(&args), Ok(top));
+
+    emit_file(initargs_path, args.as_bytes())
+}
+
+// The name of the macro for the parameter at `path` (e.g.
+// ARGS_PARAM_NET_IP for param/net/ip).
+fn args_macro(path: &str) -> String {
+    format!(
+        "ARGS_{}",
+        path.chars()
+            .map(|c| if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            })
+            .collect::<String>()
+    )
+}
 
-    if let Err(s) = emit_file(&initargs_path, args.as_bytes()) {
-        return Err(s);
+// Add the macros for the parameters in `kvs`, nested under `path`.
+// The entries of arrays of values (with "_" keys) aren't named.
+fn args_macros(kvs: &Vec<ArgsKV>, path: &str, macros: &mut Vec<(String, String)>) {
+    for kv in kvs.iter() {
+        if kv.key == "_" {
+            continue;
+        }
+        let p = format!("{}/{}", path, kv.key);
+        macros.push((args_macro(&p), p.clone()));
+        if let ArgsValType::Arr(ref nested) = kv.val {
+            args_macros(nested, &p, macros);
+        }
     }
+}
 
-    Ok(())
+// Generate the header defining a macro for the path of each of the
+// parameters that the specification provides to the component, so
+// that the component can look them up (with args_get) by name, and
+// a parameter missing from the specification is a compilation error.
+fn args_header_create(header_path: &String, c: &Component) -> Result<(), ComposeError> {
+    let mut macros = Vec::new();
+    args_macros(&c.params, "param", &mut macros);
+    args_macros(&c.globals, "global", &mut macros);
+    let guard = format!("{}_H", args_macro(&c.name.var_name));
+    let mut header = format!(
+        "/* The parameters of component {}, generated from its specification. */\n#ifndef {}\n#define {}\n\n",
+        c.name, guard, guard
+    );
+    let mut defined = HashSet::new();
+    for (m, p) in macros.iter() {
+        // Keys differing only in their punctuation share a macro.
+        if !defined.insert(m) {
+            warn!(
+                "Parameter {} of component {} has the same macro as another, {}, so it isn't defined.",
+                p, c.name, m
+            );
+            continue;
+        }
+        header.push_str(&format!("#define {} \"{}\"\n", m, c_escape(p)));
+    }
+    header.push_str(&format!("\n#endif /* {} */\n", guard));
+
+    emit_file(header_path, header.as_bytes())
 }
 
 // This is per-component.
 pub struct Parameters {
     param_file_path: String,
+    header_file_path: String,
     tar_file_path: Option<String>,
     args: Vec<ArgsKV>,
 }
@@ -173,6 +821,10 @@ impl InitParamPass for Parameters {
     fn param_fs(&self) -> &Option<String> {
         &self.tar_file_path
     }
+
+    fn param_header(&self) -> &String {
+        &self.header_file_path
+    }
 }
 
 impl TransitionIter for Parameters {
@@ -180,22 +832,125 @@ impl TransitionIter for Parameters {
         id: &ComponentId,
         s: &SystemState,
         b: &mut dyn BuildState,
-    ) -> Result<Box<Self>, String> {
-        let argpath = b.comp_file_path(&id, &"initargs.c".to_string(), s)?;
+    ) -> Result<Box<Self>, ComposeError> {
+        let c = component(s, id);
+        let argpath = b.comp_file_path(id, &"initargs.c".to_string(), s)?;
+        let headerpath = b.comp_file_path(id, &format!("{}_args.h", c.name.var_name), s)?;
+        args_header_create(&headerpath, c)?;
         let mut args = Vec::new();
 
-        let param_args = component(s, id).params.clone();
+        let param_args = c.params.clone();
         args.push(ArgsKV::new_arr(String::from("param"), param_args));
-        let resargs = s.get_restbl().args(&id);
+        // Reserved for the specification's global parameters.
+        if !c.globals.is_empty() {
+            args.push(ArgsKV::new_arr(String::from("global"), c.globals.clone()));
+        }
+        // The booter finds the device-tree (whose header holds its
+        // size) where it's embedded.
+        if let (true, Some(addr)) = (c.constructor.var_name == "kernel", s.get_spec().dtb_addr()) {
+            args.push(ArgsKV::new_key(String::from("dtb"), format!("{:#x}", addr)));
+        }
+        // With an XIP layout, it copies the components' writable
+        // segments into the RAM with the table at the flash's base.
+        if let (true, Some(x)) = (c.constructor.var_name == "kernel", s.get_spec().xip()) {
+            args.push(ArgsKV::new_key(
+                String::from("xip"),
+                format!("{:#x}", x.flash_base),
+            ));
+        }
+        let resargs = s.get_restbl().args(id);
         resargs.iter().for_each(|a| args.push(a.clone()));
         args.push(ArgsKV::new_key(String::from("compid"), id.to_string()));
+        initargs_check(c, &ArgsKV::new_top(args.clone()))?;
 
-        initargs_create(&argpath, &args)?;
+        let mut contents = initfs_contents(id, s)?;
+        match c.initargs_format {
+            InitargsFormat::C => initargs_create(&argpath, &args)?,
+            InitargsFormat::Tlv => {
+                // The compiled initargs only locate the TLV encoding.
+                let tlvpath = b.comp_file_path(id, &TLV_FILE.to_string(), s)?;
+                let top = ArgsKV::new_top(args.clone());
+                let tlv = top.tlv();
+                debug_assert_eq!(ArgsKV::from_tlv(&tlv), Ok(top));
+                emit_file(&tlvpath, &tlv)?;
+                contents.insert(0, (tlvpath, TLV_PATH.to_string()));
+                initargs_create(
+                    &argpath,
+                    &vec![ArgsKV::new_key(String::from("tlv"), TLV_PATH.to_string())],
+                )?;
+            }
+        }
+        let tarpath = if contents.is_empty() {
+            None
+        } else {
+            let p = b.comp_file_path(id, &"initfs.tar".to_string(), s)?;
+            Some(
+                archive_create(Path::new(&p), contents, ArchiveFormat::Tar, false)?
+                    .display()
+                    .to_string(),
+            )
+        };
 
         Ok(Box::new(Parameters {
             args: args.clone(),
             param_file_path: argpath,
-            tar_file_path: None,
+            header_file_path: headerpath,
+            tar_file_path: tarpath,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nested tables (one of them empty), strings that must be escaped
+    // in C, and blobs with NULs and high bytes.
+    fn args() -> ArgsKV {
+        let s = |k: &str, v: &str| ArgsKV::new_key(k.to_string(), v.to_string());
+        ArgsKV::new_top(vec![
+            s("compid", "3"),
+            s("msg", "say \"hi\"\\\n\tbye"),
+            s("empty", ""),
+            ArgsKV::new_blob(String::from("key"), vec![0, 1, 0x7f, 0x80, 0xff, 0]),
+            ArgsKV::new_blob(String::from("none"), vec![]),
+            ArgsKV::new_arr(
+                String::from("net"),
+                vec![
+                    s("ip", "10.0.2.8"),
+                    ArgsKV::new_arr(
+                        String::from("routes"),
+                        vec![
+                            s("0", "default"),
+                            ArgsKV::new_arr(String::from("1"), vec![]),
+                        ],
+                    ),
+                ],
+            ),
+        ])
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let top = args();
+        assert_eq!(ArgsKV::deserialize(&top.serialize()), Ok(top));
+    }
+
+    #[test]
+    fn tlv_round_trip() {
+        let top = args();
+        let tlv = top.tlv();
+        assert_eq!(tlv.len() as u64, TLV_MAGIC.len() as u64 + top.tlv_size());
+        assert_eq!(ArgsKV::from_tlv(&tlv), Ok(top));
+    }
+
+    #[test]
+    fn tlv_malformed() {
+        let tlv = args().tlv();
+        assert!(ArgsKV::from_tlv(&tlv[..tlv.len() - 1]).is_err());
+        assert!(ArgsKV::from_tlv(&tlv[1..]).is_err());
+        let mut trailing = tlv.clone();
+        trailing.push(0);
+        assert!(ArgsKV::from_tlv(&trailing).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+// Inspecting a built image without its specification, so that images
+// found in the wild can be understood: a kernel image embeds the
+// booter, a component (e.g. the booter) is linked with its initargs
+// and, if it is a constructor, with the tarball of the components it
+// creates, and a tarball (or cpio or flat archive) holds those
+// components' objects. Each is
+// listed with its size and hash, and the initargs of each component
+// are read back out of its object. The initargs generated into a
+// build directory can be inspected as well.
+
+use archive::archive_files;
+use initargs::{ArgsKV, ArgsValType};
+use manifest::{params_json, Json};
+use memmap::{symb_addr, INITFS_END, INITFS_START};
+use passes::{ArchiveFormat, ComposeError};
+use std::fmt;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use syshelpers::{dump_file, hex, sha256};
+use tar::Archive;
+use xmas_elf::sections::ShType;
+use xmas_elf::ElfFile;
+
+// The kernel's section holding the booter.
+const BOOTER_SECTION: &str = ".initial_component";
+// The initargs' root (see ArgsKV::serialize), and the value of its
+// type for key/value initargs (ARGS_IMPL_KV in initargs.h).
+const INITARGS_ROOT: &str = "__initargs_root";
+const ARGS_IMPL_KV: u64 = 0;
+const VTYPE_STR: u64 = 0;
+const VTYPE_BLOB: u64 = 2;
+// Bounds on the initargs read from an object, as they might not be
+// ones that the composer generated.
+const INITARGS_DEPTH: usize = 32;
+const INITARGS_ARR_MAX: u64 = 1 << 16;
+// The bytes of a blob that are printed.
+const BLOB_SHOWN: usize = 32;
+
+pub struct Inspection {
+    pub name: String,
+    pub kind: &'static str, // "kernel image", "component", "tarball", "cpio archive", "flat archive", "initargs", or "file"
+    pub size: u64,
+    pub sha256: String,
+    pub initargs: Option<ArgsKV>,
+    pub contents: Vec<Inspection>, // the booter of a kernel image, or the files in a tarball
+}
+
+// The object's contents at virtual address `addr`, to the end of its
+// section.
+fn elf_at<'a>(e: &ElfFile<'a>, obj: &'a [u8], addr: u64) -> Option<&'a [u8]> {
+    let sh = e.section_iter().find(|sh| {
+        sh.get_type() == Ok(ShType::ProgBits)
+            && sh.address() != 0
+            && addr >= sh.address()
+            && addr < sh.address() + sh.size()
+    })?;
+    let start = (sh.offset() + addr - sh.address()) as usize;
+    let end = (sh.offset() + sh.size()) as usize;
+
+    obj.get(start..end)
+}
+
+// The objects of the composite's architectures are little endian.
+fn elf_word(e: &ElfFile, obj: &[u8], addr: u64, len: usize) -> Option<u64> {
+    let bs = elf_at(e, obj, addr)?.get(..len)?;
+    Some(bs.iter().rev().fold(0, |w, b| (w << 8) | *b as u64))
+}
+
+fn elf_str(e: &ElfFile, obj: &[u8], addr: u64) -> Option<String> {
+    let bs = elf_at(e, obj, addr)?;
+    let len = bs.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&bs[..len]).to_string())
+}
+
+// Read the kv_entry (see initargs.h) at `addr`. Pointers are `ptr`
+// bytes; the value's union follows the key and the (int) type, and
+// is pointer-aligned.
+fn kv_read(e: &ElfFile, obj: &[u8], addr: u64, ptr: usize, depth: usize) -> Option<ArgsKV> {
+    let p = ptr as u64;
+    let key = elf_str(e, obj, elf_word(e, obj, addr, ptr)?)?;
+    let vtype = elf_word(e, obj, addr + p, 4)?;
+    let val = addr + 2 * p;
+    if vtype == VTYPE_STR {
+        return Some(ArgsKV::new_key(
+            key,
+            elf_str(e, obj, elf_word(e, obj, val, ptr)?)?,
+        ));
+    }
+    let sz = elf_word(e, obj, val, 4)?;
+    if vtype == VTYPE_BLOB {
+        let data = elf_at(e, obj, elf_word(e, obj, val + p, ptr)?)?;
+        return Some(ArgsKV::new_blob(key, data.get(..sz as usize)?.to_vec()));
+    }
+    let kvs = elf_word(e, obj, val + p, ptr)?;
+    if depth == INITARGS_DEPTH || sz > INITARGS_ARR_MAX {
+        return None;
+    }
+    let mut arr = Vec::new();
+    for i in 0..sz {
+        arr.push(kv_read(
+            e,
+            obj,
+            elf_word(e, obj, kvs + i * p, ptr)?,
+            ptr,
+            depth + 1,
+        )?);
+    }
+    // Serialization reverses the arrays.
+    arr.reverse();
+
+    Some(ArgsKV::new_arr(key, arr))
+}
+
+// The initargs the object was linked with, if they can be read.
+fn initargs_read(e: &ElfFile, obj: &[u8]) -> Option<ArgsKV> {
+    let root = symb_addr(e, INITARGS_ROOT)?;
+    // e_ident[EI_CLASS] is 2 for 64 bit objects
+    let ptr = if obj.get(4) == Some(&2) { 8 } else { 4 };
+    if elf_word(e, obj, root, 4)? != ARGS_IMPL_KV {
+        return None;
+    }
+    let top = elf_word(e, obj, root + ptr as u64, ptr)?;
+
+    kv_read(e, obj, top, ptr, 0)
+}
+
+// Decompress the tarball with the external gzip or zstd tools, if
+// it is compressed.
+fn decompress(name: &String, data: &[u8]) -> Result<Vec<u8>, ComposeError> {
+    let tool = match data {
+        [0x1f, 0x8b, ..] => "gzip",
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => "zstd",
+        _ => return Ok(data.to_vec()),
+    };
+    let err = |msg: String| ComposeError::Tar {
+        path: name.clone(),
+        msg,
+    };
+    let mut child = Command::new(tool)
+        .args(["-d", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            err(format!(
+                "could not execute {} to decompress it: {}",
+                tool, e
+            ))
+        })?;
+    // Write concurrently, so that neither pipe fills.
+    let mut stdin = child.stdin.take().unwrap(); // unwrap as it is piped
+    let input = data.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let mut out = Vec::new();
+    let read = child.stdout.take().unwrap().read_to_end(&mut out); // piped as well
+    let written = writer.join().unwrap_or(Ok(()));
+    let status = child.wait();
+    match (read, written, status) {
+        (Ok(_), Ok(_), Ok(ref s)) if s.success() => Ok(out),
+        _ => Err(err(format!("could not decompress it with {}", tool))),
+    }
+}
+
+fn is_tar(data: &[u8]) -> bool {
+    data.get(257..262) == Some(b"ustar")
+}
+
+// The kind of the (decompressed) archive, if it is one.
+fn archive_kind(data: &[u8]) -> Option<&'static str> {
+    if is_tar(data) {
+        return Some("tarball");
+    }
+    archive_files(data).map(|(f, _)| match f {
+        ArchiveFormat::Tar => "tarball",
+        ArchiveFormat::Cpio => "cpio archive",
+        ArchiveFormat::Flat => "flat archive",
+    })
+}
+
+fn archive_inspect(name: &String, data: &[u8]) -> Result<Vec<Inspection>, ComposeError> {
+    let tar = decompress(name, data)?;
+    if let Some((_, files)) = archive_files(&tar) {
+        let files = files.map_err(|msg| ComposeError::Tar {
+            path: name.clone(),
+            msg: format!("could not read it: {}", msg),
+        })?;
+        return files
+            .iter()
+            .map(|(n, contents)| inspect_data(n.clone(), contents))
+            .collect();
+    }
+    let err = |e: std::io::Error| ComposeError::Tar {
+        path: name.clone(),
+        msg: format!("could not read it: {}", e),
+    };
+    let mut ar = Archive::new(&tar[..]);
+    let mut files = Vec::new();
+    for entry in ar.entries().map_err(err)? {
+        let mut entry = entry.map_err(err)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(err)?.display().to_string();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).map_err(err)?;
+        files.push(inspect_data(path, &contents)?);
+    }
+
+    Ok(files)
+}
+
+fn inspect_data(name: String, data: &[u8]) -> Result<Inspection, ComposeError> {
+    let mut i = Inspection {
+        name: name.clone(),
+        kind: "file",
+        size: data.len() as u64,
+        sha256: sha256(data),
+        initargs: None,
+        contents: Vec::new(),
+    };
+    let elf = match ElfFile::new(data) {
+        Ok(e) => e,
+        Err(_) => {
+            // The initargs generated for a component (in its build
+            // directory, or in its tarball as TLV).
+            let initargs = ArgsKV::from_tlv(data).ok().or_else(|| {
+                std::str::from_utf8(data)
+                    .ok()
+                    .and_then(|c| ArgsKV::deserialize(c).ok())
+            });
+            if let Some(kv) = initargs {
+                i.kind = "initargs";
+                i.initargs = Some(kv);
+                return Ok(i);
+            }
+            let kind = match decompress(&name, data) {
+                Ok(ref d) => archive_kind(d),
+                Err(_) => None,
+            };
+            if let Some(k) = kind {
+                i.kind = k;
+                i.contents = archive_inspect(&name, data)?;
+            }
+            return Ok(i);
+        }
+    };
+
+    if let Some(sh) = elf.find_section_by_name(BOOTER_SECTION) {
+        let booter = data
+            .get(sh.offset() as usize..(sh.offset() + sh.size()) as usize)
+            .unwrap_or(&[]);
+        i.kind = "kernel image";
+        i.contents = vec![inspect_data(String::from("booter"), booter)?];
+        return Ok(i);
+    }
+    i.kind = "component";
+    i.initargs = initargs_read(&elf, data);
+    // Constructors are linked with their archive.
+    if let (Some(start), Some(end)) = (symb_addr(&elf, INITFS_START), symb_addr(&elf, INITFS_END)) {
+        if let Some(tar) = elf_at(&elf, data, start).and_then(|t| t.get(..(end - start) as usize)) {
+            i.contents = archive_inspect(&format!("{} (initfs)", name), tar)?;
+        }
+    }
+
+    Ok(i)
+}
+
+// Inspect the kernel image, component, or tarball at `path`.
+pub fn inspect(path: &String) -> Result<Inspection, ComposeError> {
+    inspect_data(path.clone(), &dump_file(path)?)
+}
+
+// Print the initargs in the order that the component iterates
+// through them.
+fn initargs_fmt(f: &mut fmt::Formatter, kv: &ArgsKV, indent: usize) -> fmt::Result {
+    let pad = "\t".repeat(indent);
+    // Arrays of values have the key "_".
+    let key = if kv.key() == "_" {
+        String::from("-")
+    } else {
+        format!("{}:", kv.key())
+    };
+    match kv.val() {
+        ArgsValType::Str(s) => writeln!(f, "{}{} {}", pad, key, s),
+        ArgsValType::Blob(b) if b.len() > BLOB_SHOWN => writeln!(
+            f,
+            "{}{} {}... ({} bytes)",
+            pad,
+            key,
+            hex(&b[..BLOB_SHOWN]),
+            b.len()
+        ),
+        ArgsValType::Blob(b) => writeln!(f, "{}{} {} ({} bytes)", pad, key, hex(b), b.len()),
+        ArgsValType::Arr(kvs) => {
+            writeln!(f, "{}{}", pad, key)?;
+            for kv in kvs.iter().rev() {
+                initargs_fmt(f, kv, indent + 1)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl Inspection {
+    fn fmt_indent(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let pad = "\t".repeat(indent);
+        writeln!(
+            f,
+            "{}{} {} ({} bytes, sha256 {})",
+            pad, self.kind, self.name, self.size, self.sha256
+        )?;
+        if let Some(ArgsValType::Arr(ref kvs)) = self.initargs.as_ref().map(|a| a.val()) {
+            writeln!(f, "{}\tinitargs:", pad)?;
+            for kv in kvs.iter().rev() {
+                initargs_fmt(f, kv, indent + 2)?;
+            }
+        }
+        for c in self.contents.iter() {
+            c.fmt_indent(f, indent + 1)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("name", Json::str(&self.name)),
+            ("kind", Json::str(self.kind)),
+            ("size", Json::Num(self.size)),
+            ("sha256", Json::str(&self.sha256)),
+            (
+                "initargs",
+                match self.initargs.as_ref().map(|a| a.val()) {
+                    Some(ArgsValType::Arr(ref kvs)) => params_json(kvs),
+                    _ => Json::Null,
+                },
+            ),
+            (
+                "contents",
+                Json::Arr(self.contents.iter().map(|c| c.to_json()).collect()),
+            ),
+        ])
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indent(f, 0)
+    }
+}
@@ -0,0 +1,69 @@
+// Interrupting the composer (SIGINT or SIGTERM) stops the build
+// rather than the composer itself: the make commands running are
+// terminated, along with their sub-makes and compilers, their logs
+// are written, and no further commands are started. The build's
+// directory is then marked as incomplete. A second interrupt exits
+// immediately.
+
+use passes::ComposeError;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use syshelpers::group_kill;
+
+static HANDLED: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// The process groups of the commands running.
+static GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn groups_kill(sig: &str) {
+    if let Ok(gs) = GROUPS.lock() {
+        gs.iter().for_each(|g| group_kill(*g, sig));
+    }
+}
+
+// Handle interrupts as above. Without this, interrupting the composer
+// kills it, and the commands in the terminal's process group.
+pub fn interrupt_handler_install() -> Result<(), ComposeError> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            groups_kill("-KILL");
+            process::exit(130);
+        }
+        warn!("Interrupted: stopping the build (interrupt again to exit immediately).");
+        groups_kill("-TERM");
+    })
+    .map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Could not install the interrupt handler: {}",
+            e
+        ))
+    })?;
+    HANDLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Commands get process groups of their own when interrupts are
+// handled, as the terminal no longer delivers them.
+pub fn interrupt_handled() -> bool {
+    HANDLED.load(Ordering::SeqCst)
+}
+
+// Track the running command's process group, `pgid`, so that an
+// interrupt terminates it. If the interrupt has already happened, it
+// is terminated now.
+pub fn group_register(pgid: u32) {
+    GROUPS.lock().unwrap().push(pgid);
+    if interrupted() {
+        group_kill(pgid, "-TERM");
+    }
+}
+
+pub fn group_unregister(pgid: u32) {
+    GROUPS.lock().unwrap().retain(|g| *g != pgid);
+}
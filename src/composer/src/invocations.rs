@@ -1,6 +1,8 @@
 use passes::{
-    component, deps, BuildState, ComponentId, InvocationsPass, SInv, SystemState, TransitionIter,
+    component, deps, BuildState, ComponentId, ComponentName, ComposeError, InvocationsPass, SInv,
+    SystemState, TransitionIter,
 };
+use std::collections::BTreeMap;
 
 pub struct Invocations {
     invs: Vec<SInv>,
@@ -14,7 +16,7 @@ fn sinvs_generate(id: &ComponentId, s: &SystemState) -> Result<Vec<SInv>, String
     for (sname, symbinfo) in s.get_objs_id(id).client_symbs() {
         let mut found = false;
 
-        for d in deps(&s, &id) {
+        for d in deps(s, id) {
             // find the correct dependency (whose interface
             // prefixes the symbol)
             if !sname.trim_matches('_').starts_with(&d.interface) {
@@ -29,16 +31,16 @@ fn sinvs_generate(id: &ComponentId, s: &SystemState) -> Result<Vec<SInv>, String
                 .next()
                 .unwrap();
             match s.get_objs_id(srv_id).server_symbs().get(sname) {
-                Some(ref srv_symbs) => {
+                Some(srv_symbs) => {
                     invs.push(SInv {
                         symb_name: sname.clone(),
-                        client: id.clone(),
-                        server: srv_id.clone(),
-                        c_fn_addr: symbinfo.func_addr.clone(),
-                        c_callgate_addr: symbinfo.callgate_addr.clone(),
-                        c_ucap_addr: symbinfo.ucap_addr.clone(),
-                        s_fn_addr: srv_symbs.func_addr.clone(),
-                        s_altfn_addr: srv_symbs.altfn_addr.clone(),
+                        client: *id,
+                        server: *srv_id,
+                        c_fn_addr: symbinfo.func_addr,
+                        c_callgate_addr: symbinfo.callgate_addr,
+                        c_ucap_addr: symbinfo.ucap_addr,
+                        s_fn_addr: srv_symbs.func_addr,
+                        s_altfn_addr: srv_symbs.altfn_addr,
                     });
                     found = true;
                 }
@@ -48,42 +50,90 @@ fn sinvs_generate(id: &ComponentId, s: &SystemState) -> Result<Vec<SInv>, String
 
         if !found {
             let mut aggdeps = String::from("");
-            for d in deps(&s, &id) {
+            for d in deps(s, id) {
                 aggdeps.push_str(&format!(" {}", d.server.clone()));
             }
 
             errors.push_str(&format!(
                 r#"Error: Undefined dependency for unresolved function.  Component {} has an undefined function call to {} that is not satisfied by any of its dependencies (i.e. that function isn't provided by any of{}).\nReasons this could happen include:\n- None of the dependent servers provide that function. Make sure to include a function that exports an interface with {}.\n- The stubs in one of the servers don't properly export the function (search for __crt_s_{} in the server's exported symbols using `nm` or `objdump`) to see if this is the problem.\n- Every function in an interface must have a namespace matching the interface name (interface "pong" must only export functions named "pong_*"). Make sure that your functions are properly named in the interface.\n"#,
-                component(&s, &id).name, sname, aggdeps, sname, sname));
+                component(s, id).name, sname, aggdeps, sname, sname));
         }
     }
 
-    if errors.len() > 0 {
+    if !errors.is_empty() {
         return Err(errors);
     }
 
     Ok(invs)
 }
 
+// Prefixes of the stub symbols that name the interface function.
+const STUB_PREFIXES: [&str; 3] = ["__cosrt_c_", "__cosrt_ucap_", "__cosrt_fast_callgate_"];
+
+// Check that each component's undefined (weak) references to
+// functions that servers export are satisfied by the dependencies it
+// declares. Otherwise the component links as the references are
+// weak, but faults when it calls the function. Other undefined
+// symbols (e.g. the toolchain's optional hooks such as
+// __gmon_start__) aren't interface functions, so are ignored. This
+// requires the objects of all components.
+pub fn undefined_check(s: &SystemState) -> Result<(), ComposeError> {
+    let ids = s.get_named().ids();
+    let mut exporters: BTreeMap<&String, Vec<&ComponentName>> = BTreeMap::new();
+    for (id, name) in ids {
+        for fname in s.get_objs_id(id).server_symbs().keys() {
+            exporters.entry(fname).or_default().push(name);
+        }
+    }
+
+    let mut errors = Vec::new();
+    for (id, name) in ids {
+        for undef in s.get_objs_id(id).undefined_symbs() {
+            let fname = STUB_PREFIXES
+                .iter()
+                .find(|p| undef.starts_with(*p))
+                .map_or(undef.clone(), |p| undef[p.len()..].to_string());
+            let srvs = match exporters.get(&fname) {
+                Some(srvs) => srvs,
+                None => continue,
+            };
+            let satisfied = deps(s, id).iter().any(|d| {
+                srvs.contains(&&d.server) && fname.trim_matches('_').starts_with(&d.interface)
+            });
+            if !satisfied {
+                errors.push(format!(
+                    "Error: Component {} has an undefined (weak) reference to {}, which isn't satisfied by any of its dependencies, so calling it will fault. It is exported by {}; add a dependency on the interface that provides it.",
+                    name,
+                    undef,
+                    srvs.iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ComposeError::Pass(errors.join("\n")));
+    }
+
+    Ok(())
+}
+
 impl TransitionIter for Invocations {
     fn transition_iter(
         id: &ComponentId,
         s: &SystemState,
         _b: &mut dyn BuildState,
-    ) -> Result<Box<Self>, String> {
+    ) -> Result<Box<Self>, ComposeError> {
         let curr = s.get_named().ids().get(id).unwrap();
         let mut invs = Vec::new();
 
-        for cid in s
-            .get_named()
-            .ids()
-            .iter()
-            .map(|(cid, _)| cid)
-            .filter(|cid| {
-                let c = component(&s, &cid);
-                c.constructor == *curr
-            })
-        {
+        for cid in s.get_named().ids().keys().filter(|cid| {
+            let c = component(s, cid);
+            c.constructor == *curr
+        }) {
             // Should be true as constructor relationships should be
             // factored into the component id total order
             assert!(cid > id);
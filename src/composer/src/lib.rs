@@ -0,0 +1,689 @@
+// The composer as a library, so that other tools (test frameworks,
+// IDEs, research harnesses) can plan and build systems from a
+// specification, and inspect the results, without executing the
+// compose binary and scraping its output. The binary (main.rs) is a
+// thin command-line layer over these functions.
+//
+// Paths and names are passed as &String, and lists as &Vec,
+// throughout, rather than as slices.
+#![allow(clippy::ptr_arg)]
+
+extern crate toml;
+#[macro_use]
+extern crate serde_derive;
+extern crate ascent;
+extern crate base64;
+extern crate ctrlc;
+extern crate ed25519_compact;
+extern crate inotify;
+extern crate itertools;
+#[macro_use]
+extern crate log;
+extern crate petgraph;
+extern crate tar;
+extern crate xmas_elf;
+
+mod address_assignment;
+mod analysis;
+mod archive;
+pub mod backend;
+pub mod build;
+mod compile_commands;
+mod compiler_cache;
+mod compobject;
+mod cossystem;
+mod dedup;
+pub mod diagnostic;
+mod disk;
+mod dtb;
+pub mod exec;
+mod fit;
+pub mod flash;
+mod gdb;
+mod graph;
+mod history;
+mod image;
+mod initargs;
+mod inspect;
+pub mod interrupt;
+mod invocations;
+mod lockfile;
+pub mod logging;
+mod manifest;
+mod matrix;
+mod memmap;
+mod netboot;
+pub mod options;
+pub mod passes;
+mod pipe;
+pub mod plan;
+mod preflight;
+mod properties;
+mod provenance;
+pub mod qemu;
+mod regions;
+mod resources;
+mod sbom;
+mod serve;
+mod signing;
+mod sizes;
+mod strip;
+mod symbols;
+mod syshelpers;
+mod timing;
+mod tot_order;
+pub mod watch;
+mod xip;
+
+use address_assignment::AddressAssignmentx86_64;
+use analysis::Analysis;
+use compile_commands::compile_commands_create;
+use compobject::{Constructor, ElfObject};
+use cossystem::{workspace_parse, SystemSpec};
+use dedup::dedup_create;
+use disk::disk_create;
+use dtb::dtb_create;
+use flash::flash_profile;
+use gdb::gdb_bundle_create;
+use graph::Graph;
+use history::history_update;
+use image::{image_create, image_supported, loader_image_format};
+use initargs::Parameters;
+use inspect::inspect;
+use interrupt::interrupted;
+use invocations::{undefined_check, Invocations};
+use lockfile::lockfile_sync;
+use manifest::manifest_create;
+use matrix::{matrix_configs, MatrixResult};
+use memmap::memmap_create;
+use netboot::netboot_create;
+use options::Mkimg;
+use passes::{CompilerCache, ComponentId, ComponentName, FlashTool, Transition, TransitionIter};
+use preflight::preflight_check;
+use properties::CompProperties;
+use qemu::run_image_format;
+use regions::regions_create;
+use resources::ResAssignPass;
+use sbom::sbom_create;
+use serve::netboot_serve;
+use sizes::sizes_check;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::thread;
+use timing::timing_create;
+use tot_order::CompTotOrd;
+use xip::xip_create;
+
+pub use backend::{CompTarget, ComponentBuilder, MakeBackend};
+pub use build::DefaultBuilder;
+pub use compiler_cache::CacheStats;
+pub use exec::{Executor, HostExecutor, Outcome, RecordingExecutor};
+pub use inspect::Inspection;
+pub use manifest::Json;
+pub use matrix::MatrixReport;
+pub use options::{Command, Options};
+pub use passes::{BuildState, ComposeError, ImageFormat, SystemState};
+pub use plan::{Action, Explanation, Plan};
+pub use sizes::SizeReport;
+pub use timing::TimingReport;
+
+// The files generated by a build, beyond the component objects.
+pub struct Artifacts {
+    pub image: String,                 // the kernel image, with the system constructor
+    pub boot_images: Vec<String>,      // for --image grub, iso, or fit
+    pub dtb: Option<String>,           // the board's (compiled) device-tree
+    pub xip: Option<(String, String)>, // the flash image, and its copy table (json)
+    pub disk: Option<String>,          // for mkimg disk
+    pub netboot: Vec<String>,          // for mkimg pxe
+    pub regions: Vec<String>,          // for mkimg split, the memory regions' images
+    pub region_layout: Option<(String, String)>, // and their layout (text, and json)
+    pub manifest: String,
+    pub sbom: String, // CycloneDX
+    pub gdbinit: String,
+    pub symbols: String,
+    pub memmap: String,
+    pub memmap_json: String,
+    pub dedup: String,
+    pub dedup_layout: Option<String>, // with --shared-layout
+    pub sizes: SizeReport,
+    pub timing: TimingReport,
+    pub timing_files: (String, String), // the steps' timing report, and trace (chrome://tracing)
+    pub regressions: Vec<String>, // in the components' build times and sizes, from past builds
+    pub compiler_cache: Option<(CompilerCache, CacheStats)>, // its hits and misses
+    pub compile_commands: Option<String>, // with --compile-commands
+}
+
+impl Artifacts {
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("image", Json::str(&self.image)),
+            (
+                "boot_images",
+                Json::Arr(self.boot_images.iter().map(|i| Json::str(i)).collect()),
+            ),
+            (
+                "dtb",
+                self.dtb.as_ref().map_or(Json::Null, |d| Json::str(d)),
+            ),
+            (
+                "xip",
+                self.xip.as_ref().map_or(Json::Null, |(bin, table)| {
+                    Json::obj(vec![("flash", Json::str(bin)), ("table", Json::str(table))])
+                }),
+            ),
+            (
+                "disk",
+                self.disk.as_ref().map_or(Json::Null, |d| Json::str(d)),
+            ),
+            (
+                "netboot",
+                Json::Arr(self.netboot.iter().map(|n| Json::str(n)).collect()),
+            ),
+            (
+                "regions",
+                Json::Arr(self.regions.iter().map(|r| Json::str(r)).collect()),
+            ),
+            (
+                "region_layout",
+                self.region_layout
+                    .as_ref()
+                    .map_or(Json::Null, |(txt, json)| {
+                        Json::obj(vec![("text", Json::str(txt)), ("json", Json::str(json))])
+                    }),
+            ),
+            ("manifest", Json::str(&self.manifest)),
+            ("sbom", Json::str(&self.sbom)),
+            ("gdbinit", Json::str(&self.gdbinit)),
+            ("symbols", Json::str(&self.symbols)),
+            ("memmap", Json::str(&self.memmap)),
+            ("memmap_json", Json::str(&self.memmap_json)),
+            ("dedup", Json::str(&self.dedup)),
+            (
+                "dedup_layout",
+                self.dedup_layout
+                    .as_ref()
+                    .map_or(Json::Null, |l| Json::str(l)),
+            ),
+            ("sizes", self.sizes.to_json()),
+            (
+                "timing",
+                Json::obj(vec![
+                    ("text", Json::str(&self.timing_files.0)),
+                    ("trace", Json::str(&self.timing_files.1)),
+                    ("report", self.timing.to_json()),
+                ]),
+            ),
+            (
+                "regressions",
+                Json::Arr(self.regressions.iter().map(|r| Json::str(r)).collect()),
+            ),
+            (
+                "compiler_cache",
+                self.compiler_cache
+                    .map_or(Json::Null, |(c, st)| st.to_json(c)),
+            ),
+            (
+                "compile_commands",
+                self.compile_commands
+                    .as_ref()
+                    .map_or(Json::Null, |c| Json::str(c)),
+            ),
+        ])
+    }
+}
+
+// The variables defined for the specification by the command line
+// and (if requested) the environment, which override its own.
+fn spec_vars(opts: &Options) -> BTreeMap<String, String> {
+    let mut vars: BTreeMap<String, String> = if opts.env_vars {
+        env::vars().collect()
+    } else {
+        BTreeMap::new()
+    };
+    vars.extend(opts.defines.clone());
+
+    vars
+}
+
+// The overrides of the specification's fields. The command line's
+// profile replaces the specification's, so that the specification's
+// conditional sections are selected for it.
+fn spec_overrides(opts: &Options) -> Vec<(String, String)> {
+    let mut overrides = opts.overrides.clone();
+    if let Some(p) = opts.profile {
+        overrides.push((String::from("system.profile"), String::from(p.name())));
+    }
+
+    overrides
+}
+
+// The state needed for the dependency graph of the specification,
+// only using the passes that don't require objects.
+fn system_deps(opts: &Options) -> Result<SystemState, ComposeError> {
+    let mut sys = SystemState::new(opts.spec.clone());
+    sys.add_vars(spec_vars(opts));
+    sys.add_overrides(spec_overrides(opts));
+    sys.add_features(opts.features.clone());
+    sys.add_prune_unreachable(opts.prune_unreachable);
+    let mut build = DefaultBuilder::new(opts);
+
+    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
+    sys.add_named(CompTotOrd::transition(&sys, &mut build)?);
+    sys.add_properties(CompProperties::transition(&sys, &mut build)?);
+
+    Ok(sys)
+}
+
+// The dependency graph of the specification, in DOT.
+pub fn system_graph(opts: &Options) -> Result<String, ComposeError> {
+    Ok(Graph::export_dot(&system_deps(opts)?))
+}
+
+// The components' dependencies, as JSON.
+pub fn system_graph_json(opts: &Options) -> Result<Json, ComposeError> {
+    Ok(Graph::export_json(&system_deps(opts)?))
+}
+
+// Check the specification without building: parse and validate it,
+// order its components, assign their addresses, and check their
+// implementations and interfaces against the source tree. All of
+// the problems found after parsing are reported together. The
+// interference that the system's structure allows between its
+// components (see analysis) is only warned of.
+pub fn system_validate(opts: &Options) -> Result<(), ComposeError> {
+    let mut sys = SystemState::new(opts.spec.clone());
+    sys.add_vars(spec_vars(opts));
+    sys.add_overrides(spec_overrides(opts));
+    sys.add_features(opts.features.clone());
+    sys.add_prune_unreachable(opts.prune_unreachable);
+    let mut build = DefaultBuilder::new(opts);
+
+    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
+    sys.add_named(CompTotOrd::transition(&sys, &mut build)?);
+    sys.add_analysis(Analysis::transition(&sys, &mut build)?);
+    let a = sys.get_analysis();
+    for id in sys
+        .get_named()
+        .ids()
+        .keys()
+        .filter(|id| a.warnings().contains_key(id))
+    {
+        warn!("{}", a.warning_str(*id, &sys).trim_end());
+    }
+    let mut errs = Vec::new();
+    match AddressAssignmentx86_64::assign(&sys, &HashMap::new()) {
+        Ok(a) => {
+            if let Err(e) = a.validate(&sys, &HashMap::new()) {
+                errs.push(e);
+            }
+        }
+        Err(e) => errs.push(e),
+    }
+    if let Err(e) = build.verify_sources(&sys) {
+        errs.push(e);
+    }
+
+    // Flatten, so that the problems are listed once.
+    let errs: Vec<ComposeError> = errs
+        .into_iter()
+        .flat_map(|e| match e {
+            ComposeError::Invalid(es) => es,
+            e => vec![e],
+        })
+        .collect();
+    if !errs.is_empty() {
+        return Err(ComposeError::Invalid(errs));
+    }
+
+    Ok(())
+}
+
+// The passes up to, and including, the generation of each
+// component's initargs, which are needed to plan or to build. Returns
+// the components in build order, and the image format to generate.
+fn system_resolve(
+    opts: &Options,
+    spec: &String,
+    name: &String,
+) -> Result<(SystemState, DefaultBuilder, Vec<ComponentId>, ImageFormat), ComposeError> {
+    let mut sys = SystemState::new(spec.clone());
+    sys.add_vars(spec_vars(opts));
+    sys.add_overrides(spec_overrides(opts));
+    sys.add_features(opts.features.clone());
+    sys.add_prune_unreachable(opts.prune_unreachable);
+    let mut build = DefaultBuilder::new(opts);
+
+    // The specification can choose the build directory, so it must
+    // be parsed before the build is initialized.
+    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
+    // Running the system requires an image that qemu can boot.
+    let image = match opts.command {
+        Command::Run | Command::Test if opts.image == ImageFormat::Kernel => run_image_format(&sys),
+        Command::Mkimg
+            if opts.image == ImageFormat::Kernel
+                && opts.mkimg == Some(Mkimg::Watch)
+                && opts.watch_run =>
+        {
+            run_image_format(&sys)
+        }
+        // The disk's boot partition (or the network) provides an image
+        // the machine's loader can boot. Split images, and those
+        // programmed onto boards, are of the kernel image.
+        Command::Mkimg
+            if opts.image == ImageFormat::Kernel && opts.mkimg.is_some_and(|m| m.loaded()) =>
+        {
+            loader_image_format(&sys)
+        }
+        _ => opts.image,
+    };
+    image_supported(image, &sys)?;
+    build.initialize(name, &sys)?;
+    sys.add_named(CompTotOrd::transition(&sys, &mut build)?);
+    sys.add_address_assign(AddressAssignmentx86_64::transition(&sys, &mut build)?);
+    sys.add_properties(CompProperties::transition(&sys, &mut build)?);
+    sys.add_restbls(ResAssignPass::transition(&sys, &mut build)?);
+    lockfile_sync(&sys, &build, opts.locked, opts.dry_run)?;
+
+    // process these in reverse order of dependencies (e.g. booter last)
+    let reverse_ids: Vec<ComponentId> = sys.get_named().ids().keys().copied().rev().collect();
+    for c_id in reverse_ids.iter() {
+        sys.add_params_iter(c_id, Parameters::transition_iter(c_id, &sys, &mut build)?);
+    }
+
+    Ok((sys, build, reverse_ids, image))
+}
+
+// The plan to build `spec` as build `name`, without building.
+pub fn system_plan(opts: &Options, spec: &String, name: &String) -> Result<Plan, ComposeError> {
+    let (sys, build, ids, _) = system_resolve(opts, spec, name)?;
+
+    build.plan(&ids, &sys)
+}
+
+// Remove the build directory of `opts.name` (or, with --all-stale,
+// all of the build directories whose builds aren't running), and
+// return the directories removed.
+pub fn system_clean(opts: &Options) -> Result<Vec<String>, ComposeError> {
+    let mut build = DefaultBuilder::new(opts);
+    if opts.all_stale {
+        return build.clean_stale();
+    }
+    // The specification can choose the build directory.
+    let mut sys = SystemState::new(opts.spec.clone());
+    sys.add_vars(spec_vars(opts));
+    sys.add_overrides(spec_overrides(opts));
+    sys.add_features(opts.features.clone());
+    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
+
+    Ok(build.clean(&opts.name, &sys)?.into_iter().collect())
+}
+
+// Serve the network-boot files of build `opts.name` (that `mkimg pxe`
+// created), until interrupted.
+pub fn system_serve(opts: &Options) -> Result<(), ComposeError> {
+    let mut build = DefaultBuilder::new(opts);
+    // The specification can choose the build directory.
+    let mut sys = SystemState::new(opts.spec.clone());
+    sys.add_vars(spec_vars(opts));
+    sys.add_overrides(spec_overrides(opts));
+    sys.add_features(opts.features.clone());
+    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
+    let dir = format!("{}/netboot", build.dir(&opts.name, &sys)?);
+
+    netboot_serve(&dir, opts.tftp_port, opts.http_port)
+}
+
+// What the build of the component named `comp` (either scope.name,
+// or just its name if that is unique) resolves to, in build `name`.
+pub fn system_explain(
+    opts: &Options,
+    spec: &String,
+    name: &String,
+    comp: &String,
+) -> Result<Explanation, ComposeError> {
+    let (sys, build, _, _) = system_resolve(opts, spec, name)?;
+    let matches: Vec<(&ComponentId, &ComponentName)> = sys
+        .get_named()
+        .ids()
+        .iter()
+        .filter(|(_, n)| n.to_string() == *comp || n.var_name == *comp)
+        .collect();
+    let all = || {
+        sys.get_named()
+            .ids()
+            .values()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    match matches.as_slice() {
+        [(id, _)] => build.explain(id, &sys),
+        [] => Err(ComposeError::Spec(format!(
+            "Error: There is no component named {} in the specification (its components are {}).",
+            comp,
+            all()
+        ))),
+        _ => Err(ComposeError::Spec(format!(
+            "Error: Component name {} is ambiguous, so qualify it with its scope (one of {}).",
+            comp,
+            matches
+                .iter()
+                .map(|(_, n)| n.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))),
+    }
+}
+
+// The contents of the built image at `opts.spec`, read from the image
+// itself rather than from its specification.
+pub fn system_inspect(opts: &Options) -> Result<Inspection, ComposeError> {
+    inspect(&opts.spec)
+}
+
+// The compositions of the workspace at `opts.spec`, each a build
+// name and the path of its specification.
+pub fn system_workspace(opts: &Options) -> Result<Vec<(String, String)>, ComposeError> {
+    workspace_parse(&opts.spec)
+}
+
+// Build (or, for a dry run, plan) `opts.spec` in each configuration
+// of the matrix, each in a thread of its own. They share the source
+// tree, and the objects built in it, so their builds take turns (see
+// src_lock), but are otherwise concurrent.
+pub fn system_matrix(opts: &Options) -> MatrixReport {
+    let configs = matrix_configs(opts);
+    let results = thread::scope(|scope| {
+        let builds: Vec<_> = configs
+            .iter()
+            .map(|c| {
+                scope.spawn(move || {
+                    let mut r = MatrixResult {
+                        name: c.name.clone(),
+                        values: c.values.clone(),
+                        image: None,
+                        sizes: None,
+                        error: None,
+                    };
+                    if c.opts.dry_run {
+                        r.error = system_plan(&c.opts, &c.opts.spec, &c.name).err();
+                        return r;
+                    }
+                    match system_build(&c.opts, &c.opts.spec, &c.name) {
+                        Ok((_, _, a)) => {
+                            r.sizes = Some((a.sizes.total(), a.sizes.initfs()));
+                            r.image = Some(a.image);
+                        }
+                        Err(e) => r.error = Some(e),
+                    }
+                    r
+                })
+            })
+            .collect();
+        builds
+            .into_iter()
+            .zip(configs.iter())
+            .map(|(b, c)| {
+                b.join().unwrap_or_else(|_| MatrixResult {
+                    name: c.name.clone(),
+                    values: c.values.clone(),
+                    image: None,
+                    sizes: None,
+                    error: Some(ComposeError::Pass(format!(
+                        "Error: The build of configuration {} panicked.",
+                        c.name
+                    ))),
+                })
+            })
+            .collect()
+    });
+
+    MatrixReport {
+        spec: opts.spec.clone(),
+        results,
+    }
+}
+
+// Build the system image for `spec` as build `name`.
+pub fn system_build(
+    opts: &Options,
+    spec: &String,
+    name: &String,
+) -> Result<(SystemState, DefaultBuilder, Artifacts), ComposeError> {
+    system_build_watched(opts, spec, name, &mut Vec::new())
+}
+
+// Build `spec` as build `name`, as system_build does, adding the
+// directories of the sources that the components are built from to
+// `sources`, once they're known (even if the build then fails), so
+// that mkimg watch can rebuild when they change.
+pub fn system_build_watched(
+    opts: &Options,
+    spec: &String,
+    name: &String,
+    sources: &mut Vec<PathBuf>,
+) -> Result<(SystemState, DefaultBuilder, Artifacts), ComposeError> {
+    let (mut sys, mut build, reverse_ids, image) = system_resolve(opts, spec, name)?;
+    sources.extend(build.src_dirs(&sys));
+    // mkimg flash fails before building, without the profile.
+    let flash = match opts.mkimg {
+        Some(Mkimg::Flash) => Some(flash_profile(&sys, &opts.flash_profile)?.tool),
+        _ => None,
+    };
+
+    // Whatever failed after an interrupt failed because of it.
+    match system_image(&mut sys, &mut build, &reverse_ids, image, opts.mkimg, flash) {
+        Ok(artifacts) => Ok((sys, build, artifacts)),
+        Err(_) if interrupted() => Err(build.incomplete()?),
+        Err(e) => Err(e),
+    }
+}
+
+// Build the components (`reverse_ids`, in build order), and the
+// system image from them, and (with `mkimg`) a disk image, the
+// network-boot files, or the memory regions' images, with it.
+// Flashing the board (with the `flash` tool) requires the regions'
+// images, if the board has regions.
+fn system_image(
+    sys: &mut SystemState,
+    build: &mut DefaultBuilder,
+    reverse_ids: &Vec<ComponentId>,
+    image: ImageFormat,
+    mkimg: Option<Mkimg>,
+    flash: Option<FlashTool>,
+) -> Result<Artifacts, ComposeError> {
+    preflight_check(sys, build, image, mkimg == Some(Mkimg::Pxe), flash)?;
+    // The component builds are independent of each other's objects,
+    // so build them all up-front (in parallel with --jobs).
+    // Don't continue on to create the constructor (and system image)
+    // from broken components.
+    if let Err(es) = build.comps_build(reverse_ids, sys) {
+        return Err(ComposeError::Build(es));
+    }
+    // Now that the sizes of the objects are known, components that
+    // don't fit at their provisional addresses are moved, and
+    // rebuilt.
+    let sizes = AddressAssignmentx86_64::comp_sizes(reverse_ids, sys, build)?;
+    let addrs = AddressAssignmentx86_64::assign(sys, &sizes)?;
+    addrs.validate(sys, &sizes)?;
+    let moved = addrs.moved(sys);
+    if !moved.is_empty() {
+        sys.add_address_assign(Box::new(addrs));
+        if let Err(es) = build.comps_build(&moved, sys) {
+            return Err(ComposeError::Build(es));
+        }
+    }
+    // The database only needs the components, so it's generated even
+    // if the image can't be.
+    let compile_commands = if build.compile_commands() {
+        Some(compile_commands_create(sys, build)?)
+    } else {
+        None
+    };
+    for c_id in reverse_ids.iter() {
+        sys.add_objs_iter(c_id, ElfObject::transition_iter(c_id, sys, build)?);
+        sys.add_invs_iter(c_id, Invocations::transition_iter(c_id, sys, build)?);
+    }
+    undefined_check(sys)?;
+    sys.add_constructor(Constructor::transition(sys, build)?);
+    // Don't package systems that exceed their size budgets.
+    let sizes = sizes_check(sys, build)?;
+    sys.add_graph(Graph::transition(sys, build)?);
+    let dtb = dtb_create(sys, build, image)?;
+    let xip = xip_create(sys, build)?;
+    let images = image_create(image, sys, build)?;
+    let (gdbinit, symbols) = gdb_bundle_create(sys, build, build.strip())?;
+    let (memmap, memmap_json) = memmap_create(sys, build)?;
+    let (dedup, dedup_layout) = dedup_create(sys, build, build.shared_layout())?;
+    let manifest = manifest_create(sys, build)?;
+    let sbom = sbom_create(sys, build)?;
+    let (timing, timing_files) = timing_create(build)?;
+    let regressions = history_update(sys, build, &sizes, &timing);
+    // The FIT image is the only one generated with it.
+    let boot = match image {
+        ImageFormat::Fit => &images[0],
+        _ => sys.get_constructor().image_path(),
+    };
+    let disk = match mkimg {
+        Some(Mkimg::Disk) => Some(disk_create(sys, build, boot)?),
+        _ => None,
+    };
+    let netboot = match mkimg {
+        Some(Mkimg::Pxe) => netboot_create(sys, build, boot)?,
+        _ => Vec::new(),
+    };
+    let split = mkimg == Some(Mkimg::Split)
+        || (mkimg == Some(Mkimg::Flash) && !sys.get_spec().memory().is_empty());
+    let (regions, region_layout) = if split {
+        let (images, layout) = regions_create(sys, build)?;
+        (images, Some(layout))
+    } else {
+        (Vec::new(), None)
+    };
+
+    Ok(Artifacts {
+        image: sys.get_constructor().image_path().clone(),
+        boot_images: images,
+        dtb,
+        xip,
+        disk,
+        netboot,
+        regions,
+        region_layout,
+        manifest,
+        sbom,
+        gdbinit,
+        symbols,
+        memmap,
+        memmap_json,
+        dedup,
+        dedup_layout,
+        sizes,
+        timing,
+        timing_files,
+        regressions,
+        compiler_cache: build.compiler_cache_stats(),
+        compile_commands,
+    })
+}
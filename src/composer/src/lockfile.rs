@@ -0,0 +1,212 @@
+// The resolution of a specification, recorded in a lockfile beside
+// it (e.g. ping_pong.lock for ping_pong.toml), as Cargo.lock records
+// the resolution of a crate's dependencies: each component's id,
+// base address, constructor and scheduler, the server and variant
+// chosen for each of its dependencies, and the closure of the
+// libraries it is linked with. Builds update the lockfile, and builds
+// with --locked instead fail if their resolution differs from it.
+//
+// The resolution depends on the architecture, profile, and features
+// (which select the specification's conditional sections), so the
+// lockfile holds one resolution for each configuration built.
+
+use build::DefaultBuilder;
+use passes::{component, deps, ComponentId, ComposeError, SystemState};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use syshelpers::{dump_file, emit_file};
+use toml;
+
+// Concurrent builds (of a matrix's configurations) update the same
+// lockfile.
+static LOCKFILE: Mutex<()> = Mutex::new(());
+
+const HEADER: &str = "# The resolution of the specification, generated by the composer.\n\
+                      # Build with --locked to fail if it changes.\n";
+
+fn str_arr<I: Iterator<Item = String>>(i: I) -> toml::Value {
+    toml::Value::Array(i.map(toml::Value::String).collect())
+}
+
+fn comp_lock(
+    id: &ComponentId,
+    s: &SystemState,
+    b: &DefaultBuilder,
+) -> Result<toml::Value, ComposeError> {
+    let c = component(s, id);
+    let mut t = toml::value::Table::new();
+    t.insert(
+        String::from("name"),
+        toml::Value::String(c.name.to_string()),
+    );
+    t.insert(String::from("id"), toml::Value::Integer(*id as i64));
+    t.insert(String::from("image"), toml::Value::String(c.source.clone()));
+    t.insert(
+        String::from("baseaddr"),
+        toml::Value::String(format!(
+            "{:#x}",
+            s.get_address_assignments().component_baseaddr(id)
+        )),
+    );
+    t.insert(
+        String::from("constructor"),
+        toml::Value::String(c.constructor.to_string()),
+    );
+    t.insert(
+        String::from("scheduler"),
+        toml::Value::String(c.scheduler.to_string()),
+    );
+    t.insert(
+        String::from("deps"),
+        str_arr(
+            deps(s, id)
+                .iter()
+                .map(|d| format!("{} {}/{}", d.server, d.interface, d.variant)),
+        ),
+    );
+    let mut libs = b.libs(id, s)?;
+    libs.sort();
+    t.insert(String::from("libs"), str_arr(libs.into_iter()));
+
+    Ok(toml::Value::Table(t))
+}
+
+// The configuration that the resolution is for.
+pub fn config(s: &SystemState, b: &DefaultBuilder) -> String {
+    let mut cfg = vec![
+        s.get_spec().arch().name().to_string(),
+        b.profile().map_or("default", |p| p.name()).to_string(),
+    ];
+    cfg.extend(s.get_features().iter().cloned());
+
+    cfg.join(" ")
+}
+
+// The lockfile of the specification at `spec`.
+pub fn lockfile_path(spec: &String) -> String {
+    Path::new(spec).with_extension("lock").display().to_string()
+}
+
+// The differences between the `old` and `new` resolutions of the
+// components.
+fn resolution_diff(old: &toml::Value, new: &toml::Value) -> Vec<String> {
+    let by_name = |v: &toml::Value| -> BTreeMap<String, toml::value::Table> {
+        v.get("component")
+            .and_then(|cs| cs.as_array())
+            .map(|cs| {
+                cs.iter()
+                    .filter_map(|c| c.as_table())
+                    .filter_map(|c| {
+                        c.get("name")
+                            .and_then(|n| n.as_str())
+                            .map(|n| (n.to_string(), c.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let (old, new) = (by_name(old), by_name(new));
+    let mut diffs = Vec::new();
+    for (name, o) in old.iter() {
+        let n = match new.get(name) {
+            Some(n) => n,
+            None => {
+                diffs.push(format!("Component {} was removed.", name));
+                continue;
+            }
+        };
+        for (field, v) in n.iter() {
+            match o.get(field) {
+                Some(ov) if ov == v => (),
+                Some(ov) => diffs.push(format!(
+                    "Component {}'s {} was {}, but is now {}.",
+                    name, field, ov, v
+                )),
+                None => diffs.push(format!("Component {}'s {} is now {}.", name, field, v)),
+            }
+        }
+    }
+    for name in new.keys().filter(|n| !old.contains_key(*n)) {
+        diffs.push(format!("Component {} was added.", name));
+    }
+
+    diffs
+}
+
+// Record the system's resolution in its specification's lockfile or,
+// if `locked`, check that it is the one recorded. A dry run doesn't
+// update the lockfile.
+pub fn lockfile_sync(
+    s: &SystemState,
+    b: &DefaultBuilder,
+    locked: bool,
+    dry_run: bool,
+) -> Result<(), ComposeError> {
+    let path = lockfile_path(&s.get_input());
+    let cfg = config(s, b);
+    let mut comps = Vec::new();
+    for id in s.get_named().ids().keys() {
+        comps.push(comp_lock(id, s, b)?);
+    }
+    let mut res = toml::value::Table::new();
+    res.insert(String::from("config"), toml::Value::String(cfg.clone()));
+    res.insert(String::from("component"), toml::Value::Array(comps));
+    let res = toml::Value::Table(res);
+
+    // The lock is only held for the lockfile, so poisoning is benign.
+    let _guard = LOCKFILE.lock().unwrap_or_else(|e| e.into_inner());
+    let prev: Vec<toml::Value> = if Path::new(&path).exists() {
+        let contents = String::from_utf8_lossy(&dump_file(&path)?).to_string();
+        let lock: toml::Value = toml::from_str(&contents).map_err(|e| ComposeError::Parse {
+            path: path.clone(),
+            msg: e.to_string(),
+        })?;
+        lock.get("resolution")
+            .and_then(|r| r.as_array())
+            .cloned()
+            .unwrap_or(Vec::new())
+    } else {
+        Vec::new()
+    };
+    let is_cfg = |r: &toml::Value| r.get("config").and_then(|c| c.as_str()) == Some(&cfg);
+    let old = prev.iter().find(|r| is_cfg(r));
+    if old == Some(&res) {
+        return Ok(());
+    }
+
+    if locked {
+        let msg = match old {
+            None => format!(
+                "Error: --locked was given, but the lockfile {} has no resolution for the configuration \"{}\"; build without --locked to record it.",
+                path, cfg
+            ),
+            Some(o) => format!(
+                "Error: --locked was given, but the resolution differs from that in the lockfile {}:\n\t{}",
+                path,
+                resolution_diff(o, &res).join("\n\t")
+            ),
+        };
+        return Err(ComposeError::Pass(msg));
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let mut rs: Vec<toml::Value> = prev.into_iter().filter(|r| !is_cfg(r)).collect();
+    rs.push(res);
+    rs.sort_by_key(|r| {
+        r.get("config")
+            .and_then(|c| c.as_str())
+            .map(|c| c.to_string())
+    });
+    let mut lock = toml::value::Table::new();
+    lock.insert(String::from("resolution"), toml::Value::Array(rs));
+    let contents = toml::to_string(&toml::Value::Table(lock)).map_err(|e| ComposeError::Io {
+        path: path.clone(),
+        msg: format!("Could not serialize the lockfile: {}", e),
+    })?;
+    info!("Updated the lockfile {}.", path);
+
+    emit_file(&path, format!("{}{}", HEADER, contents).as_bytes())
+}
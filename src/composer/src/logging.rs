@@ -0,0 +1,48 @@
+// The composer's diagnostics go through the log facade: summaries
+// of the build's progress at the info level, the full command lines
+// at the debug level, and the captured output of the commands at the
+// trace level. The library only emits them; the compose binary
+// installs this logger (at the level chosen by -v and --quiet), and
+// other tools can install their own. When stdout carries JSON, the
+// diagnostics are printed to stderr instead.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, m: &Metadata) -> bool {
+        m.level() <= log::max_level()
+    }
+
+    fn log(&self, r: &Record) {
+        if !self.enabled(r.metadata()) {
+            return;
+        }
+        // Errors are formatted by ComposeError.
+        let msg = match r.level() {
+            Level::Warn => format!("Warning: {}", r.args()),
+            _ => format!("{}", r.args()),
+        };
+        if STDERR.load(Ordering::Relaxed) {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+// Print the diagnostics up to `level` to stdout (or `stderr`).
+pub fn logging_init(level: LevelFilter, stderr: bool) {
+    STDERR.store(stderr, Ordering::Relaxed);
+    // Only fails if a logger is already installed, which is kept.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
@@ -1,91 +1,455 @@
-extern crate toml;
-#[macro_use]
-extern crate serde_derive;
-extern crate itertools;
-extern crate tar;
-extern crate xmas_elf;
-extern crate petgraph;
-extern crate ascent;
-
-mod address_assignment;
-mod build;
-mod compobject;
-mod cossystem;
-mod initargs;
-mod invocations;
-mod passes;
-mod pipe;
-mod properties;
-mod resources;
-mod symbols;
-mod syshelpers;
-mod tot_order;
-mod graph;
-mod analysis;
-
-use address_assignment::AddressAssignmentx86_64;
-use build::DefaultBuilder;
-use compobject::{Constructor, ElfObject};
-use cossystem::SystemSpec;
-use initargs::Parameters;
-use invocations::Invocations;
-use passes::{BuildState, ComponentId, SystemState, Transition, TransitionIter};
-use properties::CompProperties;
-use resources::ResAssignPass;
+// The command-line interface to the composer.
+
+extern crate compose;
+
+use compose::diagnostic::diagnostics_color;
+use compose::interrupt::{interrupt_handler_install, interrupted};
+use compose::logging::logging_init;
+use compose::flash::board_flash;
+use compose::options::{Format, Mkimg};
+use compose::qemu::{qemu_run, qemu_spawn, qemu_stop, qemu_test};
+use compose::watch::Watcher;
+use compose::{
+    system_build, system_build_watched, system_clean, system_explain, system_graph,
+    system_graph_json, system_inspect, system_matrix, system_plan, system_serve, system_validate,
+    system_workspace, Artifacts, BuildState, Command, ComposeError, Json, Options,
+};
 use std::env;
-use tot_order::CompTotOrd;
-use graph::Graph;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process;
 
-pub fn exec() -> Result<(), String> {
-    let mut args = env::args();
-    let program_name = args.next();
-
-    let arg1 = args.next();
-    let arg2 = args.next();
-
-    if None == arg1 || None == arg2 {
-        return Err(format!(
-            "usage: {} <sysspec>.toml <buildname>",
-            program_name.unwrap()
-        ));
-    }
-
-    let mut sys = SystemState::new(arg1.unwrap());
-    let mut build = DefaultBuilder::new();
-    build.initialize(&arg2.unwrap(), &sys)?;
-
-    sys.add_parsed(SystemSpec::transition(&sys, &mut build)?);
-    sys.add_named(CompTotOrd::transition(&sys, &mut build)?);
-    sys.add_address_assign(AddressAssignmentx86_64::transition(&sys, &mut build)?);
-    sys.add_properties(CompProperties::transition(&sys, &mut build)?);
-    sys.add_restbls(ResAssignPass::transition(&sys, &mut build)?);
-
-    // process these in reverse order of dependencies (e.g. booter last)
-    let reverse_ids: Vec<ComponentId> = sys
-        .get_named()
-        .ids()
-        .iter()
-        .map(|(k, _)| k.clone())
-        .rev()
-        .collect();
-    for c_id in reverse_ids.iter() {
-        sys.add_params_iter(&c_id, Parameters::transition_iter(c_id, &sys, &mut build)?);
-        sys.add_objs_iter(&c_id, ElfObject::transition_iter(c_id, &sys, &mut build)?);
-        sys.add_invs_iter(&c_id, Invocations::transition_iter(c_id, &sys, &mut build)?);
-    }
-    sys.add_constructor(Constructor::transition(&sys, &mut build)?);
-    sys.add_graph(Graph::transition(&sys, &mut build)?);
+pub fn exec(opts: &Options) -> Result<(), ComposeError> {
+    let json = opts.format == Format::Json;
+    logging_init(opts.log_level, json);
+    diagnostics_color(!json && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none());
+    interrupt_handler_install()?;
 
+    match opts.command {
+        Command::Build if opts.all => workspace(opts),
+        Command::Build | Command::Size if !opts.matrix.is_empty() => matrix(opts),
+        Command::Mkimg if opts.mkimg == Some(Mkimg::Serve) => system_serve(opts),
+        Command::Mkimg if opts.mkimg == Some(Mkimg::Watch) => watch(opts),
+        Command::Build | Command::Run | Command::Size | Command::Mkimg => compose(opts),
+        Command::Graph if json => {
+            println!("{}", system_graph_json(opts)?);
+            Ok(())
+        }
+        Command::Graph => {
+            print!("{}", system_graph(opts)?);
+            Ok(())
+        }
+        Command::Test => test(opts),
+        Command::Validate => {
+            system_validate(opts)?;
+            if json {
+                println!(
+                    "{}",
+                    Json::obj(vec![
+                        ("spec", Json::str(&opts.spec)),
+                        ("diagnostics", Json::Arr(vec![]))
+                    ])
+                );
+            } else {
+                println!("Specification {} is valid.", opts.spec);
+            }
+            Ok(())
+        }
+        Command::Clean => {
+            let removed = system_clean(opts)?;
+            if json {
+                println!(
+                    "{}",
+                    Json::obj(vec![(
+                        "removed",
+                        Json::Arr(removed.iter().map(|d| Json::str(d)).collect())
+                    )])
+                );
+            } else if removed.is_empty() {
+                println!("Nothing to clean.");
+            } else {
+                for d in removed.iter() {
+                    println!("Removed {}", d);
+                }
+            }
+            Ok(())
+        }
+        Command::Explain => {
+            let e = system_explain(opts, &opts.spec, &opts.name, &opts.component)?;
+            if json {
+                println!("{}", e.to_json());
+            } else {
+                print!("{}", e);
+            }
+            Ok(())
+        }
+        Command::Inspect => {
+            let i = system_inspect(opts)?;
+            if json {
+                println!("{}", i.to_json());
+            } else {
+                print!("{}", i);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn artifacts_print(opts: &Options, a: &Artifacts) {
+    if opts.format == Format::Json {
+        println!("{}", a.to_json());
+        return;
+    }
+    if opts.command == Command::Size {
+        print!("{}", a.sizes);
+    }
+    println!(
+        "System object generated:\n\t{}\nManifest of the build's artifacts:\n\t{}\nSoftware bill of materials:\n\t{}\nDebugging symbols (gdb -x {}):\n\t{}\nMemory map:\n\t{}\n\t{}\nDuplicated read-only contents:\n\t{}",
+        a.image, a.manifest, a.sbom, a.gdbinit, a.symbols, a.memmap, a.memmap_json, a.dedup
+    );
+    if let Some(ref l) = a.dedup_layout {
+        println!("Shared segment layout:\n\t{}", l);
+    }
+    for i in a.boot_images.iter() {
+        println!("Boot image generated:\n\t{}", i);
+    }
+    if let Some(ref d) = a.dtb {
+        println!("Device-tree:\n\t{}", d);
+    }
+    if let Some((ref bin, ref table)) = a.xip {
+        println!("XIP flash image (and its copy table):\n\t{}\n\t{}", bin, table);
+    }
+    if let Some(ref d) = a.disk {
+        println!("Disk image generated (to dd onto an SD card):\n\t{}", d);
+    }
+    if !a.netboot.is_empty() {
+        println!("Network-boot files generated (to serve with mkimg serve):");
+        for f in a.netboot.iter() {
+            println!("\t{}", f);
+        }
+    }
+    if !a.regions.is_empty() {
+        println!("Memory region images generated:");
+        for r in a.regions.iter() {
+            println!("\t{}", r);
+        }
+    }
+    if let Some((ref txt, ref json)) = a.region_layout {
+        println!("Memory region layout:\n\t{}\n\t{}", txt, json);
+    }
     println!(
-        "System object generated:\n\t{}",
-        sys.get_constructor().image_path()
+        "Build timing (the trace is for chrome://tracing):\n\t{}\n\t{}",
+        a.timing_files.0, a.timing_files.1
     );
+    print!("{}", a.timing);
+    if let Some((c, ref st)) = a.compiler_cache {
+        println!(
+            "Compiler cache ({}):\n\t{} hits, {} misses",
+            c.name(),
+            st.hits,
+            st.misses
+        );
+    }
+    if let Some(ref c) = a.compile_commands {
+        println!(
+            "Compilation database (for clangd, link it into the source tree):\n\t{}",
+            c
+        );
+    }
+}
+
+fn compose(opts: &Options) -> Result<(), ComposeError> {
+    if opts.dry_run {
+        let plan = system_plan(opts, &opts.spec, &opts.name)?;
+        match opts.format {
+            Format::Text => print!("{}", plan),
+            Format::Json => println!("{}", plan.to_json()),
+        }
+        return Ok(());
+    }
+    let (sys, build, artifacts) = system_build(opts, &opts.spec, &opts.name)?;
+    artifacts_print(opts, &artifacts);
+    if opts.command == Command::Run {
+        qemu_run(&sys, &build, opts.gdb)?;
+    }
+    if opts.mkimg == Some(Mkimg::Flash) {
+        board_flash(&sys, &build, &opts.flash_profile)?;
+    }
+
+    Ok(())
+}
+
+// Rebuild the system whenever its sources change, until interrupted.
+// Failed builds are reported, and wait for the sources to be fixed.
+// With --run, each build is booted in qemu, which is stopped when the
+// sources change.
+fn watch(opts: &Options) -> Result<(), ComposeError> {
+    let report = |e: &ComposeError| match opts.format {
+        Format::Text => println!("{}", e),
+        Format::Json => println!("{}", Json::obj(vec![("diagnostics", e.to_json())])),
+    };
+    let mut watcher = Watcher::new(&opts.spec)?;
+    loop {
+        let mut sources = Vec::new();
+        let qemu = match system_build_watched(opts, &opts.spec, &opts.name, &mut sources) {
+            Ok((sys, build, artifacts)) => {
+                artifacts_print(opts, &artifacts);
+                if opts.watch_run {
+                    qemu_spawn(&sys, &build, opts.gdb).map_err(|e| report(&e)).ok()
+                } else {
+                    None
+                }
+            }
+            Err(e) if interrupted() => return Err(e),
+            Err(e) => {
+                report(&e);
+                None
+            }
+        };
+        watcher.watch_sources(&sources)?;
+        let changed = watcher.wait();
+        if let Some(q) = qemu {
+            qemu_stop(q);
+        }
+        if changed?.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+// Build the specification in each configuration of the matrix, and
+// report their sizes. All of the configurations are built, even
+// after failures.
+fn matrix(opts: &Options) -> Result<(), ComposeError> {
+    let report = system_matrix(opts);
+    let failed = report.failed();
+    if opts.format == Format::Json {
+        println!("{}", report.to_json());
+        // The failures are reported in the results.
+        if failed != 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    print!("{}", report);
+    if failed != 0 {
+        return Err(ComposeError::Pass(format!(
+            "Error: {} of {} configurations failed to build.",
+            failed,
+            report.results.len()
+        )));
+    }
+
+    Ok(())
+}
+
+// Build each of the workspace's compositions, under its own name.
+// As when testing, all of them are built, even after failures, and
+// as JSON, their results are printed together. The compositions
+// share the cache of built objects, so those they have in common
+// are built once.
+fn workspace(opts: &Options) -> Result<(), ComposeError> {
+    let json = opts.format == Format::Json;
+    let comps = system_workspace(opts)?;
+    let mut failed = 0;
+    let mut results_json = Vec::new();
+    for (name, spec) in comps.iter() {
+        if interrupted() {
+            break;
+        }
+        let res = if opts.dry_run {
+            system_plan(opts, spec, name).map(|plan| {
+                if json {
+                    plan.to_json()
+                } else {
+                    print!("{}", plan);
+                    Json::Null
+                }
+            })
+        } else {
+            system_build(opts, spec, name).map(|(_, _, artifacts)| {
+                if json {
+                    artifacts.to_json()
+                } else {
+                    artifacts_print(opts, &artifacts);
+                    Json::Null
+                }
+            })
+        };
+        if res.is_err() {
+            failed += 1;
+        }
+        if json {
+            let (result, diags) = match res {
+                Ok(r) => (r, Json::Arr(vec![])),
+                Err(ref e) => (Json::Null, e.to_json()),
+            };
+            results_json.push(Json::obj(vec![
+                ("name", Json::str(name)),
+                ("spec", Json::str(spec)),
+                (if opts.dry_run { "plan" } else { "artifacts" }, result),
+                ("diagnostics", diags),
+            ]));
+        } else if let Err(e) = res {
+            println!("{}\nFAILED: {} ({})", e, name, spec);
+        }
+    }
+
+    if json {
+        println!(
+            "{}",
+            Json::obj(vec![
+                ("results", Json::Arr(results_json)),
+                ("failed", Json::Num(failed as u64))
+            ])
+        );
+        // The failures are reported in the results.
+        if failed != 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if failed != 0 {
+        return Err(ComposeError::Pass(format!(
+            "Error: {} of {} compositions failed to build.",
+            failed,
+            comps.len()
+        )));
+    }
 
     Ok(())
 }
 
-pub fn main() -> () {
-    if let Err(e) = exec() {
-        println!("{}", e);
+// Build and boot each specification, and report if its serial
+// output matched what it expects. All specifications are tested,
+// even after failures, so that CI reports on each of them. As JSON,
+// the results of all of the specifications are printed together.
+fn test(opts: &Options) -> Result<(), ComposeError> {
+    let json = opts.format == Format::Json;
+    let mut results = Vec::new();
+    let mut results_json = Vec::new();
+    for spec in opts.specs.iter() {
+        if interrupted() {
+            break;
+        }
+        let stem = Path::new(spec)
+            .file_stem()
+            .map_or(String::from("spec"), |s| s.to_string_lossy().to_string());
+        let name = format!("test-{}", stem);
+        if opts.dry_run {
+            let plan = system_plan(opts, spec, &name)?;
+            if json {
+                results_json.push(Json::obj(vec![
+                    ("spec", Json::str(spec)),
+                    ("plan", plan.to_json()),
+                ]));
+            } else {
+                print!("{}", plan);
+            }
+            continue;
+        }
+        let mut artifacts_json = Json::Null;
+        let res = match system_build(opts, spec, &name) {
+            Ok((sys, build, artifacts)) => {
+                if json {
+                    artifacts_json = artifacts.to_json();
+                } else {
+                    artifacts_print(opts, &artifacts);
+                }
+                qemu_test(&sys, &build).map(|missing| {
+                    (missing, build.file_path(&"serial.log".to_string()).unwrap_or(String::new()))
+                })
+            }
+            Err(e) => Err(e),
+        };
+        if json {
+            let (passed, log, missing, diags) = match res {
+                Ok((ref missing, ref log)) => (
+                    missing.is_empty(),
+                    Json::str(log),
+                    missing.iter().map(|p| Json::str(p)).collect(),
+                    Json::Arr(vec![]),
+                ),
+                Err(ref e) => (false, Json::Null, vec![], e.to_json()),
+            };
+            results_json.push(Json::obj(vec![
+                ("spec", Json::str(spec)),
+                ("passed", Json::Bool(passed)),
+                ("artifacts", artifacts_json),
+                ("serial_log", log),
+                ("missing", Json::Arr(missing)),
+                ("diagnostics", diags),
+            ]));
+            results.push(passed);
+            continue;
+        }
+        match res {
+            Ok((ref missing, ref log)) if missing.is_empty() => {
+                println!("PASS: {} (serial output in {})", spec, log);
+                results.push(true);
+            }
+            Ok((missing, log)) => {
+                println!("FAIL: {} did not print (serial output in {}):", spec, log);
+                for p in missing.iter() {
+                    println!("\t{}", p);
+                }
+                results.push(false);
+            }
+            Err(e) => {
+                println!("{}\nFAIL: {}", e, spec);
+                results.push(false);
+            }
+        }
+    }
+
+    let failed = results.iter().filter(|r| !**r).count();
+    if json {
+        println!(
+            "{}",
+            Json::obj(vec![
+                ("results", Json::Arr(results_json)),
+                ("failed", Json::Num(failed as u64))
+            ])
+        );
+        // The failures are reported in the results.
+        if failed != 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if failed != 0 {
+        return Err(ComposeError::Pass(format!(
+            "Error: {} of {} compositions failed their tests.",
+            failed,
+            results.len()
+        )));
+    }
+    if !results.is_empty() {
+        println!("All {} compositions passed their tests.", results.len());
+    }
+
+    Ok(())
+}
+
+pub fn main() {
+    let mut args = env::args();
+    let program_name = args.next().unwrap_or("compose".to_string());
+    let opts = match Options::parse(&program_name, args.collect()) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = exec(&opts) {
+        match opts.format {
+            Format::Text => println!("{}", e),
+            Format::Json => println!("{}", Json::obj(vec![("diagnostics", e.to_json())])),
+        }
+        // As if killed by SIGINT, as shells report.
+        process::exit(match e {
+            ComposeError::Interrupted { .. } => 130,
+            _ => 1,
+        });
     }
 }
@@ -0,0 +1,271 @@
+// A machine-readable (JSON) manifest of a build's artifacts -- each
+// component's sealed object, initargs, and tarballs, and the final
+// system image -- along with the inputs that determined them, so
+// that CI and loaders don't need to scrape the composer's output.
+
+use build::{constructor_archive_file, DefaultBuilder};
+use initargs::{ArgsKV, ArgsValType};
+use passes::{
+    component, deps, exports, BuildState, ComponentId, ComposeError, ServiceType, SystemState,
+};
+use std::fmt;
+use std::path::Path;
+use strip::{debug_path, stripped_path};
+use syshelpers::{dump_file, emit_file, hex, sha256};
+
+// The JSON values the composer emits, pretty-printed by Display.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(u64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn str(s: &str) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    pub fn obj(fields: Vec<(&str, Json)>) -> Json {
+        Json::Obj(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(s) => write!(f, "\"{}\"", json_escape(s)),
+            Json::Arr(vs) if vs.is_empty() => write!(f, "[]"),
+            Json::Obj(kvs) if kvs.is_empty() => write!(f, "{{}}"),
+            Json::Arr(vs) => {
+                write!(f, "[")?;
+                for (i, v) in vs.iter().enumerate() {
+                    write!(f, "{}\n{}", if i == 0 { "" } else { "," }, pad)?;
+                    v.write(f, indent + 1)?;
+                }
+                write!(f, "\n{}]", "  ".repeat(indent))
+            }
+            Json::Obj(kvs) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in kvs.iter().enumerate() {
+                    write!(
+                        f,
+                        "{}\n{}\"{}\": ",
+                        if i == 0 { "" } else { "," },
+                        pad,
+                        json_escape(k)
+                    )?;
+                    v.write(f, indent + 1)?;
+                }
+                write!(f, "\n{}}}", "  ".repeat(indent))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    s.chars().fold(String::new(), |mut acc, c| {
+        match c {
+            '"' => acc.push_str("\\\""),
+            '\\' => acc.push_str("\\\\"),
+            '\n' => acc.push_str("\\n"),
+            '\t' => acc.push_str("\\t"),
+            c if (c as u32) < 0x20 => acc.push_str(&format!("\\u{:04x}", c as u32)),
+            c => acc.push(c),
+        }
+        acc
+    })
+}
+
+fn artifact(path: &String) -> Result<Json, ComposeError> {
+    let contents = dump_file(path)?;
+    Ok(Json::obj(vec![
+        ("path", Json::str(path)),
+        ("size", Json::Num(contents.len() as u64)),
+        ("sha256", Json::Str(sha256(&contents))),
+    ]))
+}
+
+// Tarballs are only created when they have contents.
+fn artifact_opt(path: &String) -> Result<Json, ComposeError> {
+    if Path::new(path).exists() {
+        artifact(path)
+    } else {
+        Ok(Json::Null)
+    }
+}
+
+// Arrays in the specification's parameters are stored as (reversed)
+// entries with the key "_".
+pub fn params_json(kvs: &Vec<ArgsKV>) -> Json {
+    let val = |v: &ArgsValType| match v {
+        ArgsValType::Str(s) => Json::Str(s.clone()),
+        ArgsValType::Arr(a) => params_json(a),
+        ArgsValType::Blob(b) => Json::obj(vec![("hex", Json::Str(hex(b)))]),
+    };
+    if !kvs.is_empty() && kvs.iter().all(|kv| kv.key() == "_") {
+        Json::Arr(kvs.iter().rev().map(|kv| val(kv.val())).collect())
+    } else {
+        Json::Obj(
+            kvs.iter()
+                .map(|kv| (kv.key().clone(), val(kv.val())))
+                .collect(),
+        )
+    }
+}
+
+fn component_json(
+    id: &ComponentId,
+    s: &SystemState,
+    b: &DefaultBuilder,
+) -> Result<Json, ComposeError> {
+    let c = component(s, id);
+    let p = s.get_param_id(id);
+
+    let exps = exports(s, id)
+        .iter()
+        .map(|e| {
+            Json::obj(vec![
+                ("interface", Json::str(&e.interface)),
+                ("variant", Json::str(&e.variant)),
+            ])
+        })
+        .collect();
+    let ds = deps(s, id)
+        .iter()
+        .map(|d| {
+            Json::obj(vec![
+                ("server", Json::Str(d.server.to_string())),
+                ("interface", Json::str(&d.interface)),
+                ("variant", Json::str(&d.variant)),
+            ])
+        })
+        .collect();
+
+    let mut fields = vec![
+        ("id", Json::Num(*id as u64)),
+        ("name", Json::Str(c.name.to_string())),
+        ("image", Json::str(&c.source)),
+        ("arch", Json::str(c.arch.name())),
+        (
+            "baseaddr",
+            Json::Str(format!(
+                "{:#x}",
+                s.get_address_assignments().component_baseaddr(id)
+            )),
+        ),
+        ("object", artifact(s.get_objs_id(id).comp_path())?),
+        ("initargs", artifact(p.param_prog())?),
+        ("initargs_format", Json::str(c.initargs_format.name())),
+        (
+            "initfs",
+            match p.param_fs() {
+                Some(t) => artifact(t)?,
+                None => Json::Null,
+            },
+        ),
+        ("exports", Json::Arr(exps)),
+        ("deps", Json::Arr(ds)),
+        ("params", params_json(&c.params)),
+        ("globals", params_json(&c.globals)),
+        (
+            "sched",
+            c.sched.as_ref().map_or(Json::Null, |sc| {
+                Json::obj(
+                    [
+                        ("prio", sc.prio),
+                        ("budget_us", sc.budget_us),
+                        ("period_us", sc.period_us),
+                        ("core", sc.core),
+                    ]
+                    .iter()
+                    .map(|(k, v)| (*k, v.map_or(Json::Null, Json::Num)))
+                    .collect(),
+                )
+            }),
+        ),
+        (
+            "cflags",
+            c.cflags.as_ref().map_or(Json::Null, |f| Json::str(f)),
+        ),
+        (
+            "make_vars",
+            Json::Obj(
+                c.make_vars
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Json::str(v)))
+                    .collect(),
+            ),
+        ),
+    ];
+
+    // Stripped builds pack the stripped objects, and keep their
+    // debugging information.
+    if b.strip() {
+        let obj = s.get_objs_id(id).comp_path();
+        fields.push(("stripped_object", artifact(&stripped_path(obj))?));
+        fields.push(("debug", artifact(&debug_path(obj))?));
+    }
+
+    // Constructors are also linked with the objects of the
+    // components they create.
+    if s.get_properties()
+        .service_is_a(id, ServiceType::Constructor)
+    {
+        let tar = b.comp_file_path(id, &constructor_archive_file(s), s)?;
+        fields.push((
+            "constructor_initargs",
+            artifact(&b.comp_file_path(id, &"initargs_constructor.c".to_string(), s)?)?,
+        ));
+        fields.push(("constructor_initfs", artifact_opt(&tar)?));
+    }
+
+    Ok(Json::obj(fields))
+}
+
+// Write the manifest into the build directory, and return its path.
+pub fn manifest_create(s: &SystemState, b: &DefaultBuilder) -> Result<String, ComposeError> {
+    let path = b.file_path(&"manifest.json".to_string())?;
+
+    let mut comps = Vec::new();
+    for id in s.get_named().ids().keys() {
+        comps.push(component_json(id, s, b)?);
+    }
+    let manifest = Json::obj(vec![
+        ("specification", Json::Str(s.get_input())),
+        ("arch", Json::str(s.get_spec().arch().name())),
+        (
+            "profile",
+            b.profile().map_or(Json::Null, |p| Json::str(p.name())),
+        ),
+        (
+            "provenance",
+            b.provenance().map_or(Json::Null, |p| p.to_json()),
+        ),
+        ("image", artifact(s.get_constructor().image_path())?),
+        ("gdbinit", artifact(&b.file_path(&"gdbinit".to_string())?)?),
+        (
+            "symbols",
+            artifact(&b.file_path(&"symbols.map".to_string())?)?,
+        ),
+        ("components", Json::Arr(comps)),
+    ]);
+    emit_file(&path, format!("{}\n", manifest).as_bytes())?;
+
+    Ok(path)
+}
@@ -0,0 +1,191 @@
+// Building one specification in each configuration of a matrix: the
+// cross-product of the values of its axes (the architecture, the
+// build profile, and a feature to enable), given with --matrix. Each
+// configuration is built under its own name, the build's with each
+// of its values appended (e.g. ping-i386-base), but for the profile,
+// which already names the build directory, and the report
+// summarizes which configurations built, and how their sizes differ
+// from those of the first that did.
+
+use manifest::Json;
+use options::Options;
+use passes::{ComposeError, Profile};
+use std::fmt;
+
+// The value of the feature axis that enables no (further) feature.
+const NO_FEATURE: &str = "-";
+
+pub struct MatrixConfig {
+    pub name: String,                  // of the build
+    pub values: Vec<(String, String)>, // of each axis, in order
+    pub opts: Options,
+}
+
+impl MatrixConfig {
+    // This configuration, with `axis` set to `val`.
+    fn with(&self, axis: &String, val: &String) -> MatrixConfig {
+        let mut opts = self.opts.clone();
+        match axis.as_str() {
+            "arch" => opts
+                .overrides
+                .push((String::from("system.arch"), val.clone())),
+            "profile" => opts.profile = Profile::from_name(val),
+            _ if val != NO_FEATURE => {
+                opts.features.insert(val.clone());
+            }
+            _ => (),
+        }
+        let mut values = self.values.clone();
+        values.push((axis.clone(), val.clone()));
+
+        let name = match axis.as_str() {
+            "profile" => self.name.clone(),
+            _ if val == NO_FEATURE => format!("{}-base", self.name),
+            _ => format!("{}-{}", self.name, val),
+        };
+
+        MatrixConfig { name, values, opts }
+    }
+}
+
+// The configurations of the matrix, with the last axis varying
+// fastest. Builds that run concurrently wait for each other's use
+// of the source tree, rather than failing.
+pub fn matrix_configs(opts: &Options) -> Vec<MatrixConfig> {
+    let mut base = opts.clone();
+    base.wait = true;
+    let mut configs = vec![MatrixConfig {
+        name: opts.name.clone(),
+        values: Vec::new(),
+        opts: base,
+    }];
+    for (axis, vals) in opts.matrix.iter() {
+        let mut next = Vec::new();
+        for c in configs.iter() {
+            for v in vals.iter() {
+                next.push(c.with(axis, v));
+            }
+        }
+        configs = next;
+    }
+
+    configs
+}
+
+pub struct MatrixResult {
+    pub name: String,
+    pub values: Vec<(String, String)>,
+    pub image: Option<String>,
+    pub sizes: Option<(u64, u64)>, // of the components, and of the tarball; None if only planned
+    pub error: Option<ComposeError>,
+}
+
+pub struct MatrixReport {
+    pub spec: String,
+    pub results: Vec<MatrixResult>,
+}
+
+fn delta(size: u64, base: u64) -> i64 {
+    size as i64 - base as i64
+}
+
+impl MatrixReport {
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| r.error.is_some()).count()
+    }
+
+    // The configuration the others' sizes are compared to: the first
+    // that built.
+    fn baseline(&self) -> Option<&MatrixResult> {
+        self.results.iter().find(|r| r.sizes.is_some())
+    }
+
+    // The differences of the sizes are left to the reader, as JSON
+    // numbers are unsigned here.
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("spec", Json::str(&self.spec)),
+            (
+                "baseline",
+                self.baseline().map_or(Json::Null, |b| Json::str(&b.name)),
+            ),
+            (
+                "configurations",
+                Json::Arr(
+                    self.results
+                        .iter()
+                        .map(|r| {
+                            let sizes = r.sizes.map_or(Json::Null, |(comps, initfs)| {
+                                Json::obj(vec![
+                                    ("components", Json::Num(comps)),
+                                    ("initfs", Json::Num(initfs)),
+                                ])
+                            });
+                            Json::obj(vec![
+                                ("name", Json::str(&r.name)),
+                                (
+                                    "axes",
+                                    Json::obj(
+                                        r.values
+                                            .iter()
+                                            .map(|(a, v)| (a.as_str(), Json::str(v)))
+                                            .collect(),
+                                    ),
+                                ),
+                                ("passed", Json::Bool(r.error.is_none())),
+                                (
+                                    "image",
+                                    r.image.as_ref().map_or(Json::Null, |i| Json::str(i)),
+                                ),
+                                ("sizes", sizes),
+                                (
+                                    "diagnostics",
+                                    r.error.as_ref().map_or(Json::Arr(vec![]), |e| e.to_json()),
+                                ),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            ("failed", Json::Num(self.failed() as u64)),
+        ])
+    }
+}
+
+impl fmt::Display for MatrixReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let base = self.baseline().and_then(|b| b.sizes);
+        writeln!(
+            f,
+            "Matrix of {} configurations of {}:",
+            self.results.len(),
+            self.spec
+        )?;
+        for r in self.results.iter() {
+            let axes: Vec<String> = r
+                .values
+                .iter()
+                .map(|(a, v)| format!("{}={}", a, v))
+                .collect();
+            let outcome = match (&r.error, r.sizes, base) {
+                (Some(_), _, _) => String::from("FAILED"),
+                (None, Some((comps, initfs)), Some((bc, bi))) => format!(
+                    "built, components {} bytes ({:+}), initfs {} bytes ({:+})",
+                    comps,
+                    delta(comps, bc),
+                    initfs,
+                    delta(initfs, bi)
+                ),
+                (None, _, _) => String::from("planned"),
+            };
+            writeln!(f, "\t{}\t{}\t{}", r.name, axes.join(" "), outcome)?;
+        }
+        for r in self.results.iter() {
+            if let Some(ref e) = r.error {
+                writeln!(f, "{}:\n{}", r.name, e)?;
+            }
+        }
+
+        Ok(())
+    }
+}
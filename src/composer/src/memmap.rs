@@ -0,0 +1,298 @@
+// The system's memory map: for each address space, the loadable
+// segments of its components (and of those in its ancestors, which
+// it shares), and the gaps between them; where each constructor's
+// initfs tarball is linked; and where the booter is embedded in the
+// kernel image. memmap.txt is for reading while tuning baseaddrs,
+// and memmap.json for tools.
+
+use manifest::Json;
+use passes::{
+    component, BuildState, ComponentId, ComponentName, ComposeError, ServiceType, SystemState,
+};
+use syshelpers::{dump_file, emit_file};
+use xmas_elf::program;
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::Entry;
+use xmas_elf::ElfFile;
+
+// The symbols delimiting a constructor's tarball.
+pub const INITFS_START: &str = "_binary_tar_binary_start";
+pub const INITFS_END: &str = "_binary_tar_binary_end";
+
+#[derive(Clone)]
+struct Region {
+    comp: String,
+    start: u64,
+    end: u64,
+    perms: String,
+}
+
+struct CompLayout {
+    name: String,
+    baseaddr: u64,
+    segments: Vec<Region>,
+    initfs: Option<(u64, u64)>,
+}
+
+fn elf_parse<'a>(path: &String, contents: &'a Vec<u8>) -> Result<ElfFile<'a>, ComposeError> {
+    ElfFile::new(contents).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the object {} as an ELF file: {}",
+            path, e
+        ))
+    })
+}
+
+pub fn symb_addr(e: &ElfFile, name: &str) -> Option<u64> {
+    fn find<'a, E: Entry>(e: &ElfFile<'a>, sts: &[E], name: &str) -> Option<u64> {
+        sts.iter()
+            .find(|s| s.get_name(e) == Ok(name))
+            .map(|s| s.value())
+    }
+    match e.find_section_by_name(".symtab")?.get_data(e) {
+        Ok(SectionData::SymbolTable32(sts)) => find(e, sts, name),
+        Ok(SectionData::SymbolTable64(sts)) => find(e, sts, name),
+        _ => None,
+    }
+}
+
+fn comp_layout(s: &SystemState, id: &ComponentId) -> Result<CompLayout, ComposeError> {
+    let name = component(s, id).name.to_string();
+    let path = s.get_objs_id(id).comp_path();
+    let obj = dump_file(path)?;
+    let elf_file = elf_parse(path, &obj)?;
+
+    let segments = elf_file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load) && ph.mem_size() != 0)
+        .map(|ph| {
+            let f = ph.flags();
+            Region {
+                comp: name.clone(),
+                start: ph.virtual_addr(),
+                end: ph.virtual_addr() + ph.mem_size(),
+                perms: format!(
+                    "{}{}{}",
+                    if f.is_read() { "r" } else { "-" },
+                    if f.is_write() { "w" } else { "-" },
+                    if f.is_execute() { "x" } else { "-" }
+                ),
+            }
+        })
+        .collect();
+    // Only constructors are linked with a tarball.
+    let initfs = if s
+        .get_properties()
+        .service_is_a(id, ServiceType::Constructor)
+    {
+        match (
+            symb_addr(&elf_file, INITFS_START),
+            symb_addr(&elf_file, INITFS_END),
+        ) {
+            (Some(start), Some(end)) if start != 0 && end > start => Some((start, end)),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    Ok(CompLayout {
+        name,
+        baseaddr: s.get_address_assignments().component_baseaddr(id),
+        segments,
+        initfs,
+    })
+}
+
+// The components mapped into each address space: those of shared
+// address spaces include the components of their ancestors.
+fn addrspc_comps(s: &SystemState) -> Vec<(String, Vec<ComponentName>)> {
+    let spec = s.get_spec();
+    let mut ases = Vec::new();
+    for a in s.get_named().addrspc_components_shared().values() {
+        let mut comps = a.components.clone();
+        let mut parent = a.parent.as_ref();
+        while let Some(p) = parent.and_then(|p| spec.address_spaces().get(p)) {
+            comps.extend(p.components.iter().cloned());
+            parent = p.parent.as_ref();
+        }
+        ases.push((a.name.clone(), comps));
+    }
+    for c in s.get_named().addrspc_components_exclusive() {
+        ases.push((c.to_string(), vec![c.clone()]));
+    }
+
+    ases
+}
+
+// The unused ranges between consecutive, sorted regions.
+fn gaps(regions: &Vec<Region>) -> Vec<(u64, u64)> {
+    let mut gs = Vec::new();
+    let mut prev_end = None;
+    for r in regions.iter() {
+        if let Some(e) = prev_end {
+            if r.start > e {
+                gs.push((e, r.start));
+            }
+        }
+        prev_end = Some(prev_end.map_or(r.end, |e: u64| e.max(r.end)));
+    }
+
+    gs
+}
+
+fn hex(a: u64) -> Json {
+    Json::Str(format!("{:#x}", a))
+}
+
+fn range_json(start: u64, end: u64) -> Json {
+    Json::obj(vec![
+        ("start", hex(start)),
+        ("end", hex(end)),
+        ("size", Json::Num(end - start)),
+    ])
+}
+
+// Write memmap.txt and memmap.json into the build directory, and
+// return their paths.
+pub fn memmap_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+) -> Result<(String, String), ComposeError> {
+    let txt_path = b.file_path(&"memmap.txt".to_string())?;
+    let json_path = b.file_path(&"memmap.json".to_string())?;
+
+    let mut layouts = Vec::new();
+    for id in s.get_named().ids().keys() {
+        layouts.push(comp_layout(s, id)?);
+    }
+    let layout = |n: &ComponentName| layouts.iter().find(|l| l.name == n.to_string()).unwrap();
+
+    let img_path = s.get_constructor().image_path();
+    let img = dump_file(img_path)?;
+    let booter = elf_parse(img_path, &img)?
+        .find_section_by_name(".initial_component")
+        .map(|sh| (sh.address(), sh.address() + sh.size()));
+    let mut txt = String::new();
+    let mut kern_json = vec![("image", Json::str(img_path))];
+    if let Some((start, end)) = booter {
+        txt.push_str(&format!(
+            "Kernel image {}:\n  {:#018x}-{:#018x} booter (.initial_component, {} bytes)\n\n",
+            img_path,
+            start,
+            end,
+            end - start
+        ));
+        kern_json.push(("booter", range_json(start, end)));
+    }
+
+    let mut ases_json = Vec::new();
+    for (name, comps) in addrspc_comps(s).iter() {
+        let mut regions: Vec<Region> = comps
+            .iter()
+            .flat_map(|c| layout(c).segments.iter().cloned())
+            .collect();
+        regions.sort_by_key(|r| (r.start, r.end));
+        let gs = gaps(&regions);
+
+        txt.push_str(&format!("Address space {}:\n", name));
+        let mut entries: Vec<(u64, String)> = regions
+            .iter()
+            .map(|r| {
+                (
+                    r.start,
+                    format!(
+                        "  {:#018x}-{:#018x} {} {} ({} bytes)\n",
+                        r.start,
+                        r.end,
+                        r.perms,
+                        r.comp,
+                        r.end - r.start
+                    ),
+                )
+            })
+            .collect();
+        entries.extend(gs.iter().map(|(start, end)| {
+            (
+                *start,
+                format!(
+                    "  {:#018x}-{:#018x} --- gap ({} bytes)\n",
+                    start,
+                    end,
+                    end - start
+                ),
+            )
+        }));
+        for c in comps.iter() {
+            if let Some((start, end)) = layout(c).initfs {
+                entries.push((
+                    start,
+                    format!(
+                        "  {:#018x}-{:#018x}     initfs of {} ({} bytes)\n",
+                        start,
+                        end,
+                        c,
+                        end - start
+                    ),
+                ));
+            }
+        }
+        // stable, so the segment containing the initfs precedes it
+        entries.sort_by_key(|(a, _)| *a);
+        entries.iter().for_each(|(_, l)| txt.push_str(l));
+        txt.push('\n');
+
+        ases_json.push(Json::obj(vec![
+            ("name", Json::str(name)),
+            (
+                "components",
+                Json::Arr(comps.iter().map(|c| Json::Str(c.to_string())).collect()),
+            ),
+            (
+                "segments",
+                Json::Arr(
+                    regions
+                        .iter()
+                        .map(|r| {
+                            Json::obj(vec![
+                                ("component", Json::str(&r.comp)),
+                                ("start", hex(r.start)),
+                                ("end", hex(r.end)),
+                                ("size", Json::Num(r.end - r.start)),
+                                ("perms", Json::str(&r.perms)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "gaps",
+                Json::Arr(gs.iter().map(|(s, e)| range_json(*s, *e)).collect()),
+            ),
+        ]));
+    }
+
+    let comps_json = layouts
+        .iter()
+        .map(|l| {
+            Json::obj(vec![
+                ("name", Json::str(&l.name)),
+                ("baseaddr", hex(l.baseaddr)),
+                (
+                    "initfs",
+                    l.initfs.map_or(Json::Null, |(s, e)| range_json(s, e)),
+                ),
+            ])
+        })
+        .collect();
+    let json = Json::obj(vec![
+        ("kernel", Json::obj(kern_json)),
+        ("address_spaces", Json::Arr(ases_json)),
+        ("components", Json::Arr(comps_json)),
+    ]);
+
+    emit_file(&txt_path, txt.as_bytes())?;
+    emit_file(&json_path, format!("{}\n", json).as_bytes())?;
+
+    Ok((txt_path, json_path))
+}
@@ -0,0 +1,148 @@
+// Network-booting the system (`mkimg pxe`): the build directory's
+// netboot/ directory holds what lab machines fetch, to be served (e.g.
+// by `mkimg serve`) by TFTP from the server that DHCP names, and
+// optionally by HTTP from netboot.url. The system's image is at
+// netboot.dir within it (by default, composite/).
+//
+// x86 kernels are multiboot2 images, which neither pxelinux nor iPXE
+// can boot, so both chain-load grub's network image (from
+// grub-mknetdir), whose grub.cfg loads the kernel, by TFTP. The
+// pxelinux.cfg/default configuration is for pxelinux (or a firmware's
+// PXE ROM that loads it), and boot.ipxe is the iPXE script. armv7a
+// boards' U-Boot instead loads the FIT image itself, with its `pxe`
+// command, which reads the same pxelinux.cfg/default.
+
+use image::GRUB_BIOS_DIR;
+use passes::{Arch, BuildState, ComposeError, SystemState};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use syshelpers::{emit_file, io_error, reset_dir};
+
+// Where, within netboot/, grub-mknetdir installs grub, and its
+// network image for BIOSes.
+const GRUB_SUBDIR: &str = "boot/grub";
+const GRUB_CORE: &str = "boot/grub/i386-pc/core.0";
+
+fn mknetdir(netdir: &String) -> Result<(), ComposeError> {
+    // distributions name grub-mknetdir differently
+    for prog in ["grub-mknetdir", "grub2-mknetdir"].iter() {
+        let mut cmd = Command::new(prog);
+        if Path::new(GRUB_BIOS_DIR).is_dir() {
+            cmd.arg("-d").arg(GRUB_BIOS_DIR);
+        }
+        let out = match cmd
+            .arg(format!("--net-directory={}", netdir))
+            .arg(format!("--subdir={}", GRUB_SUBDIR))
+            .output()
+        {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(io_error(netdir, &format!("execute {}", prog), e)),
+            Ok(o) => o,
+        };
+        if !out.status.success() {
+            return Err(ComposeError::Io {
+                path: netdir.clone(),
+                msg: format!(
+                    "{} failed to install grub's network image:\n{}",
+                    prog,
+                    String::from_utf8_lossy(&out.stderr)
+                ),
+            });
+        }
+        // Without grub's BIOS modules, it installs only the others.
+        if !Path::new(netdir).join(GRUB_CORE).is_file() {
+            return Err(ComposeError::Io {
+                path: netdir.clone(),
+                msg: format!(
+                    "{} didn't install grub's network image for BIOSes ({}). Install grub's BIOS modules (e.g. grub-pc-bin, or grub2-pc-modules), in {}.",
+                    prog, GRUB_CORE, GRUB_BIOS_DIR
+                ),
+            });
+        }
+        return Ok(());
+    }
+
+    Err(ComposeError::Io {
+        path: netdir.clone(),
+        msg: String::from(
+            "Cannot find grub-mknetdir or grub2-mknetdir to install grub's network image",
+        ),
+    })
+}
+
+// Create netboot/, in the build directory, with the system's `image`,
+// and return the paths of the files generated in it.
+pub fn netboot_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+    image: &String,
+) -> Result<Vec<String>, ComposeError> {
+    let netdir = b.file_path(&"netboot".to_string())?;
+    let layout = s.get_spec().netboot();
+    reset_dir(&netdir)?;
+
+    let name = Path::new(image)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or(image.clone());
+    // The image's path within the served directory, and its URL
+    // path, which both TFTP and HTTP clients resolve from the root.
+    let img_path = Path::new(&layout.dir).join(&name).display().to_string();
+    let img = Path::new(&netdir).join(&img_path).display().to_string();
+    fs::create_dir_all(Path::new(&img).parent().unwrap())
+        .map_err(|e| io_error(&img, "create the image's directory", e))?;
+    fs::copy(image, &img).map_err(|e| io_error(&img, "copy the system image", e))?;
+    let mut files = vec![img];
+
+    let pxelinux = format!("{}/pxelinux.cfg/default", netdir);
+    fs::create_dir_all(format!("{}/pxelinux.cfg", netdir))
+        .map_err(|e| io_error(&pxelinux, "create the pxelinux directory", e))?;
+    if s.get_spec().arch() == Arch::Armv7a {
+        emit_file(
+            &pxelinux,
+            format!(
+                "DEFAULT composite\nLABEL composite\n  KERNEL /{}\n",
+                img_path
+            )
+            .as_bytes(),
+        )?;
+        files.push(pxelinux);
+        return Ok(files);
+    }
+
+    mknetdir(&netdir)?;
+    let cfg = format!("{}/{}/grub.cfg", netdir, GRUB_SUBDIR);
+    emit_file(
+        &cfg,
+        format!(
+            "set timeout=0\nset default=0\nmenuentry \"composite\" {{\n  multiboot2 /{}\n}}\n",
+            img_path
+        )
+        .as_bytes(),
+    )?;
+    // pxelinux loads .0 files as PXE network bootstrap programs.
+    emit_file(
+        &pxelinux,
+        format!(
+            "DEFAULT composite\nLABEL composite\n  KERNEL {}\n",
+            GRUB_CORE
+        )
+        .as_bytes(),
+    )?;
+    // Without a URL, iPXE fetches grub by TFTP from the server that
+    // DHCP named, as grub then fetches the kernel.
+    let ipxe = format!("{}/boot.ipxe", netdir);
+    let base = match layout.url {
+        Some(ref u) => u.trim_end_matches('/').to_string(),
+        None => String::from("tftp://${next-server}"),
+    };
+    emit_file(
+        &ipxe,
+        format!("#!ipxe\ndhcp\nchain {}/{}\n", base, GRUB_CORE).as_bytes(),
+    )?;
+    files.extend(vec![cfg, pxelinux, ipxe]);
+
+    Ok(files)
+}
@@ -0,0 +1,727 @@
+// Command-line processing for the composer. By default, the two
+// positional arguments (the sysspec, and the name of the build) are
+// required, and the remaining flags tweak how the build is carried
+// out. Subcommands, named by the first argument, instead perform
+// other operations on the sysspec. `test` takes any number of
+// sysspecs, each built under the name test-<sysspec>, `build --all`
+// takes a workspace (listing the sysspecs of its compositions)
+// instead of a sysspec and build name, `explain` takes the component
+// to explain after the build's name, `clean --all-stale` takes no
+// arguments, `inspect` takes a built image (a kernel image,
+// component, or tarball) instead of a sysspec, and `mkimg` takes the
+// kind of image to create (`disk`, `pxe`, or `split`) first, `flash`
+// to program the board with the system, `serve` to serve the build's
+// network-boot files (from `pxe`), or `watch` to rebuild the system
+// (and, with `--run`, reboot it) as its sources change. With `--matrix`,
+// building (or sizing) builds each configuration of the axes, each
+// under <buildname>-<value>[-<value>...].
+
+use log::LevelFilter;
+use passes::{Arch, CompilerCache, ImageFormat, Profile};
+use serve::{HTTP_PORT, TFTP_PORT};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The percent that a component's build time, or size, can grow from
+// its past builds' before it's warned about.
+const REGRESSION_THRESHOLD: u64 = 25;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Build,    // build the system image
+    Graph,    // print the dependency graph (in DOT)
+    Run,      // build the system image, and boot it in qemu
+    Test,     // build and boot each sysspec, checking its serial output
+    Size,     // build the system image, and report the components' sizes
+    Validate, // check the sysspec (against the source tree), without building
+    Explain,  // print what one component's build resolves to, without building
+    Clean,    // remove the build's directory (or, with --all-stale, all unused ones)
+    Inspect,  // list the contents of a built image, without its sysspec
+    Mkimg,    // build the system image, and a disk or network-boot image with it
+}
+
+// What `mkimg` creates, or serves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mkimg {
+    Disk,  // a partitioned disk image
+    Pxe,   // the files to network-boot from, in the build's netboot/
+    Split, // an image of each of the spec's memory regions, in regions/
+    Flash, // program the board with the system, with a profile of its configuration
+    Serve, // serve netboot/ by TFTP and HTTP, without building
+    Watch, // rebuild the system image whenever its sources change
+}
+
+impl Mkimg {
+    // Does the machine's loader boot what it creates (from the disk,
+    // or the network), rather than qemu, or the board's flash?
+    pub fn loaded(&self) -> bool {
+        match self {
+            Mkimg::Disk | Mkimg::Pxe | Mkimg::Serve => true,
+            Mkimg::Split | Mkimg::Flash | Mkimg::Watch => false,
+        }
+    }
+}
+
+// How the results of the commands are printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json, // on stdout, errors included; the log's diagnostics are on stderr
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub command: Command,
+    pub spec: String, // path to the system specification (or the image to inspect)
+    pub specs: Vec<String>, // all of the specifications (several only when testing)
+    pub name: String, // name of the build (and its directory), empty if not building
+    pub component: String, // the component to explain, empty otherwise
+    pub jobs: usize,  // number of components to build concurrently
+    pub rebuild: bool, // rebuild all components, ignoring cached objects
+    pub dry_run: bool, // print the build commands instead of executing them
+    pub keep_going: bool, // build as many components as possible despite failures
+    pub wait: bool,   // wait for the other build in the source tree to finish
+    pub require_clean: bool, // refuse to build from a source tree with uncommitted changes
+    pub locked: bool, // fail if the resolution differs from the specification's lockfile
+    pub build_dir: Option<String>, // overrides the specification's, and the default, build directory
+    pub src_root: Option<String>,  // root of the composite source tree, overriding $COSROOT
+    pub timeout: Option<u64>,      // seconds each component's build can take before it is killed
+    pub profile: Option<Profile>,  // overrides the specification's build profile
+    pub sign_key: Option<String>,  // overrides the specification's signing key
+    pub strip: bool,               // pack stripped objects, keeping their debugging information
+    pub shared_layout: bool, // lay the components' duplicated read-only contents out in a shared segment
+    pub compiler_cache: Option<CompilerCache>, // overrides the specification's compiler cache
+    pub compile_commands: bool, // record the components' compilations in compile_commands.json
+    pub image: ImageFormat,  // the bootable image(s) to generate
+    pub mkimg: Option<Mkimg>, // with the mkimg command, what it creates
+    pub tftp_port: u16,      // that mkimg serve serves TFTP on (0 to not)
+    pub http_port: u16,      // that mkimg serve serves HTTP on (0 to not)
+    pub flash_profile: Option<String>, // of the board's, that mkimg flash uses (its first by default)
+    pub watch_run: bool, // with mkimg watch, reboot the system in qemu after each build
+    pub gdb: bool,       // when running, wait for gdb to attach to qemu's gdbstub
+    pub regression_threshold: u64, // percent growth in a component's build time or size to warn about
+    pub env_vars: bool,            // define the environment's variables in the spec
+    pub prune_unreachable: bool,   // drop the servers that no application (transitively) uses
+    pub all_stale: bool,           // clean all of the build directories not in use
+    pub all: bool,                 // build each of the workspace's compositions
+    // --matrix axis=value,..., the axes (arch, profile, or feature) of
+    // the configurations to build, in order
+    pub matrix: Vec<(String, Vec<String>)>,
+    pub log_level: LevelFilter, // of the diagnostics printed, from -v and --quiet
+    pub format: Format,         // of the results, and errors, printed
+    // -D name=value, defining ${name} in the specification
+    pub defines: BTreeMap<String, String>,
+    // -D component.field=value, overriding the specification's fields, in order
+    pub overrides: Vec<(String, String)>,
+    // --feature name, selecting the specification's conditional sections
+    pub features: BTreeSet<String>,
+}
+
+pub fn usage(program_name: &String) -> String {
+    format!(
+        "usage: {0} [options] <sysspec>.toml <buildname>\n\
+         \x20      {0} build --all [options] <workspace>.toml\n\
+         \x20      {0} graph <sysspec>.toml\n\
+         \x20      {0} validate <sysspec>.toml\n\
+         \x20      {0} explain [options] <sysspec>.toml <buildname> <component>\n\
+         \x20      {0} clean [options] <sysspec>.toml <buildname>\n\
+         \x20      {0} clean --all-stale [--build-dir <dir>]\n\
+         \x20      {0} run [options] <sysspec>.toml <buildname>\n\
+         \x20      {0} inspect <image>\n\
+         \x20      {0} mkimg <disk|pxe|split|flash|serve|watch> [options] <sysspec>.toml <buildname>\n\
+         options:\n\
+         \t-j, --jobs <N>\tbuild up to N independent components concurrently (default: 1)\n\
+         \t--rebuild\trebuild all components, even those whose inputs are unchanged\n\
+         \t--dry-run\tprint the build commands and generated files without building\n\
+         \t-k, --keep-going\tafter a component fails to build, continue building the others\n\
+         \t--wait\tif another build is using the source tree, wait for it to finish (default: fail)\n\
+         \t--require-clean\trefuse to build if the source tree has uncommitted changes\n\
+         \t--locked\tfail if the resolution (servers, variants, libraries, addresses, and ids) differs from that in the specification's lockfile (<sysspec>.lock), rather than updating it\n\
+         \t--build-dir <dir>\tbuild in <dir> (default: system_binaries/cos_build-<buildname>)\n\
+         \t--src-root <dir>\tthe root of the composite source tree (default: $COSROOT, or the current directory)\n\
+         \t--timeout <secs>\tkill a component's build if it takes longer than secs (default: no timeout)\n\
+         \t--profile <debug|release>\tbuild the components unoptimized with debugging information, or optimized\n\
+         \t--sign-key <key.pem>\tsign the components and the constructor's tarball with the ed25519 private key\n\
+         \t--strip\tpack the components stripped into the image, keeping their symbols and debugging information in <object>.debug in the build directory\n\
+         \t--shared-layout\talso lay the read-only contents that components duplicate (reported in dedup.txt) out in a shared segment, in dedup.json\n\
+         \t--image, --output <kernel|grub|iso|fit>\talso generate a grub.cfg to boot the kernel image, or a bootable ISO of both, checked to boot from BIOSes and USB sticks, or (for armv7a) a U-Boot FIT image of the kernel and system.dtb (default: kernel)\n\
+         \t--tftp-port <port>\tfor mkimg serve, serve the network-boot files by TFTP on the port, or not if 0 (default: 69, which requires root)\n\
+         \t--http-port <port>\tfor mkimg serve, serve the network-boot files by HTTP on the port, or not if 0 (default: 8080)\n\
+         \t--flash-profile <name>\tfor mkimg flash, program the board with the profile of its configuration (default: its first)\n\
+         \t--run\tfor mkimg watch, boot the system in qemu after each build, stopping it when the sources change\n\
+         \t--compiler-cache <ccache|sccache>\tcompile the components through the compiler cache, and report its hits and misses\n\
+         \t--compile-commands\trecord the components' compilations in compile_commands.json in the build directory, for clangd and other tools\n\
+         \t--gdb\twhen running, start qemu's gdbstub (on port 1234) and wait for gdb to attach\n\
+         \t--regression-threshold <percent>\twarn if a component's build takes, or its object is, more than percent larger than in its past builds (default: 25)\n\
+         \t-D <name>=<value>\tdefine ${{name}} in the specification, overriding its [vars] and the environment\n\
+         \t-D <component>.<field>=<value>\toverride a field of a component (or of the system), e.g. ping.baseaddr=0x1600000, or a parameter, e.g. ping.params.loglevel=3\n\
+         \t--env-vars\tdefine the environment's variables in the specification, overriding its [vars]\n\
+         \t-v, --verbose\talso print the commands executed, and (if given twice) their output\n\
+         \t-q, --quiet\tonly print warnings and errors\n\
+         \t--format <text|json>\tprint the results (and errors) as text, or as JSON for other tools (default: text)\n\
+         \t--matrix <axis>=<value>[,<value>...]\tbuild each combination of the axes' values (arch, profile, or feature; `-` for no feature), concurrently, and report their sizes\n\
+         \t--all\tbuild each of the compositions listed in the workspace, under its name (default: the stem of its sysspec)\n\
+         \t--all-stale\twhen cleaning, remove each build directory (beside --build-dir, or in system_binaries/) whose build is not running\n\
+         \t--prune-unreachable\tdrop the components that no application (nor the booter) depends on, even indirectly\n\
+         \t--feature <name>[,<name>...]\tenable the feature(s), selecting the specification's sections with a `when` that requires them",
+        program_name
+    )
+}
+
+// Retrieve the value for a flag that requires one.
+fn flag_value(flag: &String, args: &mut dyn Iterator<Item = String>) -> Result<String, String> {
+    args.next()
+        .ok_or(format!("Error: option {} requires a value.", flag))
+}
+
+fn parse_timeout(flag: &String, val: &String) -> Result<u64, String> {
+    match val.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "Error: option {} requires a positive number of seconds, not \"{}\".",
+            flag, val
+        )),
+    }
+}
+
+fn parse_profile(flag: &String, val: &String) -> Result<Profile, String> {
+    Profile::from_name(val).ok_or(format!(
+        "Error: option {} requires \"debug\" or \"release\", not \"{}\".",
+        flag, val
+    ))
+}
+
+fn parse_compiler_cache(flag: &String, val: &String) -> Result<CompilerCache, String> {
+    CompilerCache::from_name(val).ok_or(format!(
+        "Error: option {} requires \"ccache\" or \"sccache\", not \"{}\".",
+        flag, val
+    ))
+}
+
+fn parse_image(flag: &String, val: &String) -> Result<ImageFormat, String> {
+    ImageFormat::from_name(val).ok_or(format!(
+        "Error: option {} requires \"kernel\", \"grub\", \"iso\", or \"fit\", not \"{}\".",
+        flag, val
+    ))
+}
+
+// A variable's definition, or (if the name is a dotted path into the
+// specification) an override.
+fn parse_define(flag: &String, val: &String) -> Result<(String, String), String> {
+    let mut kv = val.splitn(2, '=');
+    match (kv.next(), kv.next()) {
+        (Some(k), Some(v))
+            if !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            Ok((k.to_string(), v.to_string()))
+        }
+        (Some(k), Some(v)) if k.contains('.') && k.split('.').all(|p| !p.is_empty()) => {
+            Ok((k.to_string(), v.to_string()))
+        }
+        _ => Err(format!(
+            "Error: option {} requires a variable definition, <name>=<value>, or an override, <component>.<field>=<value>, not \"{}\".",
+            flag, val
+        )),
+    }
+}
+
+// A comma-separated list of feature names.
+fn parse_features(flag: &String, val: &String) -> Result<Vec<String>, String> {
+    let fs: Vec<String> = val.split(',').map(|f| f.trim().to_string()).collect();
+    if fs.iter().any(|f| f.is_empty()) {
+        return Err(format!(
+            "Error: option {} requires feature names, separated by commas, not \"{}\".",
+            flag, val
+        ));
+    }
+
+    Ok(fs)
+}
+
+// An axis of the matrix of configurations, and its values.
+fn parse_axis(flag: &String, val: &String) -> Result<(String, Vec<String>), String> {
+    let mut kv = val.splitn(2, '=');
+    let (axis, vals) = match (kv.next(), kv.next()) {
+        (Some(a), Some(vs)) => (
+            a.trim().to_string(),
+            vs.split(',')
+                .map(|v| v.trim().to_string())
+                .collect::<Vec<String>>(),
+        ),
+        _ => {
+            return Err(format!(
+                "Error: option {} requires <axis>=<value>[,<value>...], not \"{}\".",
+                flag, val
+            ))
+        }
+    };
+    let valid = |v: &String| match axis.as_str() {
+        "arch" => Arch::from_name(v).is_some(),
+        "profile" => Profile::from_name(v).is_some(),
+        "feature" => !v.is_empty(),
+        _ => false,
+    };
+    if let Some(v) = vals.iter().find(|v| !valid(v)) {
+        return Err(match axis.as_str() {
+            "arch" | "profile" | "feature" => {
+                format!("Error: option {} has the invalid {} \"{}\".", flag, axis, v)
+            }
+            _ => format!(
+                "Error: option {} requires the axis arch, profile, or feature, not \"{}\".",
+                flag, axis
+            ),
+        });
+    }
+    let mut uniq = vals.clone();
+    uniq.sort();
+    uniq.dedup();
+    if uniq.len() != vals.len() {
+        return Err(format!(
+            "Error: option {} lists a {} more than once in \"{}\".",
+            flag, axis, val
+        ));
+    }
+
+    Ok((axis, vals))
+}
+
+fn parse_format(flag: &String, val: &String) -> Result<Format, String> {
+    match val.as_str() {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        _ => Err(format!(
+            "Error: option {} requires \"text\" or \"json\", not \"{}\".",
+            flag, val
+        )),
+    }
+}
+
+fn parse_port(flag: &String, val: &String) -> Result<u16, String> {
+    val.parse::<u16>().map_err(|_| {
+        format!(
+            "Error: option {} requires a port number (or 0), not \"{}\".",
+            flag, val
+        )
+    })
+}
+
+fn parse_percent(flag: &String, val: &String) -> Result<u64, String> {
+    match val.trim_end_matches('%').parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "Error: option {} requires a positive percentage, not \"{}\".",
+            flag, val
+        )),
+    }
+}
+
+fn parse_jobs(flag: &String, val: &String) -> Result<usize, String> {
+    match val.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "Error: option {} requires a positive integer, not \"{}\".",
+            flag, val
+        )),
+    }
+}
+
+impl Options {
+    // The options to build `spec` as build `name` with the defaults,
+    // for tools using the composer as a library.
+    pub fn new(spec: &String, name: &String) -> Options {
+        Options {
+            command: Command::Build,
+            spec: spec.clone(),
+            specs: vec![spec.clone()],
+            name: name.clone(),
+            component: String::new(),
+            jobs: 1,
+            rebuild: false,
+            dry_run: false,
+            keep_going: false,
+            wait: false,
+            require_clean: false,
+            locked: false,
+            build_dir: None,
+            src_root: None,
+            timeout: None,
+            profile: None,
+            sign_key: None,
+            strip: false,
+            shared_layout: false,
+            compiler_cache: None,
+            compile_commands: false,
+            image: ImageFormat::Kernel,
+            mkimg: None,
+            tftp_port: TFTP_PORT,
+            http_port: HTTP_PORT,
+            flash_profile: None,
+            watch_run: false,
+            gdb: false,
+            regression_threshold: REGRESSION_THRESHOLD,
+            defines: BTreeMap::new(),
+            overrides: Vec::new(),
+            features: BTreeSet::new(),
+            env_vars: false,
+            prune_unreachable: false,
+            all_stale: false,
+            all: false,
+            matrix: Vec::new(),
+            log_level: LevelFilter::Info,
+            format: Format::Text,
+        }
+    }
+
+    // Parse the arguments, not including the program name.
+    pub fn parse(program_name: &String, args: Vec<String>) -> Result<Options, String> {
+        let mut positional = Vec::new();
+        let mut jobs = 1;
+        let mut rebuild = false;
+        let mut dry_run = false;
+        let mut keep_going = false;
+        let mut wait = false;
+        let mut require_clean = false;
+        let mut locked = false;
+        let mut build_dir = None;
+        let mut src_root = None;
+        let mut timeout = None;
+        let mut profile = None;
+        let mut sign_key = None;
+        let mut strip = false;
+        let mut shared_layout = false;
+        let mut compiler_cache = None;
+        let mut compile_commands = false;
+        let mut image = ImageFormat::Kernel;
+        let mut tftp_port = TFTP_PORT;
+        let mut http_port = HTTP_PORT;
+        let mut flash_profile = None;
+        let mut watch_run = false;
+        let mut gdb = false;
+        let mut regression_threshold = REGRESSION_THRESHOLD;
+        let mut defines = BTreeMap::new();
+        let mut overrides = Vec::new();
+        let mut features = BTreeSet::new();
+        let mut env_vars = false;
+        let mut prune_unreachable = false;
+        let mut all_stale = false;
+        let mut all = false;
+        let mut matrix = Vec::new();
+        let mut log_level = LevelFilter::Info;
+        let mut format = Format::Text;
+        let mut iter = args.into_iter();
+
+        while let Some(arg) = iter.next() {
+            if arg == "-j" || arg == "--jobs" {
+                let val = flag_value(&arg, &mut iter)?;
+                jobs = parse_jobs(&arg, &val)?;
+            } else if let Some(v) = arg.strip_prefix("--jobs=") {
+                jobs = parse_jobs(&arg, &v.to_string())?;
+            } else if arg == "--rebuild" {
+                rebuild = true;
+            } else if arg == "--dry-run" {
+                dry_run = true;
+            } else if arg == "-k" || arg == "--keep-going" {
+                keep_going = true;
+            } else if arg == "--wait" {
+                wait = true;
+            } else if arg == "--require-clean" {
+                require_clean = true;
+            } else if arg == "--locked" {
+                locked = true;
+            } else if arg == "--build-dir" {
+                build_dir = Some(flag_value(&arg, &mut iter)?);
+            } else if let Some(v) = arg.strip_prefix("--build-dir=") {
+                build_dir = Some(v.to_string());
+            } else if arg == "--src-root" {
+                src_root = Some(flag_value(&arg, &mut iter)?);
+            } else if let Some(v) = arg.strip_prefix("--src-root=") {
+                src_root = Some(v.to_string());
+            } else if arg == "--timeout" {
+                let val = flag_value(&arg, &mut iter)?;
+                timeout = Some(parse_timeout(&arg, &val)?);
+            } else if let Some(v) = arg.strip_prefix("--timeout=") {
+                timeout = Some(parse_timeout(&arg, &v.to_string())?);
+            } else if arg == "--profile" {
+                let val = flag_value(&arg, &mut iter)?;
+                profile = Some(parse_profile(&arg, &val)?);
+            } else if let Some(v) = arg.strip_prefix("--profile=") {
+                profile = Some(parse_profile(&arg, &v.to_string())?);
+            } else if arg == "--sign-key" {
+                sign_key = Some(flag_value(&arg, &mut iter)?);
+            } else if let Some(v) = arg.strip_prefix("--sign-key=") {
+                sign_key = Some(v.to_string());
+            } else if arg == "--strip" {
+                strip = true;
+            } else if arg == "--shared-layout" {
+                shared_layout = true;
+            } else if arg == "--compile-commands" {
+                compile_commands = true;
+            } else if arg == "--compiler-cache" {
+                let val = flag_value(&arg, &mut iter)?;
+                compiler_cache = Some(parse_compiler_cache(&arg, &val)?);
+            } else if let Some(v) = arg.strip_prefix("--compiler-cache=") {
+                compiler_cache = Some(parse_compiler_cache(&arg, &v.to_string())?);
+            } else if arg == "--gdb" {
+                gdb = true;
+            } else if arg == "--image" || arg == "--output" {
+                let val = flag_value(&arg, &mut iter)?;
+                image = parse_image(&arg, &val)?;
+            } else if arg.starts_with("--image=") || arg.starts_with("--output=") {
+                let val = arg[arg.find('=').unwrap() + 1..].to_string();
+                image = parse_image(&arg, &val)?;
+            } else if arg == "--tftp-port" {
+                let val = flag_value(&arg, &mut iter)?;
+                tftp_port = parse_port(&arg, &val)?;
+            } else if let Some(v) = arg.strip_prefix("--tftp-port=") {
+                tftp_port = parse_port(&arg, &v.to_string())?;
+            } else if arg == "--http-port" {
+                let val = flag_value(&arg, &mut iter)?;
+                http_port = parse_port(&arg, &val)?;
+            } else if let Some(v) = arg.strip_prefix("--http-port=") {
+                http_port = parse_port(&arg, &v.to_string())?;
+            } else if arg == "--flash-profile" {
+                flash_profile = Some(flag_value(&arg, &mut iter)?);
+            } else if let Some(v) = arg.strip_prefix("--flash-profile=") {
+                flash_profile = Some(v.to_string());
+            } else if arg == "--regression-threshold" {
+                let val = flag_value(&arg, &mut iter)?;
+                regression_threshold = parse_percent(&arg, &val)?;
+            } else if let Some(v) = arg.strip_prefix("--regression-threshold=") {
+                regression_threshold = parse_percent(&arg, &v.to_string())?;
+            } else if arg == "--run" {
+                watch_run = true;
+            } else if let Some(v) = arg.strip_prefix("-D") {
+                let val = if v.is_empty() {
+                    flag_value(&arg, &mut iter)?
+                } else {
+                    v.to_string()
+                };
+                let (k, v) = parse_define(&arg, &val)?;
+                if k.contains('.') {
+                    overrides.push((k, v));
+                } else {
+                    defines.insert(k, v);
+                }
+            } else if arg == "--feature" {
+                let val = flag_value(&arg, &mut iter)?;
+                features.extend(parse_features(&arg, &val)?);
+            } else if let Some(v) = arg.strip_prefix("--feature=") {
+                features.extend(parse_features(&arg, &v.to_string())?);
+            } else if arg == "-v" || arg == "--verbose" {
+                log_level = match log_level {
+                    LevelFilter::Debug | LevelFilter::Trace => LevelFilter::Trace,
+                    _ => LevelFilter::Debug,
+                };
+            } else if arg == "-vv" {
+                log_level = LevelFilter::Trace;
+            } else if arg == "-q" || arg == "--quiet" {
+                log_level = LevelFilter::Warn;
+            } else if arg == "--format" {
+                let val = flag_value(&arg, &mut iter)?;
+                format = parse_format(&arg, &val)?;
+            } else if let Some(v) = arg.strip_prefix("--format=") {
+                format = parse_format(&arg, &v.to_string())?;
+            } else if arg == "--prune-unreachable" {
+                prune_unreachable = true;
+            } else if arg == "--all-stale" {
+                all_stale = true;
+            } else if arg == "--all" {
+                all = true;
+            } else if arg == "--matrix" {
+                let val = flag_value(&arg, &mut iter)?;
+                matrix.push(parse_axis(&arg, &val)?);
+            } else if let Some(v) = arg.strip_prefix("--matrix=") {
+                matrix.push(parse_axis(&arg, &v.to_string())?);
+            } else if arg == "--env-vars" {
+                env_vars = true;
+            } else if arg.starts_with("-") && arg.len() > 1 {
+                return Err(format!(
+                    "Error: unknown option {}.\n{}",
+                    arg,
+                    usage(program_name)
+                ));
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        // Building is the default, but can be named.
+        let named = positional.first().is_some_and(|p| p == "build");
+        let command = match positional.first().map(|p| p.as_str()) {
+            Some("graph") => Command::Graph,
+            Some("run") => Command::Run,
+            Some("test") => Command::Test,
+            Some("size") => Command::Size,
+            Some("validate") => Command::Validate,
+            Some("explain") => Command::Explain,
+            Some("clean") => Command::Clean,
+            Some("inspect") => Command::Inspect,
+            Some("mkimg") => Command::Mkimg,
+            _ => Command::Build,
+        };
+        if command != Command::Build || named {
+            positional.remove(0);
+        }
+        // mkimg creates a disk image, unless told otherwise.
+        let mkimg = match command {
+            Command::Mkimg if positional.len() == 3 => match positional.remove(0).as_str() {
+                "disk" => Some(Mkimg::Disk),
+                "pxe" => Some(Mkimg::Pxe),
+                "split" => Some(Mkimg::Split),
+                "flash" => Some(Mkimg::Flash),
+                "serve" => Some(Mkimg::Serve),
+                "watch" => Some(Mkimg::Watch),
+                kind => {
+                    return Err(format!(
+                        "Error: mkimg can create a \"disk\", \"pxe\", or \"split\" image, \"flash\" it, \"serve\" one, or \"watch\" the sources to rebuild it, not \"{}\".",
+                        kind
+                    ))
+                }
+            },
+            Command::Mkimg => Some(Mkimg::Disk),
+            _ => None,
+        };
+        let nargs_ok = match command {
+            Command::Build if all => positional.len() == 1,
+            Command::Build | Command::Run | Command::Size | Command::Mkimg => positional.len() == 2,
+            Command::Graph | Command::Validate | Command::Inspect => positional.len() == 1,
+            Command::Explain => positional.len() == 3,
+            Command::Clean if all_stale => positional.is_empty(),
+            Command::Clean => positional.len() == 2,
+            Command::Test => !positional.is_empty(),
+        };
+        if !nargs_ok {
+            return Err(usage(program_name));
+        }
+        // Each tested specification is built in its own directory.
+        if positional.len() > 1 && command == Command::Test && build_dir.is_some() {
+            return Err(String::from(
+                "Error: option --build-dir cannot be used when testing multiple specifications.",
+            ));
+        }
+        if mkimg == Some(Mkimg::Serve) && tftp_port == 0 && http_port == 0 {
+            return Err(String::from(
+                "Error: options --tftp-port and --http-port cannot both be 0 (serving nothing).",
+            ));
+        }
+        if flash_profile.is_some() && mkimg != Some(Mkimg::Flash) {
+            return Err(String::from(
+                "Error: option --flash-profile can only be used with mkimg flash.",
+            ));
+        }
+        if watch_run && mkimg != Some(Mkimg::Watch) {
+            return Err(String::from(
+                "Error: option --run can only be used with mkimg watch (to run the system once, use the run command).",
+            ));
+        }
+        // Watching builds whenever the sources change.
+        if dry_run && mkimg == Some(Mkimg::Watch) {
+            return Err(String::from(
+                "Error: option --dry-run cannot be used with mkimg watch.",
+            ));
+        }
+        if all_stale && command != Command::Clean {
+            return Err(String::from(
+                "Error: option --all-stale can only be used when cleaning.",
+            ));
+        }
+        if all && command != Command::Build {
+            return Err(String::from(
+                "Error: option --all can only be used when building.",
+            ));
+        }
+        if !matrix.is_empty() {
+            if (command != Command::Build && command != Command::Size) || all {
+                return Err(String::from(
+                    "Error: option --matrix can only be used when building (or sizing) one specification.",
+                ));
+            }
+            let mut axes: Vec<&String> = matrix.iter().map(|(a, _)| a).collect();
+            axes.sort();
+            axes.dedup();
+            if axes.len() != matrix.len() {
+                return Err(String::from(
+                    "Error: option --matrix can only be given once for each axis.",
+                ));
+            }
+            if profile.is_some() && axes.iter().any(|a| *a == "profile") {
+                return Err(String::from(
+                    "Error: options --profile and --matrix profile=... cannot be used together.",
+                ));
+            }
+        }
+        // Each composition (or configuration) is built in its own
+        // directory.
+        if (all || !matrix.is_empty()) && build_dir.is_some() {
+            return Err(String::from(
+                "Error: option --build-dir cannot be used when building several compositions (--all) or configurations (--matrix).",
+            ));
+        }
+        // Cleaning all of the stale builds requires no specification.
+        if positional.is_empty() {
+            positional.push(String::new());
+        }
+        let specs = if command == Command::Test {
+            positional.clone()
+        } else {
+            vec![positional[0].clone()]
+        };
+        let spec = positional.remove(0);
+        let component = match command {
+            Command::Explain => positional.pop().unwrap(),
+            _ => String::new(),
+        };
+        let name = match command {
+            Command::Build if all => String::new(),
+            Command::Build | Command::Run | Command::Size | Command::Mkimg | Command::Explain => {
+                positional.pop().unwrap()
+            }
+            Command::Clean if !all_stale => positional.pop().unwrap(),
+            _ => String::new(),
+        };
+        // Explaining only resolves the build, so it mustn't disturb
+        // a previous build of the same name.
+        let dry_run = dry_run || command == Command::Explain;
+
+        Ok(Options {
+            command,
+            spec,
+            specs,
+            name,
+            component,
+            jobs,
+            rebuild,
+            dry_run,
+            keep_going,
+            wait,
+            require_clean,
+            locked,
+            build_dir,
+            src_root,
+            timeout,
+            profile,
+            sign_key,
+            strip,
+            shared_layout,
+            compiler_cache,
+            compile_commands,
+            image,
+            mkimg,
+            tftp_port,
+            http_port,
+            flash_profile,
+            watch_run,
+            gdb,
+            regression_threshold,
+            defines,
+            overrides,
+            features,
+            env_vars,
+            prune_unreachable,
+            all_stale,
+            all,
+            matrix,
+            log_level,
+            format,
+        })
+    }
+}
@@ -11,15 +11,21 @@
 /// current state, and transforms it in some way (generating a new
 /// state of the same type). Thus, the linker/loader is simply a set
 /// of these phases composed together.
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use cossystem::ConstantVal;
+use analysis::Warning;
+use cossystem::{ConstantVal, InitfsEntry, SchedParams};
+use diagnostic::{diagnostic_render, similar, SpecLoc};
 use initargs::ArgsKV;
+use manifest::Json;
 use std::fmt;
-use analysis::Warning;
 
 pub struct SystemState {
     spec: String,
+    vars: BTreeMap<String, String>, // from outside of the spec, overriding its [vars]
+    overrides: Vec<(String, String)>, // of the spec's fields, as component.field = value
+    features: BTreeSet<String>,     // enabled, selecting the spec's conditional sections
+    prune_unreachable: bool,        // remove the components the system doesn't need
 
     parse: Option<Box<dyn SpecificationPass>>,
     named: Option<Box<dyn OrderedSpecPass>>,
@@ -39,6 +45,10 @@ impl SystemState {
     pub fn new(spec: String) -> SystemState {
         SystemState {
             spec,
+            vars: BTreeMap::new(),
+            overrides: Vec::new(),
+            features: BTreeSet::new(),
+            prune_unreachable: false,
             parse: None,
             named: None,
             address_assignment: None,
@@ -48,11 +58,27 @@ impl SystemState {
             objs: HashMap::new(),
             invs: HashMap::new(),
             constructor: None,
-	    graph: None,
-	    analysis: None,
+            graph: None,
+            analysis: None,
         }
     }
 
+    pub fn add_vars(&mut self, vars: BTreeMap<String, String>) {
+        self.vars = vars;
+    }
+
+    pub fn add_overrides(&mut self, overrides: Vec<(String, String)>) {
+        self.overrides = overrides;
+    }
+
+    pub fn add_features(&mut self, features: BTreeSet<String>) {
+        self.features = features;
+    }
+
+    pub fn add_prune_unreachable(&mut self, prune: bool) {
+        self.prune_unreachable = prune;
+    }
+
     pub fn add_parsed(&mut self, p: Box<dyn SpecificationPass>) {
         self.parse = Some(p);
     }
@@ -101,6 +127,22 @@ impl SystemState {
         self.spec.clone()
     }
 
+    pub fn get_vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    pub fn get_overrides(&self) -> &Vec<(String, String)> {
+        &self.overrides
+    }
+
+    pub fn get_features(&self) -> &BTreeSet<String> {
+        &self.features
+    }
+
+    pub fn get_prune_unreachable(&self) -> bool {
+        self.prune_unreachable
+    }
+
     pub fn get_spec(&self) -> &dyn SpecificationPass {
         &**(self.parse.as_ref().unwrap())
     }
@@ -151,32 +193,292 @@ impl SystemState {
 // that file already exists. Use unique names for files, and use the
 // component-namespacing of names for per-component files.
 pub trait BuildState {
-    fn initialize(&mut self, name: &String, s: &SystemState) -> Result<(), String>; // must be called *before* the following functions
-    fn file_path(&self, file: &String) -> Result<String, String>; // create a path in the build directory for a file
-    fn comp_dir_path(&self, c: &ComponentId, state: &SystemState) -> Result<String, String>; // the component's object
+    fn initialize(&mut self, name: &String, s: &SystemState) -> Result<(), ComposeError>; // must be called *before* the following functions
+    fn file_path(&self, file: &String) -> Result<String, ComposeError>; // create a path in the build directory for a file
+    fn comp_dir_path(&self, c: &ComponentId, state: &SystemState) -> Result<String, ComposeError>; // the component's object
     fn comp_file_path(
         &self,
         c: &ComponentId,
         file: &String,
         state: &SystemState,
-    ) -> Result<String, String>; // path of a file associated with a component
+    ) -> Result<String, ComposeError>; // path of a file associated with a component
     fn comp_obj_file(&self, c: &ComponentId, s: &SystemState) -> String; // name of the object file
-    fn comp_obj_path(&self, c: &ComponentId, s: &SystemState) -> Result<String, String>; // the path to the component's object
+    fn comp_obj_path(&self, c: &ComponentId, s: &SystemState) -> Result<String, ComposeError>; // the path to the component's object
     fn comp_const_header_file(
         &self,
         header_file_path: &String,
         id: &ComponentId,
         s: &SystemState,
-    ) -> Result<(), String>; // path of header file of component constants value
+    ) -> Result<(), ComposeError>; // path of header file of component constants value
 
-    fn comp_build(&self, c: &ComponentId, state: &SystemState) -> Result<String, String>; // build the component, and return the path to the resulting object
-    fn constructor_build(&self, c: &ComponentId, state: &SystemState) -> Result<String, String>; // build a constructor, including all components it is responsible for booting
+    fn comp_build(&self, c: &ComponentId, state: &SystemState) -> Result<String, ComposeError>; // build the component, and return the path to the resulting object
+    fn comps_build(
+        &mut self,
+        ids: &Vec<ComponentId>,
+        state: &SystemState,
+    ) -> Result<(), Vec<ComposeError>>; // build a set of components (concurrently, when possible) so that later comp_builds return their objects
+    fn constructor_build(
+        &self,
+        c: &ComponentId,
+        state: &SystemState,
+    ) -> Result<String, ComposeError>; // build a constructor, including all components it is responsible for booting
     fn kernel_build(
         &self,
         kern_output: &String,
         constructor_input: &String,
         s: &SystemState,
-    ) -> Result<(), String>; // build the final kernel image
+    ) -> Result<(), ComposeError>; // build the final kernel image
+}
+
+// The errors encountered while composing a system. Callers (and the
+// passes) can match on the kind of failure, while the Display
+// implementation provides the message for the user.
+#[derive(Clone, Debug)]
+pub enum ComposeError {
+    // the sysspec could not be read, or is not valid TOML
+    Parse {
+        path: String,
+        msg: String,
+    },
+    // the sysspec is inconsistent
+    Spec(String),
+    // the sysspec references a component that isn't defined, by
+    // `reference` (e.g. "dependency") in the `referrer` component;
+    // the `candidates` are the names it could reference
+    MissingComponent {
+        name: String,
+        referrer: String,
+        reference: String,
+        candidates: Vec<String>,
+    },
+    // a component depends on an interface that its server doesn't
+    // export
+    MissingInterface {
+        client: String,
+        interface: String,
+        server: String,
+        exports: Vec<String>,
+    },
+    // an error caused by the line of the sysspec at the location
+    Located(Box<ComposeError>, SpecLoc),
+    // all of the problems found while validating the sysspec
+    Invalid(Vec<ComposeError>),
+    // a file or directory couldn't be read, written, or created
+    Io {
+        path: String,
+        msg: String,
+    },
+    // a make invocation failed with an exit status (None if it was
+    // killed by a signal); the full output is in the log
+    Make {
+        name: String,
+        status: Option<i32>,
+        stderr: String,
+        log: String,
+    },
+    // a component's build took longer than the timeout (in
+    // seconds), so was killed
+    Timeout {
+        name: String,
+        secs: u64,
+        log: String,
+    },
+    // a component wasn't built, as the build of `failed`, which it
+    // depends on, failed (with --keep-going)
+    Skipped {
+        name: String,
+        failed: String,
+    },
+    // the components that failed to build (or were skipped), thus no
+    // image was created
+    Build(Vec<ComposeError>),
+    // the constructor's archive of components couldn't be created (or
+    // an archive read)
+    Tar {
+        path: String,
+        msg: String,
+    },
+    // the build was interrupted, so its directory is incomplete
+    Interrupted {
+        dir: String,
+    },
+    // what the build requires, but is missing from the host or the
+    // source tree (e.g. tools, or the tree's configuration)
+    Environment(Vec<String>),
+    // any other error found by a pass
+    Pass(String),
+}
+
+impl fmt::Display for ComposeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComposeError::Parse { path, msg } => {
+                write!(f, "Error when parsing the system specification {}:\n{}", path, msg)
+            }
+            ComposeError::Spec(e) | ComposeError::Pass(e) => write!(f, "{}", e),
+            ComposeError::MissingComponent {
+                name,
+                referrer,
+                reference,
+                candidates,
+            } => {
+                write!(
+                    f,
+                    "Error: Cannot find component referenced by {} {} in component {}.",
+                    reference, name, referrer
+                )?;
+                if let Some(c) = similar(name, candidates) {
+                    write!(f, " Did you mean {}?", c)?;
+                }
+                write!(f, " (It must be one of [{}].)", candidates.join(", "))
+            }
+            ComposeError::MissingInterface {
+                client,
+                interface,
+                server,
+                exports,
+            } => write!(
+                f,
+                "Error: Component {} depends on interface {} from server {}, but {} only exports [{}].",
+                client,
+                interface,
+                server,
+                server,
+                exports.join(", ")
+            ),
+            ComposeError::Located(e, loc) => {
+                write!(f, "{}", diagnostic_render(&e.to_string(), loc))
+            }
+            ComposeError::Invalid(es) => write!(
+                f,
+                "Error in system specification:\n{}",
+                es.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            ComposeError::Io { path, msg } => write!(f, "Error: {}: {}", path, msg),
+            ComposeError::Make {
+                name,
+                status,
+                stderr,
+                log,
+            } => {
+                // Only the tail of the errors is useful in a summary
+                let lines: Vec<&str> = stderr.lines().collect();
+                let tail = lines[lines.len().saturating_sub(10)..].join("\n\t");
+                write!(
+                    f,
+                    "Error: Component {} failed to build ({}). See {}.\n\t{}",
+                    name,
+                    match status {
+                        Some(s) => format!("exit status {}", s),
+                        None => "killed by a signal".to_string(),
+                    },
+                    log,
+                    tail
+                )
+            }
+            ComposeError::Timeout { name, secs, log } => write!(
+                f,
+                "Error: Component {} failed to build within the {} second timeout, so its build was killed. See {}.",
+                name, secs, log
+            ),
+            ComposeError::Skipped { name, failed } => write!(
+                f,
+                "Error: Component {} was skipped, as the build of {}, which it depends on, failed.",
+                name, failed
+            ),
+            ComposeError::Build(es) => {
+                let skipped = es
+                    .iter()
+                    .filter(|e| matches!(e, ComposeError::Skipped { .. }))
+                    .count();
+                write!(
+                    f,
+                    "{}\n{} component(s) failed to build{}; no system image created.",
+                    es.iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    es.len() - skipped,
+                    if skipped != 0 {
+                        format!(", and {} were skipped", skipped)
+                    } else {
+                        String::new()
+                    }
+                )
+            }
+            ComposeError::Interrupted { dir } => write!(
+                f,
+                "Error: The build was interrupted, so {} is incomplete.",
+                dir
+            ),
+            ComposeError::Tar { path, msg } => {
+                write!(f, "Error: Could not create archive {}: {}", path, msg)
+            }
+            ComposeError::Environment(missing) => write!(
+                f,
+                "Error: The build environment lacks what the build requires, so nothing was built:\n\t{}",
+                missing.join("\n\t")
+            ),
+        }
+    }
+}
+
+impl ComposeError {
+    // The errors as a list of diagnostics, for --format json. Each
+    // has the message, the kind of error, and (if known) the
+    // location in the sysspec and the log of the failed build.
+    pub fn to_json(&self) -> Json {
+        Json::Arr(self.diagnostics(None))
+    }
+
+    fn diagnostics(&self, loc: Option<&SpecLoc>) -> Vec<Json> {
+        let diag = |kind: &str, log: Option<&String>| {
+            vec![Json::obj(vec![
+                ("kind", Json::str(kind)),
+                ("message", Json::Str(self.to_string())),
+                (
+                    "location",
+                    loc.map_or(Json::Null, |l| {
+                        Json::obj(vec![
+                            ("path", Json::str(&l.path)),
+                            ("line", Json::Num(l.line as u64)),
+                            ("column", Json::Num(l.col as u64)),
+                        ])
+                    }),
+                ),
+                ("log", log.map_or(Json::Null, |l| Json::str(l))),
+            ])]
+        };
+        match self {
+            ComposeError::Invalid(es) | ComposeError::Build(es) => {
+                es.iter().flat_map(|e| e.diagnostics(None)).collect()
+            }
+            ComposeError::Located(e, l) => e.diagnostics(Some(l)),
+            ComposeError::Parse { .. } => diag("parse", None),
+            ComposeError::Spec(_) => diag("spec", None),
+            ComposeError::MissingComponent { .. } => diag("missing_component", None),
+            ComposeError::MissingInterface { .. } => diag("missing_interface", None),
+            ComposeError::Io { .. } => diag("io", None),
+            ComposeError::Make { log, .. } => diag("make", Some(log)),
+            ComposeError::Timeout { log, .. } => diag("timeout", Some(log)),
+            ComposeError::Skipped { .. } => diag("skipped", None),
+            ComposeError::Tar { .. } => diag("tar", None),
+            ComposeError::Interrupted { .. } => diag("interrupted", None),
+            ComposeError::Environment(_) => diag("environment", None),
+            ComposeError::Pass(_) => diag("pass", None),
+        }
+    }
+}
+
+// Many passes, and their helpers, still describe their errors as
+// strings.
+impl From<String> for ComposeError {
+    fn from(e: String) -> ComposeError {
+        ComposeError::Pass(e)
+    }
 }
 
 // The following describes the means of transitioning the system
@@ -186,7 +488,7 @@ pub trait BuildState {
 // Clean state transitions directly between entire states of
 // processing.
 pub trait Transition {
-    fn transition(c: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, String>;
+    fn transition(c: &SystemState, b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError>;
 }
 
 // Transitions between states, component at a time. Ordered from most
@@ -199,7 +501,7 @@ pub trait TransitionIter {
         id: &ComponentId,
         s: &SystemState,
         b: &mut dyn BuildState,
-    ) -> Result<Box<Self>, String>;
+    ) -> Result<Box<Self>, ComposeError>;
 }
 
 // What follows is a description of each of the passes and their
@@ -243,11 +545,32 @@ pub struct Component {
     pub constructor: ComponentName, // the constructor that loads this component
     pub scheduler: ComponentName,   // our scheduler (that creates or initial thread)
 
-    pub source: String,      // Where is the component source located?
-    pub base_vaddr: String, // The lowest virtual address for the component -- could be hex, so not a VAddr
-    pub params: Vec<ArgsKV>, // initialization parameters
-    pub fsimg: Option<String>,
+    pub source: String,             // Where is the component source located?
+    pub arch: Arch,                 // the system's, unless overridden for the component
+    pub base_vaddr: Option<String>, // The lowest virtual address for the component, if pinned by the spec -- could be hex, so not a VAddr
+    pub params: Vec<ArgsKV>,        // initialization parameters
+    pub globals: Vec<ArgsKV>,       // the global parameters, with the component's overrides
+    pub initfs: Vec<InitfsEntry>,   // host files to include in the component's tarball
     pub constants: Vec<ConstantVal>,
+    pub cflags: Option<String>, // appended to the component's CFLAGS
+    pub make_vars: BTreeMap<String, String>, // passed verbatim to the component's make
+    pub max_size: Option<u64>,  // budget for the sealed object's text + data + bss
+    pub initargs_format: InitargsFormat,
+    pub max_initargs: Option<u64>, // budget for the serialized initargs
+    pub sched: Option<SchedParams>, // passed to our scheduler
+    pub caps: Option<CapDelegations>, // delegated by our capability manager
+    pub pinned_id: Option<ComponentId>, // our id, if the specification pins it
+    pub affinity: Vec<u64>,        // the cores we're initialized on
+}
+
+// The capabilities that the specification delegates to a component
+// through its capability manager.
+#[derive(Clone, Debug)]
+pub struct CapDelegations {
+    pub memory: Vec<(u64, u64)>, // physical memory, as (base, size) ranges
+    pub cores: Vec<u64>,
+    pub irqs: Vec<u64>,
+    pub untyped: Option<u64>, // a quota of untyped memory, in bytes
 }
 
 // Input/frontend pass taking the specification, and outputing the
@@ -271,6 +594,336 @@ pub struct AddrSpace {
 
 pub type AddrSpaces = HashMap<AddrSpcName, AddrSpace>;
 
+// The archive format of the constructor's initfs: tar, cpio (the
+// "newc" format), or a flat blob indexed by an offset table (see
+// archive.rs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Cpio,
+    Flat,
+}
+
+impl ArchiveFormat {
+    pub fn from_name(name: &str) -> Option<ArchiveFormat> {
+        match name {
+            "tar" => Some(ArchiveFormat::Tar),
+            "cpio" => Some(ArchiveFormat::Cpio),
+            "flat" => Some(ArchiveFormat::Flat),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::Cpio => "cpio",
+            ArchiveFormat::Flat => "flat",
+        }
+    }
+}
+
+// How a component's initargs are represented: as C data-structures
+// compiled into it, or in a binary TLV encoding (see ArgsKV::tlv)
+// packed into its tarball.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitargsFormat {
+    C,
+    Tlv,
+}
+
+impl InitargsFormat {
+    pub fn from_name(name: &str) -> Option<InitargsFormat> {
+        match name {
+            "c" => Some(InitargsFormat::C),
+            "tlv" => Some(InitargsFormat::Tlv),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InitargsFormat::C => "c",
+            InitargsFormat::Tlv => "tlv",
+        }
+    }
+}
+
+// The build profile selects the compiler optimizations and debugging
+// information of the components.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Debug,
+    Release,
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name {
+            "debug" => Some(Profile::Debug),
+            "release" => Some(Profile::Release),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Debug => "debug",
+            Profile::Release => "release",
+        }
+    }
+
+    // Replaces the default optimization flags (OPT in
+    // Makefile.comp).
+    pub fn cflags(&self) -> &'static str {
+        match self {
+            Profile::Debug => "-O0 -g -fvar-tracking",
+            Profile::Release => "-O2",
+        }
+    }
+
+    pub fn ldflags(&self) -> &'static str {
+        match self {
+            Profile::Debug => "",
+            Profile::Release => "--strip-debug",
+        }
+    }
+}
+
+// The form of the bootable system image that is output: only the
+// kernel (with the constructor linked in), additionally a directory
+// with the grub configuration to multiboot2-load it, or also an ISO
+// of that directory, or (for ARM boards) a U-Boot FIT image of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Kernel,
+    Grub,
+    Iso,
+    Fit,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "kernel" => Some(ImageFormat::Kernel),
+            "grub" => Some(ImageFormat::Grub),
+            "iso" => Some(ImageFormat::Iso),
+            "fit" => Some(ImageFormat::Fit),
+            _ => None,
+        }
+    }
+}
+
+// The partitions of the disk image that `mkimg disk` creates.
+#[derive(Clone, Debug)]
+pub struct DiskLayout {
+    pub boot_size: u64,          // of the FAT32 boot partition
+    pub data_size: Option<u64>,  // of the data partition, if any
+    pub boot_files: Vec<String>, // copied into the boot partition
+}
+
+// The flash and RAM of execute-in-place (XIP) layouts: components'
+// read-only segments are in the flash, and their writable segments
+// are copied (and zeroed) into the RAM.
+#[derive(Clone, Debug)]
+pub struct XipLayout {
+    pub flash_base: u64,
+    pub flash_size: u64,
+    pub ram_base: u64,
+    pub ram_size: u64,
+}
+
+// The kinds of a board's memory regions: the (non-volatile) flash
+// that holds the system, and the RAM that it runs in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Flash,
+    Ram,
+}
+
+impl RegionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegionKind::Flash => "flash",
+            RegionKind::Ram => "ram",
+        }
+    }
+}
+
+// A region of the board's memory, that `mkimg split` assigns the
+// system's segments and components to.
+#[derive(Clone, Debug)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub kind: RegionKind,
+    pub base: u64,
+    pub size: u64,
+}
+
+// The tools that `mkimg flash` programs boards with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashTool {
+    Openocd,
+    ProbeRs,
+    DfuUtil,
+}
+
+impl FlashTool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlashTool::Openocd => "openocd",
+            FlashTool::ProbeRs => "probe-rs",
+            FlashTool::DfuUtil => "dfu-util",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FlashTool> {
+        match name {
+            "openocd" => Some(FlashTool::Openocd),
+            "probe-rs" => Some(FlashTool::ProbeRs),
+            "dfu-util" => Some(FlashTool::DfuUtil),
+            _ => None,
+        }
+    }
+}
+
+// A profile, in the board's configuration, for programming it with
+// `mkimg flash`: the tool, its (board-specific) arguments, and the
+// memory region whose image (from `mkimg split`) is written, rather
+// than the kernel image.
+#[derive(Clone, Debug)]
+pub struct FlashProfile {
+    pub name: String,
+    pub tool: FlashTool,
+    pub args: Vec<String>,
+    pub region: Option<String>,
+    pub verify: bool, // read the image back, after writing it
+}
+
+// Where `mkimg pxe` places the system's image in the netboot
+// directory, and the URL it's served from by HTTP, if it is.
+#[derive(Clone, Debug)]
+pub struct NetbootLayout {
+    pub dir: String, // relative to the netboot directory
+    pub url: Option<String>,
+}
+
+// The compiler cache that the components' compilations go through,
+// so that unchanged sources are not recompiled across builds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompilerCache {
+    Ccache,
+    Sccache,
+}
+
+impl CompilerCache {
+    pub fn from_name(name: &str) -> Option<CompilerCache> {
+        match name {
+            "ccache" => Some(CompilerCache::Ccache),
+            "sccache" => Some(CompilerCache::Sccache),
+            _ => None,
+        }
+    }
+
+    // Also the command that runs the cache.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompilerCache::Ccache => "ccache",
+            CompilerCache::Sccache => "sccache",
+        }
+    }
+}
+
+// The architectures that components can be built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    I386,
+    Armv7a,
+    Riscv64,
+}
+
+impl Arch {
+    pub fn from_name(name: &str) -> Option<Arch> {
+        match name {
+            "x86_64" => Some(Arch::X86_64),
+            "i386" => Some(Arch::I386),
+            "armv7a" => Some(Arch::Armv7a),
+            "riscv64" => Some(Arch::Riscv64),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::I386 => "i386",
+            Arch::Armv7a => "armv7a",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    // The toolchain prefix (make's CROSS_COMPILE) for the
+    // architecture. The x86 variants use the host's toolchain.
+    pub fn cross_compile(&self) -> &'static str {
+        match self {
+            Arch::X86_64 | Arch::I386 => "",
+            Arch::Armv7a => "arm-none-eabi-",
+            Arch::Riscv64 => "riscv64-unknown-elf-",
+        }
+    }
+
+    // The ELF header's e_machine for objects of this architecture.
+    pub fn elf_machine(&self) -> u16 {
+        match self {
+            Arch::X86_64 => 0x3e,
+            Arch::I386 => 0x03,
+            Arch::Armv7a => 0x28,
+            Arch::Riscv64 => 0xf3,
+        }
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        match self {
+            Arch::X86_64 | Arch::Riscv64 => true,
+            Arch::I386 | Arch::Armv7a => false,
+        }
+    }
+
+    // The default base address of components. The 32 bit
+    // architectures use COS_MEM_COMP_START_VA (see cos_config.h).
+    pub fn default_baseaddr(&self) -> u64 {
+        if self.is_64bit() {
+            0x400000
+        } else {
+            (1 << 30) + (1 << 22)
+        }
+    }
+
+    // The size of the address range covered by each entry in the
+    // top-level page-table node. Components placed in different
+    // ranges can share the lower levels of the page-tables.
+    pub fn addrspc_name_sz(&self) -> u64 {
+        match self {
+            Arch::X86_64 => 1 << 39,  // 4 levels, 9 bits each
+            Arch::Riscv64 => 1 << 30, // Sv39: 3 levels, 9 bits each
+            Arch::I386 => 1 << 22,    // 2 levels, 10 bits each
+            Arch::Armv7a => 1 << 20,  // 1MB sections
+        }
+    }
+
+    // The highest user-level virtual address, as the kernel is
+    // mapped above it (see COS_MEM_USER_MAX_VA in cos_config.h).
+    pub fn user_max_vaddr(&self) -> u64 {
+        match self {
+            Arch::X86_64 => 0x00007fffffffffff,
+            Arch::Riscv64 => 0x0000003fffffffff,
+            Arch::I386 => 0xc0000000 - 1,
+            Arch::Armv7a => 0x80000000 - 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Export {
     pub interface: Interface,
@@ -284,6 +937,25 @@ pub trait SpecificationPass {
     fn exports_named(&self, id: &ComponentName) -> &Vec<Export>;
     fn libs_named(&self, id: &ComponentName) -> &Vec<Library>;
     fn address_spaces(&self) -> &AddrSpaces;
+    fn archive(&self) -> ArchiveFormat; // of the constructor's initfs
+    fn page_align(&self) -> bool; // the objects within the constructor's initfs
+    fn arch(&self) -> Arch; // components can override this
+    fn profile(&self) -> Option<Profile>; // unless overridden on the command line
+    fn build_dir(&self) -> &Option<String>; // unless overridden on the command line
+    fn sign_key(&self) -> &Option<String>; // unless overridden on the command line
+    fn dtb(&self) -> &Option<String>; // the board's device-tree (.dts, or .dtb)
+    fn dtb_addr(&self) -> Option<u64>; // the physical address the device-tree is loaded at
+    fn disk(&self) -> &DiskLayout; // for `mkimg disk`
+    fn netboot(&self) -> &NetbootLayout; // for `mkimg pxe`
+    fn xip(&self) -> &Option<XipLayout>; // the flash and RAM, if laid out to execute in place
+    fn memory(&self) -> &Vec<MemoryRegion>; // the board's flash and RAM, for `mkimg split`
+    fn flash_profiles(&self) -> &Vec<FlashProfile>; // the board's, for `mkimg flash`
+    fn compiler_cache(&self) -> Option<CompilerCache>; // unless overridden on the command line
+    fn max_initfs_size(&self) -> Option<u64>; // budget for the system constructor's tarball
+    fn test_expect(&self) -> &Vec<String>; // patterns a test run's serial output must match
+    fn test_timeout(&self) -> u64; // seconds a test run has to match them
+    fn cores(&self) -> u64;
+    fn boot_order(&self) -> &BTreeMap<u64, Vec<ComponentName>>; // the components initialized first on each core
 }
 
 // Integer namespacing pass. Convert the component variable names to
@@ -300,20 +972,25 @@ pub trait OrderedSpecPass {
 
 // Helper access functions
 pub fn component<'a>(s: &'a SystemState, id: &ComponentId) -> &'a Component {
-    let name = s.get_named().ids().get(&id).unwrap();
+    let name = s.get_named().ids().get(id).unwrap();
     s.get_spec().component_named(name)
 }
 
 pub fn deps<'a>(s: &'a SystemState, id: &ComponentId) -> &'a Vec<Dependency> {
-    let name = s.get_named().ids().get(&id).unwrap();
+    let name = s.get_named().ids().get(id).unwrap();
     s.get_spec().deps_named(name)
 }
 
 pub fn exports<'a>(s: &'a SystemState, id: &ComponentId) -> &'a Vec<Export> {
-    let name = s.get_named().ids().get(&id).unwrap();
+    let name = s.get_named().ids().get(id).unwrap();
     s.get_spec().exports_named(name)
 }
 
+pub fn libs<'a>(s: &'a SystemState, id: &ComponentId) -> &'a Vec<Library> {
+    let name = s.get_named().ids().get(id).unwrap();
+    s.get_spec().libs_named(name)
+}
+
 // A number of component service types are important for mkimg to
 // understand. These involve specific and somewhat complex
 // relationships often between services of the same type.
@@ -356,6 +1033,7 @@ pub trait PropertiesPass {
 // Each component must be compiled starting
 pub trait AddressAssignmentPass {
     fn component_baseaddr(&self, id: &ComponentId) -> u64;
+    fn component_dataaddr(&self, id: &ComponentId) -> Option<u64>; // of its writable segment, if XIP
 }
 
 // Compute the resource table, and resource allocations for each
@@ -380,6 +1058,7 @@ pub trait InitParamPass {
     fn param_list(&self) -> &Vec<ArgsKV>; // the arguments
     fn param_prog(&self) -> &String; // path to the parameter program
     fn param_fs(&self) -> &Option<String>; // path to the file-system image (tarball)
+    fn param_header(&self) -> &String; // path to the header of the parameters' keys
 }
 
 // The object pass creates the binary for the component, and
@@ -405,6 +1084,7 @@ pub trait ObjectsPass {
     fn client_symbs(&self) -> &HashMap<String, ClientSymb>;
     fn server_symbs(&self) -> &HashMap<String, ServerSymb>;
     fn comp_symbs(&self) -> &CompSymbs;
+    fn undefined_symbs(&self) -> &Vec<String>; // weak references left unresolved by the link
     fn comp_path(&self) -> &String;
 }
 
@@ -437,9 +1117,7 @@ pub trait ConstructorPass {
     fn image_path(&self) -> &String;
 }
 
-pub trait GraphPass {
-
-}
+pub trait GraphPass {}
 
 pub trait AnalysisPass {
     fn warnings(&self) -> &HashMap<ComponentId, Vec<Warning>>;
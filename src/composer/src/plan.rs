@@ -0,0 +1,277 @@
+// The plan of a build: the commands that would build each component,
+// constructor, and the kernel image, and the files generated for
+// them, without executing any of it. --dry-run prints it; tools
+// driving the composer as a library can inspect it instead.
+
+use manifest::Json;
+use passes::ArchiveFormat;
+use std::fmt;
+use syshelpers::Cmd;
+
+// Files packed into a tarball, as (host path, path in the tarball).
+pub type TarContents = Vec<(String, String)>;
+
+pub struct CompPlan {
+    pub name: String,
+    pub initargs: String,
+    pub constants_header: String,
+    pub deps_cmd: String,    // lists the component's dependencies on its sources
+    pub rebuild_cmd: String, // decides if the cached object can be reused
+    pub build_cmd: String,
+    pub initfs: Option<(String, TarContents)>, // the tarball and its contents
+}
+
+pub struct ConstructorPlan {
+    pub name: String,
+    pub initargs: String,
+    pub tarball: Option<(String, TarContents)>, // the components it creates (and its initfs)
+    pub build_cmd: String,
+}
+
+pub struct KernelPlan {
+    pub build_cmd: String,
+    pub constructor: String, // the object copied into the kernel image
+}
+
+pub struct Plan {
+    pub spec: String,
+    pub components: Vec<CompPlan>,
+    pub constructors: Vec<ConstructorPlan>,
+    pub kernel: Option<KernelPlan>,
+}
+
+fn tar_fmt(f: &mut fmt::Formatter, contents: &TarContents) -> fmt::Result {
+    for (path, name) in contents.iter() {
+        writeln!(f, "\t\t{} <- {}", name, path)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dry run: build plan for {}", self.spec)?;
+        for c in self.components.iter() {
+            writeln!(
+                f,
+                "Component {}:\n\tinitargs: {}\n\tconstants header: {}\n\tdependencies: {}\n\trebuild: {}\n\tbuild: {}",
+                c.name, c.initargs, c.constants_header, c.deps_cmd, c.rebuild_cmd, c.build_cmd
+            )?;
+            if let Some((ref tar, ref contents)) = c.initfs {
+                writeln!(f, "\tinitfs: {}", tar)?;
+                tar_fmt(f, contents)?;
+            }
+        }
+        for c in self.constructors.iter() {
+            writeln!(f, "Constructor {}:\n\tinitargs: {}", c.name, c.initargs)?;
+            if let Some((ref tar, ref contents)) = c.tarball {
+                writeln!(f, "\ttarball: {}", tar)?;
+                tar_fmt(f, contents)?;
+            }
+            writeln!(f, "\tbuild: {}", c.build_cmd)?;
+        }
+        if let Some(ref k) = self.kernel {
+            writeln!(
+                f,
+                "Kernel:\n\tbuild: {}\n\t(copies {} to the constructor)",
+                k.build_cmd, k.constructor
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn tar_json(tar: &Option<(String, TarContents)>) -> Json {
+    match tar {
+        Some((path, contents)) => Json::obj(vec![
+            ("path", Json::str(path)),
+            (
+                "contents",
+                Json::Arr(
+                    contents
+                        .iter()
+                        .map(|(path, name)| {
+                            Json::obj(vec![("name", Json::str(name)), ("path", Json::str(path))])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]),
+        None => Json::Null,
+    }
+}
+
+impl Plan {
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("spec", Json::str(&self.spec)),
+            (
+                "components",
+                Json::Arr(
+                    self.components
+                        .iter()
+                        .map(|c| {
+                            Json::obj(vec![
+                                ("name", Json::str(&c.name)),
+                                ("initargs", Json::str(&c.initargs)),
+                                ("constants_header", Json::str(&c.constants_header)),
+                                ("deps_cmd", Json::str(&c.deps_cmd)),
+                                ("rebuild_cmd", Json::str(&c.rebuild_cmd)),
+                                ("build_cmd", Json::str(&c.build_cmd)),
+                                ("initfs", tar_json(&c.initfs)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "constructors",
+                Json::Arr(
+                    self.constructors
+                        .iter()
+                        .map(|c| {
+                            Json::obj(vec![
+                                ("name", Json::str(&c.name)),
+                                ("initargs", Json::str(&c.initargs)),
+                                ("tarball", tar_json(&c.tarball)),
+                                ("build_cmd", Json::str(&c.build_cmd)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "kernel",
+                self.kernel.as_ref().map_or(Json::Null, |k| {
+                    Json::obj(vec![
+                        ("build_cmd", Json::str(&k.build_cmd)),
+                        ("constructor", Json::str(&k.constructor)),
+                    ])
+                }),
+            ),
+        ])
+    }
+}
+
+// Everything resolved for a single component's build, for `explain`.
+pub struct Explanation {
+    pub name: String,
+    pub source: String,              // interface.implementation
+    pub exports: Vec<String>,        // interface/variant
+    pub deps: Vec<(String, String)>, // (server, interface/variant)
+    pub libs: Vec<String>,           // including the libraries they depend on
+    pub baseaddr: u64,
+    pub initargs: (String, String),       // the file, and its contents
+    pub make_vars: Vec<(String, String)>, // passed to the component's build
+    pub output: String,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Component {} ({}):", self.name, self.source)?;
+        writeln!(f, "	exports:")?;
+        for e in self.exports.iter() {
+            writeln!(f, "		{}", e)?;
+        }
+        writeln!(f, "	dependencies:")?;
+        for (srv, d) in self.deps.iter() {
+            writeln!(f, "		{} <- {}", d, srv)?;
+        }
+        writeln!(f, "	libraries: {}", self.libs.join(" "))?;
+        writeln!(f, "	base address: {:#x}", self.baseaddr)?;
+        writeln!(f, "	make variables:")?;
+        for (k, v) in self.make_vars.iter() {
+            writeln!(f, "		{}={}", k, v)?;
+        }
+        writeln!(f, "	output: {}", self.output)?;
+        writeln!(f, "	initargs: {}", self.initargs.0)?;
+        for l in self.initargs.1.lines() {
+            writeln!(f, "		{}", l)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Explanation {
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("name", Json::str(&self.name)),
+            ("source", Json::str(&self.source)),
+            (
+                "exports",
+                Json::Arr(self.exports.iter().map(|e| Json::str(e)).collect()),
+            ),
+            (
+                "deps",
+                Json::Arr(
+                    self.deps
+                        .iter()
+                        .map(|(srv, d)| {
+                            Json::obj(vec![
+                                ("server", Json::str(srv)),
+                                ("interface", Json::str(d)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "libs",
+                Json::Arr(self.libs.iter().map(|l| Json::str(l)).collect()),
+            ),
+            ("baseaddr", Json::Str(format!("{:#x}", self.baseaddr))),
+            (
+                "make_vars",
+                Json::Obj(
+                    self.make_vars
+                        .iter()
+                        .map(|(k, v)| (k.clone(), Json::str(v)))
+                        .collect(),
+                ),
+            ),
+            ("output", Json::str(&self.output)),
+            (
+                "initargs",
+                Json::obj(vec![
+                    ("path", Json::str(&self.initargs.0)),
+                    ("contents", Json::str(&self.initargs.1)),
+                ]),
+            ),
+        ])
+    }
+}
+
+// A side effect of the build, resolved from the system state but not
+// yet performed, so that the resolution can be inspected (or tested)
+// independently of an Executor.
+#[derive(Clone, Debug)]
+pub enum Action {
+    Write {
+        path: String,
+        contents: Vec<u8>,
+    },
+    Tarball {
+        path: String,
+        files: TarContents,
+        format: ArchiveFormat,
+        page_align: bool, // the files' contents
+    },
+    Sign {
+        path: String,      // of the file signed
+        key: String,       // the path of the signing key
+        signature: String, // where the signature is written
+    },
+    Strip {
+        objcopy: String,  // of the object's architecture
+        path: String,     // of the sealed object
+        stripped: String, // where the stripped object is written...
+        debug: String,    // ...and its debugging information
+    },
+    Make {
+        name: String, // of the component (or "kernel") being built
+        cmd: Cmd,
+        log: String, // the command's output is recorded here
+    },
+}
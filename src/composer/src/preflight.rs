@@ -0,0 +1,264 @@
+// Checking, before any make runs, that the host and the source tree
+// have what the build requires: the tree's configuration (`make
+// config-<arch>`), the kernel's headers and the platform, the
+// components' sources, and the host's tools and each architecture's
+// toolchain. Everything missing is reported together, rather than
+// the build failing on the first of them with make's errors. The
+// composer creates the tarballs itself, so tar isn't required, but
+// the build directory must have the space for the build's artifacts,
+// so that the build doesn't fail midway.
+
+use build::DefaultBuilder;
+use passes::{
+    component, Arch, BuildState, ComponentId, ComposeError, FlashTool, ImageFormat, SystemState,
+};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// The files that `make config` generates in src/.
+const CONFIG_FILES: [&str; 3] = [".PLATFORM_ID", "Makefile.arch", "Makefile.cosconfig"];
+const COMPONENT_DIRS: [&str; 3] = [
+    "components/implementation",
+    "components/interface",
+    "components/lib",
+];
+// The estimated size of a component's object, if no previous build
+// cached one.
+const OBJ_ESTIMATE: u64 = 8 << 20;
+// An object is in the build directory and the object cache, and in
+// its constructor's tarball, which is linked into the constructor,
+// and so into the kernel image as well.
+const OBJ_COPIES: u64 = 4;
+const MIB: u64 = 1 << 20;
+
+// Is `prog` an executable in the PATH?
+fn in_path(prog: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|d| {
+            fs::metadata(d.join(prog))
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+    })
+}
+
+// The tools that building components, and the kernel, for `arch`
+// requires.
+fn toolchain(arch: Arch) -> Vec<String> {
+    let prefix = arch.cross_compile();
+    let mut tools: Vec<String> = ["gcc", "ld", "ar"]
+        .iter()
+        .map(|t| format!("{}{}", prefix, t))
+        .collect();
+    // The x86 platforms use the host's objcopy, which is checked
+    // separately.
+    if !prefix.is_empty() {
+        tools.push(format!("{}objcopy", prefix));
+    }
+
+    tools
+}
+
+// The space available on the filesystem holding `dir`, in bytes, as
+// df reports it; None if it cannot be determined.
+fn free_space(dir: &Path) -> Option<u64> {
+    let out = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    // The available 1024-byte blocks are the fourth column.
+    let avail: u64 = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+
+    Some(avail * 1024)
+}
+
+// The estimated size of component `id`'s object: that of the largest
+// one that previous builds cached.
+fn obj_estimate(id: &ComponentId, s: &SystemState, b: &DefaultBuilder) -> u64 {
+    let obj = b.comp_obj_file(id, s);
+    fs::read_dir(b.cachedir())
+        .ok()
+        .and_then(|es| {
+            es.filter_map(|e| e.ok())
+                .filter_map(|e| fs::metadata(e.path().join(&obj)).ok())
+                .map(|m| m.len())
+                .max()
+        })
+        .unwrap_or(OBJ_ESTIMATE)
+}
+
+fn src_missing(src: &Path, arch: Arch) -> Vec<String> {
+    let mut missing = Vec::new();
+    let unconfigured: Vec<&str> = CONFIG_FILES
+        .iter()
+        .filter(|f| !src.join(f).is_file())
+        .cloned()
+        .collect();
+    if !unconfigured.is_empty() {
+        missing.push(format!(
+            "the source tree's configuration ({} in {}); run `make config-{}` there",
+            unconfigured.join(", "),
+            src.display(),
+            arch.name()
+        ));
+    } else if let Ok(p) = fs::read_to_string(src.join(".PLATFORM_ID")) {
+        if p.trim() != arch.name() {
+            missing.push(format!(
+                "a source tree configured for {} (it is for {}); run `make config-{}` in {}",
+                arch.name(),
+                p.trim(),
+                arch.name(),
+                src.display()
+            ));
+        }
+    }
+    // `make config` links the platform's headers into chal/.
+    for h in [
+        "kernel/include/shared",
+        "kernel/include/chal/shared/cpu_ghz.h",
+    ]
+    .iter()
+    {
+        if !src.join(h).exists() {
+            missing.push(format!("the kernel's headers ({})", src.join(h).display()));
+        }
+    }
+    if !src.join("platform").join(arch.name()).is_dir() {
+        missing.push(format!(
+            "the {} platform ({})",
+            arch.name(),
+            src.join("platform").join(arch.name()).display()
+        ));
+    }
+    for d in COMPONENT_DIRS.iter() {
+        if !src.join(d).is_dir() {
+            missing.push(format!(
+                "the components' sources ({})",
+                src.join(d).display()
+            ));
+        }
+    }
+
+    missing
+}
+
+// Check that the build of system `s`, into an `image` (and, with
+// `netboot`, the files to network-boot it from), has what it
+// requires.
+pub fn preflight_check(
+    s: &SystemState,
+    b: &DefaultBuilder,
+    image: ImageFormat,
+    netboot: bool,
+    flash: Option<FlashTool>,
+) -> Result<(), ComposeError> {
+    let src = b.srcdir();
+    let arch = s.get_spec().arch();
+    let mut missing = src_missing(src, arch);
+
+    // Only the presence of the components' sources is checked here;
+    // `validate` checks them against the specification.
+    let mut archs = vec![arch];
+    let mut estimate = 0;
+    for id in s.get_named().ids().keys() {
+        let c = component(s, id);
+        estimate += obj_estimate(id, s, b) * OBJ_COPIES;
+        if !archs.contains(&c.arch) {
+            archs.push(c.arch);
+        }
+        let decomp: Vec<&str> = c.source.split(".").collect();
+        let dir = src
+            .join("components/implementation")
+            .join(decomp[0])
+            .join(decomp[1]);
+        if !dir.join("Makefile").is_file() {
+            missing.push(format!(
+                "component {}'s implementation, {} ({})",
+                c.name,
+                c.source,
+                dir.display()
+            ));
+        }
+    }
+
+    let mut tools: Vec<(String, String)> = vec![
+        (String::from("make"), String::from("to build")),
+        (
+            String::from("objcopy"),
+            String::from("to link the constructor into the kernel"),
+        ),
+    ];
+    for a in archs.iter() {
+        for t in toolchain(*a) {
+            tools.push((t, format!("of the {} toolchain", a.name())));
+        }
+    }
+    if let Some(c) = b.compiler_cache() {
+        tools.push((c.name().to_string(), String::from("the compiler cache")));
+    }
+    for (t, why) in tools.iter() {
+        if !in_path(t) {
+            missing.push(format!("{}, {} (not found in the PATH)", t, why));
+        }
+    }
+    // Distributions name grub-mkrescue differently.
+    if image == ImageFormat::Iso && !in_path("grub-mkrescue") && !in_path("grub2-mkrescue") {
+        missing.push(String::from(
+            "grub-mkrescue (or grub2-mkrescue), to create the ISO (not found in the PATH)",
+        ));
+    }
+    if image == ImageFormat::Iso && !in_path("xorriso") {
+        missing.push(String::from(
+            "xorriso, which grub-mkrescue uses to create the ISO (not found in the PATH)",
+        ));
+    }
+    if s.get_spec()
+        .dtb()
+        .as_ref()
+        .is_some_and(|d| d.ends_with(".dts"))
+        && !in_path("dtc")
+    {
+        missing.push(String::from(
+            "dtc, to compile the system's device-tree (not found in the PATH)",
+        ));
+    }
+    // x86 machines network-boot the kernel through grub.
+    if netboot && arch != Arch::Armv7a && !in_path("grub-mknetdir") && !in_path("grub2-mknetdir") {
+        missing.push(String::from(
+            "grub-mknetdir (or grub2-mknetdir), to install grub's network image (not found in the PATH)",
+        ));
+    }
+    if let Some(t) = flash {
+        if !in_path(t.name()) {
+            missing.push(format!(
+                "{}, to program the board (not found in the PATH)",
+                t.name()
+            ));
+        }
+    }
+
+    if let Some(free) = free_space(b.builddir()) {
+        if estimate > free {
+            missing.push(format!(
+                "space for the build's artifacts (an estimated {} MiB, but {} MiB are available in {})",
+                estimate / MIB,
+                free / MIB,
+                b.builddir().display()
+            ));
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(ComposeError::Environment(missing));
+    }
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use passes::{
-    component, deps, BuildState, ComponentId, Interface, PropertiesPass, ServiceClients,
-    ServiceProvider, ServiceType, SystemState, Transition,
+    component, deps, BuildState, ComponentId, ComposeError, Interface, PropertiesPass,
+    ServiceClients, ServiceProvider, ServiceType, SystemState, Transition,
 };
 use std::collections::HashMap;
 
@@ -17,42 +17,42 @@ fn interface_dependencies(
     if_name: Interface,
 ) -> (bool, Vec<ComponentId>, Option<ComponentId>) {
     let ids = s.get_named().ids();
-    let n = ids.get(&id).unwrap();
+    let n = ids.get(id).unwrap();
 
     // TODO: This is insufficient. We also need to see if
     // corresponding libraries are depended on here which mean we're
     // providing the service in question.
-    let us = deps(&s, &id)
+    let us = deps(s, id)
         .iter()
-        .find(|ref d| d.interface == if_name && d.variant == "kernel")
+        .find(|d| d.interface == if_name && d.variant == "kernel")
         .is_some();
 
     let clients: Vec<ComponentId> = ids
-        .iter()
-        .filter_map(|(ref cli_id, ref _cli_name)| {
+        .keys()
+        .filter_map(|cli_id| {
             // is the client dependent on us for initialization?
-            if deps(&s, &cli_id)
+            if deps(s, cli_id)
                 .iter()
                 .find(|&d| d.server == *n && d.interface == if_name && d.variant != "kernel")
                 .is_some()
             {
-                Some(**cli_id)
+                Some(*cli_id)
             } else {
                 None
             }
         })
         .collect();
 
-    let parent = deps(&s, &id)
+    let parent = deps(s, id)
         .iter()
-        .find(|ref d| d.interface == if_name && d.variant != "kernel")
-        .map(|ref d| s.get_named().rmap().get(&d.server).unwrap().clone());
+        .find(|d| d.interface == if_name && d.variant != "kernel")
+        .map(|d| *s.get_named().rmap().get(&d.server).unwrap());
 
     (us, clients, parent)
 }
 
 impl Transition for CompProperties {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let mut properties = HashMap::new();
 
         for (id, _) in s.get_named().ids().iter() {
@@ -60,8 +60,8 @@ impl Transition for CompProperties {
             let mut parents = Vec::new();
 
             // Scheduler properties
-            let (is_an_init, prop, parent) = interface_dependencies(&s, &id, "init".to_string());
-            if prop.len() > 0 || is_an_init {
+            let (is_an_init, prop, parent) = interface_dependencies(s, id, "init".to_string());
+            if !prop.is_empty() || is_an_init {
                 props.push(ServiceClients::Scheduler(prop));
             }
             if let Some(p) = parent {
@@ -70,10 +70,10 @@ impl Transition for CompProperties {
 
             // Capability manager properties
             let (is_a_capmgr, mut prop, parent) =
-                interface_dependencies(&s, &id, "capmgr".to_string());
+                interface_dependencies(s, id, "capmgr".to_string());
             // Capability manager (with only thread creation) properties
             let (is_a_capmgrthd, mut prop2, parent2) =
-                interface_dependencies(&s, &id, "capmgr_create".to_string());
+                interface_dependencies(s, id, "capmgr_create".to_string());
             if prop.len() + prop2.len() > 0 || is_a_capmgr || is_a_capmgrthd {
                 prop.append(&mut prop2);
                 props.push(ServiceClients::CapMgr(prop));
@@ -81,7 +81,7 @@ impl Transition for CompProperties {
             if let Some(p) = parent {
                 if let Some(p2) = parent2 {
                     if p != p2 {
-                        return Err(format!("Error: Component {} depends on both capmgr and capmgr_create, but from different components ({} and {}).", id, p, p2));
+                        return Err(ComposeError::Spec(format!("Error: Component {} depends on both capmgr and capmgr_create, but from different components ({} and {}).", id, p, p2)));
                     }
                 }
                 parents.push(ServiceProvider::CapMgr(p));
@@ -100,10 +100,10 @@ impl Transition for CompProperties {
                         let cons_id = s.get_named().rmap().get(cons).unwrap();
                         if id == id2 {
                             // this is us; set our parent
-                            (p, Some(cons_id.clone()))
+                            (p, Some(*cons_id))
                         } else if cons_id == id {
                             // this is a client, add them
-                            p.push(id2.clone());
+                            p.push(*id2);
                             (p, par)
                         } else {
                             // irrelevant component, pass on through
@@ -112,14 +112,14 @@ impl Transition for CompProperties {
                     });
             // If we have constructor clients, or are the only
             // component, we're a constructor!
-            if prop.len() > 0 || s.get_named().ids().len() == 1 {
+            if !prop.is_empty() || s.get_named().ids().len() == 1 {
                 props.push(ServiceClients::Constructor(prop));
             }
             if let Some(p) = parent {
                 parents.push(ServiceProvider::Constructor(p));
             }
 
-            properties.insert(id.clone(), (props, parents));
+            properties.insert(*id, (props, parents));
         }
 
         Ok(Box::new(CompProperties { comps: properties }))
@@ -154,7 +154,7 @@ impl PropertiesPass for CompProperties {
 
     // Is the compoent a service of the type?
     fn service_is_a(&self, id: &ComponentId, t: ServiceType) -> bool {
-        self.service_clients(&id, t).is_some()
+        self.service_clients(id, t).is_some()
     }
 
     fn service_dependency(&self, id: &ComponentId, t: ServiceType) -> Option<ComponentId> {
@@ -162,15 +162,15 @@ impl PropertiesPass for CompProperties {
         if let Some(c) = c_opt {
             c.1.iter().find_map(|r| match t {
                 ServiceType::Scheduler => match r {
-                    ServiceProvider::Scheduler(c) => Some(c.clone()),
+                    ServiceProvider::Scheduler(c) => Some(*c),
                     _ => None,
                 },
                 ServiceType::CapMgr => match r {
-                    ServiceProvider::CapMgr(c) => Some(c.clone()),
+                    ServiceProvider::CapMgr(c) => Some(*c),
                     _ => None,
                 },
                 ServiceType::Constructor => match r {
-                    ServiceProvider::Constructor(c) => Some(c.clone()),
+                    ServiceProvider::Constructor(c) => Some(*c),
                     _ => None,
                 },
             })
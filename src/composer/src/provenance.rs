@@ -0,0 +1,105 @@
+// Where a build came from: the revision of the composite source tree
+// (and whether it has uncommitted changes), the specification, and
+// the composer that built it. It is recorded in the manifest, and in
+// the system constructor's initargs, so that an image can be traced
+// back to its sources.
+
+use initargs::ArgsKV;
+use manifest::Json;
+use passes::ComposeError;
+use std::path::Path;
+use std::process::Command;
+use syshelpers::{dump_file, sha256};
+
+pub struct Provenance {
+    pub commit: Option<String>, // None if the tree isn't in a git repository
+    pub dirty: bool,            // uncommitted changes to tracked files
+    pub spec_sha256: String,
+    pub composer: String, // the composer's version
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+// Does `dir` (in a git repository) have uncommitted changes? As
+// with `git describe --dirty`, untracked files don't count, as the
+// build leaves its objects throughout the tree.
+fn dirty(dir: &Path) -> bool {
+    match git(
+        dir,
+        &["status", "--porcelain", "--untracked-files=no", "--", "."],
+    ) {
+        Some(ref changes) => !changes.is_empty(),
+        None => true,
+    }
+}
+
+// The revision of the sources in `dir`: the last commit to change
+// them, marked -dirty if they have uncommitted changes, or None if
+// they aren't in a git repository.
+pub fn revision(dir: &Path) -> Option<String> {
+    let rev = git(dir, &["log", "-1", "--format=%H", "--", "."])?;
+    if rev.is_empty() {
+        return None;
+    }
+    if dirty(dir) {
+        return Some(format!("{}-dirty", rev));
+    }
+
+    Some(rev)
+}
+
+// The provenance of a build of the specification `spec` from the
+// source tree's `src` directory.
+pub fn provenance(src: &Path, spec: &String) -> Result<Provenance, ComposeError> {
+    let root = git(src, &["rev-parse", "--show-toplevel"]);
+    let commit = root.as_ref().and_then(|_| git(src, &["rev-parse", "HEAD"]));
+
+    Ok(Provenance {
+        dirty: root.is_some_and(|r| dirty(Path::new(&r))),
+        commit,
+        spec_sha256: sha256(&dump_file(spec)?),
+        composer: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+impl Provenance {
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            (
+                "commit",
+                self.commit.as_ref().map_or(Json::Null, |c| Json::str(c)),
+            ),
+            ("dirty", Json::Bool(self.dirty)),
+            ("spec_sha256", Json::str(&self.spec_sha256)),
+            ("composer", Json::str(&self.composer)),
+        ])
+    }
+
+    // For the initargs, where all values are strings.
+    pub fn to_args(&self) -> ArgsKV {
+        ArgsKV::new_arr(
+            String::from("provenance"),
+            vec![
+                ArgsKV::new_key(
+                    String::from("commit"),
+                    self.commit.clone().unwrap_or(String::from("unknown")),
+                ),
+                ArgsKV::new_key(String::from("dirty"), self.dirty.to_string()),
+                ArgsKV::new_key(String::from("spec_sha256"), self.spec_sha256.clone()),
+                ArgsKV::new_key(String::from("composer"), self.composer.clone()),
+            ],
+        )
+    }
+}
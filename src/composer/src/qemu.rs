@@ -0,0 +1,282 @@
+// Boot the system image in qemu, replacing the ad-hoc run scripts.
+// The serial console is shown on the terminal, and is also logged
+// into the build directory. Tests instead boot headless, and watch
+// the log for the output the specification expects.
+
+use passes::{Arch, BuildState, ComposeError, ImageFormat, SystemState};
+use std::fs;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const QEMU_MEM_MB: &str = "4096";
+pub const QEMU_GDB_PORT: &str = "1234"; // qemu's default for -s
+const TEST_POLL_MS: u64 = 250;
+
+// The image that qemu boots for the architecture: x86 bootloaders
+// (grub) can only load multiboot2 kernels from an ISO.
+pub fn run_image_format(s: &SystemState) -> ImageFormat {
+    match s.get_spec().arch() {
+        Arch::X86_64 | Arch::I386 => ImageFormat::Iso,
+        _ => ImageFormat::Kernel,
+    }
+}
+
+fn kvm_available() -> bool {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok()
+}
+
+// The qemu program and its arguments (without the serial console) to
+// boot `img` on a default machine for the architecture.
+fn qemu_cmd(arch: Arch, img: &String) -> (String, Vec<String>) {
+    let args: Vec<&str> = match arch {
+        Arch::X86_64 | Arch::I386 => vec![
+            "-cpu",
+            "max",
+            "-smp",
+            "4",
+            "-m",
+            QEMU_MEM_MB,
+            "-cdrom",
+            img,
+            "-no-reboot",
+            "-nic",
+            "none",
+        ],
+        // The Zynq's console is its second UART.
+        Arch::Armv7a => vec![
+            "-M",
+            "xilinx-zynq-a9",
+            "-m",
+            "1024",
+            "-kernel",
+            img,
+            "-serial",
+            "null",
+        ],
+        Arch::Riscv64 => vec![
+            "-M",
+            "virt",
+            "-bios",
+            "default",
+            "-m",
+            QEMU_MEM_MB,
+            "-kernel",
+            img,
+            "-no-reboot",
+        ],
+    };
+    let prog = match arch {
+        Arch::X86_64 => "qemu-system-x86_64",
+        Arch::I386 => "qemu-system-i386",
+        Arch::Armv7a => "qemu-system-arm",
+        Arch::Riscv64 => "qemu-system-riscv64",
+    };
+    let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    if (arch == Arch::X86_64 || arch == Arch::I386) && kvm_available() {
+        args.push("-enable-kvm".to_string());
+    }
+
+    (prog.to_string(), args)
+}
+
+// The path of the system's image to boot, which must already be
+// built.
+fn boot_image(s: &SystemState, b: &dyn BuildState) -> Result<String, ComposeError> {
+    let img = match run_image_format(s) {
+        ImageFormat::Iso => b.file_path(&"cos.iso".to_string())?,
+        _ => s.get_constructor().image_path().clone(),
+    };
+    if !Path::new(&img).exists() {
+        return Err(ComposeError::Io {
+            path: img,
+            msg: String::from("The system image to boot was not found"),
+        });
+    }
+
+    Ok(img)
+}
+
+// The qemu program and its arguments to boot the system's image with
+// its serial console on the terminal, and the console's log. With
+// `gdb`, qemu waits for a debugger to attach before executing.
+fn qemu_console_cmd(
+    s: &SystemState,
+    b: &dyn BuildState,
+    gdb: bool,
+) -> Result<(String, Vec<String>, String), ComposeError> {
+    let img = boot_image(s, b)?;
+    let log = b.file_path(&"serial.log".to_string())?;
+
+    let (prog, mut args) = qemu_cmd(s.get_spec().arch(), &img);
+    args.push("-display".to_string());
+    args.push("none".to_string());
+    // The monitor is multiplexed with the serial console (C-a c to
+    // switch), and the console is logged as well as displayed.
+    args.push("-chardev".to_string());
+    args.push(format!(
+        "stdio,id=console,mux=on,signal=off,logfile={}",
+        log
+    ));
+    args.push("-serial".to_string());
+    args.push("chardev:console".to_string());
+    args.push("-mon".to_string());
+    args.push("chardev=console".to_string());
+    if gdb {
+        args.push("-s".to_string());
+        args.push("-S".to_string());
+        info!(
+            "Waiting for gdb to attach (target remote :{}), e.g. with: gdb -x {}",
+            QEMU_GDB_PORT,
+            b.file_path(&"gdbinit".to_string())?
+        );
+    }
+    info!("Booting {} (serial output logged to {}).", img, log);
+    debug!("\t{} {}", prog, args.join(" "));
+
+    Ok((prog, args, log))
+}
+
+// Boot the system's image, and wait for qemu to exit.
+pub fn qemu_run(s: &SystemState, b: &dyn BuildState, gdb: bool) -> Result<(), ComposeError> {
+    let (prog, args, log) = qemu_console_cmd(s, b, gdb)?;
+    let status = Command::new(&prog)
+        .args(&args)
+        .status()
+        .map_err(|e| ComposeError::Io {
+            path: prog.clone(),
+            msg: format!("could not execute qemu: {}", e),
+        })?;
+    if !status.success() {
+        return Err(ComposeError::Pass(format!(
+            "Error: {} exited with {}. See the serial output in {}.",
+            prog, status, log
+        )));
+    }
+
+    Ok(())
+}
+
+// Boot the system's image, without waiting for qemu to exit, so that
+// it can be stopped (with qemu_stop) to boot a new build.
+pub fn qemu_spawn(s: &SystemState, b: &dyn BuildState, gdb: bool) -> Result<Child, ComposeError> {
+    let (prog, args, _) = qemu_console_cmd(s, b, gdb)?;
+
+    Command::new(&prog)
+        .args(&args)
+        .spawn()
+        .map_err(|e| ComposeError::Io {
+            path: prog.clone(),
+            msg: format!("could not execute qemu: {}", e),
+        })
+}
+
+// Stop the qemu that qemu_spawn started, if it's still running. It's
+// terminated (rather than killed) so that it restores the terminal.
+pub fn qemu_stop(mut child: Child) {
+    if let Ok(None) = child.try_wait() {
+        let _ = Command::new("kill").arg(child.id().to_string()).status();
+    }
+    let _ = child.wait();
+}
+
+// Does some line of the file at `path` match the (extended) regular
+// expression `pat`? grep exits with 2 on errors, including patterns
+// that don't compile.
+fn grep(pat: &String, path: &str) -> Result<bool, ComposeError> {
+    let status = Command::new("grep")
+        .arg("-qE")
+        .arg("-e")
+        .arg(pat)
+        .arg(path)
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| ComposeError::Io {
+            path: path.to_string(),
+            msg: format!("could not execute grep: {}", e),
+        })?;
+
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(ComposeError::Spec(format!(
+            "Error: Cannot match the test's expected output \"{}\" against {}; is it a valid regular expression?",
+            pat, path
+        ))),
+    }
+}
+
+// Boot the system's image headless, and wait until each of the
+// specification's expected patterns has been printed on the serial
+// console, qemu exits, or the test times out. Returns the patterns
+// that weren't matched.
+pub fn qemu_test(s: &SystemState, b: &dyn BuildState) -> Result<Vec<String>, ComposeError> {
+    let spec = s.get_spec();
+    if spec.test_expect().is_empty() {
+        return Err(ComposeError::Spec(format!(
+            "Error: The specification {} has no expected test output; add a [test] table with `expect` patterns, or a .expected file.",
+            s.get_input()
+        )));
+    }
+    // Catch broken patterns before booting.
+    for p in spec.test_expect() {
+        grep(p, "/dev/null")?;
+    }
+
+    let img = boot_image(s, b)?;
+    let log = b.file_path(&"serial.log".to_string())?;
+    let _ = fs::remove_file(&log);
+    let (prog, mut args) = qemu_cmd(spec.arch(), &img);
+    args.push("-display".to_string());
+    args.push("none".to_string());
+    args.push("-monitor".to_string());
+    args.push("none".to_string());
+    args.push("-serial".to_string());
+    args.push(format!("file:{}", log));
+
+    let mut child = Command::new(&prog)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| ComposeError::Io {
+            path: prog.clone(),
+            msg: format!("could not execute qemu: {}", e),
+        })?;
+
+    let start = Instant::now();
+    let timeout = Duration::from_secs(spec.test_timeout());
+    let mut missing = spec.test_expect().clone();
+    let res = loop {
+        // Check the exit first so that all of the output of an
+        // exited system is matched.
+        let exited = child.try_wait().map(|st| st.is_some()).unwrap_or(true);
+        let mut err = None;
+        missing.retain(|p| match grep(p, &log) {
+            Ok(found) => !found,
+            Err(e) => {
+                err = Some(e);
+                true
+            }
+        });
+        // The log doesn't exist until qemu opens it.
+        if let Some(e) = err {
+            if Path::new(&log).exists() {
+                break Err(e);
+            }
+        }
+        if missing.is_empty() || exited || start.elapsed() >= timeout {
+            break Ok(missing);
+        }
+        thread::sleep(Duration::from_millis(TEST_POLL_MS));
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+
+    res
+}
@@ -0,0 +1,304 @@
+// Splitting the system into images of the board's memory regions (the
+// spec's [memory] table), for boards whose flash and RAM are each
+// programmed (or loaded by a debugger) separately, rather than booted
+// from a single image (`mkimg split`).
+//
+// The kernel image's loadable segments, and (with an XIP layout)
+// xip.bin and the components' writable segments, are at fixed
+// (physical) addresses, so each must be within one region, and not
+// overlap the others in it. The components that the booter loads
+// from the kernel image are instead assigned to the first RAM region
+// with room for them, once the fixed segments are placed. regions/
+// holds an image of each region with contents (<region>.bin, from its
+// base), and layout.txt and layout.json, which report what's in each
+// region, and how much of it is used.
+
+use compobject::obj_sizes;
+use manifest::Json;
+use passes::{component, BuildState, ComposeError, MemoryRegion, RegionKind, SystemState};
+use std::path::Path;
+use syshelpers::{dump_file, emit_file, reset_dir};
+use xmas_elf::program;
+use xmas_elf::ElfFile;
+
+const PAGE_SZ: u64 = 4096;
+
+// What's placed in a region: a segment at a fixed address (with its
+// contents, if it has any), or a component that the booter loads.
+struct Placed {
+    owner: String,
+    start: Option<u64>, // None if the booter allocates it
+    size: u64,
+    perms: String,
+    contents: Vec<u8>,
+}
+
+// A fixed segment, to be placed in the region that contains it.
+struct Segment {
+    owner: String,
+    start: u64,
+    size: u64,
+    perms: String,
+    contents: Vec<u8>,
+}
+
+fn hex(a: u64) -> Json {
+    Json::Str(format!("{:#x}", a))
+}
+
+// The loadable segments of the kernel image, at their physical
+// addresses.
+fn kernel_segments(path: &String) -> Result<Vec<Segment>, ComposeError> {
+    let img = dump_file(path)?;
+    let elf_file = ElfFile::new(&img).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the kernel image {} as an ELF file: {}",
+            path, e
+        ))
+    })?;
+    let mut segs = Vec::new();
+    for ph in elf_file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load) && ph.mem_size() != 0)
+    {
+        let (off, filesz) = (ph.offset() as usize, ph.file_size() as usize);
+        let contents = img
+            .get(off..off + filesz)
+            .ok_or(ComposeError::Pass(format!(
+                "Error: The kernel image {} is truncated.",
+                path
+            )))?;
+        let f = ph.flags();
+        segs.push(Segment {
+            owner: String::from("kernel image"),
+            start: ph.physical_addr(),
+            size: ph.mem_size(),
+            perms: format!(
+                "{}{}{}",
+                if f.is_read() { "r" } else { "-" },
+                if f.is_write() { "w" } else { "-" },
+                if f.is_execute() { "x" } else { "-" }
+            ),
+            contents: contents.to_vec(),
+        });
+    }
+
+    Ok(segs)
+}
+
+// Place the system's segments and components in the `regions`.
+fn regions_place(
+    s: &SystemState,
+    b: &dyn BuildState,
+    regions: &[MemoryRegion],
+) -> Result<Vec<Vec<Placed>>, ComposeError> {
+    let mut segs = kernel_segments(s.get_constructor().image_path())?;
+    let mut loaded = Vec::new();
+    for (id, _) in s.get_named().ids().iter() {
+        let name = component(s, id).name.to_string();
+        let sz = obj_sizes(s.get_objs_id(id).comp_path())?;
+        match s.get_spec().xip() {
+            // Their writable segments are copied to their addresses.
+            Some(_) => {
+                if let Some(addr) = s.get_address_assignments().component_dataaddr(id) {
+                    segs.push(Segment {
+                        owner: format!("{} (writable)", name),
+                        start: addr,
+                        size: sz.data,
+                        perms: String::from("rw-"),
+                        contents: Vec::new(),
+                    });
+                }
+            }
+            None => loaded.push((name, sz.span.div_ceil(PAGE_SZ) * PAGE_SZ)),
+        }
+    }
+    if let Some(x) = s.get_spec().xip() {
+        let path = b.file_path(&"xip.bin".to_string())?;
+        let contents = dump_file(&path)?;
+        segs.push(Segment {
+            owner: String::from("xip.bin (the XIP components' read-only segments)"),
+            start: x.flash_base,
+            size: contents.len() as u64,
+            perms: String::from("r-x"),
+            contents,
+        });
+    }
+
+    let mut placed: Vec<Vec<Placed>> = regions.iter().map(|_| Vec::new()).collect();
+    for seg in segs.into_iter() {
+        let (start, end) = (seg.start, seg.start + seg.size);
+        let i = match regions
+            .iter()
+            .position(|r| r.base <= start && end <= r.base + r.size)
+        {
+            Some(i) => i,
+            None => {
+                return Err(ComposeError::Pass(format!(
+                    "Error: The {} segment [{:#x}, {:#x}) is not within any of the memory table's regions.",
+                    seg.owner, start, end
+                )))
+            }
+        };
+        if let Some(p) = placed[i]
+            .iter()
+            .find(|p| p.start.is_some_and(|ps| start < ps + p.size && ps < end))
+        {
+            return Err(ComposeError::Pass(format!(
+                "Error: The {} segment [{:#x}, {:#x}) overlaps the {} segment at {:#x} in memory region {}.",
+                seg.owner,
+                start,
+                end,
+                p.owner,
+                p.start.unwrap(),
+                regions[i].name
+            )));
+        }
+        placed[i].push(Placed {
+            owner: seg.owner,
+            start: Some(start),
+            size: seg.size,
+            perms: seg.perms,
+            contents: seg.contents,
+        });
+    }
+
+    // The booter allocates the components' memory from the RAM that
+    // the fixed segments leave free.
+    let used = |ps: &Vec<Placed>| ps.iter().map(|p| p.size).sum::<u64>();
+    for (name, size) in loaded.into_iter() {
+        let i = match regions.iter().enumerate().position(|(i, r)| {
+            r.kind == RegionKind::Ram && used(&placed[i]) + size <= r.size
+        }) {
+            Some(i) => i,
+            None => {
+                return Err(ComposeError::Pass(format!(
+                    "Error: Component {} ({} bytes) doesn't fit in the free RAM of any of the memory table's ram regions.",
+                    name, size
+                )))
+            }
+        };
+        placed[i].push(Placed {
+            owner: name,
+            start: None,
+            size,
+            perms: String::from("rwx"),
+            contents: Vec::new(),
+        });
+    }
+    for ps in placed.iter_mut() {
+        ps.sort_by_key(|p| p.start.unwrap_or(u64::MAX));
+    }
+
+    Ok(placed)
+}
+
+// The path of the memory region `name`'s image, in the build
+// directory.
+pub fn region_image_path(b: &dyn BuildState, name: &String) -> Result<String, ComposeError> {
+    Ok(Path::new(&b.file_path(&"regions".to_string())?)
+        .join(format!("{}.bin", name))
+        .display()
+        .to_string())
+}
+
+// Write regions/, with the image of each memory region that has
+// contents, and its layout report, into the build directory, and
+// return the images' paths, and the reports' (text, then JSON).
+pub fn regions_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+) -> Result<(Vec<String>, (String, String)), ComposeError> {
+    let regions = s.get_spec().memory();
+    if regions.is_empty() {
+        return Err(ComposeError::Spec(String::from(
+            "Error: mkimg split requires the specification's memory table, with the board's regions.",
+        )));
+    }
+    let placed = regions_place(s, b, regions)?;
+    let dir = b.file_path(&"regions".to_string())?;
+    reset_dir(&dir)?;
+
+    let mut images = Vec::new();
+    let mut txt = String::new();
+    let mut regions_json = Vec::new();
+    for (r, ps) in regions.iter().zip(placed.iter()) {
+        let used: u64 = ps.iter().map(|p| p.size).sum();
+        // The region's image is of its contents, from its base.
+        let len = ps
+            .iter()
+            .filter(|p| !p.contents.is_empty())
+            .filter_map(|p| p.start.map(|s| s - r.base + p.contents.len() as u64))
+            .max();
+        let image = match len {
+            Some(len) => {
+                let mut img = vec![0u8; len as usize];
+                for p in ps.iter() {
+                    if let Some(start) = p.start {
+                        let off = (start - r.base) as usize;
+                        img[off..off + p.contents.len()].copy_from_slice(&p.contents);
+                    }
+                }
+                let path = region_image_path(b, &r.name)?;
+                emit_file(&path, &img)?;
+                images.push(path.clone());
+                Some(path)
+            }
+            None => None,
+        };
+
+        txt.push_str(&format!(
+            "Region {} ({}, {:#010x}-{:#010x}): {} of {} bytes used, {}\n",
+            r.name,
+            r.kind.name(),
+            r.base,
+            r.base + r.size,
+            used,
+            r.size,
+            image
+                .as_ref()
+                .map_or(String::from("no image"), |i| format!("image {}", i))
+        ));
+        let mut contents_json = Vec::new();
+        for p in ps.iter() {
+            match p.start {
+                Some(start) => txt.push_str(&format!(
+                    "  {:#010x}-{:#010x} {} {} ({} bytes)\n",
+                    start,
+                    start + p.size,
+                    p.perms,
+                    p.owner,
+                    p.size
+                )),
+                None => txt.push_str(&format!(
+                    "  (loaded by the booter)  {} {} ({} bytes)\n",
+                    p.perms, p.owner, p.size
+                )),
+            }
+            contents_json.push(Json::obj(vec![
+                ("owner", Json::str(&p.owner)),
+                ("start", p.start.map_or(Json::Null, hex)),
+                ("size", Json::Num(p.size)),
+                ("perms", Json::str(&p.perms)),
+            ]));
+        }
+        txt.push('\n');
+        regions_json.push(Json::obj(vec![
+            ("name", Json::str(&r.name)),
+            ("kind", Json::str(r.kind.name())),
+            ("base", hex(r.base)),
+            ("size", Json::Num(r.size)),
+            ("used", Json::Num(used)),
+            ("image", image.as_ref().map_or(Json::Null, |i| Json::str(i))),
+            ("contents", Json::Arr(contents_json)),
+        ]));
+    }
+
+    let txt_path = Path::new(&dir).join("layout.txt").display().to_string();
+    emit_file(&txt_path, txt.as_bytes())?;
+    let json_path = Path::new(&dir).join("layout.json").display().to_string();
+    let json = Json::obj(vec![("regions", Json::Arr(regions_json))]);
+    emit_file(&json_path, format!("{}\n", json).as_bytes())?;
+
+    Ok((images, (txt_path, json_path)))
+}
@@ -1,7 +1,7 @@
 use initargs::ArgsKV;
 use passes::{
-    component, BuildState, ComponentId, OrderedSpecPass, PropertiesPass, ResPass, ServiceType,
-    SystemState, Transition,
+    component, BuildState, CapDelegations, ComponentId, ComposeError, OrderedSpecPass,
+    PropertiesPass, ResPass, ServiceType, SystemState, Transition,
 };
 use std::collections::{BTreeMap, HashMap};
 
@@ -56,7 +56,7 @@ impl CaptblState {
         let sz = cap_sz(&cap);
         let frontier = self.frontier;
 
-        if (frontier % 4 != 0) && (self.prev_sz != sz) {
+        if !frontier.is_multiple_of(4) && (self.prev_sz != sz) {
             self.frontier = frontier + (4 - frontier % 4); //  round up to power of 4
         }
         self.frontier += sz;
@@ -70,7 +70,7 @@ impl CaptblState {
     }
 
     fn get_frontier(&self) -> u32 {
-        self.frontier.clone()
+        self.frontier
     }
 }
 
@@ -94,13 +94,13 @@ fn sched_config_serv_client(s: &SystemState, id: &ComponentId) -> Vec<ArgsKV> {
     let mut init = Vec::new();
 
     let props: &dyn PropertiesPass = s.get_properties();
-    if !props.service_is_a(&id, ServiceType::Scheduler) {
+    if !props.service_is_a(id, ServiceType::Scheduler) {
         return init;
     }
 
-    let clients = props.service_clients(&id, ServiceType::Scheduler);
+    let clients = props.service_clients(id, ServiceType::Scheduler);
 
-    assert!(props.service_is_a(&id, ServiceType::Scheduler));
+    assert!(props.service_is_a(id, ServiceType::Scheduler));
     if let Some(cs) = clients {
         for c in cs.iter() {
             init.push(ArgsKV::new_key(c.to_string(), id.to_string()));
@@ -114,13 +114,13 @@ fn sched_config_clients(s: &SystemState, id: &ComponentId) -> Vec<ArgsKV> {
     let mut init = Vec::new();
 
     let props: &dyn PropertiesPass = s.get_properties();
-    if !props.service_is_a(&id, ServiceType::Scheduler) {
+    if !props.service_is_a(id, ServiceType::Scheduler) {
         return init;
     }
 
-    let clients = props.service_clients(&id, ServiceType::Scheduler);
+    let clients = props.service_clients(id, ServiceType::Scheduler);
 
-    assert!(props.service_is_a(&id, ServiceType::Scheduler));
+    assert!(props.service_is_a(id, ServiceType::Scheduler));
     if let Some(cs) = clients {
         for c in cs.iter() {
             init.push(
@@ -142,15 +142,54 @@ fn sched_config_clients(s: &SystemState, id: &ComponentId) -> Vec<ArgsKV> {
     init
 }
 
+// The scheduling parameters from the specification of each of the
+// components we schedule, keyed by their ids.
+fn sched_config_params(s: &SystemState, id: &ComponentId) -> Vec<ArgsKV> {
+    let name = &component(s, id).name;
+    let mut params = Vec::new();
+
+    for (cid, _) in s.get_named().ids().iter() {
+        let c = component(s, cid);
+        let sched = match c.sched {
+            Some(ref sched) if c.scheduler == *name => sched,
+            _ => continue,
+        };
+        let mut kvs = Vec::new();
+        for (key, val) in [
+            ("prio", sched.prio),
+            ("budget_us", sched.budget_us),
+            ("period_us", sched.period_us),
+            ("core", sched.core),
+        ]
+        .iter()
+        {
+            if let Some(v) = val {
+                kvs.push(ArgsKV::new_key(key.to_string(), v.to_string()));
+            }
+        }
+        // The pushes reverse the order, as above
+        kvs.reverse();
+        params.push(ArgsKV::new_arr(cid.to_string(), kvs));
+    }
+
+    params.reverse();
+    params
+}
+
 fn sched_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
     cfg.args.push(ArgsKV::new_arr(
         "execute".to_string(),
-        sched_config_clients(&s, &id),
+        sched_config_clients(s, id),
     ));
+    let params = sched_config_params(s, id);
+    if !params.is_empty() {
+        cfg.args
+            .push(ArgsKV::new_arr("sched_params".to_string(), params));
+    }
 }
 
 fn cap2kvarg(capid: u32, cap: &CapRes) -> ArgsKV {
-    let (name, target) = cap_info(&cap);
+    let (name, target) = cap_info(cap);
     let capinfo = vec![
         ArgsKV::new_key("type".to_string(), name),
         ArgsKV::new_key("target".to_string(), target.to_string()),
@@ -158,37 +197,77 @@ fn cap2kvarg(capid: u32, cap: &CapRes) -> ArgsKV {
     ArgsKV::new_arr(capid.to_string(), capinfo)
 }
 
+fn ids2kvarg(key: &str, vs: &Vec<u64>) -> ArgsKV {
+    // Arrays have "_" keys, and are reversed so as to be iterated in order
+    ArgsKV::new_arr(
+        key.to_string(),
+        vs.iter()
+            .rev()
+            .map(|v| ArgsKV::new_key("_".to_string(), v.to_string()))
+            .collect(),
+    )
+}
+
+// The capabilities the specification delegates to a client, for the
+// capmgr to create and delegate.
+fn caps2kvarg(id: &ComponentId, caps: &CapDelegations) -> ArgsKV {
+    let memory = caps
+        .memory
+        .iter()
+        .rev()
+        .map(|(base, size)| {
+            ArgsKV::new_arr(
+                "_".to_string(),
+                vec![
+                    ArgsKV::new_key("size".to_string(), size.to_string()),
+                    ArgsKV::new_key("base".to_string(), base.to_string()),
+                ],
+            )
+        })
+        .collect();
+    let mut args = Vec::new();
+    if let Some(u) = caps.untyped {
+        args.push(ArgsKV::new_key("untyped".to_string(), u.to_string()));
+    }
+    args.push(ids2kvarg("irqs", &caps.irqs));
+    args.push(ids2kvarg("cores", &caps.cores));
+    args.push(ArgsKV::new_arr("memory".to_string(), memory));
+
+    ArgsKV::new_arr(id.to_string(), args)
+}
+
 fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
     let props: &dyn PropertiesPass = s.get_properties();
-    if !props.service_is_a(&id, ServiceType::CapMgr) {
+    if !props.service_is_a(id, ServiceType::CapMgr) {
         return;
     }
 
     let mut clients = props
-        .service_clients(&id, ServiceType::Scheduler)
-        .map(|cs| cs.clone()) // get rid of the reference
-        .unwrap_or_else(|| Vec::new());
+        .service_clients(id, ServiceType::Scheduler)
+        .cloned() // get rid of the reference
+        .unwrap_or_default();
     let mut sched_args = Vec::new();
     let mut ct_args = Vec::new();
     let mut init_args = Vec::new();
     let mut names_args = Vec::new();
+    let mut caps_args = Vec::new();
 
     // aggregate records for scheduler and capmgr dependencies
     clients.append(
         &mut props
-            .service_clients(&id, ServiceType::CapMgr)
-            .map(|cs| cs.clone())
-            .unwrap_or_else(|| Vec::new()),
+            .service_clients(id, ServiceType::CapMgr)
+            .cloned()
+            .unwrap_or_default(),
     );
     clients.sort();
     clients.dedup();
 
-    assert!(props.service_is_a(&id, ServiceType::CapMgr));
+    assert!(props.service_is_a(id, ServiceType::CapMgr));
     for c in &clients {
         // sanity
         assert!(*c != *id);
         // don't support nested capmgrs yet
-        assert!(!props.service_is_a(&c, ServiceType::CapMgr));
+        assert!(!props.service_is_a(c, ServiceType::CapMgr));
 
         // capability table entries for the client
         cfg.ct.add(CapRes::CapTbl(*c));
@@ -196,8 +275,8 @@ fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
         cfg.ct.add(CapRes::Comp(*c));
 
         // scheduler hierarchy
-        if props.service_is_a(&c, ServiceType::Scheduler) {
-            let p = props.service_dependency(&c, ServiceType::Scheduler);
+        if props.service_is_a(c, ServiceType::Scheduler) {
+            let p = props.service_dependency(c, ServiceType::Scheduler);
 
             // at the least, the capmgr should be the parent
             assert!(p.is_some());
@@ -207,18 +286,23 @@ fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
         // Initialization information for all components shipped to
         // the capmgr This effectively grants permission for the
         // scheduler to create execution in a client.
-        let mut init_clients = sched_config_serv_client(&s, &c);
+        let mut init_clients = sched_config_serv_client(s, c);
         if !init_clients.is_empty() {
             init_args.append(&mut init_clients);
         }
 
         // client names
-        let spec_comp = component(&s, &c);
+        let spec_comp = component(s, c);
         let name = format!(
             "{}.{}.{}",
             spec_comp.source, spec_comp.name.scope_name, spec_comp.name.var_name
         );
         names_args.push(ArgsKV::new_key(c.to_string(), name));
+
+        // the capabilities the specification delegates to the client
+        if let Some(ref caps) = spec_comp.caps {
+            caps_args.push(caps2kvarg(c, caps));
+        }
     }
 
     // Lets provide information to the capability manager about which
@@ -226,10 +310,10 @@ fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
     // spaces.
     let mut shared_vas = Vec::new();
     let vas: &dyn OrderedSpecPass = s.get_named();
-    for (_, addrspc) in vas.addrspc_components_shared() {
+    for addrspc in vas.addrspc_components_shared().values() {
         for c in &addrspc.components {
             // unwrap as every name should be represented (see OrderedSpecpass).
-            let id = vas.rmap().get(&c).unwrap();
+            let id = vas.rmap().get(c).unwrap();
             shared_vas.push(ArgsKV::new_key("_".to_string(), format!("{}", id)));
         }
     }
@@ -242,7 +326,7 @@ fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
         .push(ArgsKV::new_arr("init_hierarchy".to_string(), init_args));
 
     for (capid, cap) in cfg.ct.get_captbl() {
-        ct_args.push(cap2kvarg(*capid, &cap));
+        ct_args.push(cap2kvarg(*capid, cap));
     }
     cfg.args
         .push(ArgsKV::new_arr("captbl".to_string(), ct_args));
@@ -250,33 +334,35 @@ fn capmgr_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
         .push(ArgsKV::new_arr("names".to_string(), names_args));
     cfg.args
         .push(ArgsKV::new_arr("addrspc_shared".to_string(), shared_vas));
+    if !caps_args.is_empty() {
+        cfg.args
+            .push(ArgsKV::new_arr("delegations".to_string(), caps_args));
+    }
 }
 
 fn constructor_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigState) {
     let props: &dyn PropertiesPass = s.get_properties();
-    if !props.service_is_a(&id, ServiceType::Constructor) {
+    if !props.service_is_a(id, ServiceType::Constructor) {
         return;
     }
 
-    let clients = props.service_clients(&id, ServiceType::Constructor);
+    let clients = props.service_clients(id, ServiceType::Constructor);
     let mut captbls = BTreeMap::new();
 
-    assert!(props.service_is_a(&id, ServiceType::Constructor));
-    // for now, assume only a single constructor
-    assert!(props
-        .service_dependency(&id, ServiceType::Constructor)
-        .is_none());
+    assert!(props.service_is_a(id, ServiceType::Constructor));
+    // Nested constructors (e.g. the booters of sub-systems) delegate
+    // to the capmgrs they construct, as the system's constructor does.
     if let Some(cs) = clients {
         for c in cs {
             // only have captbl for capmgrs
-            if !props.service_is_a(&c, ServiceType::CapMgr) {
+            if !props.service_is_a(c, ServiceType::CapMgr) {
                 continue;
             }
 
             // Assuming that the values returned by this are the
             // same as those created while processing the capmgr
             let mut chld_cfg = CompConfigState::new();
-            capmgr_config(&s, &c, &mut chld_cfg);
+            capmgr_config(s, c, &mut chld_cfg);
             captbls.insert(*c, chld_cfg);
         }
     }
@@ -288,7 +374,7 @@ fn constructor_config(s: &SystemState, id: &ComponentId, cfg: &mut CompConfigSta
         let ct = cfg.ct.get_captbl();
 
         for (capid, cap) in ct {
-            comp_args.push(cap2kvarg(*capid, &cap));
+            comp_args.push(cap2kvarg(*capid, cap));
         }
 
         args.push(ArgsKV::new_arr(id.to_string(), comp_args));
@@ -318,7 +404,7 @@ pub struct ResAssignPass {
 }
 
 impl Transition for ResAssignPass {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let mut res = HashMap::new();
 
         for (k, _v) in s.get_named().ids().iter() {
@@ -327,11 +413,11 @@ impl Transition for ResAssignPass {
             // capmgr configuration must be first, and before
             // constructor configuration, as constructor has to be
             // able to replicate each capmgr's captbl layout
-            capmgr_config(&s, &k, &mut cfg);
-            constructor_config(&s, &k, &mut cfg);
-            sched_config(&s, &k, &mut cfg);
-            comp_config(&s, &k, &mut cfg);
-            res.insert(k.clone(), comp_config_finalize(&s, &k, cfg));
+            capmgr_config(s, k, &mut cfg);
+            constructor_config(s, k, &mut cfg);
+            sched_config(s, k, &mut cfg);
+            comp_config(s, k, &mut cfg);
+            res.insert(*k, comp_config_finalize(s, k, cfg));
         }
 
         Ok(Box::new(ResAssignPass { resources: res }))
@@ -340,6 +426,6 @@ impl Transition for ResAssignPass {
 
 impl ResPass for ResAssignPass {
     fn args(&self, id: &ComponentId) -> &Vec<ArgsKV> {
-        &self.resources.get(&id).unwrap()
+        self.resources.get(id).unwrap()
     }
 }
@@ -0,0 +1,256 @@
+// A software bill of materials (SBOM) for the system image, in
+// CycloneDX (1.5) JSON: each component's implementation, the
+// interface variants and libraries (in their transitive closure)
+// linked into it, and the source revision and license of each, so
+// that downstream users of an image can track its provenance and
+// licensing. Versions are the source directories' git revisions.
+
+use build::DefaultBuilder;
+use manifest::Json;
+use passes::{component, deps, exports, BuildState, ComponentId, ComposeError, SystemState};
+use provenance::revision;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use syshelpers::{dump_file, emit_file, sha256};
+
+// The license of the source tree, unless a directory has its own.
+const LICENSE: &str = "GPL-2.0-or-later WITH Classpath-exception-2.0";
+const LICENSE_FILES: [&str; 3] = ["LICENSE", "COPYING", "COPYRIGHT"];
+
+// The license files in `dir`, or in its immediate subdirectories
+// (e.g. those of an imported library's release), relative to `src`.
+fn license_files(src: &Path, dir: &Path) -> Vec<String> {
+    let entries = |d: &Path| -> Vec<_> {
+        let mut es: Vec<_> = fs::read_dir(d)
+            .map(|es| es.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        es.sort();
+        es
+    };
+    let is_license = |p: &Path| {
+        p.is_file()
+            && p.file_name().is_some_and(|n| {
+                let n = n.to_string_lossy().to_uppercase();
+                LICENSE_FILES.iter().any(|l| n.starts_with(l))
+            })
+    };
+
+    let mut files = Vec::new();
+    for e in entries(dir) {
+        let found: Vec<_> = if e.is_dir() {
+            entries(&e).into_iter().filter(|f| is_license(f)).collect()
+        } else if is_license(&e) {
+            vec![e]
+        } else {
+            Vec::new()
+        };
+        files.extend(
+            found
+                .iter()
+                .map(|f| f.strip_prefix(src).unwrap_or(f).display().to_string()),
+        );
+    }
+
+    files
+}
+
+fn licenses_json(src: &Path, dir: &Path) -> Json {
+    let files = license_files(src, dir);
+    if files.is_empty() {
+        return Json::Arr(vec![Json::obj(vec![("expression", Json::str(LICENSE))])]);
+    }
+
+    Json::Arr(
+        files
+            .iter()
+            .map(|f| {
+                Json::obj(vec![(
+                    "license",
+                    Json::obj(vec![("name", Json::Str(format!("See {}", f)))]),
+                )])
+            })
+            .collect(),
+    )
+}
+
+fn property(name: &str, value: String) -> Json {
+    Json::obj(vec![("name", Json::str(name)), ("value", Json::Str(value))])
+}
+
+// A component of the bill of materials built from the sources in
+// `dir`, with `fields` added.
+fn bom_component(
+    src: &Path,
+    kind: &str,
+    bom_ref: &String,
+    name: &String,
+    dir: &Path,
+    fields: Vec<(&str, Json)>,
+) -> Json {
+    let mut c = vec![
+        ("type", Json::str(kind)),
+        ("bom-ref", Json::str(bom_ref)),
+        ("name", Json::str(name)),
+    ];
+    if let Some(rev) = revision(dir) {
+        c.push(("version", Json::Str(rev)));
+    }
+    c.push(("licenses", licenses_json(src, dir)));
+    c.extend(fields);
+
+    Json::obj(c)
+}
+
+fn hashes(path: &String) -> Result<Json, ComposeError> {
+    Ok(Json::Arr(vec![Json::obj(vec![
+        ("alg", Json::str("SHA-256")),
+        ("content", Json::Str(sha256(&dump_file(path)?))),
+    ])]))
+}
+
+fn comp_ref(s: &SystemState, id: &ComponentId) -> String {
+    format!("component:{}", component(s, id).name)
+}
+
+// Write the SBOM into the build directory, and return its path.
+pub fn sbom_create(s: &SystemState, b: &DefaultBuilder) -> Result<String, ComposeError> {
+    let path = b.file_path(&"sbom.cdx.json".to_string())?;
+    let src = b.srcdir();
+
+    let mut comps = Vec::new();
+    let mut libs = BTreeSet::new();
+    let mut ifs = BTreeSet::new();
+    let mut graph = Vec::new();
+    for id in s.get_named().ids().keys() {
+        let c = component(s, id);
+        let decomp: Vec<&str> = c.source.split(".").collect();
+        let dir = src
+            .join("components/implementation")
+            .join(decomp[0])
+            .join(decomp[1]);
+        let exps: Vec<String> = exports(s, id)
+            .iter()
+            .map(|e| format!("{}/{}", e.interface, e.variant))
+            .collect();
+        comps.push(bom_component(
+            src,
+            "application",
+            &comp_ref(s, id),
+            &c.source,
+            &dir,
+            vec![
+                ("hashes", hashes(s.get_objs_id(id).comp_path())?),
+                (
+                    "properties",
+                    Json::Arr(vec![
+                        property("composite:variable", c.name.to_string()),
+                        property("composite:arch", c.arch.name().to_string()),
+                        property("composite:exports", exps.join(" ")),
+                    ]),
+                ),
+            ],
+        ));
+
+        let mut depends = Vec::new();
+        for (i, v) in exports(s, id)
+            .iter()
+            .map(|e| (&e.interface, &e.variant))
+            .chain(deps(s, id).iter().map(|d| (&d.interface, &d.variant)))
+        {
+            depends.push(Json::Str(format!("interface:{}/{}", i, v)));
+            ifs.insert((i.clone(), v.clone()));
+        }
+        for l in b.libs(id, s)?.into_iter() {
+            depends.push(Json::Str(format!("library:{}", l)));
+            libs.insert(l);
+        }
+        for d in deps(s, id).iter() {
+            depends.push(Json::Str(format!("component:{}", d.server)));
+        }
+        graph.push(Json::obj(vec![
+            ("ref", Json::Str(comp_ref(s, id))),
+            ("dependsOn", Json::Arr(depends)),
+        ]));
+    }
+    for (i, v) in ifs.iter() {
+        comps.push(bom_component(
+            src,
+            "library",
+            &format!("interface:{}/{}", i, v),
+            &format!("{}/{}", i, v),
+            &src.join("components/interface").join(i),
+            Vec::new(),
+        ));
+    }
+    for l in libs.iter() {
+        comps.push(bom_component(
+            src,
+            "library",
+            &format!("library:{}", l),
+            l,
+            &src.join("components/lib").join(l),
+            Vec::new(),
+        ));
+    }
+
+    let image = s.get_constructor().image_path();
+    let mut system = vec![
+        ("type", Json::str("operating-system")),
+        ("bom-ref", Json::str("system")),
+        (
+            "name",
+            Json::Str(
+                Path::new(&s.get_input())
+                    .file_stem()
+                    .map_or(String::new(), |n| n.to_string_lossy().to_string()),
+            ),
+        ),
+    ];
+    if let Some(rev) = revision(src) {
+        system.push(("version", Json::Str(rev)));
+    }
+    system.push(("hashes", hashes(image)?));
+    system.push((
+        "properties",
+        Json::Arr(vec![
+            property("composite:specification", s.get_input()),
+            property("composite:image", image.clone()),
+            property("composite:arch", s.get_spec().arch().name().to_string()),
+            property(
+                "composite:profile",
+                b.profile().map_or("default", |p| p.name()).to_string(),
+            ),
+        ]),
+    ));
+
+    // Without a timestamp or serial number, so that the SBOMs of
+    // identical builds are identical.
+    let sbom = Json::obj(vec![
+        ("bomFormat", Json::str("CycloneDX")),
+        ("specVersion", Json::str("1.5")),
+        ("version", Json::Num(1)),
+        (
+            "metadata",
+            Json::obj(vec![
+                (
+                    "tools",
+                    Json::obj(vec![(
+                        "components",
+                        Json::Arr(vec![Json::obj(vec![
+                            ("type", Json::str("application")),
+                            ("name", Json::str(env!("CARGO_PKG_NAME"))),
+                            ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+                        ])]),
+                    )]),
+                ),
+                ("component", Json::obj(system)),
+            ]),
+        ),
+        ("components", Json::Arr(comps)),
+        ("dependencies", Json::Arr(graph)),
+    ]);
+    emit_file(&path, format!("{}\n", sbom).as_bytes())?;
+
+    Ok(path)
+}
@@ -0,0 +1,318 @@
+// Serving a build's netboot/ directory (`mkimg serve`) to lab
+// machines, until interrupted: by TFTP (RFC 1350, with the blksize
+// and tsize options of RFCs 2348 and 2349, that PXE ROMs use), and
+// by HTTP GETs, for iPXE and grub. Only the directory's files are
+// served, and only for reading. Each transfer is handled by a thread
+// of its own. TFTP's port (69) is privileged, so serving it requires
+// root (or CAP_NET_BIND_SERVICE); either server is disabled with a
+// port of 0.
+
+use interrupt::interrupted;
+use passes::ComposeError;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use syshelpers::io_error;
+
+pub const TFTP_PORT: u16 = 69;
+pub const HTTP_PORT: u16 = 8080;
+
+const POLL_MS: u64 = 250;
+const TFTP_RRQ: u16 = 1;
+const TFTP_WRQ: u16 = 2;
+const TFTP_DATA: u16 = 3;
+const TFTP_ACK: u16 = 4;
+const TFTP_ERROR: u16 = 5;
+const TFTP_OACK: u16 = 6;
+const TFTP_ENOTFOUND: u16 = 1;
+const TFTP_EACCESS: u16 = 2;
+const TFTP_BLKSIZE: usize = 512; // without the blksize option
+const TFTP_BLKSIZE_MAX: usize = 65464;
+const TFTP_RETRIES: usize = 5;
+const TFTP_TIMEOUT_MS: u64 = 1000;
+const HTTP_HEADER_MAX: usize = 8192;
+const HTTP_TIMEOUT_S: u64 = 10;
+
+// The file at the (URL, or TFTP) `path` within `root`, if it's there.
+fn served_file(root: &Path, path: &str) -> Option<PathBuf> {
+    let rel = Path::new(path.trim_start_matches('/'));
+    if rel
+        .components()
+        .any(|c| c != Component::Normal(c.as_os_str()) && c != Component::CurDir)
+    {
+        return None;
+    }
+    let file = root.join(rel);
+
+    if file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+fn tftp_error(sock: &UdpSocket, code: u16, msg: &str) {
+    let mut pkt = TFTP_ERROR.to_be_bytes().to_vec();
+    pkt.extend_from_slice(&code.to_be_bytes());
+    pkt.extend_from_slice(msg.as_bytes());
+    pkt.push(0);
+    let _ = sock.send(&pkt);
+}
+
+// Send `pkt`, until the client acknowledges `block`, retransmitting
+// it after each timeout. Other (e.g. duplicate) acknowledgments are
+// ignored, rather than retransmitting for each of them.
+fn tftp_send_acked(sock: &UdpSocket, pkt: &[u8], block: u16) -> bool {
+    let mut buf = [0u8; 516];
+    for _ in 0..TFTP_RETRIES {
+        if sock.send(pkt).is_err() {
+            return false;
+        }
+        loop {
+            match sock.recv(&mut buf) {
+                Ok(n) if n >= 4 && buf[..2] == TFTP_ACK.to_be_bytes() => {
+                    if buf[2..4] == block.to_be_bytes() {
+                        return true;
+                    }
+                }
+                Ok(n) if n >= 2 && buf[..2] == TFTP_ERROR.to_be_bytes() => return false,
+                Ok(_) => (),
+                Err(_) => break, // timed out
+            }
+        }
+    }
+
+    false
+}
+
+// Transfer the file of the read request `req` to the `peer`, from a
+// socket of its own (the transfer's id).
+fn tftp_transfer(root: PathBuf, peer: SocketAddr, req: Vec<u8>) {
+    let sock = match UdpSocket::bind("0.0.0.0:0").and_then(|s| {
+        s.connect(peer)?;
+        s.set_read_timeout(Some(Duration::from_millis(TFTP_TIMEOUT_MS)))?;
+        Ok(s)
+    }) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    // The file's name, its mode, then the options' names and values.
+    let fields: Vec<String> = req
+        .split(|b| *b == 0)
+        .map(|f| String::from_utf8_lossy(f).to_string())
+        .collect();
+    let name = fields.first().cloned().unwrap_or_default();
+    let contents = match served_file(&root, &name).and_then(|f| fs::read(f).ok()) {
+        Some(c) => c,
+        None => {
+            info!("TFTP: {} requested {}, which isn't served.", peer, name);
+            return tftp_error(&sock, TFTP_ENOTFOUND, "File not found");
+        }
+    };
+    info!("TFTP: sending {} to {}.", name, peer);
+
+    let mut blksize = TFTP_BLKSIZE;
+    let mut oack = TFTP_OACK.to_be_bytes().to_vec();
+    for opt in fields[2.min(fields.len())..].chunks(2) {
+        let val = match opt {
+            [k, v] if k.eq_ignore_ascii_case("blksize") => match v.parse::<usize>() {
+                Ok(n) if n >= 8 => {
+                    blksize = n.min(TFTP_BLKSIZE_MAX);
+                    blksize.to_string()
+                }
+                _ => continue,
+            },
+            [k, _] if k.eq_ignore_ascii_case("tsize") => contents.len().to_string(),
+            _ => continue,
+        };
+        for f in [opt[0].as_str(), val.as_str()].iter() {
+            oack.extend_from_slice(f.as_bytes());
+            oack.push(0);
+        }
+    }
+    if oack.len() > 2 && !tftp_send_acked(&sock, &oack, 0) {
+        return;
+    }
+
+    // The last block is short (if need be, empty). Block numbers wrap
+    // around, for files of more than 65535 blocks.
+    let nblocks = contents.len() / blksize + 1;
+    for i in 0..nblocks {
+        let block = (i + 1) as u16;
+        let mut pkt = TFTP_DATA.to_be_bytes().to_vec();
+        pkt.extend_from_slice(&block.to_be_bytes());
+        let start = i * blksize;
+        pkt.extend_from_slice(&contents[start..(start + blksize).min(contents.len())]);
+        if !tftp_send_acked(&sock, &pkt, block) {
+            warn!("TFTP: sending {} to {} timed out.", name, peer);
+            return;
+        }
+    }
+}
+
+fn tftp_serve(root: PathBuf, sock: UdpSocket) {
+    let mut buf = [0u8; 1500];
+    while !interrupted() {
+        let (n, peer) = match sock.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(_) => continue, // timed out
+        };
+        if n < 2 {
+            continue;
+        }
+        match u16::from_be_bytes([buf[0], buf[1]]) {
+            TFTP_RRQ => {
+                let (root, req) = (root.clone(), buf[2..n].to_vec());
+                thread::spawn(move || tftp_transfer(root, peer, req));
+            }
+            TFTP_WRQ => {
+                if let Ok(s) = UdpSocket::bind("0.0.0.0:0") {
+                    if s.connect(peer).is_ok() {
+                        tftp_error(&s, TFTP_EACCESS, "Only reading is supported");
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Decode the %-escapes in the URL `path`.
+fn url_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
+fn http_respond(stream: &mut TcpStream, status: &str, len: u64) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, len
+    )
+}
+
+fn http_request(root: PathBuf, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(HTTP_TIMEOUT_S)))?;
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(HTTP_HEADER_MAX as u64));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    // The headers are read, and ignored.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let req: Vec<&str> = line.split_whitespace().collect();
+    let (method, target) = match req.as_slice() {
+        [m, t, v] if v.starts_with("HTTP/") => (*m, *t),
+        _ => return http_respond(&mut stream, "400 Bad Request", 0),
+    };
+    if method != "GET" && method != "HEAD" {
+        return http_respond(&mut stream, "405 Method Not Allowed", 0);
+    }
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    let file = match url_decode(path).and_then(|p| served_file(&root, &p)) {
+        Some(f) => f,
+        None => {
+            info!("HTTP: {} requested {}, which isn't served.", peer, target);
+            return http_respond(&mut stream, "404 Not Found", 0);
+        }
+    };
+    let mut f = fs::File::open(&file)?;
+    http_respond(&mut stream, "200 OK", f.metadata()?.len())?;
+    if method == "GET" {
+        info!("HTTP: sending {} to {}.", path, peer);
+        io::copy(&mut f, &mut stream)?;
+    }
+
+    Ok(())
+}
+
+fn http_serve(root: PathBuf, listener: TcpListener) {
+    while !interrupted() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let root = root.clone();
+                thread::spawn(move || {
+                    let _ = http_request(root, stream);
+                });
+            }
+            Err(_) => thread::sleep(Duration::from_millis(POLL_MS)),
+        }
+    }
+}
+
+fn bind_error(proto: &str, port: u16, e: io::Error) -> ComposeError {
+    ComposeError::Io {
+        path: format!("0.0.0.0:{}", port),
+        msg: format!(
+            "Could not serve {} on the port{}: {}",
+            proto,
+            if port < 1024 {
+                " (which is privileged, so requires root)"
+            } else {
+                ""
+            },
+            e
+        ),
+    }
+}
+
+// Serve the netboot directory `dir` by TFTP and HTTP, on the ports
+// (unless they're 0), until interrupted.
+pub fn netboot_serve(dir: &String, tftp_port: u16, http_port: u16) -> Result<(), ComposeError> {
+    let root = fs::canonicalize(dir).map_err(|e| {
+        io_error(
+            dir,
+            "find the netboot directory (that `mkimg pxe` creates)",
+            e,
+        )
+    })?;
+
+    let mut servers = Vec::new();
+    if tftp_port != 0 {
+        let sock = UdpSocket::bind(("0.0.0.0", tftp_port))
+            .and_then(|s| {
+                s.set_read_timeout(Some(Duration::from_millis(POLL_MS)))?;
+                Ok(s)
+            })
+            .map_err(|e| bind_error("TFTP", tftp_port, e))?;
+        println!("Serving {} by TFTP on port {}.", root.display(), tftp_port);
+        let root = root.clone();
+        servers.push(thread::spawn(move || tftp_serve(root, sock)));
+    }
+    if http_port != 0 {
+        let listener = TcpListener::bind(("0.0.0.0", http_port))
+            .and_then(|l| {
+                l.set_nonblocking(true)?;
+                Ok(l)
+            })
+            .map_err(|e| bind_error("HTTP", http_port, e))?;
+        println!("Serving {} by HTTP on port {}.", root.display(), http_port);
+        let root = root.clone();
+        servers.push(thread::spawn(move || http_serve(root, listener)));
+    }
+    for s in servers {
+        let _ = s.join();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+// Signing the system's images, so that a booter (or a deployment's
+// tooling) holding the public key can check that the components it
+// loads are those that were built. Each sealed component is signed,
+// and its signature packed into its constructor's tarball (at
+// signatures/<object>, alongside binaries/<object>); the tarball is
+// then signed as a whole, into <tarball>.sig. The public key is
+// passed to the constructor in its initargs.
+//
+// Keys are ed25519 private keys in PEM (PKCS#8), as generated by
+// `openssl genpkey -algorithm ed25519 -out key.pem`. Signatures are
+// the raw 64 bytes.
+
+use ed25519_compact::{KeyPair, Signature};
+use passes::ComposeError;
+use std::fs;
+use syshelpers::{dump_file, emit_file, hex, io_error};
+
+// The size of each signature, which the layout of the constructor's
+// archive depends on before the signatures are made.
+pub const SIGNATURE_SZ: u64 = Signature::BYTES as u64;
+
+pub fn signing_key_load(path: &String) -> Result<KeyPair, ComposeError> {
+    let pem = fs::read_to_string(path).map_err(|e| io_error(path, "read the signing key", e))?;
+    KeyPair::from_pem(&pem).map_err(|e| ComposeError::Io {
+        path: path.clone(),
+        msg: format!(
+            "Could not use the signing key: {} (expected an ed25519 private key in PEM)",
+            e
+        ),
+    })
+}
+
+// The public key, in hex, as the initargs hold strings.
+pub fn public_key_hex(key: &KeyPair) -> String {
+    hex(&key.pk[..])
+}
+
+// Sign the file at `path` with the key at `key`, writing the
+// signature to `signature`.
+pub fn file_sign(key: &String, path: &String, signature: &String) -> Result<(), ComposeError> {
+    let kp = signing_key_load(key)?;
+    let contents = dump_file(path)?;
+
+    emit_file(signature, &kp.sk.sign(&contents, None)[..])
+}
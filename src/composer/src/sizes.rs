@@ -0,0 +1,302 @@
+// The memory footprint of each component's sealed object, as `size`
+// (berkeley format) reports it: text includes read-only data, and
+// bss is the zero-filled data. Components (and the system
+// constructor's tarball) can be given budgets in the specification,
+// and exceeding them fails the build, which matters for embedded
+// targets with little memory. The size of each component's initargs
+// is reported in both of their formats, to help choose between them;
+// their budgets (max_initargs) are checked as they are generated.
+
+use build::{constructor_archive_file, constructor_args, DefaultBuilder};
+use initargs::{initargs_size, ArgsKV};
+use manifest::Json;
+use passes::{component, BuildState, ComposeError, InitargsFormat, ServiceType, SystemState};
+use std::fmt;
+use std::fs;
+use syshelpers::dump_file;
+use xmas_elf::sections::{ShType, SHF_ALLOC, SHF_WRITE};
+use xmas_elf::ElfFile;
+
+struct CompSize {
+    name: String,
+    text: u64,
+    data: u64,
+    bss: u64,
+    max: Option<u64>,
+}
+
+impl CompSize {
+    fn total(&self) -> u64 {
+        self.text + self.data + self.bss
+    }
+}
+
+// The size of a component's initargs in its `format` (checked
+// against its budget), and as both C data-structures and TLV.
+struct InitargsSize {
+    name: String,
+    format: InitargsFormat,
+    size: u64,
+    max: Option<u64>,
+    c: u64,
+    tlv: u64,
+}
+
+// The sizes of the components (largest first), and of the system
+// constructor's tarball.
+pub struct SizeReport {
+    comps: Vec<CompSize>,
+    initfs: u64,
+    max_initfs: Option<u64>,
+    initargs: Vec<InitargsSize>, // in the order of the components
+}
+
+// The (text, data, bss) sizes of the allocated sections of an object.
+fn obj_sizes(obj_path: &String) -> Result<(u64, u64, u64), ComposeError> {
+    let obj = dump_file(obj_path)?;
+    let elf_file = ElfFile::new(&obj).map_err(|e| {
+        ComposeError::Pass(format!(
+            "Error: Cannot parse the object {} as an ELF file: {}",
+            obj_path, e
+        ))
+    })?;
+
+    Ok(elf_file
+        .section_iter()
+        .filter(|s| s.flags() & SHF_ALLOC != 0)
+        .fold((0, 0, 0), |(text, data, bss), s| {
+            if s.get_type() == Ok(ShType::NoBits) {
+                (text, data, bss + s.size())
+            } else if s.flags() & SHF_WRITE != 0 {
+                (text, data + s.size(), bss)
+            } else {
+                (text + s.size(), data, bss)
+            }
+        }))
+}
+
+// The size of the system constructor's tarball, which holds all of
+// the other components (0 if it has no tarball).
+fn initfs_size(s: &SystemState, b: &DefaultBuilder) -> Result<u64, ComposeError> {
+    let (id, _) = s
+        .get_named()
+        .ids()
+        .iter()
+        .find(|(id, _)| component(s, id).constructor.var_name == "kernel")
+        .unwrap(); // the specification has exactly one
+    let tar = b.comp_file_path(id, &constructor_archive_file(s), s)?;
+
+    Ok(fs::metadata(&tar).map_or(0, |m| m.len()))
+}
+
+fn size_fmt(sz: Option<u64>) -> String {
+    sz.map_or(String::from("-"), |sz| sz.to_string())
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "component", "text", "data", "bss", "total", "max_size"
+        )?;
+        for c in self.comps.iter() {
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                c.name,
+                c.text,
+                c.data,
+                c.bss,
+                c.total(),
+                size_fmt(c.max)
+            )?;
+        }
+        let (text, data, bss) = self.comps.iter().fold((0, 0, 0), |(t, d, b), c| {
+            (t + c.text, d + c.data, b + c.bss)
+        });
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "(all components)",
+            text,
+            data,
+            bss,
+            text + data + bss,
+            "-"
+        )?;
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "(initfs)",
+            "-",
+            "-",
+            "-",
+            self.initfs,
+            size_fmt(self.max_initfs)
+        )?;
+        writeln!(
+            f,
+            "\n{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "initargs", "format", "size", "max_size", "c", "tlv"
+        )?;
+        for a in self.initargs.iter() {
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                a.name,
+                a.format.name(),
+                a.size,
+                size_fmt(a.max),
+                a.c,
+                a.tlv
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SizeReport {
+    // The size of all of the components.
+    pub fn total(&self) -> u64 {
+        self.comps.iter().map(|c| c.total()).sum()
+    }
+
+    pub fn initfs(&self) -> u64 {
+        self.initfs
+    }
+
+    // The total size of each component.
+    pub fn comp_totals(&self) -> Vec<(String, u64)> {
+        self.comps
+            .iter()
+            .map(|c| (c.name.clone(), c.total()))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let max = |m: Option<u64>| m.map_or(Json::Null, Json::Num);
+        Json::obj(vec![
+            (
+                "components",
+                Json::Arr(
+                    self.comps
+                        .iter()
+                        .map(|c| {
+                            Json::obj(vec![
+                                ("name", Json::str(&c.name)),
+                                ("text", Json::Num(c.text)),
+                                ("data", Json::Num(c.data)),
+                                ("bss", Json::Num(c.bss)),
+                                ("total", Json::Num(c.total())),
+                                ("max_size", max(c.max)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "initfs",
+                Json::obj(vec![
+                    ("size", Json::Num(self.initfs)),
+                    ("max_size", max(self.max_initfs)),
+                ]),
+            ),
+            (
+                "initargs",
+                Json::Arr(
+                    self.initargs
+                        .iter()
+                        .map(|a| {
+                            Json::obj(vec![
+                                ("name", Json::str(&a.name)),
+                                ("format", Json::str(a.format.name())),
+                                ("size", Json::Num(a.size)),
+                                ("max_size", max(a.max)),
+                                ("c", Json::Num(a.c)),
+                                ("tlv", Json::Num(a.tlv)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+// Check that the components, and the system constructor's tarball,
+// are within their budgets, and return the report of their sizes if
+// they are.
+pub fn sizes_check(s: &SystemState, b: &DefaultBuilder) -> Result<SizeReport, ComposeError> {
+    let mut sizes = Vec::new();
+    let mut initargs = Vec::new();
+    for (id, name) in s.get_named().ids() {
+        let c = component(s, id);
+        // Constructors are linked with initargs of their own.
+        let args = if s
+            .get_properties()
+            .service_is_a(id, ServiceType::Constructor)
+        {
+            constructor_args(id, s, b)?
+        } else {
+            ArgsKV::new_top(s.get_param_id(id).param_list().clone())
+        };
+        initargs.push(InitargsSize {
+            name: name.to_string(),
+            format: c.initargs_format,
+            size: initargs_size(c, &args),
+            max: c.max_initargs,
+            c: args.c_size(if c.arch.is_64bit() { 8 } else { 4 }),
+            tlv: args.tlv().len() as u64,
+        });
+        let (text, data, bss) = obj_sizes(s.get_objs_id(id).comp_path())?;
+        sizes.push(CompSize {
+            name: name.to_string(),
+            text,
+            data,
+            bss,
+            max: c.max_size,
+        });
+    }
+    sizes.sort_by(|a, b| b.total().cmp(&a.total()).then(a.name.cmp(&b.name)));
+
+    let mut errs = Vec::new();
+    for c in sizes.iter() {
+        if let Some(max) = c.max {
+            if c.total() > max {
+                errs.push(format!(
+                    "Error: Component {} is {} bytes (text {}, data {}, bss {}), which exceeds its max_size of {} bytes.",
+                    c.name, c.total(), c.text, c.data, c.bss, max
+                ));
+            }
+        }
+    }
+
+    let initfs = initfs_size(s, b)?;
+    let max_initfs = s.get_spec().max_initfs_size();
+    if let Some(max) = max_initfs {
+        if initfs > max {
+            errs.push(format!(
+                "Error: The constructor's initfs is {} bytes, which exceeds the system's max_initfs_size of {} bytes.",
+                initfs, max
+            ));
+        }
+    }
+
+    let report = SizeReport {
+        comps: sizes,
+        initfs,
+        max_initfs,
+        initargs,
+    };
+    if !errs.is_empty() {
+        return Err(ComposeError::Pass(format!(
+            "{}\n{}",
+            report,
+            errs.join("\n")
+        )));
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,68 @@
+// Stripping the sealed components (with --strip), so that the image
+// only holds what loading them requires. objcopy splits each sealed
+// object into a stripped object (<object>.stripped), which is packed
+// into its constructor's tarball (or, for the system's constructor,
+// linked into the kernel), and its symbols and debugging information
+// (<object>.debug), which are kept in the build directory, and which
+// the gdb bundle loads. The stripped object names its .debug file (in
+// a .gnu_debuglink section), so gdb finds it from the object as well.
+// The sealed object itself is kept, as the composer's later passes
+// read its symbols and sections.
+
+use passes::{Arch, ComposeError};
+use std::process::Command;
+use syshelpers::io_error;
+
+pub fn stripped_path(obj: &String) -> String {
+    format!("{}.stripped", obj)
+}
+
+pub fn debug_path(obj: &String) -> String {
+    format!("{}.debug", obj)
+}
+
+// The architecture's objcopy; the x86 platforms use the host's.
+pub fn objcopy(arch: Arch) -> String {
+    format!("{}objcopy", arch.cross_compile())
+}
+
+// Split the object at `path` into the `stripped` object and its
+// `debug` information, with `objcopy`.
+pub fn obj_strip(
+    objcopy: &String,
+    path: &String,
+    stripped: &String,
+    debug: &String,
+) -> Result<(), ComposeError> {
+    // The debug link is to the .debug file, so it must come first.
+    let steps = [
+        vec![
+            String::from("--only-keep-debug"),
+            path.clone(),
+            debug.clone(),
+        ],
+        vec![
+            String::from("--strip-all"),
+            format!("--add-gnu-debuglink={}", debug),
+            path.clone(),
+            stripped.clone(),
+        ],
+    ];
+    for args in steps.iter() {
+        let out = Command::new(objcopy)
+            .args(args)
+            .output()
+            .map_err(|e| io_error(path, &format!("execute {} to strip it", objcopy), e))?;
+        if !out.status.success() {
+            return Err(ComposeError::Io {
+                path: path.clone(),
+                msg: format!(
+                    "Could not strip the object: {}",
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -19,8 +19,8 @@ impl<'a> Symb<'a> {
         }
 
         Some(Symb {
-            name: name,
-            addr: addr,
+            name,
+            addr,
             stype: t,
         })
     }
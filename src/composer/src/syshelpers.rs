@@ -1,13 +1,32 @@
+extern crate shell_words;
+
 use crate::pipe::Pipe;
+use interrupt::{group_register, group_unregister, interrupt_handled};
+use passes::ComposeError;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 // FIXME: progs should be a more general iteration type
 // return a tuple of stdout/stderr
 pub fn exec_pipeline(progs: Vec<String>) -> (String, String) {
+    let (out, err, _) = exec_pipeline_status(progs);
+    (out, err)
+}
+
+// Same as exec_pipeline, but also return the exit status of the last
+// command in the pipeline.
+pub fn exec_pipeline_status(progs: Vec<String>) -> (String, String, ExitStatus) {
     let err_str = format!(
         "Failure in executing command: {}",
-        progs.iter().fold("".to_string(), |s, p| if s.len() == 0 {
-            format!("{}", p)
+        progs.iter().fold("".to_string(), |s, p| if s.is_empty() {
+            p.to_string()
         } else {
             format!("{} | {}", s, p)
         })
@@ -24,70 +43,368 @@ pub fn exec_pipeline(progs: Vec<String>) -> (String, String) {
     (
         String::from_utf8(output.stdout).unwrap(),
         String::from_utf8(output.stderr).unwrap(),
+        output.status,
     )
 }
 
-pub fn dump_file(name: &String) -> Result<Vec<u8>, String> {
-    use std::fs::File;
-    use std::io::Read;
+// A program and its arguments, executed directly (not through a
+// shell), so that each argument is passed verbatim regardless of the
+// spaces and quotes within it.
+#[derive(Clone, Debug)]
+pub struct Cmd {
+    prog: String,
+    args: Vec<String>,
+}
 
-    let file = File::open(name);
-    if let Err(e) = file {
-        return Err(String::from(format!("{}: {}", &name, e.to_string())));
+impl Cmd {
+    pub fn new(prog: &str) -> Cmd {
+        Cmd {
+            prog: prog.to_string(),
+            args: Vec::new(),
+        }
     }
 
-    let mut buf = Vec::new();
-    if file.unwrap().read_to_end(&mut buf).is_err() {
-        return Err(String::from(format!(
-            "Could not read data out of file {}.",
-            &name
-        )));
-    } else {
-        Ok(buf)
+    pub fn arg(mut self, a: &str) -> Cmd {
+        self.args.push(a.to_string());
+        self
+    }
+
+    // A make-style variable assignment, VAR=val, as a single argument
+    pub fn var(self, var: &str, val: &str) -> Cmd {
+        self.arg(&format!("{}={}", var, val))
     }
-}
 
-pub fn emit_file(name: &String, output: &[u8]) -> Result<(), String> {
-    if let Err(_) = fs::write(name, &output) {
-        return Err(String::from(format!("Could not write to file {}.\n", name)));
+    // The variables assigned by the command's arguments, in order.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        self.args
+            .iter()
+            .filter_map(|a| {
+                let mut kv = a.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some(k), Some(v))
+                        if !k.is_empty()
+                            && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                    {
+                        Some((k.to_string(), v.to_string()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
     }
 
-    let len = output.len();
-    match fs::metadata(name) {
-        Ok(md) => {
-            if md.len() as usize != len {
-                Err(String::from(format!(
-                    "File {} written to, but not correct length.\n",
-                    name
-                )))
-            } else {
-                Ok(())
+    // Execute the command, returning its stdout, stderr and exit
+    // status, as exec_pipeline_status does. If it runs for longer
+    // than `timeout`, kill it and all of the processes it started
+    // (e.g. make's sub-makes and compilers), and return None.
+    pub fn exec_timeout(&self, timeout: Option<Duration>) -> Option<(String, String, ExitStatus)> {
+        let mut cmd = Command::new(&self.prog);
+        cmd.args(&self.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // A process group of its own lets us kill all of the
+        // command's descendants. Only do so when necessary, as the
+        // group no longer receives the terminal's signals.
+        let group = timeout.is_some() || interrupt_handled();
+        if group {
+            cmd.process_group(0);
+        }
+        let mut child = cmd
+            .spawn()
+            .unwrap_or_else(|_| panic!("Failure in executing command: {}", self));
+        if group {
+            group_register(child.id());
+        }
+
+        // Drain stdout and stderr concurrently, each on its own
+        // thread, so that the command doesn't block on either full
+        // pipe while we wait on it.
+        let out_reader = pipe_drain(child.stdout.take().unwrap()); // unwrap as they are piped
+        let err_reader = pipe_drain(child.stderr.take().unwrap());
+
+        let start = Instant::now();
+        let status = loop {
+            let t = match timeout {
+                Some(t) => t,
+                None => break child.wait(),
+            };
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if start.elapsed() < t => thread::sleep(Duration::from_millis(50)),
+                Ok(None) => {
+                    group_kill(child.id(), "-KILL");
+                    let _ = child.wait();
+                    let _ = out_reader.join();
+                    let _ = err_reader.join();
+                    group_unregister(child.id());
+                    return None;
+                }
+                Err(e) => break Err(e),
             }
         }
-        _ => Err(String::from(format!(
-            "Could not retrieve the metadata for file {}.\n",
-            name
-        ))),
+        .unwrap_or_else(|_| panic!("Failure in executing command: {}", self));
+        if group {
+            group_unregister(child.id());
+        }
+        let out = out_reader.join().unwrap_or_default();
+        let err = err_reader.join().unwrap_or_default();
+
+        Some((
+            String::from_utf8_lossy(&out).to_string(),
+            String::from_utf8_lossy(&err).to_string(),
+            status,
+        ))
+    }
+}
+
+// Read all of `pipe` on a thread of its own, returning its contents
+// when joined.
+fn pipe_drain<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut contents = Vec::new();
+        let _ = pipe.read_to_end(&mut contents);
+        contents
+    })
+}
+
+// Send the signal `sig` (e.g. "-KILL") to the process group `pgid`.
+pub fn group_kill(pgid: u32, sig: &str) {
+    // The negated pid names the process group
+    let _ = Command::new("kill")
+        .arg(sig)
+        .arg("--")
+        .arg(format!("-{}", pgid))
+        .status();
+}
+
+// The command as it would be typed into a shell, with arguments
+// quoted as needed.
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words: Vec<String> = std::iter::once(&self.prog)
+            .chain(self.args.iter())
+            .map(|a| shell_words::quote(a).to_string())
+            .collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+// An error performing `op` (e.g. "open") on the file at `path`,
+// including the OS's reason (and its errno).
+pub fn io_error<P: AsRef<Path>>(path: P, op: &str, e: io::Error) -> ComposeError {
+    ComposeError::Io {
+        path: path.as_ref().display().to_string(),
+        msg: format!("Could not {}: {}", op, e),
+    }
+}
+
+pub fn dump_file(name: &String) -> Result<Vec<u8>, ComposeError> {
+    let mut file = fs::File::open(name).map_err(|e| io_error(name, "open the file", e))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)
+        .map_err(|e| io_error(name, "read the file", e))?;
+
+    Ok(buf)
+}
+
+pub fn emit_file(name: &String, output: &[u8]) -> Result<(), ComposeError> {
+    fs::write(name, output).map_err(|e| io_error(name, "write the file", e))?;
+
+    let md = fs::metadata(name).map_err(|e| io_error(name, "retrieve the file's metadata", e))?;
+    if md.len() as usize != output.len() {
+        return Err(ComposeError::Io {
+            path: name.clone(),
+            msg: String::from("File written to, but not correct length."),
+        });
     }
+
+    Ok(())
 }
 
-// remove directory, all contents, and remake it
-pub fn reset_dir(dirname: &String) -> Result<(), String> {
+// remove directory, all contents, and remake it (along with any
+// missing parents)
+pub fn reset_dir(dirname: &String) -> Result<(), ComposeError> {
     assert!(dirname != "/"); // small sanity check
-    let _ = fs::remove_dir_all(&dirname); // failure here is fine; we're creating next anyway
-    match fs::create_dir(&dirname) {
+    if let Err(e) = fs::remove_dir_all(dirname) {
+        // it is fine if there's nothing to remove
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(ComposeError::Io {
+                path: dirname.clone(),
+                msg: format!("Could not remove the previous directory: {}", e),
+            });
+        }
+    }
+    match fs::create_dir_all(dirname) {
         Ok(_) => Ok(()),
-        Err(_) => Err(String::from(format!(
-            "Could not create directory {}\n",
-            dirname
-        ))),
+        Err(e) => Err(ComposeError::Io {
+            path: dirname.clone(),
+            msg: format!("Could not create directory: {}", e),
+        }),
     }
 }
 
 pub fn dir_exists(dirname: &String) -> bool {
-    if let Err(_) = fs::read_dir(&dirname) {
-        false
-    } else {
-        true
+    fs::read_dir(dirname).is_ok()
+}
+
+// A simple, stable (across runs and toolchains) 64-bit FNV-1a hash
+// used to detect changes in build inputs. This is not cryptographic.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub fn hash_bytes(hash: Option<u64>, data: &[u8]) -> u64 {
+    data.iter().fold(hash.unwrap_or(FNV_OFFSET), |h, b| {
+        (h ^ (*b as u64)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// SHA-256 (FIPS 180-4) of `data` as a hex string, so that consumers
+// of the build's artifacts can verify them with standard tools.
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+pub fn sha256(data: &[u8]) -> String {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // pad with a 1 bit, zeros, and the message's length in bits
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                block[i * 4],
+                block[i * 4 + 1],
+                block[i * 4 + 2],
+                block[i * 4 + 3],
+            ]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for i in 0..8 {
+            h[i] = h[i].wrapping_add(v[i]);
+        }
+    }
+
+    h.iter().map(|x| format!("{:08x}", x)).collect()
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Is the file one of the sources that drive a build (rather than a
+// generated object/dependency file)?
+pub fn is_source_file(name: &str) -> bool {
+    let exts = [".c", ".h", ".cc", ".S", ".s", ".ld", ".rs", ".toml"];
+    name.starts_with("Makefile") || exts.iter().any(|e| name.ends_with(e))
+}
+
+// Hash all of the source files (names and contents) in a directory
+// tree, in a deterministic order. Missing directories hash as empty.
+pub fn hash_dir_sources(hash: u64, dirname: &String) -> Result<u64, ComposeError> {
+    let mut entries: Vec<fs::DirEntry> = match fs::read_dir(dirname) {
+        Ok(rd) => rd.filter_map(|e| e.ok()).collect(),
+        Err(_) => return Ok(hash),
+    };
+    entries.sort_by_key(|e| e.file_name());
+
+    let mut h = hash;
+    for e in entries {
+        let path = format!("{}/{}", dirname, e.file_name().to_string_lossy());
+        let ft = match e.file_type() {
+            Ok(ft) => ft,
+            Err(_) => continue,
+        };
+        if ft.is_dir() {
+            h = hash_dir_sources(h, &path)?;
+        } else if is_source_file(&e.file_name().to_string_lossy()) {
+            h = hash_bytes(Some(h), path.as_bytes());
+            h = hash_bytes(Some(h), &dump_file(&path)?);
+        }
+    }
+
+    Ok(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The examples of FIPS 180-4 (the 56-byte message is padded to a
+    // second block), and a common one.
+    #[test]
+    fn sha256_known_answers() {
+        let vectors: [(&[u8], &str); 5] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                b"The quick brown fox jumps over the lazy dog",
+                "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592",
+            ),
+            (
+                &[b'a'; 1_000_000],
+                "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            ),
+        ];
+        for (data, digest) in vectors.iter() {
+            assert_eq!(sha256(data), *digest);
+        }
     }
 }
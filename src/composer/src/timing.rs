@@ -0,0 +1,314 @@
+// The time that each of the build's steps took: each component's
+// build, and each constructor's (e.g. the booter's) steps, generating
+// the tarball of the components it creates, and linking it, then the
+// kernel's link, so that it's clear which steps dominate the build,
+// and whether building the components concurrently (--jobs) is
+// effective. Of the time that components were being built, the
+// parallelism is how many were built at once, on average.
+//
+// timing.txt lists the steps, slowest first, and trace.json is their
+// timeline, in the Trace Event Format that chrome://tracing (and
+// Perfetto) display, with a row for each of the jobs building
+// components, and one for the steps that follow them.
+
+use build::DefaultBuilder;
+use manifest::Json;
+use passes::{BuildState, ComposeError};
+use std::cmp::Reverse;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use syshelpers::emit_file;
+
+// The slowest steps that are printed after the build (timing.txt has
+// all of them).
+const TIMING_SHOWN: usize = 10;
+
+// What a step of the build does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    Component,   // build a component
+    Strip,       // strip a component's object
+    Sign,        // sign an object, or a tarball
+    Tarball,     // create a constructor's tarball of the components it creates
+    Constructor, // link a constructor with its tarball
+    Kernel,      // link the kernel image with the system constructor
+}
+
+impl StepKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepKind::Component => "component",
+            StepKind::Strip => "strip",
+            StepKind::Sign => "sign",
+            StepKind::Tarball => "tarball",
+            StepKind::Constructor => "constructor",
+            StepKind::Kernel => "kernel",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub name: String,
+    pub kind: StepKind,
+    pub lane: usize, // the job that built a component, from 1, or 0
+    pub start: Duration,
+    pub end: Duration,
+    pub cached: bool, // a component whose cached object was reused
+}
+
+impl Step {
+    fn duration(&self) -> Duration {
+        self.end.checked_sub(self.start).unwrap_or_default()
+    }
+}
+
+// The steps of a build, as they're completed (possibly by concurrent
+// jobs), timed from when the build started.
+pub struct Timings {
+    epoch: Instant,
+    steps: Mutex<Vec<Step>>,
+}
+
+impl Timings {
+    pub fn new() -> Timings {
+        Timings {
+            epoch: Instant::now(),
+            steps: Mutex::new(Vec::new()),
+        }
+    }
+
+    // When the build started, for jobs to time their steps from.
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+
+    pub fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    pub fn record(&self, step: Step) {
+        self.steps.lock().unwrap().push(step);
+    }
+
+    fn steps(&self) -> Vec<Step> {
+        self.steps.lock().unwrap().clone()
+    }
+}
+
+// The build's steps, slowest first, the time from the first's start
+// to the last's end, and that during which components were being
+// built, with the jobs that could build them.
+pub struct TimingReport {
+    steps: Vec<Step>,
+    wall: Duration,
+    comps_wall: Duration,
+    jobs: usize,
+}
+
+fn secs(d: Duration) -> String {
+    format!("{:.2}s", d.as_secs_f64())
+}
+
+fn micros(d: Duration) -> Json {
+    Json::Num(d.as_micros() as u64)
+}
+
+// The time covered by the `spans`, counting overlaps once.
+fn covered(mut spans: Vec<(Duration, Duration)>) -> Duration {
+    spans.sort();
+    let mut total = Duration::from_secs(0);
+    let mut reached = Duration::from_secs(0);
+    for (start, end) in spans.into_iter() {
+        let from = start.max(reached);
+        if end > from {
+            total += end - from;
+            reached = end;
+        }
+    }
+
+    total
+}
+
+impl TimingReport {
+    // The build's steps, slowest first.
+    pub fn steps(&self) -> &Vec<Step> {
+        &self.steps
+    }
+
+    fn comps_total(&self) -> Duration {
+        self.steps
+            .iter()
+            .filter(|s| s.kind == StepKind::Component)
+            .map(|s| s.duration())
+            .sum()
+    }
+
+    // How many components were being built at once, on average.
+    fn parallelism(&self) -> f64 {
+        if self.comps_wall.as_nanos() == 0 {
+            return 0.0;
+        }
+        self.comps_total().as_secs_f64() / self.comps_wall.as_secs_f64()
+    }
+
+    // The summary, and the `n` slowest steps (or all of them).
+    fn summary(&self, n: Option<usize>) -> String {
+        let comps: Vec<&Step> = self
+            .steps
+            .iter()
+            .filter(|s| s.kind == StepKind::Component)
+            .collect();
+        let mut txt = format!(
+            "Build steps took {}: {} components ({} cached) took {} over {}, a parallelism of {:.2} (of {} jobs).\n",
+            secs(self.wall),
+            comps.len(),
+            comps.iter().filter(|s| s.cached).count(),
+            secs(self.comps_total()),
+            secs(self.comps_wall),
+            self.parallelism(),
+            self.jobs
+        );
+        for s in self.steps.iter().take(n.unwrap_or(self.steps.len())) {
+            txt.push_str(&format!(
+                "{:>10} {:<12} {}{}\n",
+                secs(s.duration()),
+                s.kind.name(),
+                s.name,
+                if s.cached { " (cached)" } else { "" }
+            ));
+        }
+
+        txt
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::obj(vec![
+            ("wall_us", micros(self.wall)),
+            ("components_wall_us", micros(self.comps_wall)),
+            ("components_us", micros(self.comps_total())),
+            ("jobs", Json::Num(self.jobs as u64)),
+            (
+                "steps",
+                Json::Arr(
+                    self.steps
+                        .iter()
+                        .map(|s| {
+                            Json::obj(vec![
+                                ("name", Json::str(&s.name)),
+                                ("kind", Json::str(s.kind.name())),
+                                ("start_us", micros(s.start)),
+                                ("duration_us", micros(s.duration())),
+                                ("cached", Json::Bool(s.cached)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.summary(Some(TIMING_SHOWN)))
+    }
+}
+
+// The steps' timeline, as Trace Event Format "complete" events, with
+// a (named) row for each job.
+fn trace_json(steps: &[Step], b: &DefaultBuilder) -> Json {
+    let mut lanes: Vec<usize> = steps.iter().map(|s| s.lane).collect();
+    lanes.sort();
+    lanes.dedup();
+    let meta = |name: &str, tid: usize, val: String| {
+        Json::obj(vec![
+            ("name", Json::str(name)),
+            ("ph", Json::str("M")),
+            ("pid", Json::Num(1)),
+            ("tid", Json::Num(tid as u64)),
+            ("args", Json::obj(vec![("name", Json::Str(val))])),
+        ])
+    };
+    let mut events = vec![meta(
+        "process_name",
+        0,
+        format!("build {}", b.builddir().display()),
+    )];
+    for l in lanes.iter() {
+        events.push(meta(
+            "thread_name",
+            *l,
+            match l {
+                0 => String::from("constructors and kernel"),
+                _ => format!("job {}", l),
+            },
+        ));
+    }
+    let mut by_start: Vec<&Step> = steps.iter().collect();
+    by_start.sort_by_key(|s| s.start);
+    for s in by_start.iter() {
+        events.push(Json::obj(vec![
+            ("name", Json::str(&s.name)),
+            ("cat", Json::str(s.kind.name())),
+            ("ph", Json::str("X")),
+            ("ts", micros(s.start)),
+            ("dur", micros(s.duration())),
+            ("pid", Json::Num(1)),
+            ("tid", Json::Num(s.lane as u64)),
+            ("args", Json::obj(vec![("cached", Json::Bool(s.cached))])),
+        ]));
+    }
+
+    Json::obj(vec![
+        ("traceEvents", Json::Arr(events)),
+        ("displayTimeUnit", Json::str("ms")),
+    ])
+}
+
+// Write timing.txt, and trace.json, into the build directory, and
+// return the report, and their paths.
+pub fn timing_create(b: &DefaultBuilder) -> Result<(TimingReport, (String, String)), ComposeError> {
+    let mut steps = b.timings().steps();
+    let wall = match (
+        steps.iter().map(|s| s.start).min(),
+        steps.iter().map(|s| s.end).max(),
+    ) {
+        (Some(first), Some(last)) => last - first,
+        _ => Duration::from_secs(0),
+    };
+    let comps_wall = covered(
+        steps
+            .iter()
+            .filter(|s| s.kind == StepKind::Component)
+            .map(|s| (s.start, s.end))
+            .collect(),
+    );
+    let trace = trace_json(&steps, b);
+    steps.sort_by_key(|s| Reverse(s.duration()));
+    let report = TimingReport {
+        steps,
+        wall,
+        comps_wall,
+        jobs: b.jobs(),
+    };
+
+    let txt_path = b.file_path(&"timing.txt".to_string())?;
+    emit_file(&txt_path, report.summary(None).as_bytes())?;
+    let trace_path = b.file_path(&"trace.json".to_string())?;
+    emit_file(&trace_path, format!("{}\n", trace).as_bytes())?;
+
+    Ok((report, (txt_path, trace_path)))
+}
+
+// The name of a step that isn't a component's build: of the object
+// (or tarball) `path`, or of the constructor whose directory holds it.
+pub fn step_name(path: &String, dir: bool) -> String {
+    let p = Path::new(path);
+    let p = if dir { p.parent().unwrap_or(p) } else { p };
+
+    p.file_name()
+        .map_or(path.clone(), |n| n.to_string_lossy().to_string())
+}
@@ -1,6 +1,6 @@
 use passes::{
-    AddrSpace, AddrSpaces, BuildState, ComponentId, ComponentName, OrderedSpecPass, SystemState,
-    Transition,
+    AddrSpace, AddrSpaces, BuildState, ComponentId, ComponentName, ComposeError, OrderedSpecPass,
+    SystemState, Transition,
 };
 use std::collections::{BTreeMap, HashSet};
 
@@ -36,7 +36,7 @@ fn addrspc_dfs_via_children(
     addrspaces: &AddrSpaces,
 ) {
     agg.insert(*offset, curr.clone());
-    *offset = *offset + 1;
+    *offset += 1;
     for child_name in &curr.children {
         // Unwrap should be OK as we've already validated the
         // component names.
@@ -46,7 +46,7 @@ fn addrspc_dfs_via_children(
 }
 
 impl Transition for CompTotOrd {
-    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, String> {
+    fn transition(s: &SystemState, _b: &mut dyn BuildState) -> Result<Box<Self>, ComposeError> {
         let spec = s.get_spec();
 
         // Find a total order of components based on the dependency
@@ -56,7 +56,7 @@ impl Transition for CompTotOrd {
         // in the total order?
         let mut remaining: BTreeMap<ComponentName, Vec<ComponentName>> = BTreeMap::new();
         for n in spec.names().iter() {
-            let cons = &spec.component_named(&n).constructor;
+            let cons = &spec.component_named(n).constructor;
             let mut ds: Vec<ComponentName> = spec
                 .deps_named(n)
                 .iter()
@@ -67,12 +67,21 @@ impl Transition for CompTotOrd {
             }
             remaining.insert(n.clone(), ds);
         }
-        while remaining.len() != 0 {
+        // The components each must be created after (rather than the
+        // kernel)
+        let preds: BTreeMap<ComponentName, Vec<ComponentName>> = remaining
+            .iter()
+            .map(|(n, ds)| {
+                let ds = ds.iter().filter(|d| remaining.contains_key(d)).cloned();
+                (n.clone(), ds.collect())
+            })
+            .collect();
+        while !remaining.is_empty() {
             let mut no_deps = Vec::new();
             for (n, ds) in remaining.iter() {
                 let mut found = false;
                 for d in ds.iter() {
-                    if let Some(_) = remaining.get(d) {
+                    if remaining.contains_key(d) {
                         found = true;
                         break;
                     }
@@ -81,22 +90,76 @@ impl Transition for CompTotOrd {
                     no_deps.push(n.clone());
                 }
             }
-            // TODO: We found a cycle. Error out properly...
+            // If no component can be placed, the remaining
+            // components (or some subset of them) form a cycle.
             let len = no_deps.len();
-            assert!(len > 0);
-            for j in 0..len {
-                remaining.remove(&no_deps[j]);
-                tot_ord.push(no_deps[j].clone());
+            if len == 0 {
+                let stuck = remaining
+                    .iter()
+                    .map(|(n, ds)| {
+                        let waits: Vec<String> = ds
+                            .iter()
+                            .filter(|d| remaining.contains_key(d))
+                            .map(|d| d.to_string())
+                            .collect();
+                        format!("\t{} depends on {}\n", n, waits.join(", "))
+                    })
+                    .collect::<String>();
+                return Err(ComposeError::Spec(format!(
+                    "Error: Cannot order the components, as their dependencies and constructor relationships form a cycle. The following components cannot be ordered:\n{}",
+                    stuck
+                )));
+            }
+            for n in no_deps.iter() {
+                remaining.remove(n);
+                tot_ord.push(n.clone());
             }
         }
 
+        // Components are created in the order of their ids, so assign
+        // them in the total order, other than those the specification
+        // pins: each id goes to the component pinned to it, or to the
+        // first in the order that is created after only components
+        // with lower ids. Without pins, this is the total order.
+        let pins: BTreeMap<ComponentId, ComponentName> = tot_ord
+            .iter()
+            .filter_map(|n| spec.component_named(n).pinned_id.map(|id| (id, n.clone())))
+            .collect();
+        let mut unpinned: Vec<ComponentName> = tot_ord
+            .iter()
+            .filter(|n| spec.component_named(n).pinned_id.is_none())
+            .cloned()
+            .collect();
         let mut comps = BTreeMap::new();
         let mut rmap = BTreeMap::new();
-        let mut id: ComponentId = 1;
-        for c in tot_ord {
+        for id in 1..(tot_ord.len() as ComponentId + 1) {
+            let c = match pins.get(&id) {
+                Some(n) => {
+                    if let Some(d) = preds[n].iter().find(|d| !rmap.contains_key(*d)) {
+                        return Err(ComposeError::Spec(format!(
+                            "Error: Component {} is pinned to id {}, but it must be created after {}, which would have a larger id.",
+                            n, id, d
+                        )));
+                    }
+                    n.clone()
+                }
+                None => match unpinned
+                    .iter()
+                    .position(|n| preds[n].iter().all(|d| rmap.contains_key(d)))
+                {
+                    Some(i) => unpinned.remove(i),
+                    None => {
+                        let waiting: Vec<String> = unpinned.iter().map(|n| n.to_string()).collect();
+                        return Err(ComposeError::Spec(format!(
+                            "Error: Cannot assign id {}, as the components without pinned ids ({}) must be created after those pinned to larger ids.",
+                            id,
+                            waiting.join(", ")
+                        )));
+                    }
+                },
+            };
             comps.insert(id, c.clone());
-            rmap.insert(c.clone(), id);
-            id = id + 1;
+            rmap.insert(c, id);
         }
 
         // Order the address spaces so that they (and their
@@ -104,24 +167,21 @@ impl Transition for CompTotOrd {
         let mut addrspc_comps = BTreeMap::new();
         let mut offset = 0;
         let mut comps_track_exclusive: HashSet<ComponentName> = comps.values().cloned().collect();
-        for (_, a) in spec.address_spaces() {
+        for a in spec.address_spaces().values() {
             // a "root" of the AS hierarchy, recurs from there to do a DFS
             if a.parent.is_none() {
-                addrspc_dfs_via_children(
-                    &mut offset,
-                    &mut addrspc_comps,
-                    &a,
-                    &spec.address_spaces(),
-                );
+                addrspc_dfs_via_children(&mut offset, &mut addrspc_comps, a, spec.address_spaces());
             }
             // Remove components that are explicitly in address
             // spaces, so that we can track the *rest* that are in an
             // exclusive AS.
             for c in &a.components {
-                comps_track_exclusive.remove(&c);
+                comps_track_exclusive.remove(c);
             }
         }
-        let addrspc_exclusive = comps_track_exclusive.into_iter().collect();
+        // Sorted, so that the order is the same across builds.
+        let mut addrspc_exclusive: Vec<ComponentName> = comps_track_exclusive.into_iter().collect();
+        addrspc_exclusive.sort();
 
         Ok(Box::new(CompTotOrd {
             ids: comps,
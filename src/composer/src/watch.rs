@@ -0,0 +1,234 @@
+// Watching the system's sources (`mkimg watch`), to rebuild it when
+// they change. The specification is watched through its directory
+// (editors often replace files, rather than writing them), as are the
+// directories of the sources that the components are built from:
+// their implementations, and the interfaces they export and depend
+// on, including their subdirectories. Only changes to source files
+// (as the object cache hashes them) prompt a rebuild, and the
+// rebuild only builds the components whose sources changed, as the
+// others' objects are cached.
+//
+// Changes are reported once they settle, so that saving several files
+// (or a checkout) prompts one rebuild.
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use interrupt::interrupted;
+use passes::ComposeError;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use syshelpers::is_source_file;
+
+const POLL_MS: u64 = 100;
+const SETTLE_MS: u64 = 300;
+const EVENT_BUF_SZ: usize = 4096;
+
+// Editors' backups, swap files, and lock files.
+fn is_ignored(name: &str) -> bool {
+    name.starts_with('.')
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swx")
+        || name.ends_with(".lock")
+}
+
+pub struct Watcher {
+    inotify: Inotify,
+    // The directory of each watch, and whether it's a source directory
+    // (whose source files, and subdirectories, are watched), rather
+    // than the specification's (whose only the specification is).
+    dirs: HashMap<WatchDescriptor, (PathBuf, bool)>,
+    // The source directories added (with their subdirectories).
+    sources: BTreeSet<PathBuf>,
+    // The specification's name in its directory.
+    spec: OsString,
+}
+
+fn watch_error(dir: &Path, e: io::Error) -> ComposeError {
+    let hint = match e.raw_os_error() {
+        // ENOSPC
+        Some(28) => " (raise the limit on the watches, fs.inotify.max_user_watches)",
+        _ => "",
+    };
+    ComposeError::Io {
+        path: dir.display().to_string(),
+        msg: format!("Could not watch the directory for changes: {}{}", e, hint),
+    }
+}
+
+impl Watcher {
+    // Watch the directory of the specification `spec`.
+    pub fn new(spec: &String) -> Result<Watcher, ComposeError> {
+        let inotify = Inotify::init().map_err(|e| ComposeError::Io {
+            path: spec.clone(),
+            msg: format!("Could not watch the specification for changes: {}", e),
+        })?;
+        let path = Path::new(spec);
+        let mut w = Watcher {
+            inotify,
+            dirs: HashMap::new(),
+            sources: BTreeSet::new(),
+            spec: path
+                .file_name()
+                .map_or(OsString::new(), |n| n.to_os_string()),
+        };
+        let dir = match path.parent() {
+            Some(d) if d != Path::new("") => d.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        w.watch_dir(&dir, false)?;
+
+        Ok(w)
+    }
+
+    fn watch_dir(&mut self, dir: &Path, sources: bool) -> Result<(), ComposeError> {
+        let mask = WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        let wd = self
+            .inotify
+            .watches()
+            .add(dir, mask)
+            .map_err(|e| watch_error(dir, e))?;
+        self.dirs.insert(wd, (dir.to_path_buf(), sources));
+
+        Ok(())
+    }
+
+    // Watch the source directory `dir`, and its subdirectories.
+    fn watch_tree(&mut self, dir: &Path) -> Result<(), ComposeError> {
+        self.watch_dir(dir, true)?;
+        let mut subdirs: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(rd) => rd
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .filter(|e| !is_ignored(&e.file_name().to_string_lossy()))
+                .map(|e| e.path())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        subdirs.sort();
+        for d in subdirs.iter() {
+            self.watch_tree(d)?;
+        }
+
+        Ok(())
+    }
+
+    // Also watch the source directories `dirs` (that a build reported)
+    // that aren't already watched. Those that don't exist yet can't
+    // be watched, and the build reports them as missing.
+    pub fn watch_sources(&mut self, dirs: &[PathBuf]) -> Result<(), ComposeError> {
+        for d in dirs.iter() {
+            if self.sources.contains(d) || !d.is_dir() {
+                continue;
+            }
+            self.watch_tree(d)?;
+            self.sources.insert(d.clone());
+        }
+
+        Ok(())
+    }
+
+    // The file that an event in the watch `wd` is about, if it's one
+    // whose changes prompt a rebuild. Directories created in the
+    // source directories are watched as well.
+    fn changed(
+        &mut self,
+        wd: &WatchDescriptor,
+        mask: EventMask,
+        name: Option<&OsStr>,
+    ) -> Result<Option<PathBuf>, ComposeError> {
+        let (dir, sources, name) = match (self.dirs.get(wd), name) {
+            (Some((d, s)), Some(n)) if *s || n == self.spec => (d.clone(), *s, n),
+            _ => return Ok(None),
+        };
+        let name = name.to_string_lossy().to_string();
+        if is_ignored(&name) {
+            return Ok(None);
+        }
+        let path = dir.join(&name);
+        if mask.contains(EventMask::ISDIR) {
+            // The files within it may have been created before it was
+            // watched, so it's reported as changed.
+            if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) && path.is_dir() {
+                self.watch_tree(&path)?;
+            }
+            return Ok(Some(path));
+        }
+        if sources && !is_source_file(&name) {
+            return Ok(None);
+        }
+
+        Ok(Some(path))
+    }
+
+    // Wait for the sources to change, and return the files that
+    // changed, once the changes settle, or None if interrupted.
+    pub fn wait(&mut self) -> Result<Option<Vec<PathBuf>>, ComposeError> {
+        info!(
+            "Watching the specification and {} source directories for changes (interrupt to stop).",
+            self.sources.len()
+        );
+        let mut buf = [0u8; EVENT_BUF_SZ];
+        let mut changes = BTreeSet::new();
+        let mut last = Instant::now();
+        loop {
+            if interrupted() {
+                return Ok(None);
+            }
+            let events: Vec<(WatchDescriptor, EventMask, Option<PathBuf>)> =
+                match self.inotify.read_events(&mut buf) {
+                    Ok(evs) => evs
+                        .map(|e| (e.wd, e.mask, e.name.map(PathBuf::from)))
+                        .collect(),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Vec::new(),
+                    Err(e) => {
+                        return Err(ComposeError::Io {
+                            path: String::from("inotify"),
+                            msg: format!("Could not read the changes to the sources: {}", e),
+                        })
+                    }
+                };
+            for (wd, mask, name) in events.iter() {
+                // Events were lost, so anything could have changed.
+                if mask.contains(EventMask::Q_OVERFLOW) {
+                    changes.insert(PathBuf::from("(too many changes to track)"));
+                    continue;
+                }
+                // The directory was removed, so it's watched again if
+                // it's recreated.
+                if mask.contains(EventMask::IGNORED) {
+                    if let Some((d, _)) = self.dirs.remove(wd) {
+                        self.sources.remove(&d);
+                    }
+                    continue;
+                }
+                let n = name.as_ref().map(|n| n.as_os_str());
+                if let Some(p) = self.changed(wd, *mask, n)? {
+                    changes.insert(p);
+                }
+            }
+            if !events.is_empty() {
+                last = Instant::now();
+            }
+            if !changes.is_empty() && last.elapsed() >= Duration::from_millis(SETTLE_MS) {
+                info!("Rebuilding, as the sources changed:");
+                for c in changes.iter() {
+                    info!("\t{}", c.display());
+                }
+                return Ok(Some(changes.into_iter().collect()));
+            }
+            if events.is_empty() {
+                thread::sleep(Duration::from_millis(POLL_MS));
+            }
+        }
+    }
+}
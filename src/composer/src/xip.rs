@@ -0,0 +1,242 @@
+// Execute-in-place (XIP) layouts, for flash-based boards (the spec's
+// [xip] table). Each component's read-only segments (.text and
+// .rodata) are linked at, and execute from, flash addresses, and only
+// its writable segments are linked at RAM addresses. xip.bin is the
+// flash's image, from its base: the booter's copy table, each
+// component's read-only segments at their addresses, then the initial
+// contents of their writable segments, which the booter copies into
+// the RAM (zeroing the rest, their .bss) before the components run.
+// The booter's initargs ("xip") hold the table's address.
+//
+// The table is of little-endian 32 bit words: a header of the magic
+// number ("CXIP"), its version, the number of entries, and the size
+// of each, then an entry for each component of its id, the address
+// and size of its read-only segments, the (flash) address of its
+// writable segments' initial contents, and the (RAM) address, size of
+// the initial contents, and total size of its writable segments.
+
+use manifest::Json;
+use passes::{component, BuildState, ComposeError, SystemState};
+use syshelpers::{dump_file, emit_file};
+use xmas_elf::program;
+use xmas_elf::ElfFile;
+
+// Components' segments are aligned in the flash and RAM, as their
+// linker script aligns them.
+pub const XIP_ALIGN: u64 = 4096;
+
+const XIP_MAGIC: u32 = 0x5049_5843; // "CXIP", in memory
+const XIP_VERSION: u32 = 1;
+const XIP_HEADER_WORDS: u64 = 4;
+const XIP_ENTRY_WORDS: u64 = 7;
+// The alignment of the writable segments' contents in the flash.
+const XIP_LOAD_ALIGN: u64 = 8;
+
+// The size of the copy table for `n` components.
+pub fn xip_table_size(n: usize) -> u64 {
+    (XIP_HEADER_WORDS + XIP_ENTRY_WORDS * n as u64) * 4
+}
+
+fn hex(a: u64) -> Json {
+    Json::Str(format!("{:#x}", a))
+}
+
+// A component's entry in the copy table.
+struct XipEntry {
+    name: String,
+    compid: u64,
+    text: (u64, u64), // the address and size of the read-only segments
+    load: u64,        // of the writable segments' initial contents
+    data: (u64, u64), // the address and size of the initial contents
+    memsz: u64,       // of the writable segments
+}
+
+// Write xip.bin, the flash's image, and xip.json, describing its copy
+// table, into the build directory, and return their paths, if the
+// system has an XIP layout.
+pub fn xip_create(
+    s: &SystemState,
+    b: &dyn BuildState,
+) -> Result<Option<(String, String)>, ComposeError> {
+    let x = match s.get_spec().xip() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let ids = s.get_named().ids();
+    let flash_end = x.flash_base + x.flash_size;
+    let mut flash = vec![0u8; xip_table_size(ids.len()) as usize];
+    // Place `bytes` in the flash image at `addr`.
+    let place = |flash: &mut Vec<u8>, addr: u64, bytes: &[u8]| {
+        let off = (addr - x.flash_base) as usize;
+        if flash.len() < off + bytes.len() {
+            flash.resize(off + bytes.len(), 0);
+        }
+        flash[off..off + bytes.len()].copy_from_slice(bytes);
+    };
+
+    let mut entries = Vec::new();
+    let mut writable = Vec::new();
+    let mut used: Vec<(u64, u64, String)> = vec![(
+        x.flash_base,
+        x.flash_base + flash.len() as u64,
+        String::from("the copy table"),
+    )];
+    for (id, _) in ids.iter() {
+        let name = component(s, id).name.to_string();
+        let path = s.get_objs_id(id).comp_path();
+        let obj = dump_file(path)?;
+        let elf_file = ElfFile::new(&obj).map_err(|e| {
+            ComposeError::Pass(format!(
+                "Error: Cannot parse the object {} as an ELF file: {}",
+                path, e
+            ))
+        })?;
+        let contents = |off: u64, sz: u64| {
+            obj.get(off as usize..(off + sz) as usize)
+                .ok_or(ComposeError::Pass(format!(
+                    "Error: The object {} is truncated.",
+                    path
+                )))
+        };
+
+        let (mut text_lo, mut text_hi) = (u64::MAX, 0);
+        let (mut data_lo, mut data_hi, mut mem_hi) = (u64::MAX, 0, 0);
+        let mut data_segs = Vec::new();
+        for ph in elf_file
+            .program_iter()
+            .filter(|ph| ph.get_type() == Ok(program::Type::Load) && ph.mem_size() != 0)
+        {
+            let (addr, filesz, memsz) = (ph.virtual_addr(), ph.file_size(), ph.mem_size());
+            if ph.flags().is_write() {
+                data_lo = data_lo.min(addr);
+                data_hi = data_hi.max(addr + filesz);
+                mem_hi = mem_hi.max(addr + memsz);
+                data_segs.push((addr, contents(ph.offset(), filesz)?));
+                continue;
+            }
+            if addr < x.flash_base || addr + memsz > flash_end {
+                return Err(ComposeError::Pass(format!(
+                    "Error: Component {}'s read-only segment [{:#x}, {:#x}) is not within the xip layout's flash [{:#x}, {:#x}).",
+                    name,
+                    addr,
+                    addr + memsz,
+                    x.flash_base,
+                    flash_end
+                )));
+            }
+            if let Some((_, _, other)) = used
+                .iter()
+                .find(|(lo, hi, _)| addr < *hi && *lo < addr + memsz)
+            {
+                return Err(ComposeError::Pass(format!(
+                    "Error: Component {}'s read-only segment [{:#x}, {:#x}) overlaps {} in the flash.",
+                    name,
+                    addr,
+                    addr + memsz,
+                    other
+                )));
+            }
+            used.push((addr, addr + memsz, format!("component {}", name)));
+            place(&mut flash, addr, contents(ph.offset(), filesz)?);
+            text_lo = text_lo.min(addr);
+            text_hi = text_hi.max(addr + memsz);
+        }
+        // The initial contents span the writable segments, and any
+        // gaps between them.
+        let init = if data_segs.is_empty() {
+            data_lo = s
+                .get_address_assignments()
+                .component_dataaddr(id)
+                .unwrap_or(x.ram_base);
+            data_hi = data_lo;
+            mem_hi = data_lo;
+            Vec::new()
+        } else {
+            let mut init = vec![0u8; (data_hi - data_lo) as usize];
+            for (addr, bytes) in data_segs.iter() {
+                let off = (addr - data_lo) as usize;
+                init[off..off + bytes.len()].copy_from_slice(bytes);
+            }
+            init
+        };
+        if text_lo == u64::MAX {
+            text_lo = s.get_address_assignments().component_baseaddr(id);
+            text_hi = text_lo;
+        }
+        entries.push(XipEntry {
+            name,
+            compid: *id as u64,
+            text: (text_lo, text_hi - text_lo),
+            load: 0,
+            data: (data_lo, data_hi - data_lo),
+            memsz: mem_hi - data_lo,
+        });
+        writable.push(init);
+    }
+
+    // The writable segments' initial contents follow the read-only
+    // segments in the flash.
+    let mut load = x.flash_base + flash.len() as u64;
+    for (e, init) in entries.iter_mut().zip(writable.iter()) {
+        load = load.div_ceil(XIP_LOAD_ALIGN) * XIP_LOAD_ALIGN;
+        e.load = load;
+        place(&mut flash, load, init);
+        load += init.len() as u64;
+    }
+    if load > flash_end {
+        return Err(ComposeError::Pass(format!(
+            "Error: The components' read-only segments and initial writable contents ({} bytes) don't fit in the xip layout's flash ({} bytes).",
+            load - x.flash_base,
+            x.flash_size
+        )));
+    }
+
+    let mut words = vec![
+        XIP_MAGIC as u64,
+        XIP_VERSION as u64,
+        entries.len() as u64,
+        XIP_ENTRY_WORDS * 4,
+    ];
+    for e in entries.iter() {
+        words.extend_from_slice(&[
+            e.compid, e.text.0, e.text.1, e.load, e.data.0, e.data.1, e.memsz,
+        ]);
+    }
+    let table: Vec<u8> = words
+        .iter()
+        .flat_map(|w| (*w as u32).to_le_bytes().to_vec())
+        .collect();
+    place(&mut flash, x.flash_base, &table);
+
+    let bin_path = b.file_path(&"xip.bin".to_string())?;
+    emit_file(&bin_path, &flash)?;
+    let json_path = b.file_path(&"xip.json".to_string())?;
+    let json = Json::obj(vec![
+        ("flash_base", hex(x.flash_base)),
+        ("flash_used", Json::Num(flash.len() as u64)),
+        ("table", hex(x.flash_base)),
+        (
+            "components",
+            Json::Arr(
+                entries
+                    .iter()
+                    .map(|e| {
+                        Json::obj(vec![
+                            ("component", Json::str(&e.name)),
+                            ("compid", Json::Num(e.compid)),
+                            ("text", hex(e.text.0)),
+                            ("text_size", Json::Num(e.text.1)),
+                            ("load", hex(e.load)),
+                            ("data", hex(e.data.0)),
+                            ("data_size", Json::Num(e.data.1)),
+                            ("bss_size", Json::Num(e.memsz - e.data.1)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+    ]);
+    emit_file(&json_path, format!("{}\n", json).as_bytes())?;
+
+    Ok(Some((bin_path, json_path)))
+}